@@ -0,0 +1,448 @@
+use std::io;
+
+use crate::apu::noise_channel::NoiseChannel;
+use crate::apu::recorder::AudioRecorder;
+use crate::apu::square_channel::SquareChannel;
+use crate::apu::stereo_buffer::StereoBuffer;
+use crate::apu::vgm_logger::VgmLogger;
+use crate::apu::wave_channel::WaveChannel;
+use crate::constants::io_registers::*;
+use crate::error::state_error::StateError;
+use crate::state::snapshot::Snapshot;
+use crate::state::state_reader::StateReader;
+use crate::state::state_writer::StateWriter;
+
+/// Frequency of the APU clock in Hz (it doesn't change in CGB double speed mode).
+pub const APU_CLOCK: u64 = 4_194_304;
+pub const DEFAULT_SAMPLE_RATE: u32 = 48_000;
+// the channels are evaluated every 2 cycles, the fastest any of them can change
+const APU_STEP: u32 = 2;
+
+/// Audio processing unit: two square channels, a wave channel and a noise channel mixed
+/// into a stereo signal (NR50/NR51).
+///
+/// Length counters, envelopes and the sweep are driven by the frame sequencer, which is
+/// clocked by the timer (see [`APU::clock_frame_sequencer`]). The mixed output is
+/// resampled with band-limited steps and goes through the output capacitor (see
+/// [`StereoBuffer`]), producing interleaved stereo `f32` at the configured rate.
+pub struct APU {
+    // raw values written to 0xFF10–0xFF2F
+    r: [u8; 0x20],
+    powered: bool,
+    ch1: SquareChannel,
+    ch2: SquareChannel,
+    ch3: WaveChannel,
+    ch4: NoiseChannel,
+    frame_step: u8,
+    sample_rate: u32,
+    cgb_hardware: bool,
+    // cycles not evaluated yet because they don't make up a whole step
+    pending_cycles: u32,
+    mix_buffer: StereoBuffer,
+    // one buffer per channel, only while recording stems
+    stem_buffers: Option<Vec<StereoBuffer>>,
+    samples: Vec<f32>,
+    recorder: Option<AudioRecorder>,
+    vgm_logger: Option<VgmLogger>,
+}
+
+impl APU {
+    pub fn new() -> APU {
+        APU {
+            r: [0; 0x20],
+            powered: false,
+            ch1: SquareChannel::new(true),
+            ch2: SquareChannel::new(false),
+            ch3: WaveChannel::new(),
+            ch4: NoiseChannel::new(),
+            frame_step: 0,
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            cgb_hardware: false,
+            pending_cycles: 0,
+            mix_buffer: StereoBuffer::new(DEFAULT_SAMPLE_RATE, false),
+            stem_buffers: None,
+            samples: Vec::new(),
+            recorder: None,
+            vgm_logger: None,
+        }
+    }
+
+    /// Changes the output rate, dropping the samples not taken yet. It must not change
+    /// while recording.
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+        self.reset_output();
+    }
+
+    /// Returns the output rate, in samples per second and channel.
+    pub fn get_sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Selects the output capacitor of the Game Boy Color instead of the DMG one. It
+    /// depends on the hardware, so it also applies to DMG cartridges running on a CGB.
+    pub fn set_cgb_hardware(&mut self, cgb: bool) {
+        self.cgb_hardware = cgb;
+        self.reset_output();
+    }
+
+    fn reset_output(&mut self) {
+        self.mix_buffer = StereoBuffer::new(self.sample_rate, self.cgb_hardware);
+        if self.stem_buffers.is_some() {
+            self.stem_buffers = Some(self.new_stem_buffers());
+        }
+        self.samples.clear();
+    }
+
+    fn new_stem_buffers(&self) -> Vec<StereoBuffer> {
+        (0..4).map(|_| StereoBuffer::new(self.sample_rate, self.cgb_hardware)).collect()
+    }
+
+    /// Starts writing the output to a WAV file at `path`, replacing any recording in
+    /// progress.
+    ///
+    /// # Parameters
+    /// - `stems`: Also write one file per channel (see [`AudioRecorder`]).
+    pub fn start_recording(&mut self, path: &str, stems: bool) -> io::Result<()> {
+        self.stop_recording()?;
+        self.recorder = Some(AudioRecorder::create(path, self.sample_rate, stems)?);
+        // restart the mix too, so it stays sample-exact with the channel buffers
+        self.mix_buffer = StereoBuffer::new(self.sample_rate, self.cgb_hardware);
+        self.stem_buffers = if stems { Some(self.new_stem_buffers()) } else { None };
+        Ok(())
+    }
+
+    /// Finishes the current recording, if any.
+    pub fn stop_recording(&mut self) -> io::Result<()> {
+        self.stem_buffers = None;
+        match self.recorder.take() {
+            Some(recorder) => recorder.finish(),
+            None => Ok(()),
+        }
+    }
+
+    /// Starts logging sound register writes to a VGM file at `path`, replacing any log in
+    /// progress. The log begins with the current register state so it plays back from
+    /// the same point.
+    pub fn start_vgm_logging(&mut self, path: &str) -> io::Result<()> {
+        self.stop_vgm_logging()?;
+        let mut logger: VgmLogger = VgmLogger::create(path)?;
+        logger.log_write(NR52, if self.powered { 0x80 } else { 0x00 });
+        // wave RAM is only writable by the CPU while channel 3 is stopped
+        logger.log_write(NR30, 0x00);
+        for addr in WAVE_RAM_START..=WAVE_RAM_END {
+            logger.log_write(addr, self.read_register(addr));
+        }
+        for addr in NR10..NR52 {
+            let value: u8 = match addr {
+                // don't retrigger the channels
+                NR14 | NR24 | NR34 | NR44 => self.read_register(addr) & 0x7F,
+                _ => self.read_register(addr),
+            };
+            logger.log_write(addr, value);
+        }
+        self.vgm_logger = Some(logger);
+        Ok(())
+    }
+
+    /// Finishes the VGM log started with [`APU::start_vgm_logging`], if any.
+    pub fn stop_vgm_logging(&mut self) -> io::Result<()> {
+        match self.vgm_logger.take() {
+            Some(logger) => logger.finish(),
+            None => Ok(()),
+        }
+    }
+
+    /// Adds a CPU write to the VGM log, when logging.
+    pub fn log_write(&mut self, addr: u16, data: u8) {
+        if let Some(logger) = self.vgm_logger.as_mut() {
+            logger.log_write(addr, data);
+        }
+    }
+
+    /// Returns the samples generated since the last call, as interleaved left/right pairs.
+    /// At most a second of them is kept, so they have to be taken at least that often.
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.samples)
+    }
+
+    /// Returns the raw value of a sound register (0xFF10–0xFF3F), without read masks.
+    pub fn read_register(&self, addr: u16) -> u8 {
+        match addr {
+            NR52 => {
+                let mut value: u8 = if self.powered { 0x80 } else { 0 };
+                value |= self.ch1.is_enabled() as u8;
+                value |= (self.ch2.is_enabled() as u8) << 1;
+                value |= (self.ch3.is_enabled() as u8) << 2;
+                value |= (self.ch4.is_enabled() as u8) << 3;
+                value
+            }
+            WAVE_RAM_START..=WAVE_RAM_END => self.ch3.read_wave_ram((addr - WAVE_RAM_START) as usize),
+            _ => self.r[(addr - NR10) as usize]
+        }
+    }
+
+    /// Handles a CPU write to a sound register (0xFF10–0xFF3F).
+    pub fn write_register(&mut self, addr: u16, data: u8) {
+        match addr {
+            WAVE_RAM_START..=WAVE_RAM_END => {
+                self.ch3.write_wave_ram((addr - WAVE_RAM_START) as usize, data);
+                return;
+            }
+            NR52 => {
+                self.set_power(data & 0x80 != 0);
+                return;
+            }
+            // while the APU is powered off only NR52 and wave RAM accept writes, plus the
+            // length counters on the DMG, which are not wired to the power switch
+            _ if !self.powered => {
+                if !self.cgb_hardware {
+                    match addr {
+                        NR11 => self.ch1.load_length(data),
+                        NR21 => self.ch2.load_length(data),
+                        NR31 => self.ch3.write_length(data),
+                        NR41 => self.ch4.write_length(data),
+                        _ => {}
+                    }
+                }
+                return;
+            }
+            _ => {}
+        }
+        self.r[(addr - NR10) as usize] = data;
+        match addr {
+            NR10 => self.ch1.write_sweep(data),
+            NR11 => self.ch1.write_length(data),
+            NR12 => self.ch1.write_envelope(data),
+            NR13 => self.ch1.write_frequency_low(data),
+            NR14 => self.ch1.write_control(data),
+            NR21 => self.ch2.write_length(data),
+            NR22 => self.ch2.write_envelope(data),
+            NR23 => self.ch2.write_frequency_low(data),
+            NR24 => self.ch2.write_control(data),
+            NR30 => self.ch3.write_dac(data),
+            NR31 => self.ch3.write_length(data),
+            NR32 => self.ch3.write_volume(data),
+            NR33 => self.ch3.write_frequency_low(data),
+            NR34 => self.ch3.write_control(data),
+            NR41 => self.ch4.write_length(data),
+            NR42 => self.ch4.write_envelope(data),
+            NR43 => self.ch4.write_polynomial(data),
+            NR44 => self.ch4.write_control(data),
+            _ => {}
+        }
+    }
+
+    /// Powering off clears every register but wave RAM; powering on restarts the frame
+    /// sequencer.
+    fn set_power(&mut self, on: bool) {
+        if on && !self.powered {
+            self.frame_step = 0;
+        }
+        if !on && self.powered {
+            let mut ch3: WaveChannel = WaveChannel::new();
+            for i in 0..0x10 {
+                ch3.write_wave_ram(i, self.ch3.read_wave_ram(i));
+            }
+            self.r = [0; 0x20];
+            self.ch1 = SquareChannel::new(true);
+            self.ch2 = SquareChannel::new(false);
+            self.ch3 = ch3;
+            self.ch4 = NoiseChannel::new();
+        }
+        self.powered = on;
+    }
+
+    /// Advances the frame sequencer by one step (512 Hz, on a falling edge of a DIV bit).
+    ///
+    /// Steps 0, 2, 4 and 6 clock the length counters, 2 and 6 the sweep and 7 the envelopes.
+    pub fn clock_frame_sequencer(&mut self) {
+        if !self.powered {
+            return;
+        }
+        if self.frame_step & 0x01 == 0 {
+            self.ch1.clock_length();
+            self.ch2.clock_length();
+            self.ch3.clock_length();
+            self.ch4.clock_length();
+        }
+        if self.frame_step == 2 || self.frame_step == 6 {
+            self.ch1.clock_sweep();
+        }
+        if self.frame_step == 7 {
+            self.ch1.clock_envelope();
+            self.ch2.clock_envelope();
+            self.ch4.clock_envelope();
+        }
+        self.frame_step = (self.frame_step + 1) & 0x07;
+    }
+
+    /// Advances the channels by `cycles` APU cycles (4194304 Hz), generating the samples
+    /// that fall inside that time.
+    pub fn tick(&mut self, cycles: u32) {
+        if let Some(logger) = self.vgm_logger.as_mut() {
+            logger.advance(cycles as u64);
+        }
+        self.pending_cycles += cycles;
+        while self.pending_cycles >= APU_STEP {
+            self.pending_cycles -= APU_STEP;
+            if self.powered {
+                self.ch1.tick(APU_STEP);
+                self.ch2.tick(APU_STEP);
+                self.ch3.tick(APU_STEP);
+                self.ch4.tick(APU_STEP);
+            }
+            let channels: [(f32, f32); 4] = self.mix();
+            let left: f32 = channels.iter().map(|c| c.0).sum();
+            let right: f32 = channels.iter().map(|c| c.1).sum();
+            self.mix_buffer.update(left, right);
+            self.mix_buffer.advance(APU_STEP as u64);
+            if let Some(buffers) = self.stem_buffers.as_mut() {
+                for (buffer, (left, right)) in buffers.iter_mut().zip(channels) {
+                    buffer.update(left, right);
+                    buffer.advance(APU_STEP as u64);
+                }
+            }
+        }
+        self.read_samples();
+    }
+
+    /// Moves the finished samples to the output, and to the recorder when recording.
+    fn read_samples(&mut self) {
+        let start: usize = self.samples.len();
+        self.mix_buffer.read_samples(&mut self.samples);
+        if let Some(recorder) = self.recorder.as_mut() {
+            let mut stems: [Vec<f32>; 4] = Default::default();
+            if let Some(buffers) = self.stem_buffers.as_mut() {
+                for (buffer, samples) in buffers.iter_mut().zip(stems.iter_mut()) {
+                    buffer.read_samples(samples);
+                }
+            }
+            recorder.write(&self.samples[start..], &stems);
+        }
+        // nobody takes them when running headless: keep the last half second once a second
+        // piles up
+        let limit: usize = 2 * self.sample_rate as usize;
+        if self.samples.len() > limit {
+            self.samples.drain(..self.samples.len() - limit / 2);
+        }
+    }
+
+    /// Analog output of every channel DAC, in the -1.0..=1.0 range.
+    fn channel_outputs(&self) -> [f32; 4] {
+        let dac = |enabled: bool, output: u8| -> f32 {
+            if enabled { output as f32 / 7.5 - 1.0 } else { 0.0 }
+        };
+        [
+            dac(self.ch1.is_dac_enabled(), self.ch1.output()),
+            dac(self.ch2.is_dac_enabled(), self.ch2.output()),
+            dac(self.ch3.is_dac_enabled(), self.ch3.output()),
+            dac(self.ch4.is_dac_enabled(), self.ch4.output()),
+        ]
+    }
+
+    /// Left/right contribution of every channel according to NR51 (panning) and NR50
+    /// (master volume). The mix is their sum.
+    fn mix(&self) -> [(f32, f32); 4] {
+        let mut channels: [(f32, f32); 4] = [(0.0, 0.0); 4];
+        if !self.powered {
+            return channels;
+        }
+        let outputs: [f32; 4] = self.channel_outputs();
+        let panning: u8 = self.read_register(NR51);
+        let volume: u8 = self.read_register(NR50);
+        let left_volume: f32 = (((volume >> 4) & 0x07) + 1) as f32 / 8.0 / 4.0;
+        let right_volume: f32 = ((volume & 0x07) + 1) as f32 / 8.0 / 4.0;
+        for (i, (channel, output)) in channels.iter_mut().zip(outputs).enumerate() {
+            if panning & (0x10 << i) != 0 {
+                channel.0 = output * left_volume;
+            }
+            if panning & (0x01 << i) != 0 {
+                channel.1 = output * right_volume;
+            }
+        }
+        channels
+    }
+}
+
+/// The output side (sample rate, resampling buffers, recordings) is left untouched.
+impl Snapshot for APU {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.r);
+        writer.write_bool(self.powered);
+        self.ch1.save_state(writer);
+        self.ch2.save_state(writer);
+        self.ch3.save_state(writer);
+        self.ch4.save_state(writer);
+        writer.write_u8(self.frame_step);
+        writer.write_bool(self.cgb_hardware);
+        writer.write_u32(self.pending_cycles);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        reader.read_bytes(&mut self.r)?;
+        self.powered = reader.read_bool()?;
+        self.ch1.load_state(reader)?;
+        self.ch2.load_state(reader)?;
+        self.ch3.load_state(reader)?;
+        self.ch4.load_state(reader)?;
+        self.frame_step = reader.read_u8()? & 0x07;
+        self.cgb_hardware = reader.read_bool()?;
+        self.pending_cycles = reader.read_u32()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn powered() -> APU {
+        let mut apu: APU = APU::new();
+        apu.write_register(NR52, 0x80);
+        apu
+    }
+
+    #[test]
+    fn nr52_reports_running_channels() {
+        let mut apu: APU = powered();
+        apu.write_register(NR22, 0xF0);
+        apu.write_register(NR21, 0x3F);
+        apu.write_register(NR24, 0xC0);
+        assert_eq!(apu.read_register(NR52), 0x82);
+        // length 1 expires on the first length step of the frame sequencer
+        apu.clock_frame_sequencer();
+        assert_eq!(apu.read_register(NR52), 0x80);
+    }
+
+    #[test]
+    fn power_off_clears_all_but_wave_ram() {
+        let mut apu: APU = powered();
+        apu.write_register(NR50, 0x77);
+        apu.write_register(WAVE_RAM_START, 0x12);
+        apu.write_register(NR52, 0x00);
+        assert_eq!(apu.read_register(NR50), 0x00);
+        assert_eq!(apu.read_register(WAVE_RAM_START), 0x12);
+        // registers ignore writes until the APU is powered again
+        apu.write_register(NR50, 0x77);
+        assert_eq!(apu.read_register(NR50), 0x00);
+        apu.write_register(NR52, 0x80);
+        apu.write_register(NR50, 0x77);
+        assert_eq!(apu.read_register(NR50), 0x77);
+    }
+
+    #[test]
+    fn sweep_runs_on_steps_2_and_6() {
+        let mut apu: APU = powered();
+        apu.write_register(NR10, 0x11);
+        apu.write_register(NR12, 0xF0);
+        apu.write_register(NR13, 0x00);
+        // 0x400 + 0x200 fits, but the check of the value after it overflows
+        apu.write_register(NR14, 0x84);
+        apu.clock_frame_sequencer();
+        apu.clock_frame_sequencer();
+        assert_eq!(apu.read_register(NR52) & 0x01, 0x01);
+        apu.clock_frame_sequencer();
+        assert_eq!(apu.read_register(NR52) & 0x01, 0x00);
+    }
+}
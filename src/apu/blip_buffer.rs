@@ -0,0 +1,134 @@
+use std::f64::consts::PI;
+
+// sub-sample positions the step kernel is computed for
+const PHASES: usize = 64;
+// taps of the step kernel; the output is delayed by half of it
+const WIDTH: usize = 16;
+// fraction of the Nyquist frequency kept by the low-pass kernel
+const CUTOFF: f64 = 0.92;
+
+/// Band-limited synthesis buffer for a single signal.
+///
+/// Instead of sampling the APU output at the host rate (which aliases the sharp edges of
+/// the square channels), every change of amplitude is recorded as a step and drawn into
+/// the buffer with a windowed-sinc kernel placed at its exact sub-sample position. Reading
+/// integrates those band-limited steps back into samples.
+pub struct BlipBuffer {
+    clock_rate: u64,
+    sample_rate: u64,
+    kernel: Vec<[f32; WIDTH]>,
+    // band-limited impulses, integrated when samples are read
+    buffer: Vec<f32>,
+    // current time relative to buffer[0], in clock cycles times the sample rate
+    offset: u64,
+    integrator: f32,
+}
+
+impl BlipBuffer {
+    /// # Parameters
+    /// - `clock_rate`: Rate of the input clock (the time unit of [`BlipBuffer::advance`]).
+    /// - `sample_rate`: Rate of the generated samples.
+    pub fn new(clock_rate: u64, sample_rate: u64) -> BlipBuffer {
+        BlipBuffer {
+            clock_rate,
+            sample_rate,
+            kernel: BlipBuffer::build_kernel(),
+            buffer: vec![0.0; WIDTH],
+            offset: 0,
+            integrator: 0.0,
+        }
+    }
+
+    /// Low-pass impulse responses (Blackman windowed sinc) for every phase, each one
+    /// normalized so that integrating it yields a step of exactly 1.0.
+    fn build_kernel() -> Vec<[f32; WIDTH]> {
+        let mut kernel: Vec<[f32; WIDTH]> = vec![[0.0; WIDTH]; PHASES];
+        for (phase, taps) in kernel.iter_mut().enumerate() {
+            let fraction: f64 = phase as f64 / PHASES as f64;
+            let mut response: [f64; WIDTH] = [0.0; WIDTH];
+            for (i, value) in response.iter_mut().enumerate() {
+                let x: f64 = i as f64 - (WIDTH / 2) as f64 + 1.0 - fraction;
+                let sinc: f64 = if x == 0.0 { 1.0 } else { (PI * CUTOFF * x).sin() / (PI * CUTOFF * x) };
+                // window centered on the impulse, spanning the whole kernel
+                let w: f64 = (x + WIDTH as f64 / 2.0) / WIDTH as f64;
+                let window: f64 = 0.42 - 0.5 * (2.0 * PI * w).cos() + 0.08 * (4.0 * PI * w).cos();
+                *value = sinc * window.max(0.0);
+            }
+            let sum: f64 = response.iter().sum();
+            for (tap, value) in taps.iter_mut().zip(response.iter()) {
+                *tap = (value / sum) as f32;
+            }
+        }
+        kernel
+    }
+
+    /// Adds a change of amplitude at the current time.
+    pub fn add_delta(&mut self, delta: f32) {
+        let position: u64 = self.offset * PHASES as u64 / self.clock_rate;
+        let index: usize = (position / PHASES as u64) as usize;
+        let phase: usize = (position % PHASES as u64) as usize;
+        if self.buffer.len() < index + WIDTH {
+            self.buffer.resize(index + WIDTH, 0.0);
+        }
+        for (sample, tap) in self.buffer[index..].iter_mut().zip(self.kernel[phase].iter()) {
+            *sample += delta * tap;
+        }
+    }
+
+    /// Moves the current time forward by `cycles` clock cycles.
+    pub fn advance(&mut self, cycles: u64) {
+        self.offset += cycles * self.sample_rate;
+    }
+
+    /// Appends every sample that can no longer change (the ones before the current time)
+    /// to `out` and drops them from the buffer.
+    pub fn read_samples(&mut self, out: &mut Vec<f32>) {
+        let count: usize = (self.offset / self.clock_rate) as usize;
+        if self.buffer.len() < count + WIDTH {
+            self.buffer.resize(count + WIDTH, 0.0);
+        }
+        for sample in self.buffer.drain(..count) {
+            self.integrator += sample;
+            out.push(self.integrator);
+        }
+        self.offset -= count as u64 * self.clock_rate;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kernel_phases_integrate_to_one() {
+        for taps in BlipBuffer::build_kernel() {
+            assert!((taps.iter().sum::<f32>() - 1.0).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn step_settles_after_the_kernel_width() {
+        let mut blip: BlipBuffer = BlipBuffer::new(4_000, 1_000);
+        blip.advance(10);
+        blip.add_delta(1.0);
+        blip.advance(4 * 40);
+        let mut samples: Vec<f32> = Vec::new();
+        blip.read_samples(&mut samples);
+        assert_eq!(samples.len(), 42);
+        assert!(samples[0].abs() < 1e-6);
+        for sample in &samples[2 + WIDTH..] {
+            assert!((sample - 1.0).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn keeps_the_fraction_of_a_sample() {
+        let mut blip: BlipBuffer = BlipBuffer::new(4_000, 1_000);
+        let mut samples: Vec<f32> = Vec::new();
+        blip.advance(6);
+        blip.read_samples(&mut samples);
+        blip.advance(6);
+        blip.read_samples(&mut samples);
+        assert_eq!(samples.len(), 3);
+    }
+}
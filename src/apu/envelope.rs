@@ -0,0 +1,131 @@
+use crate::error::state_error::StateError;
+use crate::state::snapshot::Snapshot;
+use crate::state::state_reader::StateReader;
+use crate::state::state_writer::StateWriter;
+
+/// Volume envelope shared by the square and noise channels (NRx2).
+pub struct Envelope {
+    initial_volume: u8,
+    increase: bool,
+    period: u8,
+    volume: u8,
+    timer: u8,
+}
+
+impl Envelope {
+    pub fn new() -> Envelope {
+        Envelope {
+            initial_volume: 0,
+            increase: false,
+            period: 0,
+            volume: 0,
+            timer: 0,
+        }
+    }
+
+    pub fn write(&mut self, value: u8) {
+        self.initial_volume = value >> 4;
+        self.increase = value & 0x08 != 0;
+        self.period = value & 0x07;
+    }
+
+    /// The channel DAC is powered as long as the upper 5 bits of NRx2 are not all zero.
+    pub fn is_dac_enabled(&self) -> bool {
+        self.initial_volume != 0 || self.increase
+    }
+
+    pub fn get_volume(&self) -> u8 {
+        self.volume
+    }
+
+    pub fn trigger(&mut self) {
+        self.volume = self.initial_volume;
+        self.timer = if self.period == 0 { 8 } else { self.period };
+    }
+
+    /// Clocked at 64 Hz by step 7 of the frame sequencer.
+    pub fn clock(&mut self) {
+        if self.period == 0 {
+            return;
+        }
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer > 0 {
+            return;
+        }
+        self.timer = self.period;
+        if self.increase && self.volume < 15 {
+            self.volume += 1;
+        } else if !self.increase && self.volume > 0 {
+            self.volume -= 1;
+        }
+    }
+}
+
+impl Snapshot for Envelope {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.initial_volume);
+        writer.write_bool(self.increase);
+        writer.write_u8(self.period);
+        writer.write_u8(self.volume);
+        writer.write_u8(self.timer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.initial_volume = reader.read_u8()?;
+        self.increase = reader.read_bool()?;
+        self.period = reader.read_u8()?;
+        self.volume = reader.read_u8()? & 0x0F;
+        self.timer = reader.read_u8()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn steps_every_period_and_saturates() {
+        let mut envelope: Envelope = Envelope::new();
+        // volume 2, decreasing, period 2
+        envelope.write(0x22);
+        envelope.trigger();
+        assert_eq!(envelope.get_volume(), 2);
+        envelope.clock();
+        assert_eq!(envelope.get_volume(), 2);
+        envelope.clock();
+        assert_eq!(envelope.get_volume(), 1);
+        for _ in 0..4 {
+            envelope.clock();
+        }
+        assert_eq!(envelope.get_volume(), 0);
+        // volume 14, increasing, period 1
+        envelope.write(0xE9);
+        envelope.trigger();
+        envelope.clock();
+        envelope.clock();
+        assert_eq!(envelope.get_volume(), 15);
+    }
+
+    #[test]
+    fn period_0_holds_the_volume() {
+        let mut envelope: Envelope = Envelope::new();
+        envelope.write(0x50);
+        envelope.trigger();
+        for _ in 0..16 {
+            envelope.clock();
+        }
+        assert_eq!(envelope.get_volume(), 5);
+    }
+
+    #[test]
+    fn dac_follows_the_upper_five_bits() {
+        let mut envelope: Envelope = Envelope::new();
+        envelope.write(0x07);
+        assert!(!envelope.is_dac_enabled());
+        envelope.write(0x08);
+        assert!(envelope.is_dac_enabled());
+        envelope.write(0x10);
+        assert!(envelope.is_dac_enabled());
+    }
+}
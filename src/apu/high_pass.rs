@@ -0,0 +1,61 @@
+use crate::apu::apu::APU_CLOCK;
+
+// fraction of the capacitor charge kept after each APU cycle
+const DMG_CHARGE: f64 = 0.999958;
+const CGB_CHARGE: f64 = 0.998943;
+
+/// DC-blocking high-pass filter modelling the capacitors on the audio output.
+///
+/// Without it a channel with its DAC on and silent output adds a constant offset, and
+/// turning DACs on or off makes the output jump, exactly like the real console would
+/// without the capacitor.
+pub struct HighPass {
+    capacitor: f32,
+    charge: f32,
+}
+
+impl HighPass {
+    /// # Parameters
+    /// - `cgb`: Whether the filter of the Game Boy Color (which charges faster) is used.
+    /// - `sample_rate`: Rate of the samples given to [`HighPass::apply`].
+    pub fn new(cgb: bool, sample_rate: u32) -> HighPass {
+        let charge: f64 = if cgb { CGB_CHARGE } else { DMG_CHARGE };
+        HighPass {
+            capacitor: 0.0,
+            charge: charge.powf(APU_CLOCK as f64 / sample_rate as f64) as f32,
+        }
+    }
+
+    pub fn apply(&mut self, input: f32) -> f32 {
+        let output: f32 = input - self.capacitor;
+        self.capacitor = input - output * self.charge;
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn removes_a_constant_offset() {
+        let mut filter: HighPass = HighPass::new(false, 48_000);
+        assert_eq!(filter.apply(0.5), 0.5);
+        let mut output: f32 = 0.5;
+        for _ in 0..48_000 {
+            output = filter.apply(0.5);
+        }
+        assert!(output.abs() < 0.01);
+    }
+
+    #[test]
+    fn cgb_capacitor_charges_faster() {
+        let mut dmg: HighPass = HighPass::new(false, 48_000);
+        let mut cgb: HighPass = HighPass::new(true, 48_000);
+        for _ in 0..100 {
+            dmg.apply(1.0);
+            cgb.apply(1.0);
+        }
+        assert!(cgb.apply(1.0) < dmg.apply(1.0));
+    }
+}
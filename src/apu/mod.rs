@@ -0,0 +1,13 @@
+pub mod apu;
+pub mod blip_buffer;
+pub mod envelope;
+pub mod high_pass;
+pub mod length_counter;
+pub mod noise_channel;
+pub mod recorder;
+pub mod square_channel;
+pub mod stereo_buffer;
+pub mod sweep;
+pub mod vgm_logger;
+pub mod wave_channel;
+pub mod wav_writer;
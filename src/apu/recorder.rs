@@ -0,0 +1,124 @@
+use std::io;
+
+use crate::apu::wav_writer::WavWriter;
+
+/// File name suffixes of the per-channel stems, in channel order.
+pub const STEM_NAMES: [&str; 4] = ["square1", "square2", "wave", "noise"];
+
+/// Records the APU output to a stereo WAV file and, optionally, one WAV per channel.
+///
+/// Stems are written next to the main file (`song.wav` → `song.square1.wav`...) with the
+/// same panning and master volume as the mix, so the four of them add up to it.
+pub struct AudioRecorder {
+    mix: WavWriter,
+    stems: Option<Vec<WavWriter>>,
+    // first error hit while writing, reported by `finish`
+    error: Option<io::Error>,
+}
+
+impl AudioRecorder {
+    /// # Parameters
+    /// - `path`: File receiving the mix.
+    /// - `sample_rate`: Rate of the recorded samples.
+    /// - `stems`: Whether the per-channel files are written too.
+    pub fn create(path: &str, sample_rate: u32, stems: bool) -> io::Result<AudioRecorder> {
+        let mix: WavWriter = WavWriter::create(path, 2, sample_rate)?;
+        let stems: Option<Vec<WavWriter>> = if stems {
+            let mut writers: Vec<WavWriter> = Vec::new();
+            for name in STEM_NAMES {
+                writers.push(WavWriter::create(&AudioRecorder::stem_path(path, name), 2, sample_rate)?);
+            }
+            Some(writers)
+        } else {
+            None
+        };
+        Ok(AudioRecorder { mix, stems, error: None })
+    }
+
+    /// Inserts the channel name before the extension of `path`.
+    pub fn stem_path(path: &str, name: &str) -> String {
+        match path.strip_suffix(".wav") {
+            Some(base) => format!("{}.{}.wav", base, name),
+            None => format!("{}.{}.wav", path, name),
+        }
+    }
+
+    /// Appends interleaved stereo samples to the mix and, when recording stems, the
+    /// matching samples of every channel.
+    pub fn write(&mut self, mix: &[f32], stems: &[Vec<f32>; 4]) {
+        if self.error.is_some() {
+            return;
+        }
+        let mut result: io::Result<()> = self.mix.write_samples(mix);
+        if let Some(writers) = self.stems.as_mut() {
+            for (writer, samples) in writers.iter_mut().zip(stems.iter()) {
+                result = result.and_then(|_| writer.write_samples(samples));
+            }
+        }
+        if let Err(e) = result {
+            self.error = Some(e);
+        }
+    }
+
+    /// Completes every file.
+    ///
+    /// # Returns
+    /// The first error hit while recording, if any.
+    pub fn finish(self) -> io::Result<()> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        self.mix.finish()?;
+        for writer in self.stems.into_iter().flatten() {
+            writer.finish()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apu::apu::APU;
+    use crate::constants::io_registers::{NR12, NR14, NR22, NR24, NR50, NR51, NR52};
+
+    fn read_pcm(path: &str) -> Vec<i16> {
+        let bytes: Vec<u8> = std::fs::read(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        bytes[44..].chunks(2).map(|pair| i16::from_le_bytes([pair[0], pair[1]])).collect()
+    }
+
+    #[test]
+    fn stems_go_next_to_the_mix() {
+        assert_eq!(AudioRecorder::stem_path("song.wav", "wave"), "song.wave.wav");
+        assert_eq!(AudioRecorder::stem_path("song", "noise"), "song.noise.wav");
+    }
+
+    #[test]
+    fn stems_add_up_to_the_mix() {
+        let path = std::env::temp_dir().join(format!("rustyboy-stems-{}.wav", std::process::id()));
+        let path: &str = path.to_str().unwrap();
+        let mut apu: APU = APU::new();
+        apu.write_register(NR52, 0x80);
+        apu.write_register(NR50, 0x77);
+        apu.write_register(NR51, 0xFF);
+        apu.start_recording(path, true).unwrap();
+        apu.write_register(NR12, 0xF0);
+        apu.write_register(NR14, 0x85);
+        apu.write_register(NR22, 0x80);
+        apu.write_register(NR24, 0x87);
+        apu.tick(70_224);
+        apu.stop_recording().unwrap();
+
+        let mix: Vec<i16> = read_pcm(path);
+        let stems: Vec<Vec<i16>> = STEM_NAMES.iter().map(|name| read_pcm(&AudioRecorder::stem_path(path, name))).collect();
+        assert!(!mix.is_empty());
+        assert!(mix.iter().any(|&sample| sample != 0));
+        for (i, &sample) in mix.iter().enumerate() {
+            let sum: i32 = stems.iter().map(|stem| stem[i] as i32).sum();
+            // each file rounds on its own
+            assert!((sample as i32 - sum).abs() <= 4);
+        }
+        assert!(stems[2].iter().chain(stems[3].iter()).all(|&sample| sample == 0));
+    }
+}
@@ -0,0 +1,228 @@
+use crate::apu::envelope::Envelope;
+use crate::apu::length_counter::LengthCounter;
+use crate::apu::sweep::{Sweep, SweepResult};
+use crate::error::state_error::StateError;
+use crate::state::snapshot::Snapshot;
+use crate::state::state_reader::StateReader;
+use crate::state::state_writer::StateWriter;
+
+const DUTY_TABLE: [u8; 4] = [
+    0b0000_0001, // 12.5 %
+    0b1000_0001, // 25 %
+    0b1000_0111, // 50 %
+    0b0111_1110, // 75 %
+];
+
+/// Square wave channel. Channel 1 has a frequency sweep unit, channel 2 doesn't.
+pub struct SquareChannel {
+    enabled: bool,
+    duty: u8,
+    duty_step: u8,
+    frequency: u16,
+    timer: u32,
+    length: LengthCounter,
+    envelope: Envelope,
+    sweep: Option<Sweep>,
+}
+
+impl SquareChannel {
+    pub fn new(with_sweep: bool) -> SquareChannel {
+        SquareChannel {
+            enabled: false,
+            duty: 0,
+            duty_step: 0,
+            frequency: 0,
+            timer: 0,
+            length: LengthCounter::new(64),
+            envelope: Envelope::new(),
+            sweep: if with_sweep { Some(Sweep::new()) } else { None },
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn is_dac_enabled(&self) -> bool {
+        self.envelope.is_dac_enabled()
+    }
+
+    pub fn write_sweep(&mut self, value: u8) {
+        if let Some(sweep) = self.sweep.as_mut() {
+            if !sweep.write(value) {
+                self.enabled = false;
+            }
+        }
+    }
+
+    /// NRx1: duty and length.
+    pub fn write_length(&mut self, value: u8) {
+        self.duty = value >> 6;
+        self.length.load(value & 0x3F);
+    }
+
+    /// Loads the length alone, as a DMG write to NRx1 does while the APU is off.
+    pub fn load_length(&mut self, value: u8) {
+        self.length.load(value & 0x3F);
+    }
+
+    /// NRx2: volume envelope. Turning the DAC off also disables the channel.
+    pub fn write_envelope(&mut self, value: u8) {
+        self.envelope.write(value);
+        if !self.envelope.is_dac_enabled() {
+            self.enabled = false;
+        }
+    }
+
+    /// NRx3: lower 8 bits of the frequency.
+    pub fn write_frequency_low(&mut self, value: u8) {
+        self.frequency = (self.frequency & 0x0700) | value as u16;
+    }
+
+    /// NRx4: trigger, length enable and upper 3 bits of the frequency.
+    pub fn write_control(&mut self, value: u8) {
+        self.frequency = (self.frequency & 0x00FF) | ((value & 0x07) as u16) << 8;
+        self.length.set_enabled(value & 0x40 != 0);
+        if value & 0x80 != 0 {
+            self.trigger();
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.is_dac_enabled();
+        self.length.trigger();
+        self.timer = (2048 - self.frequency as u32) * 4;
+        self.envelope.trigger();
+        if let Some(sweep) = self.sweep.as_mut() {
+            if !sweep.trigger(self.frequency) {
+                self.enabled = false;
+            }
+        }
+    }
+
+    /// Advances the frequency timer by `cycles` T-cycles.
+    pub fn tick(&mut self, cycles: u32) {
+        let mut cycles: u32 = cycles;
+        while cycles > 0 {
+            if self.timer == 0 {
+                self.timer = (2048 - self.frequency as u32) * 4;
+            }
+            let step: u32 = cycles.min(self.timer);
+            self.timer -= step;
+            cycles -= step;
+            if self.timer == 0 {
+                self.duty_step = (self.duty_step + 1) & 0x07;
+            }
+        }
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_sweep(&mut self) {
+        let Some(sweep) = self.sweep.as_mut() else {
+            return;
+        };
+        match sweep.clock() {
+            SweepResult::Frequency(frequency) => self.frequency = frequency,
+            SweepResult::Overflow => self.enabled = false,
+            SweepResult::Unchanged => {}
+        }
+    }
+
+    /// Current digital output (0–15).
+    pub fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+        let high: bool = (DUTY_TABLE[self.duty as usize] >> (7 - self.duty_step)) & 1 != 0;
+        if high { self.envelope.get_volume() } else { 0 }
+    }
+}
+
+impl Snapshot for SquareChannel {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.enabled);
+        writer.write_u8(self.duty);
+        writer.write_u8(self.duty_step);
+        writer.write_u16(self.frequency);
+        writer.write_u32(self.timer);
+        self.length.save_state(writer);
+        self.envelope.save_state(writer);
+        writer.write_bool(self.sweep.is_some());
+        if let Some(sweep) = self.sweep.as_ref() {
+            sweep.save_state(writer);
+        }
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.enabled = reader.read_bool()?;
+        self.duty = reader.read_u8()? & 0x03;
+        self.duty_step = reader.read_u8()? & 0x07;
+        self.frequency = reader.read_u16()? & 0x07FF;
+        self.timer = reader.read_u32()?;
+        self.length.load_state(reader)?;
+        self.envelope.load_state(reader)?;
+        if reader.read_bool()? != self.sweep.is_some() {
+            return Err(StateError::InvalidValue("square channel sweep"));
+        }
+        match self.sweep.as_mut() {
+            Some(sweep) => sweep.load_state(reader),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn triggered(duty: u8, frequency: u16) -> SquareChannel {
+        let mut channel: SquareChannel = SquareChannel::new(false);
+        channel.write_length(duty << 6);
+        channel.write_envelope(0xF0);
+        channel.write_frequency_low(frequency as u8);
+        channel.write_control(0x80 | (frequency >> 8) as u8);
+        channel
+    }
+
+    #[test]
+    fn duty_cycles() {
+        for (duty, high) in [(0, 1), (1, 2), (2, 4), (3, 6)] {
+            let mut channel: SquareChannel = triggered(duty, 2047);
+            let mut count: u32 = 0;
+            for _ in 0..8 {
+                channel.tick(4);
+                count += (channel.output() != 0) as u32;
+            }
+            assert_eq!(count, high);
+        }
+    }
+
+    #[test]
+    fn one_duty_step_every_4_times_2048_minus_frequency() {
+        let mut channel: SquareChannel = triggered(0, 2047 - 9);
+        // 12.5% duty is high on the last of the 8 steps
+        channel.tick(7 * 40 - 1);
+        assert_eq!(channel.output(), 0);
+        channel.tick(1);
+        assert_eq!(channel.output(), 15);
+    }
+
+    #[test]
+    fn dac_off_disables_the_channel() {
+        let mut channel: SquareChannel = triggered(2, 0x700);
+        assert!(channel.is_enabled());
+        channel.write_envelope(0x00);
+        assert!(!channel.is_enabled());
+        channel.write_control(0x80);
+        assert!(!channel.is_enabled());
+    }
+}
@@ -0,0 +1,74 @@
+use crate::apu::apu::APU_CLOCK;
+use crate::apu::blip_buffer::BlipBuffer;
+use crate::apu::high_pass::HighPass;
+
+/// Turns a stereo signal sampled at the APU clock into filtered samples at the output rate.
+pub struct StereoBuffer {
+    output: (f32, f32),
+    left: BlipBuffer,
+    right: BlipBuffer,
+    left_filter: HighPass,
+    right_filter: HighPass,
+}
+
+impl StereoBuffer {
+    pub fn new(sample_rate: u32, cgb: bool) -> StereoBuffer {
+        StereoBuffer {
+            output: (0.0, 0.0),
+            left: BlipBuffer::new(APU_CLOCK, sample_rate as u64),
+            right: BlipBuffer::new(APU_CLOCK, sample_rate as u64),
+            left_filter: HighPass::new(cgb, sample_rate),
+            right_filter: HighPass::new(cgb, sample_rate),
+        }
+    }
+
+    /// Sets the level of the signal from the current time on.
+    pub fn update(&mut self, left: f32, right: f32) {
+        if left != self.output.0 {
+            self.left.add_delta(left - self.output.0);
+        }
+        if right != self.output.1 {
+            self.right.add_delta(right - self.output.1);
+        }
+        self.output = (left, right);
+    }
+
+    /// Moves the current time forward by `cycles` APU cycles.
+    pub fn advance(&mut self, cycles: u64) {
+        self.left.advance(cycles);
+        self.right.advance(cycles);
+    }
+
+    /// Appends the finished samples to `out`, as interleaved left/right pairs.
+    pub fn read_samples(&mut self, out: &mut Vec<f32>) {
+        let mut left: Vec<f32> = Vec::new();
+        let mut right: Vec<f32> = Vec::new();
+        self.left.read_samples(&mut left);
+        self.right.read_samples(&mut right);
+        for (l, r) in left.into_iter().zip(right) {
+            out.push(self.left_filter.apply(l));
+            out.push(self.right_filter.apply(r));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interleaves_left_and_right() {
+        let mut buffer: StereoBuffer = StereoBuffer::new(48_000, false);
+        buffer.update(0.5, -0.5);
+        // 1/64 s is 750 samples at 48 kHz
+        buffer.advance(APU_CLOCK / 64);
+        let mut out: Vec<f32> = Vec::new();
+        buffer.read_samples(&mut out);
+        assert_eq!(out.len(), 2 * 750);
+        // right after the step has settled, before the high-pass filter pulls it back to 0
+        let (left, right): (f32, f32) = (out[40], out[41]);
+        assert!(left > 0.4 && right < -0.4);
+        assert!((left + right).abs() < 1e-6);
+        assert!(out[out.len() - 2] < left);
+    }
+}
@@ -0,0 +1,153 @@
+use crate::error::state_error::StateError;
+use crate::state::snapshot::Snapshot;
+use crate::state::state_reader::StateReader;
+use crate::state::state_writer::StateWriter;
+
+/// Result of a sweep clock.
+pub enum SweepResult {
+    Unchanged,
+    Frequency(u16),
+    /// The frequency went over 2047 and the channel is disabled.
+    Overflow,
+}
+
+/// Frequency sweep unit of channel 1 (NR10).
+pub struct Sweep {
+    period: u8,
+    negate: bool,
+    shift: u8,
+    timer: u8,
+    shadow: u16,
+    enabled: bool,
+    // a calculation in negate mode happened since the last trigger
+    negate_used: bool,
+}
+
+impl Sweep {
+    pub fn new() -> Sweep {
+        Sweep {
+            period: 0,
+            negate: false,
+            shift: 0,
+            timer: 0,
+            shadow: 0,
+            enabled: false,
+            negate_used: false,
+        }
+    }
+
+    /// # Returns
+    /// `false` when clearing the negate bit after it was used disables the channel.
+    pub fn write(&mut self, value: u8) -> bool {
+        self.period = (value >> 4) & 0x07;
+        self.negate = value & 0x08 != 0;
+        self.shift = value & 0x07;
+        !self.negate_used || self.negate
+    }
+
+    fn calculate(&mut self) -> u16 {
+        let delta: u16 = self.shadow >> self.shift;
+        if self.negate {
+            self.negate_used = true;
+            self.shadow.wrapping_sub(delta)
+        } else {
+            self.shadow + delta
+        }
+    }
+
+    /// # Returns
+    /// `false` when the overflow check disables the channel right away.
+    pub fn trigger(&mut self, frequency: u16) -> bool {
+        self.shadow = frequency;
+        self.timer = if self.period == 0 { 8 } else { self.period };
+        self.enabled = self.period != 0 || self.shift != 0;
+        self.negate_used = false;
+        self.shift == 0 || self.calculate() <= 2047
+    }
+
+    /// Clocked at 128 Hz by the frame sequencer.
+    pub fn clock(&mut self) -> SweepResult {
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer > 0 {
+            return SweepResult::Unchanged;
+        }
+        self.timer = if self.period == 0 { 8 } else { self.period };
+        if !self.enabled || self.period == 0 {
+            return SweepResult::Unchanged;
+        }
+        let frequency: u16 = self.calculate();
+        if frequency > 2047 {
+            return SweepResult::Overflow;
+        }
+        if self.shift == 0 {
+            return SweepResult::Unchanged;
+        }
+        self.shadow = frequency;
+        // the new value is checked again, without being stored
+        if self.calculate() > 2047 {
+            return SweepResult::Overflow;
+        }
+        SweepResult::Frequency(frequency)
+    }
+}
+
+impl Snapshot for Sweep {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.period);
+        writer.write_bool(self.negate);
+        writer.write_u8(self.shift);
+        writer.write_u8(self.timer);
+        writer.write_u16(self.shadow);
+        writer.write_bool(self.enabled);
+        writer.write_bool(self.negate_used);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.period = reader.read_u8()?;
+        self.negate = reader.read_bool()?;
+        self.shift = reader.read_u8()?;
+        self.timer = reader.read_u8()?;
+        self.shadow = reader.read_u16()?;
+        self.enabled = reader.read_bool()?;
+        self.negate_used = reader.read_bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn raises_the_frequency_every_period() {
+        let mut sweep: Sweep = Sweep::new();
+        // period 1, add, shift 1
+        sweep.write(0x11);
+        assert!(sweep.trigger(0x100));
+        assert!(matches!(sweep.clock(), SweepResult::Frequency(0x180)));
+        assert!(matches!(sweep.clock(), SweepResult::Frequency(0x240)));
+    }
+
+    #[test]
+    fn overflow_disables_the_channel() {
+        let mut sweep: Sweep = Sweep::new();
+        sweep.write(0x11);
+        // the check on trigger already sees 0x600 + 0x300 > 2047
+        assert!(!sweep.trigger(0x600));
+        assert!(sweep.trigger(0x500));
+        // 0x500 + 0x280 fits, but the second calculation on 0x780 doesn't
+        assert!(matches!(sweep.clock(), SweepResult::Overflow));
+    }
+
+    #[test]
+    fn clearing_negate_after_use_disables() {
+        let mut sweep: Sweep = Sweep::new();
+        sweep.write(0x19);
+        sweep.trigger(0x400);
+        assert!(matches!(sweep.clock(), SweepResult::Frequency(0x200)));
+        assert!(!sweep.write(0x11));
+        // a new trigger forgets the negate calculation
+        sweep.trigger(0x400);
+        assert!(sweep.write(0x11));
+    }
+}
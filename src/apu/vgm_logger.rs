@@ -0,0 +1,166 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+
+use crate::apu::apu::APU_CLOCK;
+
+const HEADER_SIZE: u32 = 0x100;
+// VGM 1.61 is the first version with the Game Boy DMG chip
+const VERSION: u32 = 0x0000_0161;
+// every timestamp in a VGM file is a sample count at this rate
+const VGM_RATE: u64 = 44_100;
+
+const CMD_GB_WRITE: u8 = 0xB3;
+const CMD_WAIT: u8 = 0x61;
+const CMD_WAIT_NTSC: u8 = 0x62;
+const CMD_WAIT_PAL: u8 = 0x63;
+const CMD_WAIT_SHORT: u8 = 0x70;
+const CMD_END: u8 = 0x66;
+
+/// Logs sound register writes (0xFF10–0xFF3F) to a VGM file, so the music can be played
+/// back by VGM players and trackers.
+///
+/// Time between writes is converted from APU cycles to 44.1 kHz wait commands. The header
+/// is completed by [`VgmLogger::finish`].
+pub struct VgmLogger {
+    file: BufWriter<File>,
+    // data bytes written after the header
+    data_size: u32,
+    total_samples: u64,
+    // samples elapsed since the last command
+    pending_samples: u64,
+    // sub-sample remainder, in APU cycles times the VGM rate
+    remainder: u64,
+    // first error hit while writing, reported by `finish`
+    error: Option<io::Error>,
+}
+
+impl VgmLogger {
+    pub fn create(path: &str) -> io::Result<VgmLogger> {
+        let mut logger: VgmLogger = VgmLogger {
+            file: BufWriter::new(File::create(path)?),
+            data_size: 0,
+            total_samples: 0,
+            pending_samples: 0,
+            remainder: 0,
+            error: None,
+        };
+        logger.write_header()?;
+        Ok(logger)
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        let mut header: [u8; HEADER_SIZE as usize] = [0; HEADER_SIZE as usize];
+        let mut put = |offset: usize, value: u32| header[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+        // the data size includes the end command
+        put(0x04, HEADER_SIZE + self.data_size + 1 - 0x04); // EOF offset
+        put(0x08, VERSION);
+        put(0x18, self.total_samples as u32);
+        put(0x34, HEADER_SIZE - 0x34); // VGM data offset
+        put(0x80, APU_CLOCK as u32); // Game Boy DMG clock
+        header[0..4].copy_from_slice(b"Vgm ");
+        self.file.write_all(&header)
+    }
+
+    /// Moves the log time forward by `cycles` APU cycles.
+    pub fn advance(&mut self, cycles: u64) {
+        self.remainder += cycles * VGM_RATE;
+        let samples: u64 = self.remainder / APU_CLOCK;
+        self.remainder %= APU_CLOCK;
+        self.pending_samples += samples;
+        self.total_samples += samples;
+    }
+
+    /// Records a write to the sound register at `addr` at the current time.
+    pub fn log_write(&mut self, addr: u16, data: u8) {
+        self.flush_wait();
+        // registers are numbered from NR10
+        self.write_bytes(&[CMD_GB_WRITE, (addr - 0xFF10) as u8, data]);
+    }
+
+    fn flush_wait(&mut self) {
+        while self.pending_samples > 0 {
+            let (command, samples): (Vec<u8>, u64) = match self.pending_samples {
+                735 => (vec![CMD_WAIT_NTSC], 735),
+                882 => (vec![CMD_WAIT_PAL], 882),
+                n @ 1..=16 => (vec![CMD_WAIT_SHORT + (n - 1) as u8], n),
+                n => {
+                    let samples: u16 = n.min(u16::MAX as u64) as u16;
+                    let [low, high] = samples.to_le_bytes();
+                    (vec![CMD_WAIT, low, high], samples as u64)
+                }
+            };
+            self.write_bytes(&command);
+            self.pending_samples -= samples;
+        }
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        if self.error.is_some() {
+            return;
+        }
+        match self.file.write_all(bytes) {
+            Ok(_) => self.data_size += bytes.len() as u32,
+            Err(e) => self.error = Some(e),
+        }
+    }
+
+    /// Ends the command stream and completes the header.
+    ///
+    /// # Returns
+    /// The first error hit while logging, if any.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.flush_wait();
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        self.file.write_all(&[CMD_END])?;
+        self.file.seek(SeekFrom::Start(0))?;
+        self.write_header()?;
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// APU cycles covering exactly `samples` VGM samples from a sample boundary.
+    fn cycles_for(samples: u64) -> u64 {
+        (samples * APU_CLOCK).div_ceil(VGM_RATE)
+    }
+
+    #[test]
+    fn writes_and_waits() {
+        let path = std::env::temp_dir().join(format!("rustyboy-vgm-{}.vgm", std::process::id()));
+        let path: &str = path.to_str().unwrap();
+        let mut logger: VgmLogger = VgmLogger::create(path).unwrap();
+        logger.log_write(0xFF26, 0x80);
+        logger.advance(cycles_for(735));
+        logger.log_write(0xFF12, 0xF0);
+        logger.advance(cycles_for(3));
+        logger.log_write(0xFF14, 0x87);
+        logger.advance(cycles_for(70_000));
+        logger.finish().unwrap();
+        let bytes: Vec<u8> = std::fs::read(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        let u32_at = |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        assert_eq!(&bytes[0..4], b"Vgm ");
+        assert_eq!(u32_at(0x04) as usize, bytes.len() - 4);
+        assert_eq!(u32_at(0x08), VERSION);
+        assert_eq!(u32_at(0x18), 735 + 3 + 70_000);
+        assert_eq!(u32_at(0x34) as usize + 0x34, HEADER_SIZE as usize);
+        assert_eq!(u32_at(0x80), APU_CLOCK as u32);
+        assert_eq!(&bytes[HEADER_SIZE as usize..], &[
+            CMD_GB_WRITE, 0x16, 0x80,
+            CMD_WAIT_NTSC,
+            CMD_GB_WRITE, 0x02, 0xF0,
+            CMD_WAIT_SHORT + 2,
+            CMD_GB_WRITE, 0x04, 0x87,
+            // 70000 samples don't fit in one wait
+            CMD_WAIT, 0xFF, 0xFF,
+            CMD_WAIT, 0x71, 0x11,
+            CMD_END,
+        ]);
+    }
+}
@@ -0,0 +1,92 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+
+const HEADER_SIZE: u32 = 44;
+
+/// Writes 16-bit PCM WAV files.
+///
+/// The header is written with empty sizes when the file is created and completed by
+/// [`WavWriter::finish`], so samples can be streamed without knowing the length upfront.
+pub struct WavWriter {
+    file: BufWriter<File>,
+    channels: u16,
+    sample_rate: u32,
+    data_size: u32,
+}
+
+impl WavWriter {
+    /// Creates (or truncates) the file at `path` and writes a placeholder header.
+    pub fn create(path: &str, channels: u16, sample_rate: u32) -> io::Result<WavWriter> {
+        let mut writer: WavWriter = WavWriter {
+            file: BufWriter::new(File::create(path)?),
+            channels,
+            sample_rate,
+            data_size: 0,
+        };
+        writer.write_header()?;
+        Ok(writer)
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        let block_align: u16 = self.channels * 2;
+        let byte_rate: u32 = self.sample_rate * block_align as u32;
+        self.file.write_all(b"RIFF")?;
+        self.file.write_all(&(HEADER_SIZE - 8 + self.data_size).to_le_bytes())?;
+        self.file.write_all(b"WAVE")?;
+        self.file.write_all(b"fmt ")?;
+        self.file.write_all(&16u32.to_le_bytes())?;
+        self.file.write_all(&1u16.to_le_bytes())?; // PCM
+        self.file.write_all(&self.channels.to_le_bytes())?;
+        self.file.write_all(&self.sample_rate.to_le_bytes())?;
+        self.file.write_all(&byte_rate.to_le_bytes())?;
+        self.file.write_all(&block_align.to_le_bytes())?;
+        self.file.write_all(&16u16.to_le_bytes())?; // bits per sample
+        self.file.write_all(b"data")?;
+        self.file.write_all(&self.data_size.to_le_bytes())?;
+        Ok(())
+    }
+
+    /// Appends interleaved samples in the -1.0..=1.0 range (clipped outside of it).
+    pub fn write_samples(&mut self, samples: &[f32]) -> io::Result<()> {
+        for sample in samples {
+            let value: i16 = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            self.file.write_all(&value.to_le_bytes())?;
+        }
+        self.data_size += samples.len() as u32 * 2;
+        Ok(())
+    }
+
+    /// Completes the header with the final sizes and flushes the file.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(0))?;
+        self.write_header()?;
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_is_completed_on_finish() {
+        let path = std::env::temp_dir().join(format!("rustyboy-wav-{}.wav", std::process::id()));
+        let path: &str = path.to_str().unwrap();
+        let mut writer: WavWriter = WavWriter::create(path, 2, 48_000).unwrap();
+        writer.write_samples(&[0.0, 1.0, -2.0, 0.5]).unwrap();
+        writer.finish().unwrap();
+        let bytes: Vec<u8> = std::fs::read(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        let u32_at = |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        let i16_at = |offset: usize| i16::from_le_bytes([bytes[offset], bytes[offset + 1]]);
+        assert_eq!(bytes.len(), HEADER_SIZE as usize + 8);
+        assert_eq!(&bytes[0..4], b"RIFF");
+        assert_eq!(u32_at(4), HEADER_SIZE - 8 + 8);
+        assert_eq!(u32_at(24), 48_000);
+        assert_eq!(u32_at(28), 48_000 * 4);
+        assert_eq!(u32_at(40), 8);
+        // out of range samples are clipped
+        assert_eq!([i16_at(44), i16_at(46), i16_at(48)], [0, i16::MAX, -i16::MAX]);
+    }
+}
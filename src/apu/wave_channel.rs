@@ -0,0 +1,180 @@
+use crate::apu::length_counter::LengthCounter;
+use crate::error::state_error::StateError;
+use crate::state::snapshot::Snapshot;
+use crate::state::state_reader::StateReader;
+use crate::state::state_writer::StateWriter;
+
+/// Wave channel: plays the 32 4-bit samples stored in wave RAM (0xFF30–0xFF3F).
+pub struct WaveChannel {
+    enabled: bool,
+    dac_enabled: bool,
+    volume_code: u8,
+    frequency: u16,
+    timer: u32,
+    position: u8,
+    wave_ram: [u8; 16],
+    length: LengthCounter,
+}
+
+impl WaveChannel {
+    pub fn new() -> WaveChannel {
+        WaveChannel {
+            enabled: false,
+            dac_enabled: false,
+            volume_code: 0,
+            frequency: 0,
+            timer: 0,
+            position: 0,
+            wave_ram: [0; 16],
+            length: LengthCounter::new(256),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn is_dac_enabled(&self) -> bool {
+        self.dac_enabled
+    }
+
+    /// NR30: DAC power.
+    pub fn write_dac(&mut self, value: u8) {
+        self.dac_enabled = value & 0x80 != 0;
+        if !self.dac_enabled {
+            self.enabled = false;
+        }
+    }
+
+    /// NR31: length.
+    pub fn write_length(&mut self, value: u8) {
+        self.length.load(value);
+    }
+
+    /// NR32: output level.
+    pub fn write_volume(&mut self, value: u8) {
+        self.volume_code = (value >> 5) & 0x03;
+    }
+
+    pub fn write_frequency_low(&mut self, value: u8) {
+        self.frequency = (self.frequency & 0x0700) | value as u16;
+    }
+
+    pub fn write_control(&mut self, value: u8) {
+        self.frequency = (self.frequency & 0x00FF) | ((value & 0x07) as u16) << 8;
+        self.length.set_enabled(value & 0x40 != 0);
+        if value & 0x80 != 0 {
+            self.enabled = self.dac_enabled;
+            self.length.trigger();
+            self.timer = (2048 - self.frequency as u32) * 2;
+            self.position = 0;
+        }
+    }
+
+    pub fn read_wave_ram(&self, index: usize) -> u8 {
+        self.wave_ram[index]
+    }
+
+    pub fn write_wave_ram(&mut self, index: usize, value: u8) {
+        self.wave_ram[index] = value;
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        let mut cycles: u32 = cycles;
+        while cycles > 0 {
+            if self.timer == 0 {
+                self.timer = (2048 - self.frequency as u32) * 2;
+            }
+            let step: u32 = cycles.min(self.timer);
+            self.timer -= step;
+            cycles -= step;
+            if self.timer == 0 {
+                self.position = (self.position + 1) & 0x1F;
+            }
+        }
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    /// Current digital output (0–15).
+    pub fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+        let byte: u8 = self.wave_ram[(self.position / 2) as usize];
+        let sample: u8 = if self.position & 0x01 == 0 { byte >> 4 } else { byte & 0x0F };
+        match self.volume_code {
+            0 => 0,
+            1 => sample,
+            2 => sample >> 1,
+            _ => sample >> 2,
+        }
+    }
+}
+
+impl Snapshot for WaveChannel {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.enabled);
+        writer.write_bool(self.dac_enabled);
+        writer.write_u8(self.volume_code);
+        writer.write_u16(self.frequency);
+        writer.write_u32(self.timer);
+        writer.write_u8(self.position);
+        writer.write_bytes(&self.wave_ram);
+        self.length.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.enabled = reader.read_bool()?;
+        self.dac_enabled = reader.read_bool()?;
+        self.volume_code = reader.read_u8()? & 0x03;
+        self.frequency = reader.read_u16()? & 0x07FF;
+        self.timer = reader.read_u32()?;
+        self.position = reader.read_u8()? & 0x1F;
+        reader.read_bytes(&mut self.wave_ram)?;
+        self.length.load_state(reader)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plays_nibbles_high_first_with_volume_shift() {
+        let mut channel: WaveChannel = WaveChannel::new();
+        channel.write_wave_ram(0, 0x8F);
+        channel.write_dac(0x80);
+        channel.write_volume(0x20);
+        channel.write_frequency_low(0xFF);
+        channel.write_control(0x87);
+        // position 0 is only played after the first step
+        channel.tick(2);
+        assert_eq!(channel.output(), 0x0F);
+        channel.write_volume(0x40);
+        assert_eq!(channel.output(), 0x07);
+        channel.write_volume(0x60);
+        assert_eq!(channel.output(), 0x03);
+        channel.write_volume(0x00);
+        assert_eq!(channel.output(), 0);
+        channel.write_volume(0x20);
+        channel.tick(62);
+        assert_eq!(channel.output(), 0x08);
+    }
+
+    #[test]
+    fn needs_the_dac() {
+        let mut channel: WaveChannel = WaveChannel::new();
+        channel.write_control(0x80);
+        assert!(!channel.is_enabled());
+        channel.write_dac(0x80);
+        channel.write_control(0x80);
+        assert!(channel.is_enabled());
+        channel.write_dac(0x00);
+        assert!(!channel.is_enabled());
+    }
+}
@@ -0,0 +1,428 @@
+use crate::memory_bus::joypad::{Button, Player};
+use crate::model::Model;
+use crate::state::save_state::SaveState;
+
+/// A button held down during part of the run, see `--press`.
+pub struct Press {
+    pub player: Player,
+    pub button: Button,
+    // seconds from power on
+    pub from: f64,
+    // held until the end of the run when missing
+    pub until: Option<f64>,
+}
+
+/// Options given on the command line.
+pub struct Options {
+    pub rom: String,
+    // WAV file receiving the audio output
+    pub wav: Option<String>,
+    pub stems: bool,
+    // VGM file receiving the sound register writes
+    pub vgm: Option<String>,
+    // audio output rate, the APU default when missing
+    pub sample_rate: Option<u32>,
+    pub seconds: f64,
+    // 1-based song of a GBS file, the header default when missing
+    pub track: Option<u8>,
+    // address to wait on, or to connect to, for a link cable over TCP
+    pub link_listen: Option<String>,
+    pub link_connect: Option<String>,
+    pub link_tolerance: Option<u64>,
+    pub link_timeout: Option<f64>,
+    // prefix of the image files of a Game Boy Printer plugged into the link port
+    pub printer: Option<String>,
+    // second cartridge run in the same process, linked to the first
+    pub link_local: Option<String>,
+    // cartridges of players 2 to 4 plugged with the first into a DMG-07, empty without it
+    pub four_player: Vec<String>,
+    // link port output printed after the run, as test ROMs report their results there
+    pub serial_capture: bool,
+    // link port output wired back to its input
+    pub loopback: bool,
+    // file receiving the pulses of the infrared LED, or played back into the photodiode
+    pub ir_record: Option<String>,
+    pub ir_play: Option<String>,
+    // save state restored before running, and written after running
+    pub load_state: Option<String>,
+    pub save_state: Option<String>,
+    // boot ROM dump run before the cartridge, which otherwise starts at 0x0100
+    pub boot_rom: Option<String>,
+    // mode 3 emulated dot by dot instead of one line at a time
+    pub pixel_fifo: bool,
+    pub model: Model,
+    // BMP file receiving the frame completed after --seconds
+    pub frame: Option<String>,
+    // BMP file receiving the last Super Game Boy picture, border included
+    pub sgb_frame: Option<String>,
+    // colorization of DMG cartridges on a CGB, picked by the boot ROM when missing
+    pub palette: Option<u8>,
+    pub presses: Vec<Press>,
+}
+
+pub const USAGE: &str = "usage: rustyboy [ROM] [--model M [--palette N] [--sgb-frame FILE]] [--boot FILE] [--pixel-fifo] [--frame FILE] [--press SPEC]... [--wav FILE [--stems]] [--vgm FILE] [--sample-rate HZ] [--seconds N] [LINK] [STATES]
+       rustyboy FILE.gbs [--wav FILE [--stems]] [--vgm FILE] [--sample-rate HZ] [--track N] [--seconds N]
+
+  --wav FILE     record the audio output to FILE
+  --stems        also write one WAV per channel (FILE.square1.wav...)
+  --vgm FILE     log the sound register writes to FILE
+  --sample-rate HZ        rate of the recorded audio (default 48000)
+  --seconds N    emulated time to run when recording, linked or saving a state (default 10)
+  --model M      hardware to emulate: dmg (default), cgb or sgb
+  --palette N    colorization (0-50) of a DMG cartridge on cgb, as the boot ROM combinations
+  --frame FILE   write the next complete frame after --seconds, in color, to FILE
+  --sgb-frame FILE        on sgb, write the last picture with its border and colors to FILE
+  --boot FILE    run the boot ROM in FILE before the cartridge
+  --pixel-fifo   emulate the PPU pixel FIFO dot by dot (slower, exact mid-line effects)
+  --track N      song of a GBS file to render, starting at 1
+  --press [P:]BUTTON@FROM[-UNTIL]
+                 hold BUTTON (right, left, up, down, a, b, select, start) of joypad P
+                 (1-4, default 1) from FROM to UNTIL seconds, or until the end
+  --printer PREFIX        plug a Game Boy Printer writing PREFIX_001.bmp...
+  --serial-capture        print what the cartridge sends through the link port
+  --loopback              wire the link port output back to its input
+
+save states (FILE, or a slot number 0-9 for ROM.ss0...ROM.ss9), which carry BESS
+blocks so SameBoy and other emulators can load them, and theirs load here:
+  --load-state FILE|SLOT  restore the machine before running
+  --save-state FILE|SLOT  save the machine after running for --seconds
+
+link cable over TCP:
+  --link-listen ADDR      wait for the other emulator on ADDR (e.g. 0.0.0.0:5000)
+  --link-connect ADDR     connect to the other emulator at ADDR
+  --link-tolerance N      cycles a side may run ahead of the other (default 70224)
+  --link-timeout N        seconds to wait for the other emulator before hanging up (default 10)
+  --link-local ROM        run ROM on a second Game Boy linked in the same process, their
+                          infrared ports facing each other on cgb
+                          (buttons and recordings are those of the first)
+  --four-player ROM[,ROM[,ROM]]
+                          plug the cartridges of players 2 to 4 with the first into a
+                          DMG-07 four-player adapter, in the same process
+
+infrared port (cgb):
+  --ir-record FILE        write the pulses of the LED to FILE
+  --ir-play FILE          shine the pulses of FILE, written by --ir-record, on the port";
+
+impl Options {
+    /// Parses the arguments that follow the program name.
+    ///
+    /// # Returns
+    /// The options, or a message describing the first invalid argument.
+    pub fn parse<I: Iterator<Item = String>>(args: I) -> Result<Options, String> {
+        let mut options: Options = Options {
+            rom: String::from("cpu_instrs/cpu_instrs.gb"),
+            wav: None,
+            stems: false,
+            vgm: None,
+            sample_rate: None,
+            seconds: 10.0,
+            track: None,
+            link_listen: None,
+            link_connect: None,
+            link_tolerance: None,
+            link_timeout: None,
+            printer: None,
+            link_local: None,
+            four_player: Vec::new(),
+            serial_capture: false,
+            loopback: false,
+            ir_record: None,
+            ir_play: None,
+            load_state: None,
+            save_state: None,
+            boot_rom: None,
+            pixel_fifo: false,
+            model: Model::DMG,
+            frame: None,
+            sgb_frame: None,
+            palette: None,
+            presses: Vec::new(),
+        };
+        let mut args = args;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--wav" => options.wav = Some(Options::value(&mut args, &arg)?),
+                "--stems" => options.stems = true,
+                "--vgm" => options.vgm = Some(Options::value(&mut args, &arg)?),
+                "--sample-rate" => {
+                    let value: String = Options::value(&mut args, &arg)?;
+                    match value.parse::<u32>() {
+                        Ok(rate) if rate > 0 => options.sample_rate = Some(rate),
+                        _ => return Err(format!("invalid sample rate: {}", value)),
+                    }
+                }
+                "--seconds" => {
+                    let value: String = Options::value(&mut args, &arg)?;
+                    options.seconds = value.parse().map_err(|_| format!("invalid number of seconds: {}", value))?;
+                }
+                "--track" => {
+                    let value: String = Options::value(&mut args, &arg)?;
+                    let track: u8 = value.parse().map_err(|_| format!("invalid track: {}", value))?;
+                    if track == 0 {
+                        return Err(String::from("tracks start at 1"));
+                    }
+                    options.track = Some(track);
+                }
+                "--pixel-fifo" => options.pixel_fifo = true,
+                "--press" => {
+                    let value: String = Options::value(&mut args, &arg)?;
+                    let press: Press = Options::press(&value).ok_or_else(|| format!("invalid button press: {}", value))?;
+                    options.presses.push(press);
+                }
+                "--model" => {
+                    let value: String = Options::value(&mut args, &arg)?;
+                    options.model = match value.to_lowercase().as_str() {
+                        "dmg" => Model::DMG,
+                        "cgb" => Model::CGB,
+                        "sgb" => Model::SGB,
+                        _ => return Err(format!("invalid model: {}", value)),
+                    };
+                }
+                "--palette" => {
+                    let value: String = Options::value(&mut args, &arg)?;
+                    match value.parse::<u8>() {
+                        Ok(palette) if palette <= 50 => options.palette = Some(palette),
+                        _ => return Err(format!("invalid palette: {}", value)),
+                    }
+                }
+                "--frame" => options.frame = Some(Options::value(&mut args, &arg)?),
+                "--sgb-frame" => options.sgb_frame = Some(Options::value(&mut args, &arg)?),
+                "--boot" => options.boot_rom = Some(Options::value(&mut args, &arg)?),
+                "--printer" => options.printer = Some(Options::value(&mut args, &arg)?),
+                "--link-local" => options.link_local = Some(Options::value(&mut args, &arg)?),
+                "--four-player" => {
+                    let value: String = Options::value(&mut args, &arg)?;
+                    let roms: Vec<String> = value.split(',').map(String::from).collect();
+                    if roms.len() > 3 || roms.iter().any(|rom| rom.is_empty()) {
+                        return Err(format!("invalid cartridges for players 2 to 4: {}", value));
+                    }
+                    options.four_player = roms;
+                }
+                "--serial-capture" => options.serial_capture = true,
+                "--loopback" => options.loopback = true,
+                "--link-listen" => options.link_listen = Some(Options::value(&mut args, &arg)?),
+                "--link-connect" => options.link_connect = Some(Options::value(&mut args, &arg)?),
+                "--link-tolerance" => {
+                    let value: String = Options::value(&mut args, &arg)?;
+                    let tolerance: u64 = value.parse().map_err(|_| format!("invalid tolerance: {}", value))?;
+                    options.link_tolerance = Some(tolerance);
+                }
+                "--link-timeout" => {
+                    let value: String = Options::value(&mut args, &arg)?;
+                    match value.parse::<f64>() {
+                        Ok(timeout) if timeout > 0.0 => options.link_timeout = Some(timeout),
+                        _ => return Err(format!("invalid timeout: {}", value)),
+                    }
+                }
+                "--ir-record" => options.ir_record = Some(Options::value(&mut args, &arg)?),
+                "--ir-play" => options.ir_play = Some(Options::value(&mut args, &arg)?),
+                "--load-state" => options.load_state = Some(Options::value(&mut args, &arg)?),
+                "--save-state" => options.save_state = Some(Options::value(&mut args, &arg)?),
+                _ if arg.starts_with("--") => return Err(format!("unknown option: {}", arg)),
+                _ => options.rom = arg,
+            }
+        }
+        if options.stems && options.wav.is_none() {
+            return Err(String::from("--stems requires --wav"));
+        }
+        let peers: Vec<&str> = options.get_peers();
+        if peers.len() > 1 {
+            return Err(format!("only one link port peer can be used: {}", peers.join(", ")));
+        }
+        if options.palette.is_some() && options.model != Model::CGB {
+            return Err(String::from("--palette requires --model cgb"));
+        }
+        if options.sgb_frame.is_some() && options.model != Model::SGB {
+            return Err(String::from("--sgb-frame requires --model sgb"));
+        }
+        if options.ir_record.is_some() && options.ir_play.is_some() {
+            return Err(String::from("only one infrared peer can be used: --ir-record, --ir-play"));
+        }
+        if options.is_infrared() && options.model != Model::CGB {
+            return Err(String::from("--ir-record and --ir-play require --model cgb"));
+        }
+        if options.is_gbs() && !options.is_recording() {
+            return Err(String::from("GBS files are rendered headless and require --wav or --vgm"));
+        }
+        if options.is_gbs() && (options.load_state.is_some() || options.save_state.is_some()) {
+            return Err(String::from("save states are not available for GBS files"));
+        }
+        Ok(options)
+    }
+
+    /// Returns `true` when the file to run is a GBS rip instead of a cartridge.
+    pub fn is_gbs(&self) -> bool {
+        self.rom.to_lowercase().ends_with(".gbs")
+    }
+
+    /// Returns `true` when the audio output or the sound register writes are captured.
+    pub fn is_recording(&self) -> bool {
+        self.wav.is_some() || self.vgm.is_some()
+    }
+
+    /// Returns `true` when something is plugged into the link port.
+    pub fn is_linked(&self) -> bool {
+        !self.get_peers().is_empty()
+    }
+
+    /// Returns `true` when the cartridge runs for `--seconds`, instead of having its ROM
+    /// dumped.
+    pub fn is_running(&self) -> bool {
+        self.is_recording() || self.is_linked() || self.is_infrared() || self.frame.is_some()
+            || self.sgb_frame.is_some() || self.save_state.is_some()
+    }
+
+    /// Returns `true` when something faces the infrared port, besides a `--link-local` CGB.
+    pub fn is_infrared(&self) -> bool {
+        self.ir_record.is_some() || self.ir_play.is_some()
+    }
+
+    /// Returns the options plugging something into the link port.
+    fn get_peers(&self) -> Vec<&'static str> {
+        [
+            ("--link-listen", self.link_listen.is_some()),
+            ("--link-connect", self.link_connect.is_some()),
+            ("--printer", self.printer.is_some()),
+            ("--link-local", self.link_local.is_some()),
+            ("--four-player", !self.four_player.is_empty()),
+            ("--serial-capture", self.serial_capture),
+            ("--loopback", self.loopback),
+        ].iter()
+            .filter(|(_, plugged)| *plugged)
+            .map(|(option, _)| *option)
+            .collect()
+    }
+
+    /// Returns the file of the state to restore, if any.
+    pub fn get_load_state(&self) -> Option<String> {
+        self.load_state.as_deref().map(|state| self.state_path(state))
+    }
+
+    /// Returns the file of the state to write, if any.
+    pub fn get_save_state(&self) -> Option<String> {
+        self.save_state.as_deref().map(|state| self.state_path(state))
+    }
+
+    /// A single digit is a slot of the ROM, anything else a file name.
+    fn state_path(&self, state: &str) -> String {
+        match state.parse::<u8>() {
+            Ok(slot) if state.len() == 1 => SaveState::slot_path(&self.rom, slot),
+            _ => state.to_string(),
+        }
+    }
+
+    /// Parses `[P:]BUTTON@FROM[-UNTIL]`.
+    fn press(spec: &str) -> Option<Press> {
+        let (player, spec) = match spec.split_once(':') {
+            Some((player, spec)) => (player, spec),
+            None => ("1", spec),
+        };
+        let player: Player = match player {
+            "1" => Player::One,
+            "2" => Player::Two,
+            "3" => Player::Three,
+            "4" => Player::Four,
+            _ => return None,
+        };
+        let (button, time) = spec.split_once('@')?;
+        let button: Button = match button.to_lowercase().as_str() {
+            "right" => Button::Right,
+            "left" => Button::Left,
+            "up" => Button::Up,
+            "down" => Button::Down,
+            "a" => Button::A,
+            "b" => Button::B,
+            "select" => Button::Select,
+            "start" => Button::Start,
+            _ => return None,
+        };
+        let (from, until): (f64, Option<f64>) = match time.split_once('-') {
+            Some((from, until)) => (from.parse().ok()?, Some(until.parse().ok()?)),
+            None => (time.parse().ok()?, None),
+        };
+        if from < 0.0 || until.is_some_and(|until| until < from) {
+            return None;
+        }
+        Some(Press { player, button, from, until })
+    }
+
+    fn value<I: Iterator<Item = String>>(args: &mut I, option: &str) -> Result<String, String> {
+        args.next().ok_or_else(|| format!("missing value for {}", option))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        Options::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn model_defaults_to_dmg() {
+        assert_eq!(parse(&["game.gb"]).unwrap().model, Model::DMG);
+        assert_eq!(parse(&["game.gb", "--model", "CGB"]).unwrap().model, Model::CGB);
+        assert_eq!(parse(&["--model", "sgb", "game.gb"]).unwrap().model, Model::SGB);
+        assert!(parse(&["game.gb", "--model", "gba"]).is_err());
+        assert!(parse(&["game.gb", "--model"]).is_err());
+    }
+
+    #[test]
+    fn presses_name_the_player_and_the_time() {
+        let options: Options = parse(&["game.gb", "--press", "start@1.5-2", "--press", "4:A@0"]).unwrap();
+        let start: &Press = &options.presses[0];
+        assert_eq!((start.player, start.button, start.from, start.until), (Player::One, Button::Start, 1.5, Some(2.0)));
+        let a: &Press = &options.presses[1];
+        assert_eq!((a.player, a.button, a.from, a.until), (Player::Four, Button::A, 0.0, None));
+        for spec in ["5:a@0", "0:a@0", "turbo@1", "a", "a@2-1", "a@-1"] {
+            assert!(parse(&["game.gb", "--press", spec]).is_err(), "{}", spec);
+        }
+    }
+
+    #[test]
+    fn one_peer_at_a_time() {
+        assert!(parse(&["game.gb", "--serial-capture"]).unwrap().is_linked());
+        assert_eq!(parse(&["game.gb", "--link-local", "other.gb"]).unwrap().link_local.as_deref(), Some("other.gb"));
+        assert!(!parse(&["game.gb"]).unwrap().is_linked());
+        assert_eq!(parse(&["game.gb", "--four-player", "2.gb,3.gb"]).unwrap().four_player, ["2.gb", "3.gb"]);
+        assert!(parse(&["game.gb", "--four-player", "2.gb,3.gb,4.gb,5.gb"]).is_err());
+        assert!(parse(&["game.gb", "--four-player", "2.gb,"]).is_err());
+        let e: String = parse(&["game.gb", "--loopback", "--printer", "out"]).err().unwrap();
+        assert_eq!(e, "only one link port peer can be used: --printer, --loopback");
+        // the TCP link would wait for the other emulator before the clash comes out
+        let e: String = parse(&["game.gb", "--link-listen", "0.0.0.0:5000", "--serial-capture"]).err().unwrap();
+        assert_eq!(e, "only one link port peer can be used: --link-listen, --serial-capture");
+        assert!(parse(&["game.gb", "--link-connect", "host:5000", "--printer", "out"]).is_err());
+    }
+
+    #[test]
+    fn sample_rate_is_positive() {
+        assert_eq!(parse(&["game.gb", "--sample-rate", "44100"]).unwrap().sample_rate, Some(44100));
+        assert_eq!(parse(&["game.gb"]).unwrap().sample_rate, None);
+        assert!(parse(&["game.gb", "--sample-rate", "0"]).is_err());
+        assert!(parse(&["game.gb", "--sample-rate", "fast"]).is_err());
+    }
+
+    #[test]
+    fn infrared_needs_the_cgb() {
+        assert!(parse(&["game.gb", "--model", "cgb", "--ir-record", "gift.ir"]).unwrap().is_infrared());
+        assert!(parse(&["game.gb", "--ir-play", "gift.ir"]).is_err());
+        assert!(parse(&["game.gb", "--model", "cgb", "--ir-record", "a.ir", "--ir-play", "b.ir"]).is_err());
+    }
+
+    #[test]
+    fn sgb_frame_needs_the_sgb() {
+        assert!(parse(&["game.gb", "--model", "sgb", "--sgb-frame", "tv.bmp"]).unwrap().is_running());
+        assert!(parse(&["game.gb", "--sgb-frame", "tv.bmp"]).is_err());
+        assert!(!parse(&["game.gb"]).unwrap().is_running());
+        assert!(parse(&["game.gb", "--frame", "screen.bmp"]).unwrap().is_running());
+    }
+
+    #[test]
+    fn palette_needs_the_cgb() {
+        assert_eq!(parse(&["game.gb", "--model", "cgb", "--palette", "50"]).unwrap().palette, Some(50));
+        assert!(parse(&["game.gb", "--model", "cgb", "--palette", "51"]).is_err());
+        assert!(parse(&["game.gb", "--palette", "3"]).is_err());
+    }
+}
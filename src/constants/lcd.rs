@@ -0,0 +1,45 @@
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
+
+pub const DOTS_PER_LINE: u32 = 456;
+pub const LINES_PER_FRAME: u8 = 154;
+pub const OAM_SCAN_DOTS: u32 = 80;
+pub const DRAWING_DOTS: u32 = 172;
+
+// LCDC bits
+pub const LCDC_ENABLE: u8 = 0b1000_0000;
+pub const LCDC_WINDOW_MAP: u8 = 0b0100_0000;
+pub const LCDC_WINDOW_ENABLE: u8 = 0b0010_0000;
+pub const LCDC_TILE_DATA: u8 = 0b0001_0000;
+pub const LCDC_BG_MAP: u8 = 0b0000_1000;
+pub const LCDC_OBJ_SIZE: u8 = 0b0000_0100;
+pub const LCDC_OBJ_ENABLE: u8 = 0b0000_0010;
+pub const LCDC_BG_ENABLE: u8 = 0b0000_0001;
+
+// STAT bits
+pub const STAT_LYC_INT: u8 = 0b0100_0000;
+pub const STAT_MODE2_INT: u8 = 0b0010_0000;
+pub const STAT_MODE1_INT: u8 = 0b0001_0000;
+pub const STAT_MODE0_INT: u8 = 0b0000_1000;
+pub const STAT_LYC_EQUAL: u8 = 0b0000_0100;
+pub const STAT_MODE: u8 = 0b0000_0011;
+
+// OAM attribute bits
+pub const OBJ_PRIORITY: u8 = 0b1000_0000;
+pub const OBJ_Y_FLIP: u8 = 0b0100_0000;
+pub const OBJ_X_FLIP: u8 = 0b0010_0000;
+pub const OBJ_PALETTE: u8 = 0b0001_0000;
+
+pub const MAX_SPRITES_PER_LINE: usize = 10;
+pub const OBJ_BANK: u8 = 0b0000_1000;
+pub const OBJ_CGB_PALETTE: u8 = 0b0000_0111;
+
+// CGB BG map attribute bits (VRAM bank 1)
+pub const BG_ATTR_PRIORITY: u8 = 0b1000_0000;
+pub const BG_ATTR_Y_FLIP: u8 = 0b0100_0000;
+pub const BG_ATTR_X_FLIP: u8 = 0b0010_0000;
+pub const BG_ATTR_BANK: u8 = 0b0000_1000;
+pub const BG_ATTR_PALETTE: u8 = 0b0000_0111;
+
+// RGB555 shades used to show DMG output on a color framebuffer
+pub const DMG_GREYS: [u16; 4] = [0x7FFF, 0x56B5, 0x294A, 0x0000];
@@ -1 +1,3 @@
-pub mod flags;
\ No newline at end of file
+pub mod flags;
+pub mod io_registers;
+pub mod lcd;
\ No newline at end of file
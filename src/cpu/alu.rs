@@ -0,0 +1,141 @@
+use crate::constants::flags::C_FLAG;
+use crate::cpu::cpu::CPU;
+use crate::cpu::ld::GETTERS;
+use crate::memory_bus::memory_bus::MemoryBus;
+
+pub const ADD: u8 = 0;
+pub const ADC: u8 = 1;
+pub const SUB: u8 = 2;
+pub const SBC: u8 = 3;
+pub const AND: u8 = 4;
+pub const XOR: u8 = 5;
+pub const OR: u8 = 6;
+pub const CP: u8 = 7;
+
+/// 8-bit arithmetic and logic on the accumulator (opcodes 0x80 to 0xBF and the
+/// immediate forms 0xC6 to 0xFE), plus the signed SP offsets.
+pub struct ALU;
+
+impl ALU {
+
+    /// Applies one of the eight accumulator operations to `A` and `value`.
+    ///
+    /// # Parameters
+    /// - `op`: The operation, the `y` field of the opcode (`ADD` to `CP`).
+    /// - `value`: The second operand.
+    pub fn apply(cpu: &mut CPU, op: u8, value: u8) {
+        let a: u8 = cpu.get_registers().get_a();
+        let carry: u8 = if cpu.get_registers().get_f().get_flag(C_FLAG) { 1 } else { 0 };
+        let (r, c, n, h): (u8, bool, bool, bool) = match op {
+            ADD => (
+                a.wrapping_add(value),
+                a as u16 + value as u16 > 0xFF,
+                false,
+                (a & 0x0F) + (value & 0x0F) > 0x0F,
+            ),
+            ADC => (
+                a.wrapping_add(value).wrapping_add(carry),
+                a as u16 + value as u16 + carry as u16 > 0xFF,
+                false,
+                (a & 0x0F) + (value & 0x0F) + carry > 0x0F,
+            ),
+            SUB | CP => (
+                a.wrapping_sub(value),
+                a < value,
+                true,
+                (a & 0x0F) < (value & 0x0F),
+            ),
+            SBC => (
+                a.wrapping_sub(value).wrapping_sub(carry),
+                (a as u16) < value as u16 + carry as u16,
+                true,
+                (a & 0x0F) < (value & 0x0F) + carry,
+            ),
+            AND => (a & value, false, false, true),
+            XOR => (a ^ value, false, false, false),
+            OR => (a | value, false, false, false),
+            _ => panic!("Invalid ALU operation {}", op),
+        };
+        cpu.get_registers().get_f_mut().set_flags(c, n, h, r == 0);
+        //CP only compares, A is left untouched
+        if op != CP {
+            cpu.get_registers().set_a(r);
+        }
+    }
+
+    pub fn alu_a_r8(cpu: &mut CPU, op: u8, src: usize) {
+        let value: u8 = GETTERS[src](cpu.get_registers());
+        Self::apply(cpu, op, value);
+        cpu.update_pc_and_cycles(cpu.get_pc().wrapping_add(1), 4);
+    }
+
+    pub fn alu_a_hl(cpu: &mut CPU, memory_bus: &mut MemoryBus, op: u8) {
+        let hl: u16 = cpu.get_registers().get_hl();
+        let value: u8 = memory_bus.cpu_read(hl);
+        Self::apply(cpu, op, value);
+        cpu.update_pc_and_cycles(cpu.get_pc().wrapping_add(1), 8);
+    }
+
+    pub fn alu_a_n8(cpu: &mut CPU, memory_bus: &mut MemoryBus, op: u8) {
+        let value: u8 = memory_bus.cpu_read(cpu.get_pc().wrapping_add(1));
+        Self::apply(cpu, op, value);
+        cpu.update_pc_and_cycles(cpu.get_pc().wrapping_add(2), 8);
+    }
+
+    /// Returns SP plus the signed immediate following the opcode and sets the flags of
+    /// `ADD SP, e8` / `LD HL, SP + e8`: carries come from the low byte, Z and N are cleared.
+    pub fn sp_plus_e8(cpu: &mut CPU, memory_bus: &mut MemoryBus) -> u16 {
+        let sp: u16 = cpu.get_sp();
+        let offset: u8 = memory_bus.cpu_read(cpu.get_pc().wrapping_add(1));
+        let h: bool = (sp & 0x0F) + (offset as u16 & 0x0F) > 0x0F;
+        let c: bool = (sp & 0xFF) + offset as u16 > 0xFF;
+        cpu.get_registers().get_f_mut().set_flags(c, false, h, false);
+        sp.wrapping_add(offset as i8 as u16)
+    }
+
+    pub fn add_sp_e8(cpu: &mut CPU, memory_bus: &mut MemoryBus) {
+        let r: u16 = Self::sp_plus_e8(cpu, memory_bus);
+        cpu.set_sp(r);
+        cpu.update_pc_and_cycles(cpu.get_pc().wrapping_add(2), 16);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::flags::{H_FLAG, N_FLAG, Z_FLAG};
+
+    fn run(a: u8, op: u8, value: u8, carry: bool) -> (u8, u8) {
+        let mut cpu: CPU = CPU::new();
+        cpu.get_registers().set_a(a);
+        cpu.get_registers().get_f_mut().set_flag(carry, C_FLAG);
+        ALU::apply(&mut cpu, op, value);
+        (cpu.get_registers().get_a(), cpu.get_registers().get_f().get_byte())
+    }
+
+    #[test]
+    fn add_sets_half_carry_and_carry() {
+        assert_eq!(run(0x0F, ADD, 0x01, false), (0x10, H_FLAG));
+        assert_eq!(run(0xFF, ADD, 0x01, false), (0x00, Z_FLAG | H_FLAG | C_FLAG));
+        assert_eq!(run(0xFE, ADC, 0x01, true), (0x00, Z_FLAG | H_FLAG | C_FLAG));
+    }
+
+    #[test]
+    fn sub_borrows() {
+        assert_eq!(run(0x10, SUB, 0x01, false), (0x0F, N_FLAG | H_FLAG));
+        assert_eq!(run(0x00, SBC, 0x00, true), (0xFF, N_FLAG | H_FLAG | C_FLAG));
+        assert_eq!(run(0x42, SUB, 0x42, false), (0x00, Z_FLAG | N_FLAG));
+    }
+
+    #[test]
+    fn cp_keeps_a() {
+        assert_eq!(run(0x05, CP, 0x06, false), (0x05, N_FLAG | H_FLAG | C_FLAG));
+    }
+
+    #[test]
+    fn logic_flags() {
+        assert_eq!(run(0xF0, AND, 0x0F, true), (0x00, Z_FLAG | H_FLAG));
+        assert_eq!(run(0xFF, XOR, 0xFF, true), (0x00, Z_FLAG));
+        assert_eq!(run(0xF0, OR, 0x0F, true), (0xFF, 0));
+    }
+}
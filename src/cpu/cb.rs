@@ -0,0 +1,145 @@
+use crate::constants::flags::C_FLAG;
+use crate::cpu::cpu::CPU;
+use crate::cpu::ld::{GETTERS, SETTERS};
+use crate::memory_bus::memory_bus::MemoryBus;
+
+// operand index of (HL) in the r8 encoding
+const HL: u8 = 6;
+
+/// The 0xCB prefixed instructions: rotates and shifts, BIT, RES and SET on a register
+/// or on (HL).
+pub struct CB;
+
+impl CB {
+
+    /// Executes the prefixed instruction following the 0xCB byte at PC.
+    pub fn execute(cpu: &mut CPU, memory_bus: &mut MemoryBus) {
+        let opcode: u8 = memory_bus.cpu_read(cpu.get_pc().wrapping_add(1));
+        let x: u8 = opcode >> 6;
+        let y: u8 = (opcode >> 3) & 0x07;
+        let z: u8 = opcode & 0x07;
+
+        let hl: u16 = cpu.get_registers().get_hl();
+        let value: u8 = if z == HL {
+            memory_bus.cpu_read(hl)
+        } else {
+            GETTERS[z as usize](cpu.get_registers())
+        };
+        let result: Option<u8> = match x {
+            0 => Some(Self::shift(cpu, y, value)),
+            1 => {
+                Self::bit(cpu, y, value);
+                None
+            }
+            2 => Some(value & !(1 << y)),
+            _ => Some(value | (1 << y)),
+        };
+
+        if let Some(result) = result {
+            if z == HL {
+                memory_bus.cpu_write(hl, result);
+            } else {
+                SETTERS[z as usize](cpu.get_registers(), result);
+            }
+        }
+        let cycles: u64 = match (z == HL, x) {
+            (false, _) => 8,
+            (true, 1) => 12,
+            (true, _) => 16,
+        };
+        cpu.update_pc_and_cycles(cpu.get_pc().wrapping_add(2), cycles);
+    }
+
+    /// Rotates or shifts `value` and sets Z and C, N and H are cleared.
+    ///
+    /// # Parameters
+    /// - `op`: 0 = RLC, 1 = RRC, 2 = RL, 3 = RR, 4 = SLA, 5 = SRA, 6 = SWAP, 7 = SRL.
+    fn shift(cpu: &mut CPU, op: u8, value: u8) -> u8 {
+        let carry: u8 = if cpu.get_registers().get_f().get_flag(C_FLAG) { 1 } else { 0 };
+        let (r, c): (u8, bool) = match op {
+            0 => (value.rotate_left(1), value & 0x80 != 0),
+            1 => (value.rotate_right(1), value & 0x01 != 0),
+            2 => ((value << 1) | carry, value & 0x80 != 0),
+            3 => ((value >> 1) | (carry << 7), value & 0x01 != 0),
+            4 => (value << 1, value & 0x80 != 0),
+            5 => ((value >> 1) | (value & 0x80), value & 0x01 != 0),
+            6 => (value.rotate_left(4), false),
+            _ => (value >> 1, value & 0x01 != 0),
+        };
+        cpu.get_registers().get_f_mut().set_flags(c, false, false, r == 0);
+        r
+    }
+
+    /// Sets Z when bit `bit` of `value` is clear. H is set, N cleared and C kept.
+    fn bit(cpu: &mut CPU, bit: u8, value: u8) {
+        let c: bool = cpu.get_registers().get_f().get_flag(C_FLAG);
+        cpu.get_registers().get_f_mut().set_flags(c, false, true, value & (1 << bit) == 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::flags::{H_FLAG, Z_FLAG};
+
+    fn run(opcode: u8, setup: fn(&mut CPU, &mut MemoryBus)) -> (CPU, MemoryBus) {
+        let mut cpu: CPU = CPU::new();
+        let mut bus: MemoryBus = MemoryBus::new();
+        cpu.change_pc(0xC000);
+        bus.write(0xC000, 0xCB);
+        bus.write(0xC001, opcode);
+        setup(&mut cpu, &mut bus);
+        CB::execute(&mut cpu, &mut bus);
+        assert_eq!(cpu.get_pc(), 0xC002);
+        (cpu, bus)
+    }
+
+    #[test]
+    fn rl_through_carry() {
+        // RL C
+        let (mut cpu, _) = run(0x11, |cpu, _| {
+            cpu.get_registers().set_c(0x80);
+            cpu.get_registers().get_f_mut().set_flag(false, C_FLAG);
+        });
+        assert_eq!(cpu.get_registers().get_c(), 0x00);
+        assert_eq!(cpu.get_registers().get_f().get_byte(), Z_FLAG | C_FLAG);
+        assert_eq!(cpu.get_cycles(), 8);
+    }
+
+    #[test]
+    fn swap_and_sra() {
+        // SWAP A
+        let (mut cpu, _) = run(0x37, |cpu, _| cpu.get_registers().set_a(0xA5));
+        assert_eq!(cpu.get_registers().get_a(), 0x5A);
+        // SRA B
+        let (mut cpu, _) = run(0x28, |cpu, _| cpu.get_registers().set_b(0x81));
+        assert_eq!(cpu.get_registers().get_b(), 0xC0);
+        assert_eq!(cpu.get_registers().get_f().get_byte(), C_FLAG);
+    }
+
+    #[test]
+    fn bit_on_hl_keeps_memory() {
+        // BIT 7, (HL)
+        let (mut cpu, bus) = run(0x7E, |cpu, bus| {
+            cpu.get_registers().set_hl(0xC100);
+            bus.write(0xC100, 0x7F);
+        });
+        assert_eq!(cpu.get_registers().get_f().get_byte(), Z_FLAG | H_FLAG);
+        assert_eq!(bus.read(0xC100), 0x7F);
+        assert_eq!(cpu.get_cycles(), 12);
+    }
+
+    #[test]
+    fn res_and_set_on_hl() {
+        // SET 0, (HL)
+        let (cpu, bus) = run(0xC6, |cpu, bus| {
+            cpu.get_registers().set_hl(0xC100);
+            bus.write(0xC100, 0x80);
+        });
+        assert_eq!(bus.read(0xC100), 0x81);
+        assert_eq!(cpu.get_cycles(), 16);
+        // RES 7, E
+        let (mut cpu, _) = run(0xBB, |cpu, _| cpu.get_registers().set_e(0xFF));
+        assert_eq!(cpu.get_registers().get_e(), 0x7F);
+    }
+}
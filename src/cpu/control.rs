@@ -98,8 +98,13 @@ impl Control {
         cpu.update_pc_and_cycles(cpu.get_pc() + 1, 4);
     }
 
-    pub fn stop(cpu: &mut CPU){
-        cpu.set_running(false);
+    pub fn stop(cpu: &mut CPU, memory_bus: &mut MemoryBus){
+        if memory_bus.is_speed_switch_armed() {
+            //CGB speed switch, the CPU resumes by itself once the clock is stable
+            memory_bus.switch_speed();
+        } else {
+            cpu.set_running(false);
+        }
         cpu.update_pc_and_cycles(cpu.get_pc() + 2, 4);
     }
 
@@ -145,7 +150,7 @@ impl Control {
     pub fn jr_e8(cpu: &mut CPU, memory_bus: &mut MemoryBus){
         let pc: i16 = cpu.get_pc() as i16;
         let offset_addr: u16 = cpu.get_pc().wrapping_add(1);
-        let offset: i8 = memory_bus.read(offset_addr) as i8;
+        let offset: i8 = memory_bus.cpu_read(offset_addr) as i8;
         let new_pc: u16 = pc.wrapping_add(2).wrapping_add(offset as i16) as u16;
         cpu.update_pc_and_cycles(new_pc, 12);
     }
@@ -209,8 +214,8 @@ impl Control {
         } else {
             let pc: i16 = cpu.get_pc() as i16;
             let offset_addr: u16 = cpu.get_pc().wrapping_add(1);
-            let offset: i8 = memory_bus.read(offset_addr) as i8;
-            let new_pc: u16 = pc.wrapping_add(offset as i16) as u16;
+            let offset: i8 = memory_bus.cpu_read(offset_addr) as i8;
+            let new_pc: u16 = pc.wrapping_add(2).wrapping_add(offset as i16) as u16;
             cpu.update_pc_and_cycles(new_pc, 12);
         }
     }
@@ -233,7 +238,7 @@ impl Control {
     }
 
     pub fn dec_h(cpu: &mut CPU){
-        let h: u8 = cpu.get_registers().get_e();
+        let h: u8 = cpu.get_registers().get_h();
         let r: u8 = h.wrapping_sub(1);
         let half_carry: bool = (h & 0x0F) == 0x00;
         let carry: bool = cpu.get_registers().get_f_mut().get_flag(C_FLAG);
@@ -272,7 +277,7 @@ impl Control {
     pub fn jr_z_n8(cpu: &mut CPU, memory_bus: &mut MemoryBus){
         let pc: u16 = cpu.get_pc();
         let offset_addr: u16 = cpu.get_pc().wrapping_add(1);
-        let offset: i8 = memory_bus.read(offset_addr) as i8;
+        let offset: i8 = memory_bus.cpu_read(offset_addr) as i8;
         let z_flag = cpu.get_registers().get_f().get_flag(Z_FLAG);
         if z_flag{
             let new_pc: u16 = (pc as i16).wrapping_add(2).wrapping_add(offset as i16) as u16;
@@ -334,7 +339,7 @@ impl Control {
 
     pub fn jr_nc_e8(cpu: &mut CPU, memory_bus: &mut MemoryBus){
         let offset_addr: u16 = cpu.get_pc().wrapping_add(1);
-        let offset: i8 = memory_bus.read(offset_addr) as i8;
+        let offset: i8 = memory_bus.cpu_read(offset_addr) as i8;
         let c: bool = cpu.get_registers().get_f_mut().get_flag(C_FLAG);
         if !c {
             let new_pc: u16 = (cpu.get_pc() as i16).wrapping_add(2).wrapping_add(offset as i16) as u16;
@@ -353,9 +358,9 @@ impl Control {
 
     pub fn inc_hl_(cpu: &mut CPU, memory_bus: &mut MemoryBus){
         let address: u16 = cpu.get_registers().get_hl();
-        let register: u8 = memory_bus.read(address);
+        let register: u8 = memory_bus.cpu_read(address);
         let r: u8 = register.wrapping_add(1);
-        memory_bus.write(address, r);
+        memory_bus.cpu_write(address, r);
         let c: bool = cpu.get_registers().get_f_mut().get_flag(C_FLAG);
         let h: bool = get_half_carry_inc(register);
         cpu.get_registers().get_f_mut().set_flags(c,false, h,r == 0);
@@ -364,9 +369,9 @@ impl Control {
 
     pub fn dec_hl_(cpu: &mut CPU, memory_bus: &mut MemoryBus){
         let address: u16 = cpu.get_registers().get_hl();
-        let register: u8 = memory_bus.read(address);
+        let register: u8 = memory_bus.cpu_read(address);
         let r: u8 = register.wrapping_sub(1);
-        memory_bus.write(address, r);
+        memory_bus.cpu_write(address, r);
         let c: bool = cpu.get_registers().get_f_mut().get_flag(C_FLAG);
         // Half-carry occurs if low-nibble underflows when subtracting 1
         let h: bool = (register & 0x0F) == 0x00;
@@ -384,7 +389,7 @@ impl Control {
         let offset_addr: u16 = cpu.get_pc().wrapping_add(1);
         let c: bool = cpu.get_registers().get_f_mut().get_flag(C_FLAG);
         if c {
-            let offset: i8 = memory_bus.read(offset_addr) as i8;
+            let offset: i8 = memory_bus.cpu_read(offset_addr) as i8;
             let new_pc: u16 = (cpu.get_pc() as i16).wrapping_add(2).wrapping_add(offset as i16) as u16;
             cpu.update_pc_and_cycles(new_pc, 12);
         } else {
@@ -443,5 +448,70 @@ impl Control {
         cpu.update_pc_and_cycles(cpu.get_pc().wrapping_add(1), 4);
     }
 
+    pub fn di(cpu: &mut CPU){
+        cpu.set_ime(false);
+        cpu.update_pc_and_cycles(cpu.get_pc().wrapping_add(1), 4);
+    }
+
+    /// Interrupts are enabled only after the instruction following EI.
+    pub fn ei(cpu: &mut CPU){
+        cpu.schedule_ime();
+        cpu.update_pc_and_cycles(cpu.get_pc().wrapping_add(1), 4);
+    }
+
+    /// Opcodes missing from the instruction set freeze the CPU: it stays on the opcode
+    /// and, with interrupts off, nothing gets it out.
+    pub fn lock_up(cpu: &mut CPU){
+        cpu.set_ime(false);
+        cpu.add_cycles(4);
+    }
+
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn setup() -> (CPU, MemoryBus) {
+        let mut cpu: CPU = CPU::new();
+        cpu.set_sp(0xD000);
+        cpu.change_pc(0xC000);
+        (cpu, MemoryBus::new())
+    }
+
+    #[test]
+    fn dec_h_borrows_from_bit_4() {
+        let (mut cpu, _) = setup();
+        cpu.get_registers().set_h(0x10);
+        cpu.get_registers().set_e(0x55);
+        Control::dec_h(&mut cpu);
+        let registers = cpu.read_registers();
+        assert_eq!((registers.get_h(), registers.get_e()), (0x0F, 0x55));
+        assert!(registers.get_f().get_flag(H_FLAG));
+        assert!(registers.get_f().get_flag(N_FLAG));
+        assert!(!registers.get_f().get_flag(Z_FLAG));
+    }
+
+    #[test]
+    fn inc_and_dec_hl_change_memory() {
+        let (mut cpu, mut bus) = setup();
+        cpu.get_registers().set_hl(0xC100);
+        bus.write(0xC100, 0xFF);
+        Control::inc_hl_(&mut cpu, &mut bus);
+        assert_eq!(bus.read(0xC100), 0x00);
+        assert!(cpu.read_registers().get_f().get_flag(Z_FLAG));
+        Control::dec_hl_(&mut cpu, &mut bus);
+        assert_eq!(bus.read(0xC100), 0xFF);
+        assert!(cpu.read_registers().get_f().get_flag(H_FLAG));
+    }
+
+    #[test]
+    fn jr_c_is_relative_to_next_instruction() {
+        let (mut cpu, mut bus) = setup();
+        // JR C,-4
+        bus.write(0xC001, 0xFC);
+        cpu.get_registers().get_f_mut().set_flag(true, C_FLAG);
+        Control::jr_c_e8(&mut cpu, &mut bus);
+        assert_eq!((cpu.get_pc(), cpu.get_cycles()), (0xBFFE, 12));
+    }
+}
@@ -1,6 +1,14 @@
+use crate::constants::io_registers::{IE, IF};
+use crate::cpu::alu::ALU;
+use crate::cpu::cb::CB;
 use crate::cpu::control::Control;
+use crate::cpu::jp::JP;
 use crate::cpu::ld::LD;
+use crate::error::state_error::StateError;
 use crate::memory_bus::memory_bus::MemoryBus;
+use crate::state::snapshot::Snapshot;
+use crate::state::state_reader::StateReader;
+use crate::state::state_writer::StateWriter;
 use super::register::Register;
 
 
@@ -11,7 +19,8 @@ pub struct CPU {
     cycles: u64,
     is_running: bool,
     ime: bool,
-    ie: bool,
+    // EI ran, IME turns on after the next instruction
+    ime_pending: bool,
     _if: bool,
     halt: bool,
 }
@@ -25,33 +34,88 @@ impl CPU {
             cycles: 0,
             is_running: true,
             ime: false,
-            ie: false,
+            ime_pending: false,
             _if: false,
             halt: false,
         }
     }
 
+    /// Runs one instruction, or services a pending interrupt, or idles 4 T-cycles in HALT.
     pub fn step(&mut self, bus: &mut MemoryBus) {
-        let opcode = bus.read(self.pc);
+        let pending: u8 = bus.read(IE) & bus.read(IF) & 0x1F;
+        if self.halt {
+            // HALT ends as soon as an interrupt is requested, even with IME off
+            if pending == 0 {
+                self.cycles += 4;
+                return;
+            }
+            self.halt = false;
+        }
+        if self.ime && pending != 0 {
+            self.service_interrupt(bus, pending);
+            return;
+        }
+        let enable_ime: bool = self.ime_pending;
+        let opcode = bus.cpu_read(self.pc);
         self.decode(opcode, bus);
+        // a DI right after EI cancels it
+        if enable_ime && self.ime_pending {
+            self.ime = true;
+            self.ime_pending = false;
+        }
+    }
+
+    /// Acknowledges the highest priority interrupt in `pending` and calls its handler
+    /// (0x40 VBlank, 0x48 STAT, 0x50 timer, 0x58 serial, 0x60 joypad).
+    fn service_interrupt(&mut self, bus: &mut MemoryBus, pending: u8) {
+        let bit: u8 = pending.trailing_zeros() as u8;
+        bus.write(IF, bus.read(IF) & !(1 << bit));
+        self.ime = false;
+        JP::push(self, bus, self.pc);
+        self.pc = 0x40 + 8 * bit as u16;
+        self.cycles += 20;
     }
 
     pub fn set_running(&mut self, is_running: bool) {
         self.is_running = is_running;
     }
 
+    /// Returns `false` while the CPU is stopped by the STOP instruction.
+    pub fn is_running(&self) -> bool {
+        self.is_running
+    }
+
     pub fn get_pc(&self) -> u16 {
         self.pc
     }
 
     pub fn get_sp(&self) -> u16 { self.sp }
 
+    pub fn get_cycles(&self) -> u64 { self.cycles }
+
     pub fn get_halt(&self) -> bool { self.halt }
+
+    pub fn get_ime(&self) -> bool { self.ime }
+
+    pub fn set_ime(&mut self, ime: bool) {
+        self.ime = ime;
+        self.ime_pending = false;
+    }
+
+    /// Turns IME on once the next instruction has run, as EI does.
+    pub fn schedule_ime(&mut self) {
+        self.ime_pending = true;
+    }
     
     pub fn get_registers(&mut self) -> &mut Register {
         &mut self.registers
     }
 
+    /// Same as [`CPU::get_registers`] for callers that only need to look.
+    pub fn read_registers(&self) -> &Register {
+        &self.registers
+    }
+
     pub fn change_pc(&mut self, new_pc: u16) {
         self.pc = new_pc;
     }
@@ -78,8 +142,6 @@ impl CPU {
         let y: u8 = (opcode & 0b00111000) >> 3; //op1
         let z: u8 = opcode & 0b00000111; //op2
 
-        dbg!(x,y,z);
-
         match(x,y,z){
             (0, 0, 0) => Control::nop(self), //0x00
             (0, 0, 1) => LD::ld_bc_n16(self, bus), //0x01
@@ -98,7 +160,7 @@ impl CPU {
             (0, 1, 6) => LD::ld_c_n8(self, bus),
             (0, 1, 7) => Control::rrca(self),
             
-            (0, 2, 0) => Control::stop(self),
+            (0, 2, 0) => Control::stop(self, bus),
             (0, 2, 1) => LD::ld_de_n16(self, bus),
             (0, 2, 2) => LD::ld_de_a(self, bus),
             (0, 2, 3) => Control::inc_de(self),
@@ -163,144 +225,235 @@ impl CPU {
                 Control::halt(self)
             }
             
-            (2, 0, 0) => todo!(),
-            (2, 0, 1) => todo!(),
-            (2, 0, 2) => todo!(),
-            (2, 0, 3) => todo!(),
-            (2, 0, 4) => todo!(),
-            (2, 0, 5) => todo!(),
-            (2, 0, 6) => todo!(),
-            (2, 0, 7) => todo!(),
-            (2, 1, 0) => todo!(),
-            (2, 1, 1) => todo!(),
-            (2, 1, 2) => todo!(),
-            (2, 1, 3) => todo!(),
-            (2, 1, 4) => todo!(),
-            (2, 1, 5) => todo!(),
-            (2, 1, 6) => todo!(),
-            (2, 1, 7) => todo!(),
-            
-            (2, 2, 0) => todo!(),
-            (2, 2, 1) => todo!(),
-            (2, 2, 2) => todo!(),
-            (2, 2, 3) => todo!(),
-            (2, 2, 4) => todo!(),
-            (2, 2, 5) => todo!(),
-            (2, 2, 6) => todo!(),
-            (2, 2, 7) => todo!(),
-            (2, 3, 0) => todo!(),
-            (2, 3, 1) => todo!(),
-            (2, 3, 2) => todo!(),
-            (2, 3, 3) => todo!(),
-            (2, 3, 4) => todo!(),
-            (2, 3, 5) => todo!(),
-            (2, 3, 6) => todo!(),
-            (2, 3, 7) => todo!(),
-            
-            (2, 4, 0) => todo!(),
-            (2, 4, 1) => todo!(),
-            (2, 4, 2) => todo!(),
-            (2, 4, 3) => todo!(),
-            (2, 4, 4) => todo!(),
-            (2, 4, 5) => todo!(),
-            (2, 4, 6) => todo!(),
-            (2, 4, 7) => todo!(),
-            (2, 5, 0) => todo!(),
-            (2, 5, 1) => todo!(),
-            (2, 5, 2) => todo!(),
-            (2, 5, 3) => todo!(),
-            (2, 5, 4) => todo!(),
-            (2, 5, 5) => todo!(),
-            (2, 5, 6) => todo!(),
-            (2, 5, 7) => todo!(),
-            
-            (2, 6, 0) => todo!(),
-            (2, 6, 1) => todo!(),
-            (2, 6, 2) => todo!(),
-            (2, 6, 3) => todo!(),
-            (2, 6, 4) => todo!(),
-            (2, 6, 5) => todo!(),
-            (2, 6, 6) => todo!(),
-            (2, 6, 7) => todo!(),
-            (2, 7, 0) => todo!(),
-            (2, 7, 1) => todo!(),
-            (2, 7, 2) => todo!(),
-            (2, 7, 3) => todo!(),
-            (2, 7, 4) => todo!(),
-            (2, 7, 5) => todo!(),
-            (2, 7, 6) => todo!(),
-            (2, 7, 7) => todo!(),
-            
-            (3, 0, 0) => todo!(),
-            (3, 0, 1) => todo!(),
-            (3, 0, 2) => todo!(),
-            (3, 0, 3) => todo!(),
-            (3, 0, 4) => todo!(),
-            (3, 0, 5) => todo!(),
-            (3, 0, 6) => todo!(),
-            (3, 0, 7) => todo!(),
-            (3, 1, 0) => todo!(),
-            (3, 1, 1) => todo!(),
-            (3, 1, 2) => todo!(),
-            (3, 1, 3) => todo!(),
-            (3, 1, 4) => todo!(),
-            (3, 1, 5) => todo!(),
-            (3, 1, 6) => todo!(),
-            (3, 1, 7) => todo!(),
-            
-            (3, 2, 0) => todo!(),
-            (3, 2, 1) => todo!(),
-            (3, 2, 2) => todo!(),
-            (3, 2, 3) => todo!(),
-            (3, 2, 4) => todo!(),
-            (3, 2, 5) => todo!(),
-            (3, 2, 6) => todo!(),
-            (3, 2, 7) => todo!(),
-            (3, 3, 0) => todo!(),
-            (3, 3, 1) => todo!(),
-            (3, 3, 2) => todo!(),
-            (3, 3, 3) => todo!(),
-            (3, 3, 4) => todo!(),
-            (3, 3, 5) => todo!(),
-            (3, 3, 6) => todo!(),
-            (3, 3, 7) => todo!(),
-            
-            (3, 4, 0) => todo!(),
-            (3, 4, 1) => todo!(),
-            (3, 4, 2) => todo!(),
-            (3, 4, 3) => todo!(),
-            (3, 4, 4) => todo!(),
-            (3, 4, 5) => todo!(),
-            (3, 4, 6) => todo!(),
-            (3, 4, 7) => todo!(),
-            (3, 5, 0) => todo!(),
-            (3, 5, 1) => todo!(),
-            (3, 5, 2) => todo!(),
-            (3, 5, 3) => todo!(),
-            (3, 5, 4) => todo!(),
-            (3, 5, 5) => todo!(),
-            (3, 5, 6) => todo!(),
-            (3, 5, 7) => todo!(),
-            
-            (3, 6, 0) => todo!(),
-            (3, 6, 1) => todo!(),
-            (3, 6, 2) => todo!(),
-            (3, 6, 3) => todo!(),
-            (3, 6, 4) => todo!(),
-            (3, 6, 5) => todo!(),
-            (3, 6, 6) => todo!(),
-            (3, 6, 7) => todo!(),
-            (3, 7, 0) => todo!(),
-            (3, 7, 1) => todo!(),
-            (3, 7, 2) => todo!(),
-            (3, 7, 3) => todo!(),
-            (3, 7, 4) => todo!(),
-            (3, 7, 5) => todo!(),
-            (3, 7, 6) => todo!(),
-            (3, 7, 7) => todo!(),
-            
+            (2, op, 6) => ALU::alu_a_hl(self, bus, op),
+            (2, op, src) => ALU::alu_a_r8(self, op, src as usize),
+
+            (3, 0, 0) => JP::ret_cc(self, bus, 0), //0xC0
+            (3, 0, 1) => JP::pop_r16(self, bus, 0),
+            (3, 0, 2) => JP::jp_cc_a16(self, bus, 0),
+            (3, 0, 3) => JP::jp_a16(self, bus),
+            (3, 0, 4) => JP::call_cc_a16(self, bus, 0),
+            (3, 0, 5) => JP::push_r16(self, bus, 0),
+            (3, 0, 6) => ALU::alu_a_n8(self, bus, 0),
+            (3, 0, 7) => JP::rst(self, bus, 0x00),
+            (3, 1, 0) => JP::ret_cc(self, bus, 1),
+            (3, 1, 1) => JP::ret(self, bus),
+            (3, 1, 2) => JP::jp_cc_a16(self, bus, 1),
+            (3, 1, 3) => CB::execute(self, bus),
+            (3, 1, 4) => JP::call_cc_a16(self, bus, 1),
+            (3, 1, 5) => JP::call_a16(self, bus),
+            (3, 1, 6) => ALU::alu_a_n8(self, bus, 1),
+            (3, 1, 7) => JP::rst(self, bus, 0x08),
+
+            (3, 2, 0) => JP::ret_cc(self, bus, 2), //0xD0
+            (3, 2, 1) => JP::pop_r16(self, bus, 1),
+            (3, 2, 2) => JP::jp_cc_a16(self, bus, 2),
+            (3, 2, 3) => Control::lock_up(self),
+            (3, 2, 4) => JP::call_cc_a16(self, bus, 2),
+            (3, 2, 5) => JP::push_r16(self, bus, 1),
+            (3, 2, 6) => ALU::alu_a_n8(self, bus, 2),
+            (3, 2, 7) => JP::rst(self, bus, 0x10),
+            (3, 3, 0) => JP::ret_cc(self, bus, 3),
+            (3, 3, 1) => JP::reti(self, bus),
+            (3, 3, 2) => JP::jp_cc_a16(self, bus, 3),
+            (3, 3, 3) => Control::lock_up(self),
+            (3, 3, 4) => JP::call_cc_a16(self, bus, 3),
+            (3, 3, 5) => Control::lock_up(self),
+            (3, 3, 6) => ALU::alu_a_n8(self, bus, 3),
+            (3, 3, 7) => JP::rst(self, bus, 0x18),
+
+            (3, 4, 0) => LD::ldh_a8_a(self, bus), //0xE0
+            (3, 4, 1) => JP::pop_r16(self, bus, 2),
+            (3, 4, 2) => LD::ldh_c_a(self, bus),
+            (3, 4, 3) => Control::lock_up(self),
+            (3, 4, 4) => Control::lock_up(self),
+            (3, 4, 5) => JP::push_r16(self, bus, 2),
+            (3, 4, 6) => ALU::alu_a_n8(self, bus, 4),
+            (3, 4, 7) => JP::rst(self, bus, 0x20),
+            (3, 5, 0) => ALU::add_sp_e8(self, bus),
+            (3, 5, 1) => JP::jp_hl(self),
+            (3, 5, 2) => LD::ld_a16_a(self, bus),
+            (3, 5, 3) => Control::lock_up(self),
+            (3, 5, 4) => Control::lock_up(self),
+            (3, 5, 5) => Control::lock_up(self),
+            (3, 5, 6) => ALU::alu_a_n8(self, bus, 5),
+            (3, 5, 7) => JP::rst(self, bus, 0x28),
+
+            (3, 6, 0) => LD::ldh_a_a8(self, bus), //0xF0
+            (3, 6, 1) => JP::pop_r16(self, bus, 3),
+            (3, 6, 2) => LD::ldh_a_c(self, bus),
+            (3, 6, 3) => Control::di(self),
+            (3, 6, 4) => Control::lock_up(self),
+            (3, 6, 5) => JP::push_r16(self, bus, 3),
+            (3, 6, 6) => ALU::alu_a_n8(self, bus, 6),
+            (3, 6, 7) => JP::rst(self, bus, 0x30),
+            (3, 7, 0) => LD::ld_hl_sp_e8(self, bus),
+            (3, 7, 1) => LD::ld_sp_hl(self),
+            (3, 7, 2) => LD::ld_a_a16(self, bus),
+            (3, 7, 3) => Control::ei(self),
+            (3, 7, 4) => Control::lock_up(self),
+            (3, 7, 5) => Control::lock_up(self),
+            (3, 7, 6) => ALU::alu_a_n8(self, bus, 7),
+            (3, 7, 7) => JP::rst(self, bus, 0x38),
+
             _ => panic!("NOT IMPLEMENTED")
         }
     }
 
-}
\ No newline at end of file
+}
+
+impl Snapshot for CPU {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u16(self.pc);
+        writer.write_u16(self.sp);
+        self.registers.save_state(writer);
+        writer.write_u64(self.cycles);
+        writer.write_bool(self.is_running);
+        writer.write_bool(self.ime);
+        writer.write_bool(self.ime_pending);
+        writer.write_bool(self._if);
+        writer.write_bool(self.halt);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.pc = reader.read_u16()?;
+        self.sp = reader.read_u16()?;
+        self.registers.load_state(reader)?;
+        self.cycles = reader.read_u64()?;
+        self.is_running = reader.read_bool()?;
+        self.ime = reader.read_bool()?;
+        self.ime_pending = reader.read_bool()?;
+        self._if = reader.read_bool()?;
+        self.halt = reader.read_bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::flags::{C_FLAG, H_FLAG};
+    use crate::constants::io_registers::{DIV, INT_TIMER, KEY1};
+
+    /// Places `program` in WRAM at 0xC000 and points PC at it.
+    fn setup(program: &[u8]) -> (CPU, MemoryBus) {
+        let mut cpu: CPU = CPU::new();
+        let mut bus: MemoryBus = MemoryBus::new();
+        for (i, byte) in program.iter().enumerate() {
+            bus.write(0xC000 + i as u16, *byte);
+        }
+        cpu.change_pc(0xC000);
+        cpu.set_sp(0xD000);
+        (cpu, bus)
+    }
+
+    #[test]
+    fn registers_move_independently() {
+        // LD C,0x11 / LD D,C / LD E,0x22 / LD H,E / LD L,D
+        let (mut cpu, mut bus) = setup(&[0x0E, 0x11, 0x51, 0x1E, 0x22, 0x63, 0x6A]);
+        for _ in 0..5 {
+            cpu.step(&mut bus);
+        }
+        let registers = cpu.read_registers();
+        assert_eq!(registers.get_b(), 0x00);
+        assert_eq!((registers.get_c(), registers.get_d()), (0x11, 0x11));
+        assert_eq!((registers.get_e(), registers.get_h(), registers.get_l()), (0x22, 0x22, 0x11));
+    }
+
+    #[test]
+    fn jr_nz_is_relative_to_next_instruction() {
+        // JR NZ,+2
+        let (mut cpu, mut bus) = setup(&[0x20, 0x02]);
+        cpu.step(&mut bus);
+        assert_eq!(cpu.get_pc(), 0xC004);
+    }
+
+    #[test]
+    fn interrupt_is_dispatched_one_instruction_after_ei() {
+        // EI / NOP / NOP
+        let (mut cpu, mut bus) = setup(&[0xFB, 0x00, 0x00]);
+        bus.write(IE, INT_TIMER);
+        bus.write(IF, INT_TIMER);
+        cpu.step(&mut bus);
+        assert!(!cpu.get_ime());
+        cpu.step(&mut bus);
+        assert_eq!(cpu.get_pc(), 0xC002);
+        cpu.step(&mut bus);
+        assert_eq!(cpu.get_pc(), 0x0050);
+        assert!(!cpu.get_ime());
+        assert_eq!(bus.read(IF) & INT_TIMER, 0);
+        assert_eq!(JP::pop(&mut cpu, &mut bus), 0xC002);
+    }
+
+    #[test]
+    fn di_after_ei_cancels_it() {
+        // EI / DI / NOP
+        let (mut cpu, mut bus) = setup(&[0xFB, 0xF3, 0x00]);
+        for _ in 0..3 {
+            cpu.step(&mut bus);
+        }
+        assert!(!cpu.get_ime());
+    }
+
+    #[test]
+    fn halt_waits_for_an_interrupt() {
+        // HALT / INC A
+        let (mut cpu, mut bus) = setup(&[0x76, 0x3C]);
+        bus.write(IE, INT_TIMER);
+        cpu.step(&mut bus);
+        cpu.step(&mut bus);
+        assert!(cpu.get_halt());
+        assert_eq!(cpu.get_cycles(), 8);
+        // with IME off the CPU just resumes
+        bus.write(IF, INT_TIMER);
+        cpu.step(&mut bus);
+        assert!(!cpu.get_halt());
+        assert_eq!(cpu.get_registers().get_a(), 0x01);
+    }
+
+    #[test]
+    fn illegal_opcode_locks_up() {
+        let (mut cpu, mut bus) = setup(&[0xD3]);
+        cpu.step(&mut bus);
+        cpu.step(&mut bus);
+        assert_eq!(cpu.get_pc(), 0xC000);
+    }
+
+    #[test]
+    fn ld_hl_sp_offset_flags() {
+        // LD HL,SP-1
+        let (mut cpu, mut bus) = setup(&[0xF8, 0xFF]);
+        cpu.set_sp(0x00FF);
+        cpu.step(&mut bus);
+        assert_eq!(cpu.get_registers().get_hl(), 0x00FE);
+        assert_eq!(cpu.get_registers().get_f().get_byte(), H_FLAG | C_FLAG);
+    }
+
+    #[test]
+    fn stop_switches_speed_when_armed() {
+        // STOP
+        let (mut cpu, mut bus) = setup(&[0x10, 0x00, 0x10, 0x00]);
+        bus.set_cgb_mode(true);
+        bus.tick(1024);
+        bus.write(KEY1, 0x01);
+        cpu.step(&mut bus);
+        assert!(cpu.is_running());
+        assert!(bus.is_double_speed());
+        assert_eq!(bus.read(KEY1) & 0x81, 0x80);
+        assert_eq!(bus.read(DIV), 0);
+        assert_eq!(bus.take_stall_cycles(), 2050 * 4);
+        // without the armed bit STOP just stops
+        cpu.step(&mut bus);
+        assert!(!cpu.is_running());
+        assert!(bus.is_double_speed());
+    }
+
+    #[test]
+    fn key1_is_ignored_on_dmg() {
+        let (mut cpu, mut bus) = setup(&[0x10, 0x00]);
+        bus.write(KEY1, 0x01);
+        cpu.step(&mut bus);
+        assert!(!cpu.is_running());
+        assert!(!bus.is_double_speed());
+    }
+}
@@ -1,4 +1,8 @@
 use crate::constants::flags::{C_FLAG, H_FLAG, N_FLAG, Z_FLAG};
+use crate::error::state_error::StateError;
+use crate::state::snapshot::Snapshot;
+use crate::state::state_reader::StateReader;
+use crate::state::state_writer::StateWriter;
 
 pub struct Flag{
     bit: u8,
@@ -10,7 +14,16 @@ impl Flag {
             bit: 0x00,
         }
     }
-    
+
+    /// Returns the F register: Z, N, H and C in bits 7 to 4, the low nibble always 0.
+    pub fn get_byte(&self) -> u8 {
+        self.bit & 0xF0
+    }
+
+    pub fn set_byte(&mut self, value: u8) {
+        self.bit = value & 0xF0;
+    }
+
     /// Retrieves the state of a specific flag bit from the `bit` field.
     ///
     /// # Parameters
@@ -44,6 +57,7 @@ impl Flag {
     /// - `N_FLAG`: Negative flag
     /// - `H_FLAG`: Half-carry flag
     /// - `Z_FLAG`: Zero flag
+    ///
     /// The panic message will include the invalid `bit` value in binary format.
     pub fn set_flag(&mut self, set:bool, bit: u8){
         debug_assert!(bit == C_FLAG || bit == N_FLAG || bit == H_FLAG || bit == Z_FLAG,
@@ -81,4 +95,15 @@ impl Flag {
         self.set_flags(carry, false, half_carry, zero);
     }*/
     
-}
\ No newline at end of file
+}
+
+impl Snapshot for Flag {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.bit);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.bit = reader.read_u8()? & 0xF0;
+        Ok(())
+    }
+}
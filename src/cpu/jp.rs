@@ -0,0 +1,193 @@
+use crate::constants::flags::{C_FLAG, Z_FLAG};
+use crate::cpu::cpu::CPU;
+use crate::memory_bus::memory_bus::MemoryBus;
+use crate::utils::byte_utils::{format_u16, get_lsb_u16, get_msb_u16};
+
+/// Jumps, calls, returns, restarts and the stack (PUSH / POP).
+pub struct JP;
+
+impl JP {
+
+    /// Evaluates a branch condition.
+    ///
+    /// # Parameters
+    /// - `condition`: 0 = NZ, 1 = Z, 2 = NC, 3 = C (bits 3-4 of the opcode).
+    pub fn condition(cpu: &mut CPU, condition: u8) -> bool {
+        let flags = cpu.get_registers().get_f();
+        match condition {
+            0 => !flags.get_flag(Z_FLAG),
+            1 => flags.get_flag(Z_FLAG),
+            2 => !flags.get_flag(C_FLAG),
+            3 => flags.get_flag(C_FLAG),
+            _ => panic!("Invalid condition {}", condition),
+        }
+    }
+
+    /// Pushes `value` on the stack, high byte first.
+    pub fn push(cpu: &mut CPU, memory_bus: &mut MemoryBus, value: u16) {
+        let sp: u16 = cpu.get_sp().wrapping_sub(1);
+        memory_bus.cpu_write(sp, get_msb_u16(value));
+        let sp: u16 = sp.wrapping_sub(1);
+        memory_bus.cpu_write(sp, get_lsb_u16(value));
+        cpu.set_sp(sp);
+    }
+
+    /// Pops a 16-bit value from the stack.
+    pub fn pop(cpu: &mut CPU, memory_bus: &mut MemoryBus) -> u16 {
+        let sp: u16 = cpu.get_sp();
+        let low: u8 = memory_bus.cpu_read(sp);
+        let high: u8 = memory_bus.cpu_read(sp.wrapping_add(1));
+        cpu.set_sp(sp.wrapping_add(2));
+        format_u16(high, low)
+    }
+
+    fn read_a16(cpu: &mut CPU, memory_bus: &mut MemoryBus) -> u16 {
+        let low: u8 = memory_bus.cpu_read(cpu.get_pc().wrapping_add(1));
+        let high: u8 = memory_bus.cpu_read(cpu.get_pc().wrapping_add(2));
+        format_u16(high, low)
+    }
+
+    pub fn jp_a16(cpu: &mut CPU, memory_bus: &mut MemoryBus) {
+        let address: u16 = Self::read_a16(cpu, memory_bus);
+        cpu.update_pc_and_cycles(address, 16);
+    }
+
+    pub fn jp_cc_a16(cpu: &mut CPU, memory_bus: &mut MemoryBus, condition: u8) {
+        if Self::condition(cpu, condition) {
+            Self::jp_a16(cpu, memory_bus);
+        } else {
+            cpu.update_pc_and_cycles(cpu.get_pc().wrapping_add(3), 12);
+        }
+    }
+
+    pub fn jp_hl(cpu: &mut CPU) {
+        let hl: u16 = cpu.get_registers().get_hl();
+        cpu.update_pc_and_cycles(hl, 4);
+    }
+
+    pub fn call_a16(cpu: &mut CPU, memory_bus: &mut MemoryBus) {
+        let address: u16 = Self::read_a16(cpu, memory_bus);
+        let return_address: u16 = cpu.get_pc().wrapping_add(3);
+        Self::push(cpu, memory_bus, return_address);
+        cpu.update_pc_and_cycles(address, 24);
+    }
+
+    pub fn call_cc_a16(cpu: &mut CPU, memory_bus: &mut MemoryBus, condition: u8) {
+        if Self::condition(cpu, condition) {
+            Self::call_a16(cpu, memory_bus);
+        } else {
+            cpu.update_pc_and_cycles(cpu.get_pc().wrapping_add(3), 12);
+        }
+    }
+
+    pub fn ret(cpu: &mut CPU, memory_bus: &mut MemoryBus) {
+        let address: u16 = Self::pop(cpu, memory_bus);
+        cpu.update_pc_and_cycles(address, 16);
+    }
+
+    pub fn ret_cc(cpu: &mut CPU, memory_bus: &mut MemoryBus, condition: u8) {
+        if Self::condition(cpu, condition) {
+            let address: u16 = Self::pop(cpu, memory_bus);
+            cpu.update_pc_and_cycles(address, 20);
+        } else {
+            cpu.update_pc_and_cycles(cpu.get_pc().wrapping_add(1), 8);
+        }
+    }
+
+    /// RET that also enables interrupts, without the delay of EI.
+    pub fn reti(cpu: &mut CPU, memory_bus: &mut MemoryBus) {
+        cpu.set_ime(true);
+        Self::ret(cpu, memory_bus);
+    }
+
+    /// Calls the fixed address `vector` (0x00, 0x08 ... 0x38).
+    pub fn rst(cpu: &mut CPU, memory_bus: &mut MemoryBus, vector: u16) {
+        let return_address: u16 = cpu.get_pc().wrapping_add(1);
+        Self::push(cpu, memory_bus, return_address);
+        cpu.update_pc_and_cycles(vector, 16);
+    }
+
+    /// PUSH of a register pair.
+    ///
+    /// # Parameters
+    /// - `pair`: 0 = BC, 1 = DE, 2 = HL, 3 = AF.
+    pub fn push_r16(cpu: &mut CPU, memory_bus: &mut MemoryBus, pair: u8) {
+        let registers = cpu.read_registers();
+        let value: u16 = match pair {
+            0 => registers.get_bc(),
+            1 => registers.get_de(),
+            2 => registers.get_hl(),
+            _ => registers.get_af(),
+        };
+        Self::push(cpu, memory_bus, value);
+        cpu.update_pc_and_cycles(cpu.get_pc().wrapping_add(1), 16);
+    }
+
+    /// POP into a register pair, see [`JP::push_r16`]. The low nibble of F always reads 0.
+    pub fn pop_r16(cpu: &mut CPU, memory_bus: &mut MemoryBus, pair: u8) {
+        let value: u16 = Self::pop(cpu, memory_bus);
+        let registers = cpu.get_registers();
+        match pair {
+            0 => registers.set_bc(value),
+            1 => registers.set_de(value),
+            2 => registers.set_hl(value),
+            _ => registers.set_af(value),
+        }
+        cpu.update_pc_and_cycles(cpu.get_pc().wrapping_add(1), 12);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn setup() -> (CPU, MemoryBus) {
+        let mut cpu: CPU = CPU::new();
+        cpu.set_sp(0xD000);
+        cpu.change_pc(0xC000);
+        (cpu, MemoryBus::new())
+    }
+
+    #[test]
+    fn call_and_ret() {
+        let (mut cpu, mut bus) = setup();
+        bus.write(0xC001, 0x34);
+        bus.write(0xC002, 0x12);
+        JP::call_a16(&mut cpu, &mut bus);
+        assert_eq!(cpu.get_pc(), 0x1234);
+        assert_eq!(cpu.get_sp(), 0xCFFE);
+        assert_eq!(bus.read(0xCFFE), 0x03);
+        assert_eq!(bus.read(0xCFFF), 0xC0);
+        JP::ret(&mut cpu, &mut bus);
+        assert_eq!(cpu.get_pc(), 0xC003);
+        assert_eq!(cpu.get_sp(), 0xD000);
+        assert_eq!(cpu.get_cycles(), 24 + 16);
+    }
+
+    #[test]
+    fn conditional_timing() {
+        let (mut cpu, mut bus) = setup();
+        cpu.get_registers().get_f_mut().set_flag(true, Z_FLAG);
+        JP::ret_cc(&mut cpu, &mut bus, 0);
+        assert_eq!((cpu.get_pc(), cpu.get_cycles()), (0xC001, 8));
+        JP::jp_cc_a16(&mut cpu, &mut bus, 0);
+        assert_eq!((cpu.get_pc(), cpu.get_cycles()), (0xC004, 20));
+    }
+
+    #[test]
+    fn pop_af_masks_low_nibble() {
+        let (mut cpu, mut bus) = setup();
+        cpu.get_registers().set_bc(0x12FF);
+        JP::push_r16(&mut cpu, &mut bus, 0);
+        JP::pop_r16(&mut cpu, &mut bus, 3);
+        assert_eq!(cpu.read_registers().get_af(), 0x12F0);
+    }
+
+    #[test]
+    fn rst_pushes_next_instruction() {
+        let (mut cpu, mut bus) = setup();
+        JP::rst(&mut cpu, &mut bus, 0x38);
+        assert_eq!(cpu.get_pc(), 0x38);
+        assert_eq!(JP::pop(&mut cpu, &mut bus), 0xC001);
+    }
+}
@@ -1,3 +1,4 @@
+use crate::cpu::alu::ALU;
 use crate::cpu::cpu::CPU;
 use crate::cpu::register::Register;
 use crate::memory_bus::memory_bus::MemoryBus;
@@ -6,7 +7,7 @@ use crate::utils::byte_utils::{format_u16, get_lsb_u16, get_msb_u16};
 
 pub struct LD;
 
-const SETTERS: [fn(&mut Register, u8); 8] = [
+pub const SETTERS: [fn(&mut Register, u8); 8] = [
     Register::set_b,
     Register::set_c,
     Register::set_d,
@@ -17,7 +18,7 @@ const SETTERS: [fn(&mut Register, u8); 8] = [
     Register::set_a,
 ];
 
-const GETTERS: [fn(&Register) -> u8; 8] = [
+pub const GETTERS: [fn(&Register) -> u8; 8] = [
     Register::get_b,
     Register::get_c,
     Register::get_d,
@@ -32,8 +33,8 @@ const GETTERS: [fn(&Register) -> u8; 8] = [
 impl LD {
 
     pub fn ld_bc_n16(cpu: &mut CPU, memory_bus: &mut  MemoryBus) {
-        let low_byte = memory_bus.read(cpu.get_pc() + 1);
-        let high_byte = memory_bus.read(cpu.get_pc() + 2);
+        let low_byte = memory_bus.cpu_read(cpu.get_pc() + 1);
+        let high_byte = memory_bus.cpu_read(cpu.get_pc() + 2);
         cpu.get_registers().set_b(high_byte);
         cpu.get_registers().set_c(low_byte);
         cpu.update_pc_and_cycles(cpu.get_pc() + 3, 12);
@@ -42,44 +43,44 @@ impl LD {
     pub fn ld_bc_a(cpu: &mut CPU, memory_bus: &mut MemoryBus){
         let a: u8 = cpu.get_registers().get_a();
         let address: u16 = cpu.get_registers().get_bc();
-        memory_bus.write(address, a);
+        memory_bus.cpu_write(address, a);
         cpu.update_pc_and_cycles(cpu.get_pc() + 1, 8);
     }
 
     pub fn ld_b_n8(cpu: &mut CPU, memory_bus: &mut MemoryBus){
-        let value: u8 = memory_bus.read(cpu.get_pc() + 1);
+        let value: u8 = memory_bus.cpu_read(cpu.get_pc() + 1);
         cpu.get_registers().set_b(value);
         cpu.update_pc_and_cycles(cpu.get_pc() + 2, 8);
     }
 
     pub fn ld_a16_sp(cpu: &mut CPU, memory_bus: &mut MemoryBus){
-        let a_low_byte = memory_bus.read(cpu.get_pc() + 1);
-        let a_high_byte = memory_bus.read(cpu.get_pc() + 2);
+        let a_low_byte = memory_bus.cpu_read(cpu.get_pc() + 1);
+        let a_high_byte = memory_bus.cpu_read(cpu.get_pc() + 2);
         //TODO: a16 == 0xFFFF check this if works
         let a16: u16 = format_u16(a_high_byte, a_low_byte);
         let sp_lsb: u8 =  get_lsb_u16(cpu.get_sp());
         let sp_msb: u8 = get_msb_u16(cpu.get_sp());
-        memory_bus.write(a16, sp_lsb);
-        memory_bus.write(a16.wrapping_add(1), sp_msb);
+        memory_bus.cpu_write(a16, sp_lsb);
+        memory_bus.cpu_write(a16.wrapping_add(1), sp_msb);
         cpu.update_pc_and_cycles(cpu.get_pc() + 3, 20);
     }
 
     pub fn ld_a_bc(cpu: &mut CPU, memory_bus: &mut MemoryBus){
         let bc: u16 = cpu.get_registers().get_bc();
-        let value: u8 = memory_bus.read(bc);
+        let value: u8 = memory_bus.cpu_read(bc);
         cpu.get_registers().set_a(value);
         cpu.update_pc_and_cycles(cpu.get_pc() + 1, 8);
     }
 
     pub fn ld_c_n8(cpu: &mut CPU, memory_bus: &mut MemoryBus){
-        let value: u8 = memory_bus.read(cpu.get_pc() + 1);
+        let value: u8 = memory_bus.cpu_read(cpu.get_pc() + 1);
         cpu.get_registers().set_c(value);
         cpu.update_pc_and_cycles(cpu.get_pc() + 2, 8);
     }
 
     pub fn ld_de_n16(cpu: &mut CPU, memory_bus: &mut MemoryBus){
-        let low_byte = memory_bus.read(cpu.get_pc() + 1);
-        let high_byte = memory_bus.read(cpu.get_pc() + 2);
+        let low_byte = memory_bus.cpu_read(cpu.get_pc() + 1);
+        let high_byte = memory_bus.cpu_read(cpu.get_pc() + 2);
         cpu.get_registers().set_d(high_byte);
         cpu.get_registers().set_e(low_byte);
         cpu.update_pc_and_cycles(cpu.get_pc() + 3, 12);
@@ -88,32 +89,32 @@ impl LD {
     pub fn ld_de_a(cpu: &mut CPU, memory_bus:&mut MemoryBus){
         let a: u8 = cpu.get_registers().get_a();
         let address: u16 = cpu.get_registers().get_de();
-        memory_bus.write(address, a);
+        memory_bus.cpu_write(address, a);
         cpu.update_pc_and_cycles(cpu.get_pc() + 1, 8);
     }
 
     pub fn ld_d_n8(cpu: &mut CPU, memory_bus: &mut MemoryBus){
-        let value: u8 = memory_bus.read(cpu.get_pc() + 1);
+        let value: u8 = memory_bus.cpu_read(cpu.get_pc() + 1);
         cpu.get_registers().set_d(value);
         cpu.update_pc_and_cycles(cpu.get_pc() + 2, 8);
     }
 
     pub fn ld_a_de(cpu: &mut CPU, memory_bus: &mut MemoryBus){
         let de: u16 = cpu.get_registers().get_de();
-        let value: u8 = memory_bus.read(de);
+        let value: u8 = memory_bus.cpu_read(de);
         cpu.get_registers().set_a(value);
         cpu.update_pc_and_cycles(cpu.get_pc() + 1, 8);
     }
 
     pub fn ld_e_n8(cpu: &mut CPU, memory_bus: &mut MemoryBus){
-        let value: u8 = memory_bus.read(cpu.get_pc() + 1);
+        let value: u8 = memory_bus.cpu_read(cpu.get_pc() + 1);
         cpu.get_registers().set_e(value);
         cpu.update_pc_and_cycles(cpu.get_pc() + 2, 8);
     }
 
     pub fn ld_hl_n16(cpu: &mut CPU, memory_bus: &mut MemoryBus){
-        let low_byte = memory_bus.read(cpu.get_pc() + 1);
-        let high_byte = memory_bus.read(cpu.get_pc() + 2);
+        let low_byte = memory_bus.cpu_read(cpu.get_pc() + 1);
+        let high_byte = memory_bus.cpu_read(cpu.get_pc() + 2);
         cpu.get_registers().set_hl(format_u16(high_byte, low_byte));
         cpu.update_pc_and_cycles(cpu.get_pc() + 3, 12);
     }
@@ -121,7 +122,7 @@ impl LD {
     pub fn ld_hl_plus_a(cpu: &mut CPU, memory_bus: &mut MemoryBus){
         let a: u8 = cpu.get_registers().get_a();
         let hl: u16 = cpu.get_registers().get_hl();
-        memory_bus.write(hl, a);
+        memory_bus.cpu_write(hl, a);
         let new_hl = hl.wrapping_add(1);
         cpu.get_registers().set_hl(new_hl);
         cpu.update_pc_and_cycles(cpu.get_pc() + 1, 8);
@@ -129,14 +130,14 @@ impl LD {
 
     pub fn ld_h_n8(cpu: &mut CPU, memory_bus: &mut MemoryBus){
         let n8: u16 = cpu.get_pc() + 1;
-        let value: u8 = memory_bus.read(n8);
+        let value: u8 = memory_bus.cpu_read(n8);
         cpu.get_registers().set_h(value);
         cpu.update_pc_and_cycles(cpu.get_pc() + 2, 8);
     }
 
     pub fn ld_a_hl_plus(cpu: &mut CPU, memory_bus: &mut MemoryBus){
         let hl: u16 = cpu.get_registers().get_hl();
-        let value: u8 = memory_bus.read(hl);
+        let value: u8 = memory_bus.cpu_read(hl);
         cpu.get_registers().set_a(value);
         let new_hl = hl.wrapping_add(1);
         cpu.get_registers().set_hl(new_hl);
@@ -145,14 +146,14 @@ impl LD {
 
     pub fn ld_l_n8(cpu: &mut CPU, memory_bus: &mut MemoryBus){
         let offset_addr: u16 = cpu.get_pc().wrapping_add(1);
-        let offset: u8 = memory_bus.read(offset_addr) as u8;
+        let offset: u8 = memory_bus.cpu_read(offset_addr);
         cpu.get_registers().set_l(offset);
         cpu.update_pc_and_cycles(cpu.get_pc() + 2, 8);
     }
 
     pub fn ld_sp_n16(cpu: &mut CPU, memory_bus: &mut MemoryBus){
-        let low_byte = memory_bus.read(  cpu.get_pc().wrapping_add(1));
-        let high_byte = memory_bus.read( cpu.get_pc().wrapping_add(2));
+        let low_byte = memory_bus.cpu_read(  cpu.get_pc().wrapping_add(1));
+        let high_byte = memory_bus.cpu_read( cpu.get_pc().wrapping_add(2));
         let hl: u16 = format_u16(high_byte,low_byte);
         cpu.set_sp(hl);
         cpu.update_pc_and_cycles(cpu.get_pc().wrapping_add(3), 12);
@@ -161,7 +162,7 @@ impl LD {
     pub fn ld_hl_minus_a(cpu: &mut CPU, memory_bus: &mut MemoryBus){
         let a: u8 = cpu.get_registers().get_a();
         let hl: u16 = cpu.get_registers().get_hl();
-        memory_bus.write(hl, a);
+        memory_bus.cpu_write(hl, a);
         let new_hl = hl.wrapping_sub(1);
         cpu.get_registers().set_hl(new_hl);
         cpu.update_pc_and_cycles(cpu.get_pc().wrapping_add(1), 8);
@@ -169,15 +170,15 @@ impl LD {
 
     pub fn ld_hl_n8(cpu: &mut CPU, memory_bus: &mut MemoryBus){
         let offset_address: u16 = cpu.get_pc().wrapping_add(1);
-        let register: u8 = memory_bus.read(offset_address);
+        let register: u8 = memory_bus.cpu_read(offset_address);
         let hl: u16 = cpu.get_registers().get_hl();
-        memory_bus.write(hl, register);
+        memory_bus.cpu_write(hl, register);
         cpu.update_pc_and_cycles(cpu.get_pc().wrapping_add(2), 12);
     }
 
     pub fn ld_a_hl_minus(cpu: &mut CPU, memory_bus: &mut MemoryBus){
         let hl_address: u16 = cpu.get_registers().get_hl();
-        let value: u8 = memory_bus.read(hl_address);
+        let value: u8 = memory_bus.cpu_read(hl_address);
         cpu.get_registers().set_a(value);
         let new_hl = hl_address.wrapping_sub(1);
         cpu.get_registers().set_hl(new_hl);
@@ -186,7 +187,7 @@ impl LD {
 
     pub fn ld_a_n8(cpu: &mut CPU, memory_bus: &mut MemoryBus){
         let addr: u16 = cpu.get_pc().wrapping_add(1);
-        let value: u8 = memory_bus.read(addr);
+        let value: u8 = memory_bus.cpu_read(addr);
         cpu.get_registers().set_a(value);
         cpu.update_pc_and_cycles(cpu.get_pc().wrapping_add(2), 8);
     }
@@ -199,7 +200,7 @@ impl LD {
     
     pub fn ld_r_hl(cpu: &mut CPU, memory_bus: &mut MemoryBus,  dst: usize){
         let addr = cpu.get_registers().get_hl();
-        let value: u8 = memory_bus.read(addr);
+        let value: u8 = memory_bus.cpu_read(addr);
         SETTERS[dst](cpu.get_registers(), value);
         cpu.update_pc_and_cycles(cpu.get_pc().wrapping_add(1), 8);
     }
@@ -207,10 +208,66 @@ impl LD {
     pub fn ld_hl_r(cpu: &mut CPU, memory_bus: &mut MemoryBus, src: usize){
         let addr: u16 = cpu.get_registers().get_hl();
         let value: u8 = GETTERS[src](cpu.get_registers());
-        memory_bus.write(addr, value);
+        memory_bus.cpu_write(addr, value);
         cpu.update_pc_and_cycles(cpu.get_pc().wrapping_add(1), 8);    
     }
 
+    pub fn ldh_a8_a(cpu: &mut CPU, memory_bus: &mut MemoryBus){
+        let offset: u8 = memory_bus.cpu_read(cpu.get_pc().wrapping_add(1));
+        let a: u8 = cpu.get_registers().get_a();
+        memory_bus.cpu_write(0xFF00 | offset as u16, a);
+        cpu.update_pc_and_cycles(cpu.get_pc().wrapping_add(2), 12);
+    }
+
+    pub fn ldh_a_a8(cpu: &mut CPU, memory_bus: &mut MemoryBus){
+        let offset: u8 = memory_bus.cpu_read(cpu.get_pc().wrapping_add(1));
+        let value: u8 = memory_bus.cpu_read(0xFF00 | offset as u16);
+        cpu.get_registers().set_a(value);
+        cpu.update_pc_and_cycles(cpu.get_pc().wrapping_add(2), 12);
+    }
+
+    pub fn ldh_c_a(cpu: &mut CPU, memory_bus: &mut MemoryBus){
+        let c: u8 = cpu.get_registers().get_c();
+        let a: u8 = cpu.get_registers().get_a();
+        memory_bus.cpu_write(0xFF00 | c as u16, a);
+        cpu.update_pc_and_cycles(cpu.get_pc().wrapping_add(1), 8);
+    }
+
+    pub fn ldh_a_c(cpu: &mut CPU, memory_bus: &mut MemoryBus){
+        let c: u8 = cpu.get_registers().get_c();
+        let value: u8 = memory_bus.cpu_read(0xFF00 | c as u16);
+        cpu.get_registers().set_a(value);
+        cpu.update_pc_and_cycles(cpu.get_pc().wrapping_add(1), 8);
+    }
+
+    pub fn ld_a16_a(cpu: &mut CPU, memory_bus: &mut MemoryBus){
+        let low_byte = memory_bus.cpu_read(cpu.get_pc().wrapping_add(1));
+        let high_byte = memory_bus.cpu_read(cpu.get_pc().wrapping_add(2));
+        let a: u8 = cpu.get_registers().get_a();
+        memory_bus.cpu_write(format_u16(high_byte, low_byte), a);
+        cpu.update_pc_and_cycles(cpu.get_pc().wrapping_add(3), 16);
+    }
+
+    pub fn ld_a_a16(cpu: &mut CPU, memory_bus: &mut MemoryBus){
+        let low_byte = memory_bus.cpu_read(cpu.get_pc().wrapping_add(1));
+        let high_byte = memory_bus.cpu_read(cpu.get_pc().wrapping_add(2));
+        let value: u8 = memory_bus.cpu_read(format_u16(high_byte, low_byte));
+        cpu.get_registers().set_a(value);
+        cpu.update_pc_and_cycles(cpu.get_pc().wrapping_add(3), 16);
+    }
+
+    pub fn ld_hl_sp_e8(cpu: &mut CPU, memory_bus: &mut MemoryBus){
+        let r: u16 = ALU::sp_plus_e8(cpu, memory_bus);
+        cpu.get_registers().set_hl(r);
+        cpu.update_pc_and_cycles(cpu.get_pc().wrapping_add(2), 12);
+    }
+
+    pub fn ld_sp_hl(cpu: &mut CPU){
+        let hl: u16 = cpu.get_registers().get_hl();
+        cpu.set_sp(hl);
+        cpu.update_pc_and_cycles(cpu.get_pc().wrapping_add(1), 8);
+    }
+
     pub fn ld_r8_r8_(cpu: &mut CPU, setter: fn(&mut Register, u8), getter: fn(&Register) -> u8){
         let value = {
             let registers = cpu.get_registers();
@@ -220,4 +277,57 @@ impl LD {
         setter(registers, value);
         cpu.update_pc_and_cycles(cpu.get_pc().wrapping_add(1), 4);
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn setup() -> (CPU, MemoryBus) {
+        let mut cpu: CPU = CPU::new();
+        cpu.set_sp(0xD000);
+        cpu.change_pc(0xC000);
+        (cpu, MemoryBus::new())
+    }
+
+    #[test]
+    fn ld_a16_sp_stores_low_byte_first() {
+        let (mut cpu, mut bus) = setup();
+        bus.write(0xC001, 0x00);
+        bus.write(0xC002, 0xC1);
+        cpu.set_sp(0xBEEF);
+        LD::ld_a16_sp(&mut cpu, &mut bus);
+        assert_eq!((bus.read(0xC100), bus.read(0xC101)), (0xEF, 0xBE));
+        assert_eq!((cpu.get_pc(), cpu.get_cycles()), (0xC003, 20));
+    }
+
+    #[test]
+    fn hl_moves_after_the_access() {
+        let (mut cpu, mut bus) = setup();
+        cpu.get_registers().set_hl(0xC100);
+        cpu.get_registers().set_a(0x42);
+        LD::ld_hl_plus_a(&mut cpu, &mut bus);
+        assert_eq!((bus.read(0xC100), cpu.get_registers().get_hl()), (0x42, 0xC101));
+        bus.write(0xC101, 0x24);
+        LD::ld_a_hl_minus(&mut cpu, &mut bus);
+        assert_eq!((cpu.get_registers().get_a(), cpu.get_registers().get_hl()), (0x24, 0xC100));
+    }
+
+    #[test]
+    fn register_indexes_follow_the_opcode_table() {
+        let (mut cpu, mut bus) = setup();
+        cpu.get_registers().set_hl(0xC100);
+        bus.write(0xC100, 0x99);
+        // LD E,(HL) then LD (HL),C
+        LD::ld_r_hl(&mut cpu, &mut bus, 3);
+        assert_eq!(cpu.get_registers().get_e(), 0x99);
+        cpu.get_registers().set_c(0x77);
+        LD::ld_hl_r(&mut cpu, &mut bus, 1);
+        assert_eq!(bus.read(0xC100), 0x77);
+        // LDH (C),A reaches the high page
+        cpu.get_registers().set_c(0x80);
+        cpu.get_registers().set_a(0x55);
+        LD::ldh_c_a(&mut cpu, &mut bus);
+        assert_eq!(bus.read(0xFF80), 0x55);
+        assert_eq!(cpu.get_cycles(), 24);
+    }
+}
@@ -1,4 +1,8 @@
 use crate::cpu::flag::Flag;
+use crate::error::state_error::StateError;
+use crate::state::snapshot::Snapshot;
+use crate::state::state_reader::StateReader;
+use crate::state::state_writer::StateWriter;
 use crate::utils::byte_utils::{format_u16, get_lsb_u16, get_msb_u16};
 
 pub struct Register {
@@ -35,23 +39,23 @@ impl Register {
     }
 
     pub fn get_c(&self) -> u8 {
-        self.b
+        self.c
     }
 
     pub fn get_d(&self) -> u8 {
-        self.b
+        self.d
     }
 
     pub fn get_e(&self) -> u8 {
-        self.b
+        self.e
     }
 
     pub fn get_h(&self) -> u8 {
-        self.b
+        self.h
     }
 
     pub fn get_l(&self) -> u8 {
-        self.b
+        self.l
     }
 
     pub fn get_hl(&self) -> u16 {
@@ -66,6 +70,10 @@ impl Register {
         &self.f
     }   
     
+    pub fn get_af(&self) -> u16 {
+        (self.a as u16) << 8 | self.f.get_byte() as u16
+    }
+
     pub fn get_bc(&self) -> u16 {
         format_u16(self.b, self.c)
     }
@@ -109,6 +117,11 @@ impl Register {
         self.l = low;
     }
     
+    pub fn set_af(&mut self, value: u16) {
+        self.a = (value >> 8) as u8;
+        self.f.set_byte(value as u8);
+    }
+
     pub fn set_bc(&mut self, value: u16) {
         let high: u8 = get_msb_u16(value);
         let low: u8 = get_lsb_u16(value);
@@ -125,4 +138,41 @@ impl Register {
     
     
     
-}
\ No newline at end of file
+}
+
+impl Snapshot for Register {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&[self.a, self.b, self.c, self.d, self.e, self.h, self.l]);
+        self.f.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        let mut values: [u8; 7] = [0; 7];
+        reader.read_bytes(&mut values)?;
+        [self.a, self.b, self.c, self.d, self.e, self.h, self.l] = values;
+        self.f.load_state(reader)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn af_keeps_the_low_nibble_of_f_clear() {
+        let mut register: Register = Register::new();
+        register.set_af(0x12FF);
+        assert_eq!(register.get_af(), 0x12F0);
+
+        let mut writer: StateWriter = StateWriter::new();
+        register.set_b(0x34);
+        register.save_state(&mut writer);
+        let mut bytes: Vec<u8> = writer.into_bytes();
+        assert_eq!(bytes[..2], [0x12, 0x34]);
+        // F comes last
+        *bytes.last_mut().unwrap() = 0xAF;
+        let mut restored: Register = Register::new();
+        restored.load_state(&mut StateReader::new(&bytes)).unwrap();
+        assert_eq!((restored.get_af(), restored.get_b()), (0x12A0, 0x34));
+    }
+}
@@ -0,0 +1,33 @@
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum GbsError {
+    Io(io::Error),
+    InvalidMagic,
+    UnsupportedVersion(u8),
+    // the file is shorter than the header
+    Truncated,
+    InvalidSong(u8),
+    // the init or play routine at this address was still running after a second
+    RoutineDidNotReturn(u16),
+}
+
+impl fmt::Display for GbsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GbsError::Io(e) => write!(f, "{}", e),
+            GbsError::InvalidMagic => write!(f, "not a GBS file"),
+            GbsError::UnsupportedVersion(version) => write!(f, "unsupported GBS version {}", version),
+            GbsError::Truncated => write!(f, "the file ends inside the header"),
+            GbsError::InvalidSong(song) => write!(f, "no song {}", *song as u16 + 1),
+            GbsError::RoutineDidNotReturn(address) => write!(f, "the routine at {:04X} did not return", address),
+        }
+    }
+}
+
+impl From<io::Error> for GbsError {
+    fn from(e: io::Error) -> GbsError {
+        GbsError::Io(e)
+    }
+}
@@ -1,4 +1,14 @@
+use std::fmt;
+
 #[derive(Debug)]
 pub enum MemoryError {
     InvalidAddress(u16)
-}
\ No newline at end of file
+}
+
+impl fmt::Display for MemoryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MemoryError::InvalidAddress(addr) => write!(f, "invalid address {:04X}", addr),
+        }
+    }
+}
@@ -1 +1,2 @@
-pub mod memory_error;
\ No newline at end of file
+pub mod memory_error;
+pub mod gbs_error;pub mod state_error;
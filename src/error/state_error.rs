@@ -0,0 +1,40 @@
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum StateError {
+    Io(io::Error),
+    InvalidMagic,
+    // the state was written by a newer version of the emulator
+    UnsupportedVersion(u32),
+    // the state belongs to another cartridge
+    RomMismatch { expected: u64, found: u64 },
+    MissingSection([u8; 4]),
+    // the title or checksum in the INFO block of a BESS state names another cartridge
+    CartridgeMismatch,
+    // the data ends before every field was read
+    Truncated,
+    InvalidValue(&'static str),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::Io(e) => write!(f, "{}", e),
+            StateError::InvalidMagic => write!(f, "not a save state"),
+            StateError::UnsupportedVersion(version) => write!(f, "written by a newer version (format {})", version),
+            StateError::RomMismatch { expected, found } =>
+                write!(f, "saved with another cartridge (ROM hash {:016X}, expected {:016X})", found, expected),
+            StateError::MissingSection(tag) => write!(f, "missing section {}", String::from_utf8_lossy(tag)),
+            StateError::CartridgeMismatch => write!(f, "saved with another cartridge"),
+            StateError::Truncated => write!(f, "the state ends early"),
+            StateError::InvalidValue(field) => write!(f, "invalid {}", field),
+        }
+    }
+}
+
+impl From<io::Error> for StateError {
+    fn from(e: io::Error) -> StateError {
+        StateError::Io(e)
+    }
+}
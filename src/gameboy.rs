@@ -1,25 +1,379 @@
+use std::io;
+
+use crate::apu::apu::APU_CLOCK;
+use crate::constants::io_registers::{BGP, BOOT, LCDC, NR50, NR51, NR52};
+use crate::constants::lcd::{DOTS_PER_LINE, LINES_PER_FRAME};
 use crate::cpu::cpu::CPU;
+use crate::error::state_error::StateError;
+use crate::infrared::ir_peer::IrPeer;
+use crate::memory_bus::joypad::{Button, Player};
 use crate::memory_bus::memory_bus::MemoryBus;
+use crate::model::Model;
+use crate::ppu::compat_palette::CompatPalette;
+use crate::ppu::ppu::RenderMode;
+use crate::serial::serial_peer::SerialPeer;
+use crate::state::save_state::SaveState;
 
 pub struct Gameboy {
     pub cpu: CPU,
-    pub memory_bus: MemoryBus
+    pub memory_bus: MemoryBus,
+    model: Model,
+    compat_palette: Option<CompatPalette>,
 }
 
 #[warn(unused_must_use)]
 impl Gameboy {
     pub fn new() -> Self{
-        Self{cpu: CPU::new(), memory_bus: MemoryBus::new()}
+        Self{cpu: CPU::new(), memory_bus: MemoryBus::new(), model: Model::DMG, compat_palette: None}
+    }
+
+    /// Selects the hardware to emulate, DMG by default. Takes effect on the next call to
+    /// `start`.
+    pub fn set_model(&mut self, model: Model) {
+        self.model = model;
     }
+
+    pub fn get_model(&self) -> Model {
+        self.model
+    }
+
+    /// Chooses how the PPU emulates mode 3, see [`RenderMode`].
+    pub fn set_render_mode(&mut self, render_mode: RenderMode) {
+        self.memory_bus.ppu.set_render_mode(render_mode);
+    }
+
+    /// Overrides the colorization palette used for DMG cartridges on a CGB. `None`
+    /// restores the choice of the boot ROM, see [`Gameboy::get_compat_palette`].
+    pub fn set_compat_palette(&mut self, palette: Option<CompatPalette>) {
+        self.compat_palette = palette;
+        if self.memory_bus.is_cgb_mode() || self.model != Model::CGB {
+            return;
+        }
+        let palette: CompatPalette = self.get_compat_palette();
+        self.memory_bus.set_dmg_compat(Some(palette));
+    }
+
+    /// Returns the colorization palette for the DMG cartridge: the override if there is
+    /// one, else the combination of the buttons held down (as holding them during the boot
+    /// logo does), else the palette the boot ROM looks up for the title.
+    pub fn get_compat_palette(&self) -> CompatPalette {
+        self.compat_palette
+            .or_else(|| CompatPalette::from_buttons(self.memory_bus.get_buttons()))
+            .unwrap_or_else(|| CompatPalette::select(&self.memory_bus.rom))
+    }
+
+    /// Reads a boot ROM dump to run before the cartridge. Must be called before `start`.
+    pub fn set_boot_rom(&mut self, path: &str) -> io::Result<()> {
+        let boot_rom: Vec<u8> = std::fs::read(path)?;
+        self.memory_bus.rom.set_boot_rom(boot_rom);
+        Ok(())
+    }
+
     pub fn start(&mut self, path: &str) {
         let r = self.memory_bus.rom.read(path);
         print!("ROM read result: ");
         match r {
-            Ok(_) => println!("Success"),
+            Ok(_) => {
+                println!("Success");
+                self.setup_model();
+                if !self.memory_bus.rom.has_boot_rom() {
+                    self.skip_boot();
+                }
+            }
             Err(e) => println!("Error: {}", e),
         }
     }
-    pub fn step(&mut self) {
+
+    /// Puts the machine in the state the boot ROM leaves it in when it jumps to the
+    /// cartridge at 0x0100.
+    pub fn skip_boot(&mut self) {
+        let (af, bc, de, hl): (u16, u16, u16, u16) = match self.model {
+            Model::DMG => (0x01B0, 0x0013, 0x00D8, 0x014D),
+            Model::CGB if self.memory_bus.is_cgb_mode() => (0x1180, 0x0000, 0xFF56, 0x000D),
+            Model::CGB => (0x1180, 0x0000, 0x0008, 0x007C),
+            Model::SGB => (0x0100, 0x0014, 0x0000, 0xC060),
+        };
+        let registers = self.cpu.get_registers();
+        registers.set_af(af);
+        registers.set_bc(bc);
+        registers.set_de(de);
+        registers.set_hl(hl);
+        self.cpu.set_sp(0xFFFE);
+        self.cpu.change_pc(0x0100);
+        for (addr, value) in [(NR52, 0x80), (NR51, 0xF3), (NR50, 0x77), (BGP, 0xFC), (LCDC, 0x91), (BOOT, 0x01)] {
+            self.memory_bus.write(addr, value);
+        }
+    }
+
+    /// Picks CGB mode, DMG compatibility or the Super Game Boy functions for the loaded
+    /// cartridge on the selected model, as the boot ROM would.
+    pub fn setup_model(&mut self) {
+        let cgb_rom: bool = self.memory_bus.rom.is_cgb();
+        match self.model {
+            Model::CGB if cgb_rom => {
+                self.memory_bus.set_cgb_mode(true);
+                self.memory_bus.set_dmg_compat(None);
+            }
+            Model::CGB => {
+                self.memory_bus.set_cgb_mode(false);
+                let palette: CompatPalette = self.get_compat_palette();
+                self.memory_bus.set_dmg_compat(Some(palette));
+            }
+            Model::DMG => {
+                self.memory_bus.set_cgb_mode(false);
+                self.memory_bus.set_dmg_compat(None);
+            }
+            Model::SGB => {
+                self.memory_bus.set_cgb_mode(false);
+                self.memory_bus.set_dmg_compat(None);
+                let sgb_rom: bool = self.memory_bus.rom.is_sgb();
+                self.memory_bus.set_sgb_mode(sgb_rom);
+            }
+        }
+    }
+
+    /// Writes a snapshot of the whole machine to `path` (see [`SaveState`]).
+    pub fn save_state(&self, path: &str) -> Result<(), StateError> {
+        SaveState::capture(self).write(path)
+    }
+
+    /// Restores a snapshot written by [`Gameboy::save_state`] with the same cartridge.
+    /// Peers, recordings and other host settings are kept.
+    pub fn load_state(&mut self, path: &str) -> Result<(), StateError> {
+        SaveState::read(path)?.restore(self)
+    }
+
+    /// Plugs `peer` into the link port (see [`crate::serial`] for the built-in ones).
+    pub fn set_serial_peer(&mut self, peer: Box<dyn SerialPeer>) {
+        self.memory_bus.get_serial_mut().set_peer(peer);
+    }
+
+    /// Returns the error that broke the link cable, once (see [`SerialPeer::take_error`]).
+    pub fn take_serial_error(&mut self) -> Option<io::Error> {
+        self.memory_bus.get_serial_mut().take_peer_error()
+    }
+
+    /// Puts `peer` in front of the infrared port of the CGB (see [`crate::infrared`]).
+    pub fn set_ir_peer(&mut self, peer: Box<dyn IrPeer>) {
+        self.memory_bus.get_infrared_mut().set_peer(peer);
+    }
+
+    /// Replaces the set of buttons held down.
+    pub fn set_buttons(&mut self, buttons: &[Button]) {
+        let pressed: u8 = buttons.iter().fold(0, |mask, button| mask | button.mask());
+        self.update_buttons(pressed);
+    }
+
+    /// Replaces the buttons held down on the joypad of `player`, for Super Game Boy games
+    /// reading several joypads.
+    pub fn set_player_buttons(&mut self, player: Player, buttons: &[Button]) {
+        if player == Player::One {
+            return self.set_buttons(buttons);
+        }
+        let pressed: u8 = buttons.iter().fold(0, |mask, button| mask | button.mask());
+        self.memory_bus.set_player_buttons(player, pressed);
+    }
+
+    /// Returns the 160×144 picture of the last frame as RGB555 colors (bits 0-4 red, 5-9
+    /// green, 10-14 blue), DMG shades included.
+    pub fn get_color_framebuffer(&self) -> &[u16] {
+        self.memory_bus.ppu.get_color_framebuffer()
+    }
+
+    /// Returns the 256×224 RGB555 picture with the Super Game Boy border and colors,
+    /// `None` unless an SGB game runs on the SGB model.
+    pub fn get_sgb_frame(&self) -> Option<Vec<u16>> {
+        self.memory_bus.get_sgb_frame()
+    }
+
+    pub fn press(&mut self, button: Button) {
+        let pressed: u8 = self.memory_bus.get_buttons() | button.mask();
+        self.update_buttons(pressed);
+    }
+
+    pub fn release(&mut self, button: Button) {
+        let pressed: u8 = self.memory_bus.get_buttons() & !button.mask();
+        self.update_buttons(pressed);
+    }
+
+    fn update_buttons(&mut self, pressed: u8) {
+        self.memory_bus.set_buttons(pressed);
+        // a low P1 line restarts the oscillator stopped by STOP
+        if !self.cpu.is_running() && self.memory_bus.is_joypad_line_low() {
+            self.cpu.set_running(true);
+        }
+    }
+
+    /// Runs a single CPU instruction (or a VRAM DMA stall) and the hardware alongside it.
+    ///
+    /// # Returns
+    /// The T-cycles that elapsed, 0 while the CPU is stopped waiting for a button.
+    pub fn step(&mut self) -> u64 {
+        // STOP halts the whole system clock until a button is pressed
+        if !self.cpu.is_running() {
+            return 0;
+        }
+        // the CPU does nothing while a VRAM DMA holds the bus
+        let stall: u64 = self.memory_bus.take_stall_cycles();
+        if stall > 0 {
+            self.memory_bus.tick(stall);
+            return stall;
+        }
+        self.memory_bus.set_cpu_halted(self.cpu.get_halt());
+        let before: u64 = self.cpu.get_cycles();
         self.cpu.step(&mut self.memory_bus);
+        let elapsed: u64 = self.cpu.get_cycles() - before;
+        // the memory accesses already ticked their M-cycles
+        let ticked: u64 = self.memory_bus.take_cpu_cycles();
+        self.memory_bus.tick(elapsed.saturating_sub(ticked));
+        elapsed
+    }
+
+    /// Runs the emulation for `seconds` of emulated time, or until the CPU is stopped.
+    pub fn run_for(&mut self, seconds: f64) {
+        let target: u64 = (seconds * APU_CLOCK as f64) as u64;
+        let mut elapsed: u64 = 0;
+        while elapsed < target {
+            let cycles: u64 = self.step();
+            if cycles == 0 {
+                break;
+            }
+            // in double speed the CPU runs twice as many cycles in the same time
+            elapsed += if self.memory_bus.is_double_speed() { cycles / 2 } else { cycles };
+        }
+    }
+
+    /// Changes the audio output rate, in samples per second (48 kHz by default).
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.memory_bus.get_apu_mut().set_sample_rate(sample_rate);
+    }
+
+    pub fn get_sample_rate(&self) -> u32 {
+        self.memory_bus.get_apu().get_sample_rate()
+    }
+
+    /// Returns the audio generated since the last call, as interleaved left/right samples
+    /// at the rate set with [`Gameboy::set_sample_rate`]. A frontend drains it to play it.
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        self.memory_bus.get_apu_mut().take_samples()
+    }
+
+    /// Runs the emulation until the PPU finishes a frame, when VBlank starts.
+    ///
+    /// # Returns
+    /// `true` once a new frame is in the framebuffer, `false` if none came within two frame
+    /// periods because the LCD is off or the CPU is stopped.
+    pub fn step_frame(&mut self) -> bool {
+        // a frame completed earlier is already on screen
+        self.memory_bus.ppu.take_frame_ready();
+        // the first frame after the LCD is turned on is not shown
+        let period: u64 = 2 * DOTS_PER_LINE as u64 * LINES_PER_FRAME as u64;
+        let mut elapsed: u64 = 0;
+        while elapsed < period {
+            let cycles: u64 = self.step();
+            if cycles == 0 {
+                break;
+            }
+            if self.memory_bus.ppu.take_frame_ready() {
+                return true;
+            }
+            elapsed += if self.memory_bus.is_double_speed() { cycles / 2 } else { cycles };
+        }
+        false
     }
-}
\ No newline at end of file
+
+    /// Starts recording the audio output to a WAV file.
+    ///
+    /// # Parameters
+    /// - `stems`: Also write one WAV per APU channel next to `path`.
+    pub fn start_recording(&mut self, path: &str, stems: bool) -> io::Result<()> {
+        self.memory_bus.get_apu_mut().start_recording(path, stems)
+    }
+
+    /// Finishes the audio recording started with [`Gameboy::start_recording`].
+    pub fn stop_recording(&mut self) -> io::Result<()> {
+        self.memory_bus.get_apu_mut().stop_recording()
+    }
+
+    /// Starts logging every sound register write to a VGM file.
+    pub fn start_vgm_logging(&mut self, path: &str) -> io::Result<()> {
+        self.memory_bus.get_apu_mut().start_vgm_logging(path)
+    }
+
+    /// Finishes the VGM log started with [`Gameboy::start_vgm_logging`].
+    pub fn stop_vgm_logging(&mut self) -> io::Result<()> {
+        self.memory_bus.get_apu_mut().stop_vgm_logging()
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::io_registers::LY;
+    use crate::constants::lcd::{DMG_GREYS, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+    #[test]
+    fn skip_boot_starts_the_cartridge() {
+        let mut gameboy: Gameboy = Gameboy::new();
+        gameboy.set_model(Model::DMG);
+        gameboy.setup_model();
+        gameboy.skip_boot();
+        assert_eq!(gameboy.cpu.get_pc(), 0x0100);
+        assert_eq!(gameboy.cpu.get_sp(), 0xFFFE);
+        assert_eq!(gameboy.cpu.read_registers().get_af(), 0x01B0);
+        assert_eq!(gameboy.memory_bus.read(BOOT), 0xFF);
+        assert_eq!(gameboy.memory_bus.read(LCDC), 0x91);
+    }
+
+    #[test]
+    fn step_frame_stops_at_vblank() {
+        let mut gameboy: Gameboy = Gameboy::new();
+        gameboy.setup_model();
+        gameboy.skip_boot();
+        assert!(gameboy.step_frame());
+        assert_eq!(gameboy.memory_bus.read(LY), 144);
+        assert!(gameboy.step_frame());
+        assert_eq!(gameboy.memory_bus.read(LY), 144);
+        // no frame comes with the LCD off
+        gameboy.memory_bus.write(LCDC, 0x11);
+        assert!(!gameboy.step_frame());
+    }
+
+    #[test]
+    fn color_framebuffer_holds_the_frame() {
+        let mut gameboy: Gameboy = Gameboy::new();
+        gameboy.setup_model();
+        gameboy.skip_boot();
+        // every color of the background palette is black
+        gameboy.memory_bus.write(BGP, 0xFF);
+        assert!(gameboy.step_frame());
+        let frame: &[u16] = gameboy.get_color_framebuffer();
+        assert_eq!(frame.len(), SCREEN_WIDTH * SCREEN_HEIGHT);
+        assert!(frame.iter().all(|color| *color == DMG_GREYS[3]));
+    }
+
+    #[test]
+    fn dmg_is_the_default_model() {
+        let mut gameboy: Gameboy = Gameboy::new();
+        assert_eq!(gameboy.get_model(), Model::DMG);
+        gameboy.setup_model();
+        assert!(!gameboy.memory_bus.is_cgb_mode());
+        assert!(!gameboy.memory_bus.is_dmg_compat());
+    }
+
+    #[test]
+    fn buttons_held_at_boot_pick_the_compat_palette() {
+        let mut gameboy: Gameboy = Gameboy::new();
+        gameboy.set_model(Model::CGB);
+        // POKEMON RED gets combination 13 from its title
+        gameboy.memory_bus.rom.bank0[0x0134..0x013F].copy_from_slice(b"POKEMON RED");
+        gameboy.memory_bus.rom.bank0[0x014B] = 0x01;
+        assert_eq!(gameboy.get_compat_palette().get_combination(), 13);
+        gameboy.set_buttons(&[Button::Left, Button::B]);
+        gameboy.setup_model();
+        assert!(gameboy.memory_bus.is_dmg_compat());
+        assert_eq!(gameboy.get_compat_palette().get_combination(), 7);
+        // an explicit palette wins over the buttons
+        gameboy.set_compat_palette(Some(CompatPalette::new(2)));
+        assert_eq!(gameboy.get_compat_palette().get_combination(), 2);
+    }
+}
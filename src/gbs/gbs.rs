@@ -0,0 +1,148 @@
+use std::fs;
+
+use crate::error::gbs_error::GbsError;
+
+pub const GBS_HEADER_SIZE: usize = 0x70;
+// T-cycles between two VBlank interrupts
+const VBLANK_PERIOD: u64 = 70224;
+
+/// A Game Boy Sound System rip: the music code and data of a game, plus a header telling
+/// where to load it and which routines to call.
+pub struct GBS {
+    pub song_count: u8,
+    // 1-based, as stored in the header
+    pub first_song: u8,
+    pub load_address: u16,
+    pub init_address: u16,
+    pub play_address: u16,
+    pub stack_pointer: u16,
+    pub timer_modulo: u8,
+    pub timer_control: u8,
+    pub title: String,
+    pub author: String,
+    pub copyright: String,
+    pub data: Vec<u8>,
+}
+
+impl GBS {
+    pub fn read(path: &str) -> Result<GBS, GbsError> {
+        GBS::parse(&fs::read(path)?)
+    }
+
+    /// Parses a whole `.gbs` file.
+    pub fn parse(bytes: &[u8]) -> Result<GBS, GbsError> {
+        if bytes.len() < GBS_HEADER_SIZE {
+            return Err(GbsError::Truncated);
+        }
+        if &bytes[0..3] != b"GBS" {
+            return Err(GbsError::InvalidMagic);
+        }
+        if bytes[3] != 1 {
+            return Err(GbsError::UnsupportedVersion(bytes[3]));
+        }
+        let word = |offset: usize| -> u16 { u16::from_le_bytes([bytes[offset], bytes[offset + 1]]) };
+        Ok(GBS {
+            song_count: bytes[4],
+            first_song: bytes[5],
+            load_address: word(0x06),
+            init_address: word(0x08),
+            play_address: word(0x0A),
+            stack_pointer: word(0x0C),
+            timer_modulo: bytes[0x0E],
+            timer_control: bytes[0x0F],
+            title: GBS::read_string(&bytes[0x10..0x30]),
+            author: GBS::read_string(&bytes[0x30..0x50]),
+            copyright: GBS::read_string(&bytes[0x50..0x70]),
+            data: bytes[GBS_HEADER_SIZE..].to_vec(),
+        })
+    }
+
+    fn read_string(bytes: &[u8]) -> String {
+        bytes.iter()
+            .take_while(|b| **b != 0)
+            .map(|b| *b as char)
+            .collect()
+    }
+
+    /// Returns `true` when the play routine is driven by the timer interrupt instead of
+    /// VBlank (TAC bit 2).
+    pub fn uses_timer(&self) -> bool {
+        self.timer_control & 0x04 != 0
+    }
+
+    /// Returns `true` when the rip needs the CGB double speed mode (TAC bit 7).
+    pub fn is_double_speed(&self) -> bool {
+        self.timer_control & 0x80 != 0
+    }
+
+    /// CPU T-cycles between two calls of the play routine.
+    ///
+    /// # Parameters
+    /// - `tma`, `tac`: Current timer registers; the music code is allowed to change them.
+    pub fn play_period(&self, tma: u8, tac: u8) -> u64 {
+        if !self.uses_timer() {
+            // the PPU doesn't speed up, so VBlank takes twice as many CPU cycles
+            return if self.is_double_speed() { VBLANK_PERIOD * 2 } else { VBLANK_PERIOD };
+        }
+        let divider: u64 = match tac & 0x03 {
+            0b00 => 1024,
+            0b01 => 16,
+            0b10 => 64,
+            _ => 256,
+        };
+        divider * (256 - tma as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header() -> Vec<u8> {
+        let mut bytes: Vec<u8> = vec![0; GBS_HEADER_SIZE];
+        bytes[0..4].copy_from_slice(b"GBS\x01");
+        bytes[4] = 12;
+        bytes[5] = 2;
+        bytes[0x06..0x10].copy_from_slice(&[0x00, 0x04, 0x00, 0x04, 0x03, 0x04, 0xFE, 0xFF, 0x00, 0x00]);
+        bytes[0x10..0x15].copy_from_slice(b"Title");
+        bytes[0x30..0x50].copy_from_slice(&[b'A'; 0x20]);
+        bytes
+    }
+
+    #[test]
+    fn header_fields_are_read() {
+        let mut bytes: Vec<u8> = header();
+        bytes.extend([0xC9, 0xC9]);
+        let gbs: GBS = GBS::parse(&bytes).unwrap();
+        assert_eq!((gbs.song_count, gbs.first_song), (12, 2));
+        assert_eq!((gbs.load_address, gbs.init_address, gbs.play_address), (0x0400, 0x0400, 0x0403));
+        assert_eq!(gbs.stack_pointer, 0xFFFE);
+        assert_eq!(gbs.title, "Title");
+        // a full field has no terminator
+        assert_eq!(gbs.author.len(), 0x20);
+        assert_eq!(gbs.copyright, "");
+        assert_eq!(gbs.data, vec![0xC9, 0xC9]);
+    }
+
+    #[test]
+    fn invalid_files_are_refused() {
+        assert!(matches!(GBS::parse(&header()[..GBS_HEADER_SIZE - 1]), Err(GbsError::Truncated)));
+        let mut bytes: Vec<u8> = header();
+        bytes[3] = 2;
+        assert!(matches!(GBS::parse(&bytes), Err(GbsError::UnsupportedVersion(2))));
+        bytes[0] = b'N';
+        assert!(matches!(GBS::parse(&bytes), Err(GbsError::InvalidMagic)));
+    }
+
+    #[test]
+    fn play_period_follows_the_timer() {
+        let mut gbs: GBS = GBS::parse(&header()).unwrap();
+        assert_eq!(gbs.play_period(0, 0), VBLANK_PERIOD);
+        gbs.timer_control = 0x80;
+        assert_eq!(gbs.play_period(0, 0), VBLANK_PERIOD * 2);
+        gbs.timer_control = 0x04;
+        // 16 cycles per tick, 0x100 - 0xC0 ticks
+        assert_eq!(gbs.play_period(0xC0, 0x05), 16 * 0x40);
+        assert_eq!(gbs.play_period(0x00, 0x04), 1024 * 0x100);
+    }
+}
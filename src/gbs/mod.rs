@@ -0,0 +1,2 @@
+pub mod gbs;
+pub mod player;
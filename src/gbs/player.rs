@@ -0,0 +1,260 @@
+use std::io;
+
+use crate::apu::apu::{APU, APU_CLOCK};
+use crate::constants::io_registers::{NR50, NR51, NR52, TAC, TMA};
+use crate::cpu::cpu::CPU;
+use crate::error::gbs_error::GbsError;
+use crate::gbs::gbs::GBS;
+use crate::memory_bus::memory_bus::MemoryBus;
+
+// fake return address pushed before calling init/play; reaching it means the call is over
+const RETURN_ADDRESS: u16 = 0xF000;
+// a routine still running after this many T-cycles is considered stuck and abandoned
+const CALL_TIMEOUT: u64 = APU_CLOCK;
+// largest chunk of time the hardware is advanced by while the CPU idles
+const IDLE_CYCLES: u64 = 64;
+
+/// Plays GBS rips through the CPU core and the APU.
+///
+/// Unlike [`crate::gameboy::Gameboy`] there is no cartridge boot: the music code is loaded
+/// at its load address, the PPU is not clocked, and the player itself calls the init
+/// routine once and the play routine at the rate given by the header (VBlank or timer).
+pub struct GbsPlayer {
+    pub cpu: CPU,
+    pub memory_bus: MemoryBus,
+    gbs: GBS,
+    song: u8,
+    // CPU T-cycles left until the next call of the play routine
+    until_play: u64,
+}
+
+impl GbsPlayer {
+    /// Loads `gbs` and initializes its first song.
+    pub fn new(gbs: GBS) -> Result<GbsPlayer, GbsError> {
+        let song: u8 = gbs.first_song.max(1) - 1;
+        let mut player: GbsPlayer = GbsPlayer {
+            cpu: CPU::new(),
+            memory_bus: MemoryBus::new(),
+            gbs,
+            song,
+            until_play: 0,
+        };
+        player.select_song(song)?;
+        Ok(player)
+    }
+
+    /// Returns the current song (0-based).
+    pub fn get_song(&self) -> u8 {
+        self.song
+    }
+
+    pub fn get_apu_mut(&mut self) -> &mut APU {
+        self.memory_bus.get_apu_mut()
+    }
+
+    /// Resets the machine and runs the init routine for `song` (0-based).
+    pub fn select_song(&mut self, song: u8) -> Result<(), GbsError> {
+        if song >= self.gbs.song_count {
+            return Err(GbsError::InvalidSong(song));
+        }
+        self.song = song;
+        // keep the audio settings (and a recording in progress) across songs
+        let apu: APU = std::mem::replace(self.memory_bus.get_apu_mut(), APU::new());
+        self.cpu = CPU::new();
+        self.memory_bus = MemoryBus::new();
+        *self.memory_bus.get_apu_mut() = apu;
+        self.memory_bus.set_ppu_enabled(false);
+        self.memory_bus.rom.load_gbs(&self.gbs.data, self.gbs.load_address);
+        if self.gbs.is_double_speed() {
+            self.memory_bus.set_cgb_mode(true);
+            self.memory_bus.switch_speed();
+        }
+
+        // power cycling the APU silences whatever the previous song left playing
+        self.memory_bus.write(NR52, 0x00);
+        self.memory_bus.write(NR52, 0x80);
+        self.memory_bus.write(NR51, 0xFF);
+        self.memory_bus.write(NR50, 0x77);
+        self.memory_bus.write(TMA, self.gbs.timer_modulo);
+        self.memory_bus.write(TAC, self.gbs.timer_control & 0x07);
+
+        self.cpu.set_sp(self.gbs.stack_pointer);
+        self.cpu.get_registers().set_a(song);
+        let cycles: u64 = self.call(self.gbs.init_address)?;
+        self.until_play = self.play_period().saturating_sub(cycles);
+        Ok(())
+    }
+
+    fn play_period(&self) -> u64 {
+        let tma: u8 = self.memory_bus.read(TMA);
+        let tac: u8 = self.memory_bus.read(TAC);
+        self.gbs.play_period(tma, tac)
+    }
+
+    /// Pushes [`RETURN_ADDRESS`] and runs the routine at `address` until it returns.
+    ///
+    /// # Returns
+    /// The T-cycles the routine took, or an error if it was still running after
+    /// [`CALL_TIMEOUT`].
+    fn call(&mut self, address: u16) -> Result<u64, GbsError> {
+        let sp: u16 = self.cpu.get_sp().wrapping_sub(2);
+        self.memory_bus.write(sp, RETURN_ADDRESS as u8);
+        self.memory_bus.write(sp.wrapping_add(1), (RETURN_ADDRESS >> 8) as u8);
+        self.cpu.set_sp(sp);
+        self.cpu.change_pc(address);
+
+        let mut elapsed: u64 = 0;
+        while self.cpu.get_pc() != RETURN_ADDRESS && elapsed < CALL_TIMEOUT {
+            elapsed += self.step();
+        }
+        if self.cpu.get_pc() != RETURN_ADDRESS {
+            return Err(GbsError::RoutineDidNotReturn(address));
+        }
+        Ok(elapsed)
+    }
+
+    fn step(&mut self) -> u64 {
+        let stall: u64 = self.memory_bus.take_stall_cycles();
+        if stall > 0 {
+            self.memory_bus.tick(stall);
+            return stall;
+        }
+        let before: u64 = self.cpu.get_cycles();
+        self.cpu.step(&mut self.memory_bus);
+        let elapsed: u64 = self.cpu.get_cycles() - before;
+        // the memory accesses already ticked their M-cycles
+        let ticked: u64 = self.memory_bus.take_cpu_cycles();
+        self.memory_bus.tick(elapsed.saturating_sub(ticked));
+        elapsed
+    }
+
+    /// Plays the current song for `seconds` of emulated time.
+    pub fn run_for(&mut self, seconds: f64) -> Result<(), GbsError> {
+        let speed: u64 = if self.gbs.is_double_speed() { 2 } else { 1 };
+        let target: u64 = (seconds * (APU_CLOCK * speed) as f64) as u64;
+        let mut elapsed: u64 = 0;
+        while elapsed < target {
+            if self.until_play == 0 {
+                let cycles: u64 = self.call(self.gbs.play_address)?;
+                elapsed += cycles;
+                self.until_play = self.play_period().saturating_sub(cycles);
+            } else {
+                // the CPU waits for the next interrupt, only the hardware runs
+                let cycles: u64 = self.until_play.min(IDLE_CYCLES);
+                self.memory_bus.tick(cycles);
+                self.until_play -= cycles;
+                elapsed += cycles;
+            }
+        }
+        Ok(())
+    }
+
+    /// Starts recording the audio output to a WAV file (see [`APU::start_recording`]).
+    pub fn start_recording(&mut self, path: &str, stems: bool) -> io::Result<()> {
+        self.get_apu_mut().start_recording(path, stems)
+    }
+
+    pub fn stop_recording(&mut self) -> io::Result<()> {
+        self.get_apu_mut().stop_recording()
+    }
+
+    /// Starts logging sound register writes to a VGM file (see [`APU::start_vgm_logging`]).
+    pub fn start_vgm_logging(&mut self, path: &str) -> io::Result<()> {
+        self.get_apu_mut().start_vgm_logging(path)
+    }
+
+    pub fn stop_vgm_logging(&mut self) -> io::Result<()> {
+        self.get_apu_mut().stop_vgm_logging()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::io_registers::{NR12, NR52};
+    use crate::gbs::gbs::GBS_HEADER_SIZE;
+
+    const LOAD_ADDRESS: u16 = 0x0400;
+    const PLAY_ADDRESS: u16 = 0x0420;
+
+    /// A rip loaded at [`LOAD_ADDRESS`], whose init routine is there and play routine at
+    /// [`PLAY_ADDRESS`].
+    fn build_gbs_with(code: &[u8]) -> GBS {
+        let mut bytes: Vec<u8> = vec![0; GBS_HEADER_SIZE];
+        bytes[0..4].copy_from_slice(b"GBS\x01");
+        bytes[4] = 1;
+        bytes[5] = 1;
+        bytes[0x06..0x08].copy_from_slice(&LOAD_ADDRESS.to_le_bytes());
+        bytes[0x08..0x0A].copy_from_slice(&LOAD_ADDRESS.to_le_bytes());
+        bytes[0x0A..0x0C].copy_from_slice(&PLAY_ADDRESS.to_le_bytes());
+        bytes[0x0C..0x0E].copy_from_slice(&0xDFFFu16.to_le_bytes());
+        bytes.extend_from_slice(code);
+        GBS::parse(&bytes).expect("Valid GBS")
+    }
+
+    /// A rip whose init routine starts channel 1 through a subroutine call and returns.
+    fn build_gbs() -> GBS {
+        let mut code: Vec<u8> = vec![
+            0xCD, 0x10, 0x04, // CALL 0x0410
+            0xC9,             // RET
+        ];
+        code.resize(0x10, 0x00);
+        code.extend_from_slice(&[
+            0x3E, 0x80, 0xE0, 0x11, // NR11 = 0x80
+            0x3E, 0xF0, 0xE0, 0x12, // NR12 = 0xF0
+            0x3E, 0x00, 0xE0, 0x13, // NR13 = 0x00
+            0x3E, 0x87, 0xE0, 0x14, // NR14 = 0x87, trigger
+            0xC9,                   // RET
+        ]);
+        code.resize(0x20, 0x00);
+        code.push(0xC9); // play: RET
+        build_gbs_with(&code)
+    }
+
+    #[test]
+    fn init_routine_returns_and_starts_a_channel() {
+        let player: GbsPlayer = GbsPlayer::new(build_gbs()).expect("Song 0 exists");
+        assert_eq!(player.cpu.get_pc(), RETURN_ADDRESS);
+        assert_eq!(player.cpu.get_sp(), 0xDFFF);
+        assert_eq!(player.memory_bus.read(NR12), 0xF0);
+        assert_eq!(player.memory_bus.read(NR52) & 0x01, 0x01);
+    }
+
+    #[test]
+    fn renders_audio() {
+        let mut player: GbsPlayer = GbsPlayer::new(build_gbs()).expect("Song 0 exists");
+        player.run_for(0.05).unwrap();
+        let samples: Vec<f32> = player.get_apu_mut().take_samples();
+        assert!(!samples.is_empty());
+        assert!(samples.iter().any(|sample| sample.abs() > 0.01));
+    }
+
+    #[test]
+    fn rst_jumps_relative_to_the_load_address() {
+        let mut code: Vec<u8> = vec![
+            0xDF, // RST 0x18
+            0xC9, // RET
+        ];
+        code.resize(0x18, 0x00);
+        code.extend_from_slice(&[
+            0x3E, 0x42, 0xE0, 0x80, // (0xFF80) = 0x42
+            0xC9,                   // RET
+        ]);
+        let player: GbsPlayer = GbsPlayer::new(build_gbs_with(&code)).expect("Init returns");
+        assert_eq!(player.cpu.get_pc(), RETURN_ADDRESS);
+        assert_eq!(player.memory_bus.read(0xFF80), 0x42);
+    }
+
+    #[test]
+    fn stuck_routine() {
+        // init: JR -2
+        let result = GbsPlayer::new(build_gbs_with(&[0x18, 0xFE]));
+        assert!(matches!(result, Err(GbsError::RoutineDidNotReturn(LOAD_ADDRESS))));
+    }
+
+    #[test]
+    fn invalid_song() {
+        let mut player: GbsPlayer = GbsPlayer::new(build_gbs()).expect("Song 0 exists");
+        assert!(matches!(player.select_song(1), Err(GbsError::InvalidSong(1))));
+    }
+}
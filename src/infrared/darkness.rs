@@ -0,0 +1,12 @@
+use crate::infrared::ir_peer::IrPeer;
+
+/// Nothing in front of the port: the LED goes nowhere and no light is ever received.
+pub struct Darkness;
+
+impl IrPeer for Darkness {
+    fn set_led(&mut self, _on: bool) {}
+
+    fn is_emitting(&self) -> bool {
+        false
+    }
+}
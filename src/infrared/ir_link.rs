@@ -0,0 +1,62 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::infrared::ir_peer::IrPeer;
+
+/// One of two Game Boy Colors in the same process with their IR ports facing each other.
+///
+/// Each side sees the LED of the other one. The light travels instantly, so both sides
+/// must be stepped in lockstep, for example as the two ends of a
+/// [`crate::serial::link_cable::LinkCable`].
+pub struct IrPort {
+    leds: Rc<RefCell<[bool; 2]>>,
+    side: usize,
+}
+
+impl IrPort {
+    /// Returns both ends, to plug into each Game Boy.
+    pub fn pair() -> (IrPort, IrPort) {
+        let leds: Rc<RefCell<[bool; 2]>> = Rc::new(RefCell::new([false; 2]));
+        (IrPort { leds: leds.clone(), side: 0 }, IrPort { leds, side: 1 })
+    }
+}
+
+impl IrPeer for IrPort {
+    fn set_led(&mut self, on: bool) {
+        self.leds.borrow_mut()[self.side] = on;
+    }
+
+    fn is_emitting(&self) -> bool {
+        self.leds.borrow()[1 - self.side]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::io_registers::RP;
+    use crate::memory_bus::bus::BUS;
+    use crate::memory_bus::infrared::Infrared;
+
+    #[test]
+    fn led_of_one_side_reaches_the_other() {
+        let (first_port, second_port) = IrPort::pair();
+        let mut first: Infrared = Infrared::new();
+        let mut second: Infrared = Infrared::new();
+        first.set_peer(Box::new(first_port));
+        second.set_peer(Box::new(second_port));
+
+        first.write(RP, 0x01).unwrap();
+        // light is only reported with both read enable bits set
+        second.write(RP, 0x40).unwrap();
+        assert_eq!(second.read(RP).unwrap(), 0x42);
+        second.write(RP, 0xC0).unwrap();
+        assert_eq!(second.read(RP).unwrap(), 0xC0);
+        // the LED of a side doesn't shine on its own photodiode
+        first.write(RP, 0xC1).unwrap();
+        assert_eq!(first.read(RP).unwrap(), 0xC3);
+
+        first.write(RP, 0xC0).unwrap();
+        assert_eq!(second.read(RP).unwrap(), 0xC2);
+    }
+}
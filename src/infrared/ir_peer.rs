@@ -0,0 +1,20 @@
+/// Whatever faces the infrared port of a Game Boy Color.
+///
+/// The port is a LED the program turns on and off through RP bit 0, and a photodiode
+/// reporting whether some light is received. Times are counted in 4194304 Hz cycles, so
+/// they don't depend on the CGB speed mode.
+pub trait IrPeer {
+    /// Called whenever the program switches the LED.
+    fn set_led(&mut self, on: bool);
+
+    /// Returns `true` while the peer shines light on the photodiode.
+    fn is_emitting(&self) -> bool;
+
+    /// Called on every tick, for peers that keep their own notion of time.
+    ///
+    /// # Parameters
+    /// - `elapsed`: Time since the previous call.
+    fn tick(&mut self, elapsed: u64) {
+        let _ = elapsed;
+    }
+}
@@ -0,0 +1,5 @@
+pub mod darkness;
+pub mod ir_link;
+pub mod ir_peer;
+pub mod playback;
+pub mod recorder;
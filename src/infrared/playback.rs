@@ -0,0 +1,107 @@
+use std::fs;
+use std::io;
+
+use crate::infrared::ir_peer::IrPeer;
+
+/// The light is on or off for `cycles` 4194304 Hz cycles.
+#[derive(Clone, Copy)]
+pub struct IrPulse {
+    pub on: bool,
+    pub cycles: u64,
+}
+
+/// Plays a recorded signal back into the photodiode, ignoring the LED of the Game Boy.
+/// The port stays dark once the recording is over.
+pub struct IrPlayback {
+    pulses: Vec<IrPulse>,
+    index: usize,
+    // time left in the current pulse
+    left: u64,
+}
+
+impl IrPlayback {
+    pub fn new(pulses: Vec<IrPulse>) -> IrPlayback {
+        let left: u64 = pulses.first().map_or(0, |pulse| pulse.cycles);
+        IrPlayback { pulses, index: 0, left }
+    }
+
+    /// Loads a recording saved by [`crate::infrared::recorder::IrRecorder::save`]: one
+    /// `on <cycles>` or `off <cycles>` line per pulse.
+    pub fn load(path: &str) -> io::Result<IrPlayback> {
+        let text: String = fs::read_to_string(path)?;
+        let mut pulses: Vec<IrPulse> = Vec::new();
+        for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            let invalid = || io::Error::new(io::ErrorKind::InvalidData, format!("invalid IR pulse: {}", line));
+            let (level, cycles) = line.split_once(' ').ok_or_else(invalid)?;
+            let on: bool = match level {
+                "on" => true,
+                "off" => false,
+                _ => return Err(invalid()),
+            };
+            let cycles: u64 = cycles.trim().parse().map_err(|_| invalid())?;
+            pulses.push(IrPulse { on, cycles });
+        }
+        Ok(IrPlayback::new(pulses))
+    }
+
+    /// Returns `true` once every pulse was played.
+    pub fn is_finished(&self) -> bool {
+        self.index >= self.pulses.len()
+    }
+}
+
+impl IrPeer for IrPlayback {
+    fn set_led(&mut self, _on: bool) {}
+
+    fn is_emitting(&self) -> bool {
+        self.pulses.get(self.index).is_some_and(|pulse| pulse.on)
+    }
+
+    fn tick(&mut self, elapsed: u64) {
+        let mut elapsed: u64 = elapsed;
+        while !self.is_finished() && elapsed >= self.left {
+            elapsed -= self.left;
+            self.index += 1;
+            self.left = self.pulses.get(self.index).map_or(0, |pulse| pulse.cycles);
+        }
+        if !self.is_finished() {
+            self.left -= elapsed;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pulses_end_on_their_boundaries() {
+        let mut playback: IrPlayback = IrPlayback::new(vec![
+            IrPulse { on: true, cycles: 100 },
+            IrPulse { on: false, cycles: 50 },
+            IrPulse { on: true, cycles: 10 },
+        ]);
+        assert!(playback.is_emitting());
+        playback.tick(99);
+        assert!(playback.is_emitting());
+        playback.tick(1);
+        assert!(!playback.is_emitting());
+        // a tick may cross several pulses
+        playback.tick(55);
+        assert!(playback.is_emitting());
+        assert!(!playback.is_finished());
+        playback.tick(5);
+        assert!(playback.is_finished());
+        assert!(!playback.is_emitting());
+        playback.tick(1000);
+        assert!(!playback.is_emitting());
+    }
+
+    #[test]
+    fn empty_recording_stays_dark() {
+        let mut playback: IrPlayback = IrPlayback::new(Vec::new());
+        assert!(playback.is_finished());
+        playback.tick(10);
+        assert!(!playback.is_emitting());
+    }
+}
@@ -0,0 +1,119 @@
+use std::cell::RefCell;
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::rc::Rc;
+
+use crate::infrared::ir_peer::IrPeer;
+use crate::infrared::playback::IrPulse;
+
+struct Recording {
+    pulses: Vec<IrPulse>,
+    led: bool,
+    // time since the LED last changed
+    elapsed: u64,
+}
+
+/// Records the LED of the Game Boy as a list of pulses, for [`crate::infrared::playback::IrPlayback`].
+/// Receives no light.
+///
+/// Clones share the same recording: keep one to save it after plugging the other in.
+#[derive(Clone)]
+pub struct IrRecorder {
+    recording: Rc<RefCell<Recording>>,
+}
+
+impl IrRecorder {
+    pub fn new() -> IrRecorder {
+        IrRecorder { recording: Rc::new(RefCell::new(Recording { pulses: Vec::new(), led: false, elapsed: 0 })) }
+    }
+
+    /// Returns the pulses recorded so far, including the current one.
+    pub fn get_pulses(&self) -> Vec<IrPulse> {
+        let recording = self.recording.borrow();
+        let mut pulses: Vec<IrPulse> = recording.pulses.clone();
+        if recording.elapsed > 0 {
+            pulses.push(IrPulse { on: recording.led, cycles: recording.elapsed });
+        }
+        pulses
+    }
+
+    /// Writes the recording to `path`, one `on <cycles>` or `off <cycles>` line per pulse.
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        let mut writer: BufWriter<File> = BufWriter::new(File::create(path)?);
+        for pulse in self.get_pulses() {
+            writeln!(writer, "{} {}", if pulse.on { "on" } else { "off" }, pulse.cycles)?;
+        }
+        writer.flush()
+    }
+}
+
+impl IrPeer for IrRecorder {
+    fn set_led(&mut self, on: bool) {
+        let mut recording = self.recording.borrow_mut();
+        if recording.led == on {
+            return;
+        }
+        if recording.elapsed > 0 {
+            let pulse: IrPulse = IrPulse { on: recording.led, cycles: recording.elapsed };
+            recording.pulses.push(pulse);
+        }
+        recording.led = on;
+        recording.elapsed = 0;
+    }
+
+    fn is_emitting(&self) -> bool {
+        false
+    }
+
+    fn tick(&mut self, elapsed: u64) {
+        self.recording.borrow_mut().elapsed += elapsed;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infrared::playback::IrPlayback;
+
+    #[test]
+    fn saved_recording_plays_back() {
+        let recorder: IrRecorder = IrRecorder::new();
+        let mut peer: IrRecorder = recorder.clone();
+        peer.tick(30);
+        peer.set_led(true);
+        peer.tick(20);
+        // switching to the same level doesn't split the pulse
+        peer.set_led(true);
+        peer.tick(20);
+        peer.set_led(false);
+        peer.tick(5);
+        let pulses: Vec<(bool, u64)> = recorder.get_pulses().iter().map(|pulse| (pulse.on, pulse.cycles)).collect();
+        assert_eq!(pulses, [(false, 30), (true, 40), (false, 5)]);
+
+        let path = std::env::temp_dir().join(format!("rustyboy-ir-{}.txt", std::process::id()));
+        let path: &str = path.to_str().unwrap();
+        recorder.save(path).unwrap();
+        let mut playback: IrPlayback = IrPlayback::load(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert!(!playback.is_emitting());
+        playback.tick(30);
+        assert!(playback.is_emitting());
+        playback.tick(39);
+        assert!(playback.is_emitting());
+        playback.tick(1);
+        assert!(!playback.is_emitting());
+        playback.tick(5);
+        assert!(playback.is_finished());
+    }
+
+    #[test]
+    fn invalid_lines_are_rejected() {
+        let path = std::env::temp_dir().join(format!("rustyboy-ir-invalid-{}.txt", std::process::id()));
+        let path: &str = path.to_str().unwrap();
+        std::fs::write(path, "on 10\nblink 3\n").unwrap();
+        let e = IrPlayback::load(path).err().unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+}
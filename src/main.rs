@@ -1,20 +1,398 @@
+// components are named after the hardware blocks (CPU, PPU, VRAM), in modules of the same name
+#![allow(clippy::upper_case_acronyms, clippy::module_inception)]
+
 mod gameboy;
 mod memory_bus;
 mod cpu;
 mod constants;
 mod error;
 mod utils;
+mod ppu;
+mod model;
+mod apu;
+mod cli;
+mod gbs;
+mod serial;
+mod infrared;
+mod sgb;
+mod state;
+
+use std::time::Duration;
 
+use cli::{Options, USAGE};
+use constants::lcd::{SCREEN_HEIGHT, SCREEN_WIDTH};
 use gameboy::Gameboy;
+use error::gbs_error::GbsError;
+use gbs::gbs::GBS;
+use gbs::player::GbsPlayer;
+use infrared::ir_link::IrPort;
+use infrared::playback::IrPlayback;
+use infrared::recorder::IrRecorder;
+use memory_bus::joypad::{Button, Player};
+use model::Model;
+use ppu::compat_palette::CompatPalette;
+use ppu::ppu::RenderMode;
+use serial::capture::SerialCapture;
+use serial::four_player_adapter::FourPlayerAdapter;
+use serial::link_cable::LinkCable;
+use serial::loopback::Loopback;
+use serial::printer::{PrintedPage, Printer};
+use serial::tcp_link::TcpLink;
+use sgb::sgb::{SGB_HEIGHT, SGB_WIDTH};
+use utils::bmp::{rgb555_to_rgb, write_bmp};
 
-fn main() {
-    let mut gameboy: Gameboy = Gameboy::new();
-    gameboy.start("cpu_instrs/cpu_instrs.gb");
-    for i in 0..0x4000 {
-        print!("{:02X} ", gameboy.memory_bus.rom.bank1[i]);
-        if (i + 1) % 16 == 0 {
-            println!();            
+/// Renders a song of a GBS file to WAV and/or VGM, without any video.
+fn render_gbs(options: &Options) {
+    let result = GBS::read(&options.rom).and_then(|gbs| {
+        println!("{} - {} ({}), {} songs", gbs.title, gbs.author, gbs.copyright, gbs.song_count);
+        let mut player: GbsPlayer = GbsPlayer::new(gbs)?;
+        if let Some(track) = options.track {
+            player.select_song(track - 1)?;
+        }
+        if let Some(sample_rate) = options.sample_rate {
+            player.get_apu_mut().set_sample_rate(sample_rate);
+        }
+        if let Some(wav) = options.wav.as_deref() {
+            player.start_recording(wav, options.stems)?;
+        }
+        if let Some(vgm) = options.vgm.as_deref() {
+            player.start_vgm_logging(vgm)?;
+        }
+        // the recordings are finished even when a routine gets stuck
+        let result: Result<(), GbsError> = player.run_for(options.seconds);
+        player.stop_recording()?;
+        player.stop_vgm_logging()?;
+        result.map(|_| player.get_song())
+    });
+    match result {
+        Ok(song) => println!("Song {} rendered", song + 1),
+        Err(e) => {
+            eprintln!("Could not render {}: {}", options.rom, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// What runs for `--seconds`: the Game Boy, both ends of `--link-local`, or the Game Boys
+/// plugged into the DMG-07 of `--four-player`.
+trait Machine {
+    fn run_for(&mut self, seconds: f64);
+
+    /// Returns the Game Boy taking the buttons of `--press` and recording the audio.
+    fn get_gameboy_mut(&mut self) -> &mut Gameboy;
+}
+
+impl Machine for Gameboy {
+    fn run_for(&mut self, seconds: f64) {
+        Gameboy::run_for(self, seconds);
+    }
+
+    fn get_gameboy_mut(&mut self) -> &mut Gameboy {
+        self
+    }
+}
+
+impl Machine for LinkCable {
+    fn run_for(&mut self, seconds: f64) {
+        LinkCable::run_for(self, seconds);
+    }
+
+    fn get_gameboy_mut(&mut self) -> &mut Gameboy {
+        &mut self.first
+    }
+}
+
+impl Machine for FourPlayerAdapter {
+    fn run_for(&mut self, seconds: f64) {
+        FourPlayerAdapter::run_for(self, seconds);
+    }
+
+    fn get_gameboy_mut(&mut self) -> &mut Gameboy {
+        self.get_mut(0)
+    }
+}
+
+/// Runs the cartridge for the requested time while capturing its audio, if asked to.
+///
+/// # Returns
+/// The peak level of the audio output.
+fn record<M: Machine>(machine: &mut M, options: &Options) -> std::io::Result<f32> {
+    let gameboy: &mut Gameboy = machine.get_gameboy_mut();
+    if let Some(sample_rate) = options.sample_rate {
+        gameboy.set_sample_rate(sample_rate);
+    }
+    if let Some(wav) = options.wav.as_deref() {
+        gameboy.start_recording(wav, options.stems)?;
+    }
+    if let Some(vgm) = options.vgm.as_deref() {
+        gameboy.start_vgm_logging(vgm)?;
+    }
+    let peak: f32 = run(machine, options);
+    let gameboy: &mut Gameboy = machine.get_gameboy_mut();
+    gameboy.stop_recording()?;
+    gameboy.stop_vgm_logging()?;
+    Ok(peak)
+}
+
+/// Runs the cartridge for `--seconds`, pressing and releasing the buttons of `--press` on
+/// time.
+///
+/// # Returns
+/// The peak level of the audio output.
+fn run<M: Machine>(machine: &mut M, options: &Options) -> f32 {
+    let mut events: Vec<(f64, Player, Button, bool)> = Vec::new();
+    for press in options.presses.iter() {
+        events.push((press.from, press.player, press.button, true));
+        if let Some(until) = press.until {
+            events.push((until, press.player, press.button, false));
         }
     }
+    events.sort_by(|a, b| a.0.total_cmp(&b.0));
+    // buttons held on the joypads read after MLT_REQ
+    let mut held: [Vec<Button>; 4] = Default::default();
+    let mut now: f64 = 0.0;
+    let mut peak: f32 = 0.0;
+    for (time, player, button, down) in events.into_iter().filter(|event| event.0 < options.seconds) {
+        peak = peak.max(run_for(machine, time - now));
+        now = time;
+        let gameboy: &mut Gameboy = machine.get_gameboy_mut();
+        match (player, down) {
+            (Player::One, true) => gameboy.press(button),
+            (Player::One, false) => gameboy.release(button),
+            _ => {
+                let buttons: &mut Vec<Button> = &mut held[player.index()];
+                buttons.retain(|held| *held != button);
+                if down {
+                    buttons.push(button);
+                }
+                gameboy.set_player_buttons(player, buttons);
+            }
+        }
+    }
+    peak.max(run_for(machine, options.seconds - now))
 }
 
+/// Runs the machine a second at a time, taking the audio output of the first Game Boy as it
+/// goes.
+///
+/// # Returns
+/// The peak level of the audio output.
+fn run_for<M: Machine>(machine: &mut M, seconds: f64) -> f32 {
+    let mut peak: f32 = 0.0;
+    let mut left: f64 = seconds;
+    while left > 0.0 {
+        machine.run_for(left.min(1.0));
+        left -= 1.0;
+        let samples: Vec<f32> = machine.get_gameboy_mut().take_samples();
+        peak = samples.iter().fold(peak, |peak, sample| peak.max(sample.abs()));
+    }
+    peak
+}
+
+/// Plugs the TCP link cable requested on the command line, waiting for the other side.
+fn connect_link(gameboy: &mut Gameboy, options: &Options) -> std::io::Result<()> {
+    let mut link: TcpLink = match (options.link_listen.as_deref(), options.link_connect.as_deref()) {
+        (Some(addr), _) => {
+            println!("Waiting for the other emulator on {}", addr);
+            TcpLink::listen(addr)?
+        }
+        (_, Some(addr)) => TcpLink::connect(addr)?,
+        _ => return Ok(()),
+    };
+    if let Some(tolerance) = options.link_tolerance {
+        link.set_tolerance(tolerance);
+    }
+    if let Some(timeout) = options.link_timeout {
+        link.set_timeout(Duration::from_secs_f64(timeout));
+    }
+    println!("Link cable connected, {} cycles of tolerance", link.get_tolerance());
+    gameboy.set_serial_peer(Box::new(link));
+    Ok(())
+}
+
+fn main() {
+    let options: Options = match Options::parse(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            std::process::exit(1);
+        }
+    };
+    if options.is_gbs() {
+        render_gbs(&options);
+        return;
+    }
+    let mut gameboy: Box<Gameboy> = Box::new(Gameboy::new());
+    gameboy.set_model(options.model);
+    gameboy.set_compat_palette(options.palette.map(CompatPalette::new));
+    if let Some(path) = options.boot_rom.as_deref() {
+        if let Err(e) = gameboy.set_boot_rom(path) {
+            eprintln!("Could not read the boot ROM {}: {}", path, e);
+            std::process::exit(1);
+        }
+    }
+    if options.pixel_fifo {
+        gameboy.set_render_mode(RenderMode::PixelFifo);
+    }
+    // buttons held at power on select the colorization of DMG cartridges on a CGB
+    for press in options.presses.iter().filter(|press| press.player == Player::One && press.from == 0.0) {
+        gameboy.press(press.button);
+    }
+    gameboy.start(&options.rom);
+    if gameboy.memory_bus.is_dmg_compat() {
+        println!("Colorization palette: {}", gameboy.get_compat_palette().get_combination());
+    }
+    if let Err(e) = connect_link(&mut gameboy, &options) {
+        eprintln!("Could not connect the link cable: {}", e);
+        std::process::exit(1);
+    }
+    let printer: Option<Printer> = options.printer.as_deref().map(|prefix| {
+        let printer: Printer = Printer::new();
+        printer.set_output_prefix(prefix);
+        gameboy.set_serial_peer(Box::new(printer.clone()));
+        printer
+    });
+    let capture: Option<SerialCapture> = options.serial_capture.then(|| {
+        let capture: SerialCapture = SerialCapture::new();
+        gameboy.set_serial_peer(Box::new(capture.clone()));
+        capture
+    });
+    if options.loopback {
+        gameboy.set_serial_peer(Box::new(Loopback));
+    }
+    // the other end of an in-process link cable, or the other players of the DMG-07,
+    // plugged in when running
+    let start = |rom: &str| {
+        let mut other: Box<Gameboy> = Box::new(Gameboy::new());
+        other.set_model(options.model);
+        other.start(rom);
+        other
+    };
+    let mut second: Option<Box<Gameboy>> = options.link_local.as_deref().map(start);
+    let players: Vec<Box<Gameboy>> = options.four_player.iter().map(|rom| start(rom)).collect();
+    let ir_recorder: Option<IrRecorder> = options.ir_record.as_ref().map(|_| {
+        let recorder: IrRecorder = IrRecorder::new();
+        gameboy.set_ir_peer(Box::new(recorder.clone()));
+        recorder
+    });
+    if let Some(path) = options.ir_play.as_deref() {
+        match IrPlayback::load(path) {
+            Ok(playback) => gameboy.set_ir_peer(Box::new(playback)),
+            Err(e) => {
+                eprintln!("Could not read the infrared recording {}: {}", path, e);
+                std::process::exit(1);
+            }
+        }
+    }
+    if let Some(second) = second.as_mut().filter(|_| options.model == Model::CGB && !options.is_infrared()) {
+        let (first_port, second_port) = IrPort::pair();
+        gameboy.set_ir_peer(Box::new(first_port));
+        second.set_ir_peer(Box::new(second_port));
+    }
+    if let Some(path) = options.get_load_state() {
+        if let Err(e) = gameboy.load_state(&path) {
+            eprintln!("Could not load the state {}: {}", path, e);
+            std::process::exit(1);
+        }
+        println!("State loaded from {}", path);
+    }
+    if options.is_running() {
+        let result: std::io::Result<f32> = match second {
+            Some(second) => {
+                let mut cable: LinkCable = LinkCable::new(gameboy, second);
+                let result = record(&mut cable, &options);
+                gameboy = cable.unplug().0;
+                result
+            }
+            None if !players.is_empty() => {
+                let mut adapter: FourPlayerAdapter = FourPlayerAdapter::new(std::iter::once(gameboy).chain(players).collect());
+                let result = record(&mut adapter, &options);
+                let connected: Vec<String> = adapter.get_connected().iter()
+                    .enumerate()
+                    .filter(|(_, connected)| **connected)
+                    .map(|(player, _)| (player + 1).to_string())
+                    .collect();
+                println!("Four-player adapter: players [{}] connected, {}", connected.join(", "),
+                         if adapter.is_transmitting() { "transmitting" } else { "pinging" });
+                gameboy = adapter.unplug().remove(0);
+                result
+            }
+            None => record(gameboy.as_mut(), &options),
+        };
+        if let Some(printer) = printer {
+            printer.cut_paper();
+            let pages: Vec<PrintedPage> = printer.get_pages();
+            for path in pages.iter().filter_map(|page| page.path.as_deref()) {
+                println!("Printed {}", path);
+            }
+            println!("Printer: {} pages printed", pages.len());
+            if let Some(e) = printer.take_error() {
+                eprintln!("Could not write a printed page: {}", e);
+                std::process::exit(1);
+            }
+        }
+        match result {
+            Ok(peak) if options.is_recording() => {
+                println!("Audio recorded at {} Hz, peak level {:.1} dBFS", gameboy.get_sample_rate(),
+                         20.0 * peak.log10());
+            }
+            Ok(_) => {}
+            Err(e) => {
+                eprintln!("Error while recording: {}", e);
+                std::process::exit(1);
+            }
+        }
+    } else {
+        for i in 0..0x4000 {
+            print!("{:02X} ", gameboy.memory_bus.rom.bank1[i]);
+            if (i + 1) % 16 == 0 {
+                println!();            
+            }
+        }
+    }
+    if let Some(e) = gameboy.take_serial_error() {
+        eprintln!("Link cable disconnected: {}", e);
+        std::process::exit(1);
+    }
+    if let Some(capture) = capture {
+        println!("Link port output:\n{}", capture.get_text());
+    }
+    if let Some(path) = options.frame.as_deref() {
+        if !gameboy.step_frame() {
+            eprintln!("{} doesn't display anything", options.rom);
+            std::process::exit(1);
+        }
+        let pixels: Vec<[u8; 3]> = gameboy.get_color_framebuffer().iter().map(|color| rgb555_to_rgb(*color)).collect();
+        if let Err(e) = write_bmp(path, SCREEN_WIDTH, SCREEN_HEIGHT, &pixels) {
+            eprintln!("Could not write the frame {}: {}", path, e);
+            std::process::exit(1);
+        }
+        println!("Frame written to {}", path);
+    }
+    if let Some(path) = options.sgb_frame.as_deref() {
+        let Some(frame) = gameboy.get_sgb_frame() else {
+            eprintln!("{} doesn't use the Super Game Boy functions", options.rom);
+            std::process::exit(1);
+        };
+        let pixels: Vec<[u8; 3]> = frame.iter().map(|color| rgb555_to_rgb(*color)).collect();
+        if let Err(e) = write_bmp(path, SGB_WIDTH, SGB_HEIGHT, &pixels) {
+            eprintln!("Could not write the Super Game Boy picture {}: {}", path, e);
+            std::process::exit(1);
+        }
+        println!("Super Game Boy picture written to {}", path);
+    }
+    if let (Some(recorder), Some(path)) = (ir_recorder, options.ir_record.as_deref()) {
+        if let Err(e) = recorder.save(path) {
+            eprintln!("Could not write the infrared recording {}: {}", path, e);
+            std::process::exit(1);
+        }
+        println!("Infrared recorded to {}", path);
+    }
+    if let Some(path) = options.get_save_state() {
+        if let Err(e) = gameboy.save_state(&path) {
+            eprintln!("Could not save the state {}: {}", path, e);
+            std::process::exit(1);
+        }
+        println!("State saved to {}", path);
+    }
+}
@@ -0,0 +1,117 @@
+use crate::error::state_error::StateError;
+use crate::state::snapshot::Snapshot;
+use crate::state::state_reader::StateReader;
+use crate::state::state_writer::StateWriter;
+
+/// CGB color palette memory, accessed through an index register (BCPS/OCPS) and a data
+/// register (BCPD/OCPD).
+///
+/// Holds 8 palettes of 4 colors each. Every color is a little-endian RGB555 value
+/// (bits 0-4 red, 5-9 green, 10-14 blue), so the whole memory is 64 bytes.
+pub struct ColorPalette {
+    data: [u8; 64],
+    index: u8,
+    auto_increment: bool,
+}
+
+impl ColorPalette {
+    pub fn new() -> ColorPalette {
+        ColorPalette {
+            data: [0xFF; 64],
+            index: 0,
+            auto_increment: false,
+        }
+    }
+
+    /// Value of the specification register (BCPS/OCPS), without the unused bit 6.
+    pub fn read_spec(&self) -> u8 {
+        self.index | if self.auto_increment { 0x80 } else { 0x00 }
+    }
+
+    pub fn write_spec(&mut self, value: u8) {
+        self.index = value & 0x3F;
+        self.auto_increment = value & 0x80 != 0;
+    }
+
+    pub fn read_data(&self) -> u8 {
+        self.data[self.index as usize]
+    }
+
+    /// Writes the byte selected by the index register and, if enabled, advances the index.
+    pub fn write_data(&mut self, value: u8) {
+        self.data[self.index as usize] = value;
+        if self.auto_increment {
+            self.index = (self.index + 1) & 0x3F;
+        }
+    }
+
+    /// Returns the 64 bytes of palette memory, as read through the data register.
+    pub fn get_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn set_bytes(&mut self, bytes: &[u8]) {
+        let size: usize = bytes.len().min(self.data.len());
+        self.data[..size].copy_from_slice(&bytes[..size]);
+    }
+
+    /// Returns color `color` (0–3) of palette `palette` (0–7) as RGB555.
+    pub fn get_color(&self, palette: u8, color: u8) -> u16 {
+        let offset: usize = (palette as usize & 0x07) * 8 + (color as usize & 0x03) * 2;
+        (self.data[offset] as u16 | (self.data[offset + 1] as u16) << 8) & 0x7FFF
+    }
+
+    /// Stores an RGB555 color directly, bypassing the index register.
+    pub fn set_color(&mut self, palette: u8, color: u8, value: u16) {
+        let offset: usize = (palette as usize & 0x07) * 8 + (color as usize & 0x03) * 2;
+        self.data[offset] = value as u8;
+        self.data[offset + 1] = (value >> 8) as u8 & 0x7F;
+    }
+}
+
+impl Snapshot for ColorPalette {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.data);
+        writer.write_u8(self.index);
+        writer.write_bool(self.auto_increment);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        reader.read_bytes(&mut self.data)?;
+        self.index = reader.read_u8()? & 0x3F;
+        self.auto_increment = reader.read_bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn data_writes_follow_the_index() {
+        let mut palette: ColorPalette = ColorPalette::new();
+        // palette 7 color 3, auto-increment on: wraps to the first byte
+        palette.write_spec(0x80 | 0x3E);
+        palette.write_data(0x1F);
+        palette.write_data(0x00);
+        palette.write_data(0xE0);
+        assert_eq!(palette.read_spec(), 0x81);
+        assert_eq!(palette.get_color(7, 3), 0x001F);
+        assert_eq!(palette.get_bytes()[0], 0xE0);
+        // without auto-increment the index stays put
+        palette.write_spec(0x02);
+        palette.write_data(0x12);
+        palette.write_data(0x34);
+        assert_eq!(palette.read_spec(), 0x02);
+        assert_eq!(palette.read_data(), 0x34);
+    }
+
+    #[test]
+    fn colors_are_little_endian_rgb555() {
+        let mut palette: ColorPalette = ColorPalette::new();
+        palette.set_color(2, 1, 0xFC00);
+        assert_eq!(palette.get_color(2, 1), 0x7C00);
+        assert_eq!(&palette.get_bytes()[18..20], &[0x00, 0x7C]);
+    }
+}
@@ -0,0 +1,167 @@
+use crate::error::state_error::StateError;
+use crate::state::snapshot::Snapshot;
+use crate::state::state_reader::StateReader;
+use crate::state::state_writer::StateWriter;
+
+/// OAM DMA controller, started by writing the source page to 0xFF46.
+///
+/// After a one M-cycle startup delay it copies 160 bytes from `XX00` to OAM, one byte per
+/// M-cycle. Writing 0xFF46 again while a transfer runs starts a new one from the beginning,
+/// and the old transfer keeps going (and keeps OAM locked) during the new startup delay.
+pub struct DMA {
+    source: u16,
+    index: u16,
+    active: bool,
+    // M-cycles left before a requested transfer starts, and its source
+    starting: u8,
+    next_source: u16,
+    // byte currently driven on the bus by the transfer
+    value: u8,
+}
+
+pub const DMA_LENGTH: u16 = 0xA0;
+
+impl DMA {
+    pub fn new() -> DMA {
+        DMA {
+            source: 0,
+            index: 0,
+            active: false,
+            starting: 0,
+            next_source: 0,
+            value: 0xFF,
+        }
+    }
+
+    /// Requests a new transfer from `page << 8`.
+    pub fn start(&mut self, page: u8) {
+        self.next_source = (page as u16) << 8;
+        self.starting = 2;
+    }
+
+    /// Returns `true` while bytes are being copied (OAM is not accessible to the CPU).
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// Last byte read by the transfer, returned to the CPU on a bus conflict.
+    pub fn get_value(&self) -> u8 {
+        self.value
+    }
+
+    pub fn set_value(&mut self, value: u8) {
+        self.value = value;
+    }
+
+    /// Returns `true` if a CPU access to `addr` collides with the running transfer, which
+    /// happens when both use the same bus (video bus for VRAM, external bus for the rest).
+    pub fn conflicts(&self, addr: u16) -> bool {
+        let video = |a: u16| (0x8000..=0x9FFF).contains(&a);
+        self.active && addr < 0xFE00 && video(addr) == video(self.source)
+    }
+
+    /// Advances the controller by one M-cycle.
+    ///
+    /// # Returns
+    /// `Some((source, destination))` with the addresses of the byte to copy this cycle.
+    pub fn step(&mut self) -> Option<(u16, u16)> {
+        if self.starting > 0 {
+            self.starting -= 1;
+            if self.starting == 0 {
+                self.source = self.next_source;
+                self.index = 0;
+                self.active = true;
+            }
+        }
+        if !self.active {
+            return None;
+        }
+        let mut source: u16 = self.source + self.index;
+        // there is no ROM/RAM behind 0xE000-0xFFFF for the DMA, it sees echo RAM instead
+        if source >= 0xE000 {
+            source -= 0x2000;
+        }
+        let destination: u16 = 0xFE00 + self.index;
+        self.index += 1;
+        if self.index == DMA_LENGTH {
+            self.active = false;
+        }
+        Some((source, destination))
+    }
+}
+
+impl Snapshot for DMA {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u16(self.source);
+        writer.write_u16(self.index);
+        writer.write_bool(self.active);
+        writer.write_u8(self.starting);
+        writer.write_u16(self.next_source);
+        writer.write_u8(self.value);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.source = reader.read_u16()?;
+        self.index = reader.read_u16()?;
+        self.active = reader.read_bool()?;
+        self.starting = reader.read_u8()?;
+        self.next_source = reader.read_u16()?;
+        self.value = reader.read_u8()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn starts_after_one_m_cycle() {
+        let mut dma: DMA = DMA::new();
+        dma.start(0xC1);
+        assert_eq!(dma.step(), None);
+        assert!(!dma.is_active());
+        assert_eq!(dma.step(), Some((0xC100, 0xFE00)));
+        for i in 1..DMA_LENGTH {
+            assert_eq!(dma.step(), Some((0xC100 + i, 0xFE00 + i)));
+        }
+        assert!(!dma.is_active());
+        assert_eq!(dma.step(), None);
+    }
+
+    #[test]
+    fn high_pages_read_echo_ram() {
+        let mut dma: DMA = DMA::new();
+        dma.start(0xFE);
+        dma.step();
+        assert_eq!(dma.step(), Some((0xDE00, 0xFE00)));
+    }
+
+    #[test]
+    fn restart_keeps_the_old_transfer_during_the_delay() {
+        let mut dma: DMA = DMA::new();
+        dma.start(0xC0);
+        dma.step();
+        dma.step();
+        dma.start(0xD0);
+        assert_eq!(dma.step(), Some((0xC001, 0xFE01)));
+        assert!(dma.is_active());
+        assert_eq!(dma.step(), Some((0xD000, 0xFE00)));
+    }
+
+    #[test]
+    fn conflicts_follow_the_bus() {
+        let mut dma: DMA = DMA::new();
+        dma.start(0x80);
+        dma.step();
+        dma.step();
+        assert!(dma.conflicts(0x9000));
+        assert!(!dma.conflicts(0xC000));
+        assert!(!dma.conflicts(0xFF80));
+        dma.start(0xC0);
+        dma.step();
+        dma.step();
+        assert!(dma.conflicts(0x0150));
+        assert!(!dma.conflicts(0x8000));
+    }
+}
@@ -1,5 +1,9 @@
 use crate::error::memory_error::MemoryError;
+use crate::error::state_error::StateError;
 use crate::memory_bus::bus::BUS;
+use crate::state::snapshot::Snapshot;
+use crate::state::state_reader::StateReader;
+use crate::state::state_writer::StateWriter;
 
 pub struct ExternalRAM{
     r: [u8; 0x2000] //8 kiB = 8192 bytes = 0x2000 
@@ -11,11 +15,20 @@ impl ExternalRAM{
             r: [0; 0x2000]
         }
     }
+
+    pub fn get_bytes(&self) -> &[u8] {
+        &self.r
+    }
+
+    /// Copies `bytes` from the start of the area, ignoring what doesn't fit.
+    pub fn set_bytes(&mut self, bytes: &[u8]) {
+        let size: usize = bytes.len().min(self.r.len());
+        self.r[..size].copy_from_slice(&bytes[..size]);
+    }
 }
 
 impl BUS for ExternalRAM {
     fn read(&self, addr: u16) -> Result<u8, MemoryError> {
-        dbg!("Reading from External RAM");
         match addr {
             0xA000..=0xBFFF => Ok(self.r[(addr - 0xA000) as usize]),
             _ => Err(MemoryError::InvalidAddress(addr))
@@ -31,4 +44,14 @@ impl BUS for ExternalRAM {
             _ => Err(MemoryError::InvalidAddress(addr))
         }
     }
-}
\ No newline at end of file
+}
+
+impl Snapshot for ExternalRAM {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.r);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        reader.read_bytes(&mut self.r)
+    }
+}
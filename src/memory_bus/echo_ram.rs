@@ -1,5 +1,9 @@
 use crate::error::memory_error::MemoryError;
+use crate::error::state_error::StateError;
 use crate::memory_bus::bus::BUS;
+use crate::state::snapshot::Snapshot;
+use crate::state::state_reader::StateReader;
+use crate::state::state_writer::StateWriter;
 
 pub struct EchoRAM{
     r: [u8; 0x1E00] //8 kiB = 8192 bytes = 0x2000
@@ -15,7 +19,6 @@ impl EchoRAM{
 
 impl BUS for EchoRAM {
     fn read(&self, addr: u16) -> Result<u8, MemoryError> {
-        dbg!("Reading from ECHO RAM");
         match addr {
             0xE000..=0xFDFF => Ok(self.r[(addr - 0xE000) as usize]),
             _ => Err(MemoryError::InvalidAddress(addr))
@@ -31,4 +34,14 @@ impl BUS for EchoRAM {
             _ => Err(MemoryError::InvalidAddress(addr))
         }
     }
-}
\ No newline at end of file
+}
+
+impl Snapshot for EchoRAM {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.r);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        reader.read_bytes(&mut self.r)
+    }
+}
@@ -1,5 +1,9 @@
 use crate::error::memory_error::MemoryError;
+use crate::error::state_error::StateError;
 use crate::memory_bus::bus::BUS;
+use crate::state::snapshot::Snapshot;
+use crate::state::state_reader::StateReader;
+use crate::state::state_writer::StateWriter;
 
 pub struct HRAM  {
     r: [u8; 127],
@@ -11,13 +15,22 @@ impl HRAM {
             r: [0; 127],
         }
     }
+
+    pub fn get_bytes(&self) -> &[u8] {
+        &self.r
+    }
+
+    /// Copies `bytes` from the start of the area, ignoring what doesn't fit.
+    pub fn set_bytes(&mut self, bytes: &[u8]) {
+        let size: usize = bytes.len().min(self.r.len());
+        self.r[..size].copy_from_slice(&bytes[..size]);
+    }
 }
 
 impl BUS for HRAM {
     fn read(&self, address: u16) -> Result<u8, MemoryError> {
-        dbg!("Reading from HRAM");
         match address {
-            0xFF80..=0xFFFE => Ok(self.r[(address - 0xC000) as usize]),
+            0xFF80..=0xFFFE => Ok(self.r[(address - 0xFF80) as usize]),
             _ => Err(MemoryError::InvalidAddress(address)),
         }
     }
@@ -25,10 +38,36 @@ impl BUS for HRAM {
     fn write(&mut self, address: u16, value: u8) -> Result<(), MemoryError>{
         match address {
             0xFF80..=0xFFFE => {
-                self.r[(address - 0xC000) as usize] = value;
+                self.r[(address - 0xFF80) as usize] = value;
                 Ok(())
             }
             _ => Err(MemoryError::InvalidAddress(address)),
         }
     }
-}
\ No newline at end of file
+}
+
+impl Snapshot for HRAM {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.r);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        reader.read_bytes(&mut self.r)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn covers_0xff80_to_0xfffe() {
+        let mut h_ram: HRAM = HRAM::new();
+        h_ram.write(0xFF80, 0x12).unwrap();
+        h_ram.write(0xFFFE, 0x34).unwrap();
+        assert_eq!(h_ram.get_bytes()[0], 0x12);
+        assert_eq!(h_ram.get_bytes()[126], 0x34);
+        assert_eq!(h_ram.read(0xFFFE).unwrap(), 0x34);
+        assert!(h_ram.read(0xFFFF).is_err());
+    }
+}
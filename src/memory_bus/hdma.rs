@@ -0,0 +1,166 @@
+use crate::constants::io_registers::{HDMA1, HDMA2, HDMA3, HDMA4, HDMA5};
+use crate::error::state_error::StateError;
+use crate::state::snapshot::Snapshot;
+use crate::state::state_reader::StateReader;
+use crate::state::state_writer::StateWriter;
+
+/// Length in bytes of every VRAM DMA block.
+pub const HDMA_BLOCK: u16 = 0x10;
+
+/// CGB VRAM DMA (HDMA1–HDMA5).
+///
+/// Copies blocks of 16 bytes from ROM/RAM to VRAM, either all at once (general purpose DMA)
+/// or one block at the start of every HBlank (HBlank DMA). The copy itself is done by the
+/// memory bus, which also stalls the CPU while it happens; this struct only keeps track of
+/// addresses and remaining length.
+pub struct HDMA {
+    source: u16,
+    destination: u16,
+    // blocks left to copy
+    remaining: u16,
+    hblank_active: bool,
+}
+
+/// What a write to HDMA5 asks the bus to do.
+pub enum HDMARequest {
+    None,
+    /// Copy every block right now.
+    General,
+    /// Copy one block at the start of each HBlank.
+    HBlank,
+}
+
+impl HDMA {
+    pub fn new() -> HDMA {
+        HDMA {
+            source: 0,
+            destination: 0x8000,
+            remaining: 0,
+            hblank_active: false,
+        }
+    }
+
+    pub fn is_hblank_active(&self) -> bool {
+        self.hblank_active
+    }
+
+    /// Value read from HDMA5: bit 7 is clear while an HBlank transfer is running and the
+    /// lower bits hold the remaining length (in blocks, minus one). 0xFF once finished.
+    pub fn read_status(&self) -> u8 {
+        let length: u8 = (self.remaining.wrapping_sub(1) & 0x7F) as u8;
+        if self.hblank_active { length } else { 0x80 | length }
+    }
+
+    /// Handles a write to HDMA1–HDMA5.
+    pub fn write(&mut self, addr: u16, value: u8) -> HDMARequest {
+        match addr {
+            HDMA1 => self.source = (self.source & 0x00FF) | (value as u16) << 8,
+            HDMA2 => self.source = (self.source & 0xFF00) | (value & 0xF0) as u16,
+            HDMA3 => self.destination = (self.destination & 0x00F0) | ((value & 0x1F) as u16) << 8 | 0x8000,
+            HDMA4 => self.destination = (self.destination & 0xFF00) | (value & 0xF0) as u16,
+            HDMA5 => {
+                if self.hblank_active && value & 0x80 == 0 {
+                    // clearing bit 7 during an HBlank transfer cancels it
+                    self.hblank_active = false;
+                    return HDMARequest::None;
+                }
+                self.remaining = (value & 0x7F) as u16 + 1;
+                if value & 0x80 != 0 {
+                    self.hblank_active = true;
+                    return HDMARequest::HBlank;
+                }
+                return HDMARequest::General;
+            }
+            _ => {}
+        }
+        HDMARequest::None
+    }
+
+    /// Takes the next block to copy and advances the addresses.
+    ///
+    /// # Returns
+    /// `Some((source, destination))` of the 16 bytes to copy, or `None` when nothing is left.
+    pub fn next_block(&mut self) -> Option<(u16, u16)> {
+        if self.remaining == 0 {
+            self.hblank_active = false;
+            return None;
+        }
+        let block: (u16, u16) = (self.source, self.destination);
+        self.source = self.source.wrapping_add(HDMA_BLOCK);
+        self.destination = self.destination.wrapping_add(HDMA_BLOCK);
+        self.remaining -= 1;
+        // the transfer stops when the destination runs past the end of VRAM
+        if self.destination > 0x9FFF {
+            self.destination = 0x8000;
+            self.remaining = 0;
+        }
+        if self.remaining == 0 {
+            self.hblank_active = false;
+        }
+        Some(block)
+    }
+}
+
+impl Snapshot for HDMA {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u16(self.source);
+        writer.write_u16(self.destination);
+        writer.write_u16(self.remaining);
+        writer.write_bool(self.hblank_active);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.source = reader.read_u16()?;
+        self.destination = reader.read_u16()?;
+        self.remaining = reader.read_u16()?;
+        self.hblank_active = reader.read_bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn setup(source: u16, destination: u16) -> HDMA {
+        let mut hdma: HDMA = HDMA::new();
+        hdma.write(HDMA1, (source >> 8) as u8);
+        hdma.write(HDMA2, source as u8);
+        hdma.write(HDMA3, (destination >> 8) as u8);
+        hdma.write(HDMA4, destination as u8);
+        hdma
+    }
+
+    #[test]
+    fn addresses_drop_their_low_nibble() {
+        let mut hdma: HDMA = setup(0xC12F, 0xE35F);
+        assert!(matches!(hdma.write(HDMA5, 0x00), HDMARequest::General));
+        // the destination keeps only bits 4-12 and always lands in VRAM
+        assert_eq!(hdma.next_block(), Some((0xC120, 0x8350)));
+        assert_eq!(hdma.next_block(), None);
+        assert_eq!(hdma.read_status(), 0xFF);
+    }
+
+    #[test]
+    fn hblank_transfer_reports_and_cancels() {
+        let mut hdma: HDMA = setup(0xC000, 0x8000);
+        assert!(matches!(hdma.write(HDMA5, 0x82), HDMARequest::HBlank));
+        assert_eq!(hdma.read_status(), 0x02);
+        hdma.next_block();
+        assert_eq!(hdma.read_status(), 0x01);
+        // clearing bit 7 stops it, the remaining length stays readable
+        assert!(matches!(hdma.write(HDMA5, 0x00), HDMARequest::None));
+        assert!(!hdma.is_hblank_active());
+        assert_eq!(hdma.read_status(), 0x81);
+    }
+
+    #[test]
+    fn stops_at_the_end_of_vram() {
+        let mut hdma: HDMA = setup(0xC000, 0x9FE0);
+        hdma.write(HDMA5, 0x83);
+        assert_eq!(hdma.next_block(), Some((0xC000, 0x9FE0)));
+        assert_eq!(hdma.next_block(), Some((0xC010, 0x9FF0)));
+        assert_eq!(hdma.next_block(), None);
+        assert!(!hdma.is_hblank_active());
+    }
+}
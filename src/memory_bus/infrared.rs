@@ -0,0 +1,162 @@
+use crate::constants::io_registers::RP;
+use crate::error::memory_error::MemoryError;
+use crate::error::state_error::StateError;
+use crate::infrared::darkness::Darkness;
+use crate::infrared::ir_peer::IrPeer;
+use crate::memory_bus::bus::BUS;
+use crate::state::snapshot::Snapshot;
+use crate::state::state_reader::StateReader;
+use crate::state::state_writer::StateWriter;
+
+/// Infrared port of the Game Boy Color (RP).
+///
+/// Bit 0 drives the LED. Bit 1 reads 0 while light is received, but only when both read
+/// enable bits (6 and 7) are set; otherwise it reads 1.
+pub struct Infrared {
+    rp: u8,
+    double_speed: bool,
+    peer: Box<dyn IrPeer>,
+}
+
+impl Infrared {
+    pub fn new() -> Infrared {
+        Infrared {
+            rp: 0,
+            double_speed: false,
+            peer: Box::new(Darkness),
+        }
+    }
+
+    /// Peers count time at normal speed.
+    pub fn set_double_speed(&mut self, double_speed: bool) {
+        self.double_speed = double_speed;
+    }
+
+    /// Puts `peer` in front of the port, replacing the current one.
+    pub fn set_peer(&mut self, peer: Box<dyn IrPeer>) {
+        self.peer = peer;
+        self.peer.set_led(self.is_led_on());
+    }
+
+    pub fn is_led_on(&self) -> bool {
+        self.rp & 0x01 != 0
+    }
+
+    fn is_read_enabled(&self) -> bool {
+        self.rp & 0xC0 == 0xC0
+    }
+
+    /// Advances the peer by `cycles` T-cycles.
+    pub fn tick(&mut self, cycles: u64) {
+        let elapsed: u64 = if self.double_speed { cycles / 2 } else { cycles };
+        self.peer.tick(elapsed);
+    }
+}
+
+impl BUS for Infrared {
+    fn read(&self, addr: u16) -> Result<u8, MemoryError> {
+        match addr {
+            RP => {
+                let dark: bool = !self.is_read_enabled() || !self.peer.is_emitting();
+                Ok(self.rp | if dark { 0x02 } else { 0x00 })
+            }
+            _ => Err(MemoryError::InvalidAddress(addr))
+        }
+    }
+
+    fn write(&mut self, addr: u16, data: u8) -> Result<(), MemoryError> {
+        match addr {
+            RP => {
+                let led: bool = self.is_led_on();
+                self.rp = data & 0xC1;
+                if led != self.is_led_on() {
+                    self.peer.set_led(self.is_led_on());
+                }
+            }
+            _ => return Err(MemoryError::InvalidAddress(addr))
+        }
+        Ok(())
+    }
+}
+
+/// The peer is not part of the state, it only sees the LED of the restored machine.
+impl Snapshot for Infrared {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.rp);
+        writer.write_bool(self.double_speed);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.rp = reader.read_u8()? & 0xC1;
+        self.double_speed = reader.read_bool()?;
+        self.peer.set_led(self.is_led_on());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::infrared::ir_link::IrPort;
+
+    // counts the time the port gives it
+    struct Clock(Rc<Cell<u64>>);
+
+    impl IrPeer for Clock {
+        fn set_led(&mut self, _on: bool) {}
+
+        fn is_emitting(&self) -> bool {
+            false
+        }
+
+        fn tick(&mut self, elapsed: u64) {
+            self.0.set(self.0.get() + elapsed);
+        }
+    }
+
+    #[test]
+    fn light_is_only_seen_with_reads_enabled() {
+        let (port, mut other) = IrPort::pair();
+        let mut infrared: Infrared = Infrared::new();
+        infrared.set_peer(Box::new(port));
+        other.set_led(true);
+        assert_eq!(infrared.read(RP).unwrap(), 0x02);
+        infrared.write(RP, 0x80).unwrap();
+        assert_eq!(infrared.read(RP).unwrap(), 0x82);
+        infrared.write(RP, 0xFF).unwrap();
+        assert_eq!(infrared.read(RP).unwrap(), 0xC1);
+        assert!(other.is_emitting());
+        other.set_led(false);
+        assert_eq!(infrared.read(RP).unwrap(), 0xC3);
+    }
+
+    #[test]
+    fn peers_count_time_at_normal_speed() {
+        let elapsed: Rc<Cell<u64>> = Rc::new(Cell::new(0));
+        let mut infrared: Infrared = Infrared::new();
+        infrared.set_peer(Box::new(Clock(elapsed.clone())));
+        infrared.tick(8);
+        infrared.set_double_speed(true);
+        infrared.tick(8);
+        assert_eq!(elapsed.get(), 12);
+    }
+
+    #[test]
+    fn restored_led_reaches_the_peer() {
+        let mut infrared: Infrared = Infrared::new();
+        infrared.write(RP, 0xC1).unwrap();
+        let mut writer: StateWriter = StateWriter::new();
+        infrared.save_state(&mut writer);
+        let bytes: Vec<u8> = writer.into_bytes();
+
+        let (port, other) = IrPort::pair();
+        let mut restored: Infrared = Infrared::new();
+        restored.set_peer(Box::new(port));
+        restored.load_state(&mut StateReader::new(&bytes)).unwrap();
+        assert!(restored.is_led_on());
+        assert!(other.is_emitting());
+    }
+}
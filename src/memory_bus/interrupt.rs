@@ -1,5 +1,9 @@
 use crate::error::memory_error::MemoryError;
+use crate::error::state_error::StateError;
 use crate::memory_bus::bus::BUS;
+use crate::state::snapshot::Snapshot;
+use crate::state::state_reader::StateReader;
+use crate::state::state_writer::StateWriter;
 
 pub struct Interrupt{
     r: [u8; 0x01] // 128 bytes 
@@ -15,7 +19,6 @@ impl Interrupt{
 
 impl BUS for Interrupt {
     fn read(&self, addr: u16) -> Result<u8, MemoryError> {
-        dbg!("Reading from Interrupt");
         match addr {
             0xFFFF..=0xFFFF => Ok(self.r[(addr - 0xFFFF) as usize]),
             _ => Err(MemoryError::InvalidAddress(addr))
@@ -31,4 +34,14 @@ impl BUS for Interrupt {
             _ => Err(MemoryError::InvalidAddress(addr))
         }
     }
-}
\ No newline at end of file
+}
+
+impl Snapshot for Interrupt {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.r);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        reader.read_bytes(&mut self.r)
+    }
+}
@@ -1,34 +1,378 @@
+use crate::apu::apu::APU;
+use crate::constants::io_registers::{BCPD, BCPS, BOOT, DIV, IF, INT_JOYPAD, INT_SERIAL, INT_TIMER, KEY1, NR10, OCPD, OCPS, P1, RP, SB, SC, STAT, TAC, TIMA, TMA, WAVE_RAM_END};
 use crate::error::memory_error::MemoryError;
+use crate::error::state_error::StateError;
 use crate::memory_bus::bus::BUS;
+use crate::memory_bus::color_palette::ColorPalette;
+use crate::memory_bus::infrared::Infrared;
+use crate::memory_bus::io_register::IORegister;
+use crate::memory_bus::joypad::{Joypad, Player};
+use crate::memory_bus::serial::Serial;
+use crate::memory_bus::timer::Timer;
+use crate::state::snapshot::Snapshot;
+use crate::state::state_reader::StateReader;
+use crate::state::state_writer::StateWriter;
 
+/// I/O register file mapped at 0xFF00–0xFF7F.
+///
+/// Every CPU access goes through [`IORegister::lookup`], so unused bits read back as `1`,
+/// read-only bits survive writes and unmapped addresses read `0xFF`. Hardware blocks
+/// (PPU, timer, APU...) use [`IO::get_register`] and [`IO::set_register`] instead, which
+/// bypass those rules.
 pub struct IO{
-    r: [u8; 0x80] // 128 bytes 
+    r: [u8; 0x80], // 128 bytes
+    cgb: bool,
+    dmg_compat: bool,
+    joypad: Joypad,
+    serial: Serial,
+    infrared: Infrared,
+    timer: Timer,
+    stat_written: bool,
+    bg_palette: ColorPalette,
+    obj_palette: ColorPalette,
+    apu: APU,
 }
 
 impl IO{
     pub fn new() -> IO{
         IO{
-            r: [0; 0x80]
+            r: [0; 0x80],
+            cgb: false,
+            dmg_compat: false,
+            joypad: Joypad::new(),
+            serial: Serial::new(),
+            infrared: Infrared::new(),
+            timer: Timer::new(),
+            stat_written: false,
+            bg_palette: ColorPalette::new(),
+            obj_palette: ColorPalette::new(),
+            apu: APU::new(),
+        }
+    }
+
+    pub fn set_cgb_mode(&mut self, cgb: bool) {
+        self.cgb = cgb;
+        self.serial.set_cgb_mode(cgb);
+        self.apu.set_cgb_hardware(self.cgb || self.dmg_compat);
+    }
+
+    pub fn is_cgb_mode(&self) -> bool {
+        self.cgb
+    }
+
+    /// Enables the DMG compatibility mode of the CGB: registers behave as on DMG but the
+    /// DMG palettes index into the CGB color palettes.
+    pub fn set_dmg_compat(&mut self, dmg_compat: bool) {
+        self.dmg_compat = dmg_compat;
+        self.apu.set_cgb_hardware(self.cgb || self.dmg_compat);
+    }
+
+    pub fn is_dmg_compat(&self) -> bool {
+        self.dmg_compat
+    }
+
+    /// Returns `true` while the CGB runs in double speed mode (KEY1 bit 7).
+    pub fn is_double_speed(&self) -> bool {
+        self.get_register(KEY1) & 0x80 != 0
+    }
+
+    /// Returns `true` if the program armed a speed switch (KEY1 bit 0) for the next STOP.
+    pub fn is_speed_switch_armed(&self) -> bool {
+        self.cgb && self.get_register(KEY1) & 0x01 != 0
+    }
+
+    /// Toggles between normal and double speed, clearing the armed bit. Like every STOP,
+    /// it also resets DIV.
+    pub fn switch_speed(&mut self) {
+        let speed: u8 = (self.get_register(KEY1) ^ 0x80) & 0x80;
+        self.set_register(KEY1, speed);
+        self.timer.set_double_speed(speed != 0);
+        self.serial.set_double_speed(speed != 0);
+        self.infrared.set_double_speed(speed != 0);
+        self.timer.write(DIV, 0).expect("Invalid addr for TIMER");
+    }
+
+    pub fn get_joypad(&self) -> &Joypad {
+        &self.joypad
+    }
+
+    /// Updates the buttons held down (see [`crate::memory_bus::joypad::Button::mask`]),
+    /// requesting the joypad interrupt if a selected line goes low.
+    pub fn set_buttons(&mut self, pressed: u8) {
+        self.joypad.set_pressed(pressed);
+        if self.joypad.take_interrupt() {
+            self.request_interrupt(INT_JOYPAD);
+        }
+    }
+
+    /// Same as [`IO::set_buttons`] for one of the joypads read after MLT_REQ.
+    pub fn set_player_buttons(&mut self, player: Player, pressed: u8) {
+        self.joypad.set_player_pressed(player, pressed);
+        if self.joypad.take_interrupt() {
+            self.request_interrupt(INT_JOYPAD);
+        }
+    }
+
+    pub fn set_joypad_players(&mut self, players: usize) {
+        self.joypad.set_players(players);
+    }
+
+    pub fn get_serial_mut(&mut self) -> &mut Serial {
+        &mut self.serial
+    }
+
+    pub fn get_infrared_mut(&mut self) -> &mut Infrared {
+        &mut self.infrared
+    }
+
+    pub fn get_timer_mut(&mut self) -> &mut Timer {
+        &mut self.timer
+    }
+
+    pub fn get_apu(&self) -> &APU {
+        &self.apu
+    }
+
+    pub fn get_apu_mut(&mut self) -> &mut APU {
+        &mut self.apu
+    }
+
+    pub fn get_bg_palette(&self) -> &ColorPalette {
+        &self.bg_palette
+    }
+
+    pub fn get_bg_palette_mut(&mut self) -> &mut ColorPalette {
+        &mut self.bg_palette
+    }
+
+    pub fn get_obj_palette(&self) -> &ColorPalette {
+        &self.obj_palette
+    }
+
+    pub fn get_obj_palette_mut(&mut self) -> &mut ColorPalette {
+        &mut self.obj_palette
+    }
+
+    /// Returns `true` if the CPU wrote STAT since the last call.
+    pub fn take_stat_written(&mut self) -> bool {
+        let written: bool = self.stat_written;
+        self.stat_written = false;
+        written
+    }
+
+    /// Returns the raw value stored for `addr`, without applying any read mask.
+    pub fn get_register(&self, addr: u16) -> u8 {
+        match addr {
+            P1 => self.joypad.read(addr).expect("Invalid addr for JOYPAD"),
+            SB | SC => self.serial.read(addr).expect("Invalid addr for SERIAL"),
+            RP => self.infrared.read(addr).expect("Invalid addr for INFRARED"),
+            DIV | TIMA | TMA | TAC => self.timer.read(addr).expect("Invalid addr for TIMER"),
+            BCPS => self.bg_palette.read_spec(),
+            BCPD => self.bg_palette.read_data(),
+            OCPS => self.obj_palette.read_spec(),
+            OCPD => self.obj_palette.read_data(),
+            NR10..=WAVE_RAM_END => self.apu.read_register(addr),
+            _ => self.r[(addr - 0xFF00) as usize]
+        }
+    }
+
+    /// Stores `value` for `addr` as the hardware would, ignoring read-only bits.
+    pub fn set_register(&mut self, addr: u16, value: u8) {
+        self.r[(addr - 0xFF00) as usize] = value;
+    }
+
+    /// Sets the given interrupt bit (see `INT_*` in `io_registers`) in IF.
+    pub fn request_interrupt(&mut self, interrupt: u8) {
+        let value: u8 = self.get_register(IF) | interrupt;
+        self.set_register(IF, value);
+    }
+
+    /// Advances the devices living in the I/O range by `cycles` T-cycles.
+    pub fn tick(&mut self, cycles: u64) {
+        if self.timer.tick(cycles) {
+            self.request_interrupt(INT_TIMER);
+        }
+        if self.serial.tick(cycles) {
+            self.request_interrupt(INT_SERIAL);
+        }
+        self.infrared.tick(cycles);
+        for _ in 0..self.timer.take_apu_events() {
+            self.apu.clock_frame_sequencer();
+        }
+        // the APU runs at the same rate in both speed modes
+        let apu_cycles: u64 = if self.is_double_speed() { cycles / 2 } else { cycles };
+        self.apu.tick(apu_cycles as u32);
+    }
+
+    /// Applies the side effects of a CPU write that go beyond storing the writable bits.
+    ///
+    /// # Returns
+    /// `true` when the write was fully handled here and must not be stored as usual.
+    fn write_side_effects(&mut self, addr: u16, data: u8) -> bool {
+        match addr {
+            P1 => {
+                // selecting a row with a button held down also pulls a line low
+                self.joypad.write(addr, data).expect("Invalid addr for JOYPAD");
+                if self.joypad.take_interrupt() {
+                    self.request_interrupt(INT_JOYPAD);
+                }
+                true
+            }
+            SB | SC => {
+                self.serial.write(addr, data).expect("Invalid addr for SERIAL");
+                true
+            }
+            RP => {
+                self.infrared.write(addr, data).expect("Invalid addr for INFRARED");
+                true
+            }
+            DIV | TIMA | TMA | TAC => {
+                self.timer.write(addr, data).expect("Invalid addr for TIMER");
+                true
+            }
+            STAT => {
+                self.stat_written = true;
+                false
+            }
+            BCPS => {
+                self.bg_palette.write_spec(data);
+                true
+            }
+            BCPD => {
+                self.bg_palette.write_data(data);
+                true
+            }
+            OCPS => {
+                self.obj_palette.write_spec(data);
+                true
+            }
+            OCPD => {
+                self.obj_palette.write_data(data);
+                true
+            }
+            NR10..=WAVE_RAM_END => {
+                self.apu.log_write(addr, data);
+                self.apu.write_register(addr, data);
+                true
+            }
+            BOOT => {
+                let value: u8 = self.get_register(BOOT) | (data & 0x01);
+                self.set_register(BOOT, value);
+                true
+            }
+            _ => false
         }
     }
 }
 
 impl BUS for IO {
     fn read(&self, addr: u16) -> Result<u8, MemoryError> {
-        dbg!("Reading from I/O");
         match addr {
-            0xFF00..=0xFF7F => Ok(self.r[(addr - 0xFF00) as usize]),
+            0xFF00..=0xFF7F => {
+                let value = match IORegister::lookup(addr, self.cgb) {
+                    Some(reg) if reg.readable => self.get_register(addr) | reg.unused,
+                    _ => 0xFF
+                };
+                Ok(value)
+            },
             _ => Err(MemoryError::InvalidAddress(addr))
         }
     }
 
     fn write(&mut self, addr: u16, data: u8) -> Result<(), MemoryError> {
         match addr {
-            0x8000..=0xFF7F => {
-                self.r[(addr - 0xFF00) as usize] = data;
+            0xFF00..=0xFF7F => {
+                let Some(reg) = IORegister::lookup(addr, self.cgb) else {
+                    return Ok(());
+                };
+                if self.write_side_effects(addr, data) {
+                    return Ok(());
+                }
+                let old: u8 = self.get_register(addr);
+                let value: u8 = (old & !reg.writable) | (data & reg.writable);
+                self.set_register(addr, value);
                 Ok(())
             },
             _ => Err(MemoryError::InvalidAddress(addr))
         }
     }
-}
\ No newline at end of file
+}
+
+impl Snapshot for IO {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.r);
+        writer.write_bool(self.cgb);
+        writer.write_bool(self.dmg_compat);
+        writer.write_bool(self.stat_written);
+        self.joypad.save_state(writer);
+        self.serial.save_state(writer);
+        self.infrared.save_state(writer);
+        self.timer.save_state(writer);
+        self.bg_palette.save_state(writer);
+        self.obj_palette.save_state(writer);
+        self.apu.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        reader.read_bytes(&mut self.r)?;
+        self.cgb = reader.read_bool()?;
+        self.dmg_compat = reader.read_bool()?;
+        self.stat_written = reader.read_bool()?;
+        self.joypad.load_state(reader)?;
+        self.serial.load_state(reader)?;
+        self.infrared.load_state(reader)?;
+        self.timer.load_state(reader)?;
+        self.bg_palette.load_state(reader)?;
+        self.obj_palette.load_state(reader)?;
+        self.apu.load_state(reader)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::io_registers::{NR41, NR42, NR44, NR52, SVBK};
+
+    #[test]
+    fn read_masks_and_unmapped_registers() {
+        let mut io: IO = IO::new();
+        io.write(TAC, 0xFF).unwrap();
+        assert_eq!(io.read(TAC).unwrap(), 0xFF);
+        io.write(TAC, 0x05).unwrap();
+        assert_eq!(io.read(TAC).unwrap(), 0xFD);
+        // 0xFF03 is not wired, SVBK only exists on the CGB
+        io.write(0xFF03, 0x12).unwrap();
+        assert_eq!(io.read(0xFF03).unwrap(), 0xFF);
+        io.write(SVBK, 0x02).unwrap();
+        assert_eq!(io.read(SVBK).unwrap(), 0xFF);
+    }
+
+    #[test]
+    fn boot_register_is_sticky() {
+        let mut io: IO = IO::new();
+        assert_eq!(io.read(BOOT).unwrap(), 0xFE);
+        io.write(BOOT, 0x01).unwrap();
+        io.write(BOOT, 0x00).unwrap();
+        assert_eq!(io.read(BOOT).unwrap(), 0xFF);
+    }
+
+    /// Loads a length of 1 into channel 4 while the APU is off, then triggers it with the
+    /// length counter enabled.
+    fn length_written_while_off(cgb: bool) -> bool {
+        let mut io: IO = IO::new();
+        io.set_cgb_mode(cgb);
+        io.write(NR52, 0x00).unwrap();
+        io.write(NR41, 0x3F).unwrap();
+        io.write(NR52, 0x80).unwrap();
+        io.write(NR42, 0xF0).unwrap();
+        io.write(NR44, 0xC0).unwrap();
+        io.get_apu_mut().clock_frame_sequencer();
+        io.read(NR52).unwrap() & 0x08 == 0
+    }
+
+    #[test]
+    fn dmg_length_counters_accept_writes_while_off() {
+        assert!(length_written_while_off(false));
+        assert!(!length_written_while_off(true));
+    }
+}
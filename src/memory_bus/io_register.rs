@@ -0,0 +1,115 @@
+use crate::constants::io_registers::*;
+
+/// Describes how a single I/O register behaves on the bus.
+///
+/// - `unused`: bits that are not wired and always read back as `1`.
+/// - `writable`: bits the CPU is allowed to change. Everything else is read-only
+///   (or unused) and keeps its current value on a CPU write.
+/// - `readable`: when `false` the register is write-only and reads return `0xFF`.
+#[derive(Clone, Copy)]
+pub struct IORegister {
+    pub unused: u8,
+    pub writable: u8,
+    pub readable: bool,
+}
+
+impl IORegister {
+    const fn rw(unused: u8) -> IORegister {
+        IORegister { unused, writable: !unused, readable: true }
+    }
+
+    const fn masked(unused: u8, writable: u8) -> IORegister {
+        IORegister { unused, writable, readable: true }
+    }
+
+    const fn write_only() -> IORegister {
+        IORegister { unused: 0xFF, writable: 0xFF, readable: false }
+    }
+
+    /// Returns the description of the register mapped at `addr`.
+    ///
+    /// # Parameters
+    /// - `addr`: Address in the 0xFF00–0xFF7F range.
+    /// - `cgb`: Whether the Game Boy Color only registers are mapped.
+    ///
+    /// # Returns
+    /// `None` when nothing is mapped at `addr`, in which case the bus reads `0xFF`
+    /// and ignores writes.
+    pub fn lookup(addr: u16, cgb: bool) -> Option<IORegister> {
+        let register = match addr {
+            P1 => IORegister::masked(0xC0, 0x30),
+            SB => IORegister::rw(0x00),
+            SC if cgb => IORegister::rw(0x7C),
+            SC => IORegister::rw(0x7E),
+            DIV => IORegister::rw(0x00),
+            TIMA | TMA => IORegister::rw(0x00),
+            TAC => IORegister::rw(0xF8),
+            IF => IORegister::rw(0xE0),
+
+            NR10 => IORegister::rw(0x80),
+            NR11 | NR21 => IORegister::masked(0x3F, 0xFF),
+            NR12 | NR22 | NR42 | NR43 | NR50 | NR51 => IORegister::rw(0x00),
+            NR13 | NR23 | NR31 | NR33 | NR41 => IORegister::write_only(),
+            NR14 | NR24 | NR34 | NR44 => IORegister::masked(0xBF, 0xFF),
+            NR30 => IORegister::rw(0x7F),
+            NR32 => IORegister::rw(0x9F),
+            // bits 0-3 are the read-only channel status flags
+            NR52 => IORegister::masked(0x70, 0x80),
+            WAVE_RAM_START..=WAVE_RAM_END => IORegister::rw(0x00),
+
+            LCDC => IORegister::rw(0x00),
+            // bits 0-2 (mode and LY=LYC) are driven by the PPU
+            STAT => IORegister::masked(0x80, 0x78),
+            SCY | SCX | LYC | DMA | BGP | OBP0 | OBP1 | WY | WX => IORegister::rw(0x00),
+            LY => IORegister::masked(0x00, 0x00),
+
+            // writing 1 unmaps the boot ROM for good
+            BOOT => IORegister::masked(0xFE, 0x01),
+
+            KEY1 if cgb => IORegister::masked(0x7E, 0x01),
+            VBK if cgb => IORegister::rw(0xFE),
+            HDMA1 | HDMA2 | HDMA3 | HDMA4 if cgb => IORegister::write_only(),
+            HDMA5 if cgb => IORegister::rw(0x00),
+            // bit 1 is the receive signal, driven by the IR peer
+            RP if cgb => IORegister::masked(0x3C, 0xC1),
+            BCPS | OCPS if cgb => IORegister::rw(0x40),
+            BCPD | OCPD if cgb => IORegister::rw(0x00),
+            OPRI if cgb => IORegister::rw(0xFE),
+            SVBK if cgb => IORegister::rw(0xF8),
+            UNDOC_72 | UNDOC_73 => IORegister::rw(0x00),
+            UNDOC_74 if cgb => IORegister::rw(0x00),
+            UNDOC_75 if cgb => IORegister::rw(0x8F),
+            PCM12 | PCM34 if cgb => IORegister::masked(0x00, 0x00),
+
+            _ => return None,
+        };
+        Some(register)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unused_bits_are_not_writable() {
+        let tac: IORegister = IORegister::lookup(TAC, false).unwrap();
+        assert_eq!((tac.unused, tac.writable), (0xF8, 0x07));
+        let stat: IORegister = IORegister::lookup(STAT, false).unwrap();
+        assert_eq!(stat.unused & stat.writable, 0);
+        assert_eq!(stat.writable & 0x07, 0);
+        let nr13: IORegister = IORegister::lookup(NR13, false).unwrap();
+        assert!(!nr13.readable);
+    }
+
+    #[test]
+    fn cgb_registers_are_only_mapped_on_cgb() {
+        for addr in [KEY1, VBK, HDMA5, RP, BCPS, OCPD, SVBK, PCM12] {
+            assert!(IORegister::lookup(addr, false).is_none(), "{:04X}", addr);
+            assert!(IORegister::lookup(addr, true).is_some(), "{:04X}", addr);
+        }
+        assert_eq!(IORegister::lookup(SC, false).unwrap().unused, 0x7E);
+        assert_eq!(IORegister::lookup(SC, true).unwrap().unused, 0x7C);
+        assert!(IORegister::lookup(0xFF03, true).is_none());
+    }
+}
@@ -0,0 +1,267 @@
+use crate::constants::io_registers::P1;
+use crate::error::memory_error::MemoryError;
+use crate::error::state_error::StateError;
+use crate::memory_bus::bus::BUS;
+use crate::state::snapshot::Snapshot;
+use crate::state::state_reader::StateReader;
+use crate::state::state_writer::StateWriter;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Button {
+    Right,
+    Left,
+    Up,
+    Down,
+    A,
+    B,
+    Select,
+    Start,
+}
+
+impl Button {
+    /// Bit of the button in the pressed mask: directions in the low nibble, actions in
+    /// the high one, both in the order of the P1 lines.
+    pub fn mask(self) -> u8 {
+        match self {
+            Button::Right => 0x01,
+            Button::Left => 0x02,
+            Button::Up => 0x04,
+            Button::Down => 0x08,
+            Button::A => 0x10,
+            Button::B => 0x20,
+            Button::Select => 0x40,
+            Button::Start => 0x80,
+        }
+    }
+}
+
+/// One of the joypads read in turn by Super Game Boy games after MLT_REQ.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Player {
+    One,
+    Two,
+    Three,
+    Four,
+}
+
+impl Player {
+    /// Index of the joypad, 0 for player 1.
+    pub fn index(self) -> usize {
+        match self {
+            Player::One => 0,
+            Player::Two => 1,
+            Player::Three => 2,
+            Player::Four => 3,
+        }
+    }
+}
+
+/// Joypad register (P1).
+///
+/// Buttons are wired in a 2×4 matrix: writing 0 to bit 4 selects the directions, to bit 5
+/// the actions, and the selected buttons pull their line (bits 0-3) low while pressed.
+/// The joypad interrupt fires whenever one of those lines goes from high to low.
+///
+/// After the Super Game Boy MLT_REQ command, up to four joypads are read in turn: with
+/// nothing selected the lines give the current player (0xF for player 1, 0xE for player
+/// 2...), and every rising edge of P15 (bit 5) moves on to the next one, whatever P14 does,
+/// as in SameBoy (`GB_sgb_write` in Core/sgb.c).
+pub struct Joypad {
+    // select bits 4-5, as written by the CPU
+    select: u8,
+    // buttons held down by each player, see `Button::mask`
+    pressed: [u8; 4],
+    players: usize,
+    player: usize,
+    // current state of the four input lines (active low)
+    lines: u8,
+    interrupt: bool,
+}
+
+impl Joypad {
+    pub fn new() -> Joypad {
+        Joypad {
+            select: 0x30,
+            pressed: [0; 4],
+            players: 1,
+            player: 0,
+            lines: 0x0F,
+            interrupt: false,
+        }
+    }
+
+    /// Returns the buttons held down by player 1.
+    pub fn get_pressed(&self) -> u8 {
+        self.pressed[0]
+    }
+
+    /// Replaces the set of buttons held down by player 1.
+    pub fn set_pressed(&mut self, pressed: u8) {
+        self.set_player_pressed(Player::One, pressed);
+    }
+
+    /// Replaces the set of buttons held down by `player`.
+    pub fn set_player_pressed(&mut self, player: Player, pressed: u8) {
+        self.pressed[player.index()] = pressed;
+        self.update();
+    }
+
+    /// Sets how many joypads are read in turn (1, 2 or 4), starting again from player 1.
+    pub fn set_players(&mut self, players: usize) {
+        self.players = players;
+        self.player = 0;
+        self.update();
+    }
+
+    /// Returns `true` while a selected button holds its line low, which is what wakes
+    /// the CPU up from STOP.
+    pub fn is_any_line_low(&self) -> bool {
+        self.lines != 0x0F
+    }
+
+    /// Returns `true` if a line went low since the last call.
+    pub fn take_interrupt(&mut self) -> bool {
+        let interrupt: bool = self.interrupt;
+        self.interrupt = false;
+        interrupt
+    }
+
+    fn compute_lines(&self) -> u8 {
+        if self.players > 1 && self.select == 0x30 {
+            return 0x0F - self.player as u8;
+        }
+        let pressed: u8 = self.pressed[self.player];
+        let mut lines: u8 = 0x0F;
+        if self.select & 0x10 == 0 {
+            lines &= !(pressed & 0x0F);
+        }
+        if self.select & 0x20 == 0 {
+            lines &= !(pressed >> 4);
+        }
+        lines
+    }
+
+    fn update(&mut self) {
+        let lines: u8 = self.compute_lines();
+        // any bit that was 1 and is now 0
+        if self.lines & !lines != 0 {
+            self.interrupt = true;
+        }
+        self.lines = lines;
+    }
+}
+
+impl BUS for Joypad {
+    fn read(&self, addr: u16) -> Result<u8, MemoryError> {
+        match addr {
+            // bits 6-7 are not connected and read back as 1
+            P1 => Ok(0xC0 | self.select | self.lines),
+            _ => Err(MemoryError::InvalidAddress(addr))
+        }
+    }
+
+    fn write(&mut self, addr: u16, data: u8) -> Result<(), MemoryError> {
+        match addr {
+            P1 => {
+                let select: u8 = data & 0x30;
+                if self.players > 1 && self.select & 0x20 == 0 && select & 0x20 != 0 {
+                    self.player = (self.player + 1) % self.players;
+                }
+                self.select = select;
+                self.update();
+                Ok(())
+            }
+            _ => Err(MemoryError::InvalidAddress(addr))
+        }
+    }
+}
+
+impl Snapshot for Joypad {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.select);
+        writer.write_bytes(&self.pressed);
+        writer.write_u8(self.players as u8);
+        writer.write_u8(self.player as u8);
+        writer.write_u8(self.lines);
+        writer.write_bool(self.interrupt);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.select = reader.read_u8()?;
+        reader.read_bytes(&mut self.pressed)?;
+        self.players = reader.read_u8()? as usize;
+        self.player = reader.read_u8()? as usize;
+        if !(1..=4).contains(&self.players) || self.player >= self.players {
+            return Err(StateError::InvalidValue("joypad players"));
+        }
+        self.lines = reader.read_u8()?;
+        self.interrupt = reader.read_bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn select_lines_pick_the_row() {
+        let mut joypad: Joypad = Joypad::new();
+        joypad.set_pressed(Button::Down.mask() | Button::Start.mask());
+        joypad.write(P1, 0x20).unwrap();
+        assert_eq!(joypad.read(P1).unwrap(), 0xE7);
+        joypad.write(P1, 0x10).unwrap();
+        assert_eq!(joypad.read(P1).unwrap(), 0xD7);
+        joypad.write(P1, 0x30).unwrap();
+        assert_eq!(joypad.read(P1).unwrap(), 0xFF);
+    }
+
+    #[test]
+    fn interrupt_on_high_to_low_only() {
+        let mut joypad: Joypad = Joypad::new();
+        joypad.write(P1, 0x10).unwrap();
+        joypad.set_pressed(Button::A.mask());
+        assert!(joypad.take_interrupt());
+        assert!(joypad.is_any_line_low());
+        joypad.set_pressed(0);
+        assert!(!joypad.take_interrupt());
+        // a button of the row not selected pulls nothing
+        joypad.set_pressed(Button::Left.mask());
+        assert!(!joypad.take_interrupt());
+    }
+
+    #[test]
+    fn four_players_are_read_in_turn() {
+        let mut joypad: Joypad = Joypad::new();
+        joypad.set_players(4);
+        joypad.set_player_pressed(Player::Four, Button::B.mask());
+        let mut ids: Vec<u8> = Vec::new();
+        for _ in 0..4 {
+            joypad.write(P1, 0x30).unwrap();
+            ids.push(joypad.read(P1).unwrap() & 0x0F);
+            // the buttons of the current player on the actions row
+            joypad.write(P1, 0x10).unwrap();
+            let lines: u8 = joypad.read(P1).unwrap() & 0x0F;
+            assert_eq!(lines, if ids.len() == 4 { 0x0D } else { 0x0F });
+        }
+        assert_eq!(ids, [0x0F, 0x0E, 0x0D, 0x0C]);
+        assert_eq!(Player::Three.index(), 2);
+    }
+
+    #[test]
+    fn p15_rising_edge_moves_to_the_next_player() {
+        let mut joypad: Joypad = Joypad::new();
+        joypad.set_players(2);
+        joypad.set_player_pressed(Player::Two, Button::Right.mask());
+        joypad.write(P1, 0x10).unwrap();
+        // P15 goes high while P14 goes low: the directions of player 2
+        joypad.write(P1, 0x20).unwrap();
+        assert_eq!(joypad.read(P1).unwrap() & 0x0F, 0x0E);
+        // P14 alone doesn't advance
+        joypad.write(P1, 0x30).unwrap();
+        assert_eq!(joypad.read(P1).unwrap() & 0x0F, 0x0E);
+        joypad.write(P1, 0x00).unwrap();
+        joypad.write(P1, 0x30).unwrap();
+        assert_eq!(joypad.read(P1).unwrap() & 0x0F, 0x0F);
+    }
+}
@@ -1,14 +1,32 @@
+use crate::apu::apu::APU;
+use crate::constants::io_registers::{BCPD, BOOT, DIV, DMA as DMA_REGISTER, HDMA1, HDMA4, HDMA5, IE, KEY1, LY, NR14, NR24, NR34, NR44, NR52, OCPD, P1, SC, STAT, SVBK, VBK};
+use crate::constants::lcd::STAT_MODE;
+use crate::error::state_error::StateError;
 use crate::memory_bus::bus::BUS;
+use crate::memory_bus::dma::DMA;
+use crate::memory_bus::hdma::{HDMA, HDMARequest, HDMA_BLOCK};
 use crate::memory_bus::e_ram::ExternalRAM;
 use crate::memory_bus::echo_ram::EchoRAM;
 use crate::memory_bus::h_ram::HRAM;
 use crate::memory_bus::interrupt::Interrupt;
 use crate::memory_bus::io::IO;
+use crate::memory_bus::joypad::Player;
 use crate::memory_bus::not_usable::NotUsable;
 use crate::memory_bus::oam::OAM;
 use crate::memory_bus::rom::ROM;
+use crate::memory_bus::infrared::Infrared;
+use crate::memory_bus::region::Region;
+use crate::memory_bus::serial::Serial;
 use crate::memory_bus::v_ram::VRAM;
 use crate::memory_bus::w_ram::WRAM;
+use crate::ppu::compat_palette::CompatPalette;
+use crate::ppu::ppu::{MODE_HBLANK, PPU};
+use crate::sgb::sgb::SGB;
+use crate::state::snapshot::Snapshot;
+use crate::state::state_reader::StateReader;
+use crate::state::state_writer::StateWriter;
+
+const SPEED_SWITCH_CYCLES: u64 = 2050 * 4;
 
 pub struct MemoryBus {
     pub rom: ROM,
@@ -20,8 +38,17 @@ pub struct MemoryBus {
     h_ram: HRAM,
     oam: OAM,
     interrupt: Interrupt,
-    not_usable: NotUsable
-
+    not_usable: NotUsable,
+    dma: DMA,
+    hdma: HDMA,
+    pub ppu: PPU,
+    // T-cycles the CPU must stay stopped for, because of a VRAM DMA
+    stall_cycles: u64,
+    cpu_halted: bool,
+    ppu_enabled: bool,
+    // T-cycles already ticked by the CPU accesses of the current instruction
+    cpu_cycles: u64,
+    sgb: Option<SGB>,
 }
 
 impl MemoryBus{
@@ -36,12 +63,315 @@ impl MemoryBus{
             h_ram: HRAM::new(),
             oam: OAM::new(),
             interrupt: Interrupt::new(),
-            not_usable: NotUsable::new()
+            not_usable: NotUsable::new(),
+            dma: DMA::new(),
+            hdma: HDMA::new(),
+            ppu: PPU::new(),
+            stall_cycles: 0,
+            cpu_halted: false,
+            ppu_enabled: true,
+            cpu_cycles: 0,
+            sgb: None,
         }
     }
     
+    /// Switches between DMG and CGB mode (VRAM/WRAM banking, color palettes...).
+    pub fn set_cgb_mode(&mut self, cgb: bool) {
+        self.io.set_cgb_mode(cgb);
+        if !cgb {
+            self.v_ram.set_bank(0);
+            self.w_ram.set_bank(1);
+        }
+    }
+
+    pub fn is_cgb_mode(&self) -> bool {
+        self.io.is_cgb_mode()
+    }
+
+    pub fn is_dmg_compat(&self) -> bool {
+        self.io.is_dmg_compat()
+    }
+
+    /// Enables (with the palette the boot ROM would pick) or disables the CGB
+    /// compatibility mode for DMG cartridges.
+    pub fn set_dmg_compat(&mut self, palette: Option<CompatPalette>) {
+        self.io.set_dmg_compat(palette.is_some());
+        let Some(palette) = palette else {
+            return;
+        };
+        let [bg, obj0, obj1] = palette.colors();
+        for color in 0..4 {
+            self.io.get_bg_palette_mut().set_color(0, color as u8, bg[color]);
+            self.io.get_obj_palette_mut().set_color(0, color as u8, obj0[color]);
+            self.io.get_obj_palette_mut().set_color(1, color as u8, obj1[color]);
+        }
+    }
+
+    /// Turns the Super Game Boy functions on or off. Turning them on starts from a blank
+    /// state, with one joypad.
+    pub fn set_sgb_mode(&mut self, sgb: bool) {
+        self.sgb = if sgb { Some(SGB::new()) } else { None };
+        self.io.set_joypad_players(1);
+    }
+
+    /// Returns the 256×224 RGB555 picture of the Super Game Boy, `None` outside SGB mode.
+    pub fn get_sgb_frame(&self) -> Option<Vec<u16>> {
+        self.sgb.as_ref().map(|sgb| sgb.compose(self.ppu.get_framebuffer()))
+    }
+
+    /// Lets the bus know whether the CPU is in HALT, which pauses the HBlank DMA.
+    pub fn set_cpu_halted(&mut self, halted: bool) {
+        self.cpu_halted = halted;
+    }
+
+    /// Returns (and clears) the T-cycles the CPU has to wait because of a VRAM DMA.
+    pub fn take_stall_cycles(&mut self) -> u64 {
+        let cycles: u64 = self.stall_cycles;
+        self.stall_cycles = 0;
+        cycles
+    }
+
+    pub fn is_double_speed(&self) -> bool {
+        self.io.is_double_speed()
+    }
+
+    /// Stops clocking the PPU, for sound-only programs (see [`crate::gbs`]).
+    pub fn set_ppu_enabled(&mut self, enabled: bool) {
+        self.ppu_enabled = enabled;
+    }
+
+    /// Returns the buttons held down (see [`crate::memory_bus::joypad::Button::mask`]).
+    pub fn get_buttons(&self) -> u8 {
+        self.io.get_joypad().get_pressed()
+    }
+
+    pub fn set_buttons(&mut self, pressed: u8) {
+        self.io.set_buttons(pressed);
+    }
+
+    pub fn set_player_buttons(&mut self, player: Player, pressed: u8) {
+        self.io.set_player_buttons(player, pressed);
+    }
+
+    /// Returns `true` while a selected button pulls its P1 line low.
+    pub fn is_joypad_line_low(&self) -> bool {
+        self.io.get_joypad().is_any_line_low()
+    }
+
+    pub fn get_serial_mut(&mut self) -> &mut Serial {
+        self.io.get_serial_mut()
+    }
+
+    pub fn get_infrared_mut(&mut self) -> &mut Infrared {
+        self.io.get_infrared_mut()
+    }
+
+    pub fn get_apu(&self) -> &APU {
+        self.io.get_apu()
+    }
+
+    pub fn get_apu_mut(&mut self) -> &mut APU {
+        self.io.get_apu_mut()
+    }
+
+    /// Returns `true` if the next STOP has to switch the CPU speed (CGB only).
+    pub fn is_speed_switch_armed(&self) -> bool {
+        self.io.is_speed_switch_armed()
+    }
+
+    /// Performs the CGB speed switch triggered by STOP. The CPU stays stopped for about
+    /// 2050 M-cycles while the clock settles.
+    pub fn switch_speed(&mut self) {
+        self.io.switch_speed();
+        self.stall_cycles += SPEED_SWITCH_CYCLES;
+    }
+
+    /// Returns a copy of `region`.
+    pub fn get_region(&self, region: Region) -> Vec<u8> {
+        match region {
+            Region::WRAM if self.is_cgb_mode() => self.w_ram.get_bytes(),
+            Region::WRAM => self.w_ram.get_bytes()[..0x2000].to_vec(),
+            Region::VRAM => self.v_ram.get_bytes(self.is_cgb_mode()),
+            Region::ExternalRAM => self.e_ram.get_bytes().to_vec(),
+            Region::OAM => self.oam.get_bytes().to_vec(),
+            Region::ExtraOAM => self.not_usable.get_bytes().to_vec(),
+            Region::HRAM => self.h_ram.get_bytes().to_vec(),
+            Region::BgPalettes => self.io.get_bg_palette().get_bytes().to_vec(),
+            Region::ObjPalettes => self.io.get_obj_palette().get_bytes().to_vec(),
+        }
+    }
+
+    /// Fills `region` from the start with `bytes`. Whatever doesn't fit is dropped.
+    pub fn set_region(&mut self, region: Region, bytes: &[u8]) {
+        match region {
+            Region::WRAM => self.w_ram.set_bytes(bytes),
+            Region::VRAM => self.v_ram.set_bytes(bytes),
+            Region::ExternalRAM => self.e_ram.set_bytes(bytes),
+            Region::OAM => self.oam.set_bytes(bytes),
+            Region::ExtraOAM => self.not_usable.set_bytes(bytes),
+            Region::HRAM => self.h_ram.set_bytes(bytes),
+            Region::BgPalettes => self.io.get_bg_palette_mut().set_bytes(bytes),
+            Region::ObjPalettes => self.io.get_obj_palette_mut().set_bytes(bytes),
+        }
+    }
+
+    /// Returns the raw value of the I/O register (or IE) at `addr`, write-only bits
+    /// included.
+    pub fn get_io_register(&self, addr: u16) -> u8 {
+        match addr {
+            IE => self.interrupt.read(addr).expect("Invalid addr for Interrupt"),
+            _ => self.io.get_register(addr),
+        }
+    }
+
+    /// Loads the 0x80 I/O registers (0xFF00–0xFF7F) of a state written by another emulator.
+    ///
+    /// Registers are written as the CPU would, except that nothing gets started: no serial
+    /// transfer, OAM or VRAM DMA, sound trigger nor STAT interrupt. DIV sets the upper byte of the system
+    /// counter and LY restarts the PPU at the beginning of that line. The CGB mode must
+    /// already be set, since it decides which registers exist.
+    pub fn restore_io_registers(&mut self, registers: &[u8; 0x80]) {
+        let value = |addr: u16| registers[(addr - 0xFF00) as usize];
+        // the sound registers ignore writes while the APU is off
+        self.io.write(NR52, value(NR52)).expect("Invalid addr for I/O");
+        for addr in 0xFF00..=0xFF7F {
+            let data: u8 = value(addr);
+            match addr {
+                NR52 | LY | KEY1 | HDMA5 | BCPD | OCPD => {}
+                DIV => self.io.get_timer_mut().set_div(data),
+                SC => self.io.write(addr, data & 0x7F).expect("Invalid addr for I/O"),
+                NR14 | NR24 | NR34 | NR44 => self.io.write(addr, data & 0x7F).expect("Invalid addr for I/O"),
+                // stored as is: a CPU write would raise the spurious STAT interrupt of the DMG
+                STAT => self.io.set_register(addr, data & !STAT_MODE),
+                DMA_REGISTER | HDMA1..=HDMA4 => self.io.set_register(addr, data),
+                VBK | SVBK => self.write(addr, data),
+                _ => self.io.write(addr, data).expect("Invalid addr for I/O"),
+            }
+        }
+        let key1: u8 = value(KEY1);
+        if self.is_cgb_mode() {
+            if (key1 & 0x80 != 0) != self.is_double_speed() {
+                self.io.switch_speed();
+                self.io.get_timer_mut().set_div(value(DIV));
+            }
+            self.io.write(KEY1, key1).expect("Invalid addr for I/O");
+        }
+        self.ppu.restore_line(&mut self.io, value(LY));
+        self.sync_boot_rom();
+    }
+
+    /// Maps the boot ROM back in when BOOT says it hasn't run to its end yet.
+    fn sync_boot_rom(&mut self) {
+        let booting: bool = self.io.get_register(BOOT) & 0x01 == 0;
+        self.rom.set_boot_rom_mapped(booting);
+    }
+
+    /// Advances every clocked component attached to the bus by `cycles` T-cycles.
+    ///
+    /// Cycles are counted at the CPU clock. In double speed mode the timer, DIV and OAM DMA
+    /// follow the CPU while the PPU keeps running at the normal rate, so it only sees half
+    /// of them.
+    pub fn tick(&mut self, cycles: u64) {
+        self.io.tick(cycles);
+        self.tick_dma(cycles);
+        if self.ppu_enabled {
+            let dots: u64 = if self.is_double_speed() { cycles / 2 } else { cycles };
+            self.ppu.tick(dots, &mut self.io, &self.v_ram, &self.oam);
+        }
+        self.tick_hdma();
+    }
+
+    fn tick_hdma(&mut self) {
+        let hblanks: u32 = self.ppu.take_hblank_count();
+        if self.cpu_halted {
+            return;
+        }
+        for _ in 0..hblanks {
+            if !self.hdma.is_hblank_active() {
+                break;
+            }
+            self.copy_hdma_block();
+        }
+    }
+
+    /// Copies the next VRAM DMA block, stalling the CPU for 8 M-cycles (16 in double speed,
+    /// since the transfer takes the same real time).
+    fn copy_hdma_block(&mut self) -> bool {
+        let Some((source, destination)) = self.hdma.next_block() else {
+            return false;
+        };
+        for i in 0..HDMA_BLOCK {
+            let value: u8 = self.read_direct(source.wrapping_add(i));
+            self.v_ram.write(destination + i, value).unwrap_or_else(|e| panic!("HDMA: {}", e));
+        }
+        self.stall_cycles += if self.is_double_speed() { 64 } else { 32 };
+        true
+    }
+
+    fn start_hdma(&mut self, addr: u16, value: u8) {
+        match self.hdma.write(addr, value) {
+            HDMARequest::General => while self.copy_hdma_block() {},
+            // a transfer started outside mode 3 (or with the LCD off) copies its first block at once
+            HDMARequest::HBlank if !self.ppu.is_lcd_on() || self.ppu.get_mode() == MODE_HBLANK => {
+                self.copy_hdma_block();
+            }
+            _ => {}
+        }
+    }
+
+    fn tick_dma(&mut self, cycles: u64) {
+        for _ in 0..cycles / 4 {
+            if let Some((source, destination)) = self.dma.step() {
+                let value: u8 = self.read_direct(source);
+                self.dma.set_value(value);
+                self.oam.write(destination, value).unwrap_or_else(|e| panic!("OAM DMA: {}", e));
+            }
+        }
+    }
+
+    /// Memory read of the CPU: the hardware first runs through the M-cycle (4 T-cycles)
+    /// that ends with the access, so PPU, timer and APU see it at the right point of the
+    /// instruction.
+    pub fn cpu_read(&mut self, addr: u16) -> u8 {
+        self.tick_cpu_cycle();
+        self.read(addr)
+    }
+
+    /// Memory write of the CPU, timed as [`MemoryBus::cpu_read`].
+    pub fn cpu_write(&mut self, addr: u16, value: u8) {
+        self.tick_cpu_cycle();
+        self.write(addr, value);
+    }
+
+    fn tick_cpu_cycle(&mut self) {
+        self.tick(4);
+        self.cpu_cycles += 4;
+    }
+
+    /// Returns (and clears) the T-cycles ticked by the CPU accesses since the last call.
+    /// The caller ticks the rest of the instruction, the cycles without a memory access.
+    pub fn take_cpu_cycles(&mut self) -> u64 {
+        let cycles: u64 = self.cpu_cycles;
+        self.cpu_cycles = 0;
+        cycles
+    }
+
+    /// CPU side read. While OAM DMA runs, OAM reads as 0xFF and reads on the bus used by
+    /// the transfer return the byte the DMA is copying.
     pub fn read(&self, addr: u16) -> u8{
+        if self.dma.is_active() {
+            match addr {
+                0xFE00..=0xFEFF => return 0xFF,
+                _ if self.dma.conflicts(addr) => return self.dma.get_value(),
+                _ => {}
+            }
+        }
+        self.read_direct(addr)
+    }
+
+    fn read_direct(&self, addr: u16) -> u8{
         match addr {
+            HDMA5 if self.is_cgb_mode() => self.hdma.read_status(),
             0x0000..=0x7FFF => self.rom.read_byte(addr),
             0x8000..=0x9FFF => self.v_ram.read(addr).expect(&format!("Invalid addr for VRAM {:04X} ",addr)),
             0xA000..=0xBFFF => self.e_ram.read(addr).expect(&format!("Invalid addr for EXTERNAL RAM {:04X} ",addr)),
@@ -57,19 +387,307 @@ impl MemoryBus{
     }
     
     pub fn write(&mut self, addr: u16, value: u8) {
+        if self.dma.is_active() && (matches!(addr, 0xFE00..=0xFEFF) || self.dma.conflicts(addr)) {
+            return;
+        }
+        match addr {
+            DMA_REGISTER => self.dma.start(value),
+            HDMA1..=HDMA5 if self.is_cgb_mode() => self.start_hdma(addr, value),
+            VBK if self.is_cgb_mode() => self.v_ram.set_bank(value),
+            SVBK if self.is_cgb_mode() => self.w_ram.set_bank(value),
+            BOOT if value & 0x01 != 0 => self.rom.set_boot_rom_mapped(false),
+            _ => {}
+        }
         match addr {
+            // ROM bank number, the only mapper register emulated so far: GBS rips switch
+            // their banks through it the way MBC1 cartridges do
+            0x2000..=0x3FFF => self.rom.write_byte(addr, value),
+            // the ROM itself is read-only, writes to the other mapper registers are dropped
+            0x0000..=0x1FFF | 0x4000..=0x7FFF => {}
             0x8000..=0x9FFF => self.v_ram.write(addr, value).expect(&format!("Invalid addr for VRAM {:04X} ",addr)),
             0xA000..=0xBFFF => self.e_ram.write(addr, value).expect(&format!("Invalid addr for EXTERNAL RAM {:04X} ",addr)),
             0xC000..=0xDFFF => self.w_ram.write(addr, value).expect(&format!("Invalid addr for WORK RAM {:04X} ",addr)),
             0xE000..=0xFDFF => self.echo_ram.write(addr, value).expect(&format!("Invalid addr for ECHO RAM {:04X} ",addr)),
             0xFE00..=0xFE9F => self.oam.write(addr, value).expect(&format!("Invalid addr for OAM {:04X} ",addr)),
             0xFEA0..=0xFEFF => self.not_usable.write(addr, value).expect(&format!("Invalid addr for NOT USABLE {:04X}",addr)),
-            0xFF00..=0xFF7F => self.io.write(addr, value).expect(&format!("Invalid addr for I/O {:04X} ",addr)),
+            0xFF00..=0xFF7F => {
+                self.io.write(addr, value).expect(&format!("Invalid addr for I/O {:04X} ",addr));
+                if let (P1, Some(sgb)) = (addr, self.sgb.as_mut()) {
+                    sgb.write_joypad(value, &mut self.io, &self.v_ram, self.ppu.get_framebuffer());
+                }
+            }
             0xFF80..=0xFFFE => self.h_ram.write(addr, value).expect(&format!("Invalid addr for HRAM {:04X} ",addr)),
             0xFFFF          => self.interrupt.write(addr, value).expect(&format!("Invalid addr for Interrupt {:04X} ",addr)),
-            _ => panic!("Invalid addr {:04X} ", addr)
         }
     }
     
     
-}
\ No newline at end of file
+}
+
+impl Snapshot for MemoryBus {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.rom.save_state(writer);
+        self.v_ram.save_state(writer);
+        self.e_ram.save_state(writer);
+        self.w_ram.save_state(writer);
+        self.echo_ram.save_state(writer);
+        self.io.save_state(writer);
+        self.h_ram.save_state(writer);
+        self.oam.save_state(writer);
+        self.interrupt.save_state(writer);
+        self.not_usable.save_state(writer);
+        self.dma.save_state(writer);
+        self.hdma.save_state(writer);
+        self.ppu.save_state(writer);
+        writer.write_u64(self.stall_cycles);
+        writer.write_bool(self.cpu_halted);
+        writer.write_bool(self.ppu_enabled);
+        writer.write_bool(self.sgb.is_some());
+        if let Some(sgb) = self.sgb.as_ref() {
+            sgb.save_state(writer);
+        }
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.rom.load_state(reader)?;
+        self.v_ram.load_state(reader)?;
+        self.e_ram.load_state(reader)?;
+        self.w_ram.load_state(reader)?;
+        self.echo_ram.load_state(reader)?;
+        self.io.load_state(reader)?;
+        self.h_ram.load_state(reader)?;
+        self.oam.load_state(reader)?;
+        self.interrupt.load_state(reader)?;
+        self.not_usable.load_state(reader)?;
+        self.dma.load_state(reader)?;
+        self.hdma.load_state(reader)?;
+        self.ppu.load_state(reader)?;
+        self.stall_cycles = reader.read_u64()?;
+        self.cpu_halted = reader.read_bool()?;
+        self.ppu_enabled = reader.read_bool()?;
+        self.sgb = if reader.read_bool()? {
+            let mut sgb: SGB = SGB::new();
+            sgb.load_state(reader)?;
+            Some(sgb)
+        } else {
+            None
+        };
+        self.sync_boot_rom();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::io_registers::{BGP, HDMA2, HDMA3, IF, INT_SERIAL, LCDC, SB};
+    use crate::serial::loopback::Loopback;
+    use crate::constants::lcd::{DOTS_PER_LINE, DRAWING_DOTS, OAM_SCAN_DOTS, SCREEN_WIDTH};
+    use crate::cpu::cpu::CPU;
+    use crate::ppu::ppu::RenderMode;
+
+    /// Four 16 KiB banks, each filled with its own number.
+    fn banked_bus() -> MemoryBus {
+        let mut bus: MemoryBus = MemoryBus::new();
+        let image: Vec<u8> = (0..4u8).flat_map(|bank| vec![bank; 0x4000]).collect();
+        bus.rom.load_gbs(&image, 0);
+        bus
+    }
+
+    #[test]
+    fn bank_number_register_switches_banks() {
+        let mut bus: MemoryBus = banked_bus();
+        assert_eq!(bus.read(0x4000), 1);
+        bus.write(0x2000, 3);
+        assert_eq!(bus.read(0x4000), 3);
+        bus.write(0x3FFF, 0);
+        assert_eq!(bus.read(0x4000), 1);
+    }
+
+    #[test]
+    fn other_rom_writes_are_ignored() {
+        let mut bus: MemoryBus = banked_bus();
+        for addr in [0x0000, 0x1FFF, 0x4000, 0x6000, 0x7FFF] {
+            bus.write(addr, 0x02);
+        }
+        assert_eq!(bus.read(0x0000), 0);
+        assert_eq!(bus.read(0x4000), 1);
+        assert_eq!(bus.read(0x7FFF), 1);
+    }
+
+    #[test]
+    fn banking_registers_only_exist_in_cgb_mode() {
+        let mut bus: MemoryBus = MemoryBus::new();
+        bus.write(0xD000, 0x11);
+        bus.write(SVBK, 0x03);
+        bus.write(VBK, 0x01);
+        bus.write(0xD000, 0x22);
+        assert_eq!(bus.read(0xD000), 0x22);
+        bus.set_cgb_mode(true);
+        bus.write(SVBK, 0x03);
+        bus.write(VBK, 0x01);
+        bus.write(0xD000, 0x33);
+        bus.write(0x8000, 0x44);
+        assert_eq!(bus.read(0x8000), 0x44);
+        bus.write(SVBK, 0x00);
+        bus.write(VBK, 0x00);
+        assert_eq!(bus.read(0xD000), 0x22);
+        assert_eq!(bus.read(0x8000), 0x00);
+    }
+
+    fn vram_dma_bus(length: u8) -> MemoryBus {
+        let mut bus: MemoryBus = MemoryBus::new();
+        bus.set_cgb_mode(true);
+        for i in 0..0x40u16 {
+            bus.write(0xC000 + i, i as u8 + 1);
+        }
+        bus.write(HDMA1, 0xC0);
+        bus.write(HDMA2, 0x00);
+        bus.write(HDMA3, 0x01);
+        bus.write(HDMA4, 0x00);
+        bus.write(HDMA5, length);
+        bus
+    }
+
+    #[test]
+    fn general_dma_copies_everything_and_stalls_the_cpu() {
+        let mut bus: MemoryBus = vram_dma_bus(0x01);
+        assert_eq!(bus.read(0x8100), 0x01);
+        assert_eq!(bus.read(0x811F), 0x20);
+        assert_eq!(bus.read(0x8120), 0x00);
+        assert_eq!(bus.read(HDMA5), 0xFF);
+        assert_eq!(bus.take_stall_cycles(), 2 * 32);
+    }
+
+    #[test]
+    fn hblank_dma_copies_one_block_per_hblank() {
+        let mut bus: MemoryBus = vram_dma_bus(0x81);
+        bus.write(LCDC, 0x91);
+        // the LCD was off, so the first block goes at once
+        assert_eq!(bus.read(0x810F), 0x10);
+        assert_eq!(bus.read(0x8110), 0x00);
+        assert_eq!(bus.read(HDMA5), 0x00);
+        bus.take_stall_cycles();
+        // the next block waits for mode 3 to end
+        bus.tick((OAM_SCAN_DOTS + DRAWING_DOTS) as u64);
+        assert_eq!(bus.read(0x8110), 0x00);
+        bus.tick(1);
+        assert_eq!(bus.read(0x811F), 0x20);
+        assert_eq!(bus.read(HDMA5), 0xFF);
+        assert_eq!(bus.take_stall_cycles(), 32);
+    }
+
+    #[test]
+    fn double_speed_halves_the_ppu_clock() {
+        let mut bus: MemoryBus = MemoryBus::new();
+        bus.set_cgb_mode(true);
+        bus.write(LCDC, 0x91);
+        bus.write(KEY1, 0x01);
+        bus.switch_speed();
+        bus.tick(DOTS_PER_LINE as u64);
+        assert_eq!(bus.read(LY), 0);
+        bus.tick(DOTS_PER_LINE as u64);
+        assert_eq!(bus.read(LY), 1);
+        // the timer follows the CPU clock: DIV counts every 256 T-cycles in both speeds
+        assert_eq!(bus.read(DIV), 3);
+    }
+
+    #[test]
+    fn oam_dma_copies_a_page_and_locks_oam() {
+        let mut bus: MemoryBus = MemoryBus::new();
+        for i in 0..0xA0u16 {
+            bus.write(0xC100 + i, i as u8);
+        }
+        bus.write(DMA_REGISTER, 0xC1);
+        // one M-cycle of delay, then bytes 0 to 3
+        bus.tick(20);
+        assert!(bus.dma.is_active());
+        // OAM reads 0xFF and ignores writes, HRAM stays reachable, WRAM returns the DMA byte
+        assert_eq!(bus.read(0xFE00), 0xFF);
+        bus.write(0xFE00, 0x55);
+        bus.write(0xFF80, 0x42);
+        assert_eq!(bus.read(0xFF80), 0x42);
+        assert_eq!(bus.read(0xC000), 0x03);
+        bus.tick(156 * 4);
+        assert!(!bus.dma.is_active());
+        for i in 0..0xA0u16 {
+            assert_eq!(bus.read(0xFE00 + i), i as u8);
+        }
+    }
+
+    #[test]
+    fn boot_rom_is_unmapped_by_boot_register() {
+        let mut bus: MemoryBus = banked_bus();
+        bus.rom.set_boot_rom(vec![0xAA; 0x900]);
+        assert_eq!(bus.read(0x0000), 0xAA);
+        // the cartridge header stays visible between the two parts of the CGB boot ROM
+        assert_eq!(bus.read(0x0100), 0x00);
+        assert_eq!(bus.read(0x0200), 0xAA);
+        bus.write(BOOT, 0x01);
+        assert_eq!(bus.read(0x0000), 0x00);
+        bus.write(BOOT, 0x00);
+        assert_eq!(bus.read(0x0000), 0x00);
+    }
+
+    /// Starts the LCD in pixel FIFO mode with a background of color 3 and BGP = 0xFF,
+    /// then runs to the first dot of line 1.
+    fn drawing_bus() -> MemoryBus {
+        let mut bus: MemoryBus = MemoryBus::new();
+        bus.ppu.set_render_mode(RenderMode::PixelFifo);
+        for addr in 0x8000..0x8010 {
+            bus.write(addr, 0xFF);
+        }
+        bus.write(BGP, 0xFF);
+        bus.write(LCDC, 0x91);
+        bus.tick(DOTS_PER_LINE as u64);
+        bus
+    }
+
+    fn line_1(bus: &MemoryBus) -> Vec<u8> {
+        bus.ppu.get_framebuffer()[SCREEN_WIDTH..SCREEN_WIDTH * 2].to_vec()
+    }
+
+    /// BGP set to 0 at `dot` of line 1, by the bus directly.
+    fn bgp_written_at(dot: u64) -> Vec<u8> {
+        let mut bus: MemoryBus = drawing_bus();
+        bus.tick(dot);
+        bus.write(BGP, 0x00);
+        bus.tick(DOTS_PER_LINE as u64 - dot);
+        line_1(&bus)
+    }
+
+    #[test]
+    fn cpu_write_lands_on_its_m_cycle() {
+        let reference: Vec<u8> = bgp_written_at(200);
+        assert!(reference.contains(&0) && reference.contains(&3));
+
+        // LDH (0x47),A: the write is the third M-cycle of the instruction
+        let mut bus: MemoryBus = drawing_bus();
+        let mut cpu: CPU = CPU::new();
+        bus.write(0xC000, 0xE0);
+        bus.write(0xC001, 0x47);
+        cpu.change_pc(0xC000);
+        bus.tick(188);
+        cpu.step(&mut bus);
+        assert_eq!(cpu.get_cycles(), 12);
+        assert_eq!(bus.take_cpu_cycles(), 12);
+        bus.tick(DOTS_PER_LINE as u64 - 200);
+        assert_eq!(line_1(&bus), reference);
+        // applying it when the instruction starts would change the picture
+        assert_ne!(bgp_written_at(188), reference);
+    }
+
+    #[test]
+    fn serial_interrupt_after_eight_bits() {
+        let mut bus: MemoryBus = MemoryBus::new();
+        bus.get_serial_mut().set_peer(Box::new(Loopback));
+        bus.write(IF, 0x00);
+        bus.write(SB, 0x3C);
+        bus.write(SC, 0x81);
+        bus.tick(512 * 8 - 4);
+        assert_eq!(bus.read(IF) & INT_SERIAL, 0);
+        bus.tick(4);
+        assert_eq!(bus.read(IF) & INT_SERIAL, INT_SERIAL);
+        assert_eq!(bus.read(SB), 0x3C);
+    }
+}
@@ -1,12 +1,22 @@
 pub mod rom;
 pub mod bus;
 pub mod memory_bus;
-mod io;
+pub mod io;
+mod io_register;
 mod w_ram;
 pub mod v_ram;
 pub mod h_ram;
 pub mod echo_ram;
 mod e_ram;
-mod oam;
+pub mod oam;
 mod interrupt;
-mod not_usable;
\ No newline at end of file
+mod not_usable;
+pub mod timer;
+pub mod dma;
+pub mod color_palette;
+pub mod hdma;
+pub mod joypad;
+pub mod serial;
+pub mod infrared;
+pub mod region;
+
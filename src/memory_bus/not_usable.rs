@@ -1,5 +1,9 @@
 use crate::error::memory_error::MemoryError;
+use crate::error::state_error::StateError;
 use crate::memory_bus::bus::BUS;
+use crate::state::snapshot::Snapshot;
+use crate::state::state_reader::StateReader;
+use crate::state::state_writer::StateWriter;
 
 pub struct NotUsable{
     r: [u8; 0x60] // 128 bytes
@@ -11,11 +15,20 @@ impl NotUsable{
             r: [0; 0x60]
         }
     }
+
+    pub fn get_bytes(&self) -> &[u8] {
+        &self.r
+    }
+
+    /// Copies `bytes` from the start of the area, ignoring what doesn't fit.
+    pub fn set_bytes(&mut self, bytes: &[u8]) {
+        let size: usize = bytes.len().min(self.r.len());
+        self.r[..size].copy_from_slice(&bytes[..size]);
+    }
 }
 
 impl BUS for NotUsable {
     fn read(&self, addr: u16) -> Result<u8, MemoryError> {
-        dbg!("Reading from NOT USABLE");
         match addr {
             0xFEA0..=0xFEFF => Ok(self.r[(addr - 0xFEA0) as usize]),
             _ => Err(MemoryError::InvalidAddress(addr))
@@ -23,7 +36,6 @@ impl BUS for NotUsable {
     }
 
     fn write(&mut self, addr: u16, data: u8) -> Result<(), MemoryError> {
-        dbg!("In theory i cannot write here... ");
         match addr {
             0xFEA0..=0xFEFF => {
                 self.r[(addr - 0xFEA0) as usize] = data;
@@ -32,4 +44,14 @@ impl BUS for NotUsable {
             _ => Err(MemoryError::InvalidAddress(addr))
         }
     }
-}
\ No newline at end of file
+}
+
+impl Snapshot for NotUsable {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.r);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        reader.read_bytes(&mut self.r)
+    }
+}
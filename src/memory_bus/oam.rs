@@ -1,5 +1,9 @@
 use crate::error::memory_error::MemoryError;
+use crate::error::state_error::StateError;
 use crate::memory_bus::bus::BUS;
+use crate::state::snapshot::Snapshot;
+use crate::state::state_reader::StateReader;
+use crate::state::state_writer::StateWriter;
 
 pub struct OAM{
     r: [u8; 0xA0] // 128 bytes 
@@ -11,11 +15,25 @@ impl OAM{
             r: [0; 0xA0]
         }
     }
+
+    pub fn get_bytes(&self) -> &[u8] {
+        &self.r
+    }
+
+    /// Copies `bytes` from the start of the area, ignoring what doesn't fit.
+    pub fn set_bytes(&mut self, bytes: &[u8]) {
+        let size: usize = bytes.len().min(self.r.len());
+        self.r[..size].copy_from_slice(&bytes[..size]);
+    }
+
+    /// Returns the byte at `addr` without going through the CPU side checks.
+    pub fn read_byte(&self, addr: u16) -> u8 {
+        self.r[(addr - 0xFE00) as usize]
+    }
 }
 
 impl BUS for OAM {
     fn read(&self, addr: u16) -> Result<u8, MemoryError> {
-        dbg!("Reading from OAM");
         match addr {
             0xFE00..=0xFE9F => Ok(self.r[(addr - 0xFE00) as usize]),
             _ => Err(MemoryError::InvalidAddress(addr))
@@ -31,4 +49,14 @@ impl BUS for OAM {
             _ => Err(MemoryError::InvalidAddress(addr))
         }
     }
-}
\ No newline at end of file
+}
+
+impl Snapshot for OAM {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.r);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        reader.read_bytes(&mut self.r)
+    }
+}
@@ -0,0 +1,16 @@
+/// Memory areas that can be read or filled in one go, to exchange them with states of
+/// other emulators (see [`crate::state::bess`]).
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Region {
+    /// Every work RAM bank, bank 0 first: 8 KiB on DMG, 32 KiB in CGB mode.
+    WRAM,
+    /// Both video RAM banks in CGB mode, only the first one otherwise.
+    VRAM,
+    ExternalRAM,
+    OAM,
+    /// The unusable area right after OAM (0xFEA0–0xFEFF).
+    ExtraOAM,
+    HRAM,
+    BgPalettes,
+    ObjPalettes,
+}
@@ -1,18 +1,98 @@
 use std::fs::File;
 use std::io::{self, Read};
 
+use crate::error::state_error::StateError;
+use crate::state::snapshot::Snapshot;
+use crate::state::state_reader::StateReader;
+use crate::state::state_writer::StateWriter;
+
 pub struct ROM {
     pub bank0: [u8; 0x4000],
+    // bank currently mapped at 0x4000–0x7FFF
     pub bank1: [u8; 0x4000],
+    // every 16 KiB bank of an image that doesn't fit in 32 KiB
+    banks: Vec<[u8; 0x4000]>,
+    // index in `banks` of the bank mapped at 0x4000–0x7FFF
+    bank: usize,
+    // DMG (256 bytes) or CGB (2304 bytes) boot ROM, laid over the cartridge until BOOT is written
+    boot_rom: Option<Vec<u8>>,
+    boot_rom_mapped: bool,
 }
 
 impl ROM {
     pub fn new() -> Self {
         ROM {
             bank0: [0; 0x4000],
-            bank1: [0; 0x4000]
+            bank1: [0; 0x4000],
+            banks: Vec::new(),
+            bank: 1,
+            boot_rom: None,
+            boot_rom_mapped: false,
+        }
+    }
+
+    /// Installs a boot ROM and maps it over the cartridge.
+    ///
+    /// # Parameters
+    /// - `boot_rom`: The 256 bytes mapped at 0x0000–0x00FF, or the 2304 bytes of the CGB
+    ///   boot ROM, whose second part is mapped at 0x0200–0x08FF around the cartridge header.
+    pub fn set_boot_rom(&mut self, boot_rom: Vec<u8>) {
+        self.boot_rom = Some(boot_rom);
+        self.boot_rom_mapped = true;
+    }
+
+    pub fn has_boot_rom(&self) -> bool {
+        self.boot_rom.is_some()
+    }
+
+    /// Maps or unmaps the boot ROM, if there is one. Writing BOOT (0xFF50) unmaps it.
+    pub fn set_boot_rom_mapped(&mut self, mapped: bool) {
+        self.boot_rom_mapped = mapped && self.boot_rom.is_some();
+    }
+
+    /// Maps a GBS rip: `data` is placed at `load_address` and the result split in banks.
+    /// The RST vectors jump to `load_address` plus the vector, where rips expect them.
+    pub fn load_gbs(&mut self, data: &[u8], load_address: u16) {
+        let mut image: Vec<u8> = vec![0; load_address as usize];
+        // a rip loaded below 0x40 brings its own vectors
+        for vector in (0..0x40).step_by(8).take_while(|vector| vector + 3 <= load_address as usize) {
+            let target: u16 = load_address + vector as u16;
+            image[vector..vector + 3].copy_from_slice(&[0xC3, target as u8, (target >> 8) as u8]); // JP target
         }
+        image.extend_from_slice(data);
+        let size: usize = image.len().max(0x8000).div_ceil(0x4000) * 0x4000;
+        image.resize(size, 0);
+        self.banks = image.chunks(0x4000)
+            .map(|chunk| chunk.try_into().expect("Chunks are 16 KiB"))
+            .collect();
+        self.bank0 = self.banks[0];
+        self.bank1 = self.banks[1];
+        self.bank = 1;
     }
+
+    /// Maps `bank` at 0x4000–0x7FFF (bank 0 selects bank 1, as on MBC1). Does nothing
+    /// for images without banks.
+    pub fn select_bank(&mut self, bank: u8) {
+        if self.banks.is_empty() {
+            return;
+        }
+        self.bank = (bank.max(1) as usize) % self.banks.len();
+        self.bank1 = self.banks[self.bank];
+    }
+
+    /// Returns the bank mapped at 0x4000–0x7FFF.
+    pub fn get_bank(&self) -> u8 {
+        self.bank as u8
+    }
+
+    /// CPU write to the ROM area. Only the bank number register (0x2000–0x3FFF) is
+    /// handled.
+    pub fn write_byte(&mut self, addr: u16, value: u8) {
+        if let 0x2000..=0x3FFF = addr {
+            self.select_bank(value);
+        }
+    }
+
     pub fn read(&mut self, path: &str) -> io::Result<()> {
         let mut file = File::open(path)?;
         let mut buffer = [0u8; 0x8000]; // 32 KB buffer
@@ -26,7 +106,37 @@ impl ROM {
         Ok(())        
     }
     
+    /// Returns the CGB flag of the cartridge header (0x0143).
+    pub fn get_cgb_flag(&self) -> u8 {
+        self.bank0[0x0143]
+    }
+
+    /// Returns `true` if the cartridge supports (0x80) or requires (0xC0) Game Boy Color mode.
+    pub fn is_cgb(&self) -> bool {
+        self.get_cgb_flag() & 0x80 != 0
+    }
+
+    /// Returns `true` if the header asks for the Super Game Boy functions (SGB flag 0x03
+    /// at 0x0146, with the old licensee code 0x33).
+    pub fn is_sgb(&self) -> bool {
+        self.bank0[0x0146] == 0x03 && self.bank0[0x014B] == 0x33
+    }
+
+    /// Returns the 64-bit FNV-1a hash of the image, which tells save states of
+    /// different cartridges apart.
+    pub fn get_hash(&self) -> u64 {
+        let banks: Vec<&[u8; 0x4000]> = if self.banks.is_empty() { vec![&self.bank0, &self.bank1] } else { self.banks.iter().collect() };
+        banks.iter()
+            .flat_map(|bank| bank.iter())
+            .fold(0xCBF2_9CE4_8422_2325, |hash: u64, byte| (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01B3))
+    }
+
     pub fn read_byte(&self, addr: u16) -> u8 {
+        if let (true, Some(boot_rom)) = (self.boot_rom_mapped, self.boot_rom.as_ref()) {
+            if matches!(addr, 0x0000..=0x00FF | 0x0200..=0x08FF) && (addr as usize) < boot_rom.len() {
+                return boot_rom[addr as usize];
+            }
+        }
         match addr { 
             0x0000..=0x3FFF => self.bank0[addr as usize],
             0x4000..=0x7FFF => self.bank1[addr as usize - 0x4000],
@@ -36,4 +146,96 @@ impl ROM {
     
 }
 
-//C3 20 C2 D6 05 30 FC 1F 30 00 CE 01 D0 C8 00 C9
\ No newline at end of file
+//C3 20 C2 D6 05 30 FC 1F 30 00 CE 01 D0 C8 00 C9
+
+/// Only the mapper state is saved: the contents come from the cartridge, which the save
+/// state identifies by its hash.
+impl Snapshot for ROM {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u32(self.bank as u32);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        let bank: usize = reader.read_u32()? as usize;
+        if self.banks.is_empty() {
+            return Ok(());
+        }
+        if bank >= self.banks.len() {
+            return Err(StateError::InvalidValue("ROM bank"));
+        }
+        self.bank = bank;
+        self.bank1 = self.banks[bank];
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn boot_rom_is_laid_over_the_cartridge() {
+        let mut rom: ROM = ROM::new();
+        rom.bank0[0x0000] = 0x11;
+        rom.bank0[0x0100] = 0x22;
+        rom.bank0[0x0200] = 0x33;
+        rom.set_boot_rom(vec![0xAA; 0x900]);
+        // the CGB boot ROM leaves the header visible
+        assert_eq!([rom.read_byte(0x0000), rom.read_byte(0x0100), rom.read_byte(0x0200)], [0xAA, 0x22, 0xAA]);
+        rom.set_boot_rom_mapped(false);
+        assert_eq!([rom.read_byte(0x0000), rom.read_byte(0x0100), rom.read_byte(0x0200)], [0x11, 0x22, 0x33]);
+
+        // a DMG boot ROM only covers the first 256 bytes
+        let mut rom: ROM = ROM::new();
+        rom.bank0[0x0200] = 0x33;
+        rom.set_boot_rom(vec![0xAA; 0x100]);
+        assert_eq!([rom.read_byte(0x00FF), rom.read_byte(0x0200)], [0xAA, 0x33]);
+        // nothing to map without a boot ROM
+        let mut rom: ROM = ROM::new();
+        rom.bank0[0x0000] = 0x11;
+        rom.set_boot_rom_mapped(true);
+        assert_eq!(rom.read_byte(0x0000), 0x11);
+    }
+
+    #[test]
+    fn gbs_images_are_split_in_banks() {
+        let mut rom: ROM = ROM::new();
+        let mut data: Vec<u8> = vec![0; 0x8000];
+        data[0] = 0x01;
+        data[0x4000 - 0x0400] = 0x02;
+        data[0x8000 - 0x0400] = 0x03;
+        rom.load_gbs(&data, 0x0400);
+        assert_eq!([rom.read_byte(0x0400), rom.read_byte(0x4000)], [0x01, 0x02]);
+        // RST 0x38 jumps to 0x0438
+        assert_eq!([rom.read_byte(0x0038), rom.read_byte(0x0039), rom.read_byte(0x003A)], [0xC3, 0x38, 0x04]);
+        rom.write_byte(0x2000, 2);
+        assert_eq!((rom.get_bank(), rom.read_byte(0x4000)), (2, 0x03));
+        // bank 0 selects bank 1, out of range banks wrap
+        rom.write_byte(0x3FFF, 0);
+        assert_eq!(rom.get_bank(), 1);
+        rom.select_bank(4);
+        assert_eq!(rom.get_bank(), 1);
+        // writes outside the bank register are ignored
+        rom.write_byte(0x1000, 2);
+        assert_eq!(rom.get_bank(), 1);
+    }
+
+    #[test]
+    fn states_restore_the_bank_of_the_same_image() {
+        let mut rom: ROM = ROM::new();
+        let hash: u64 = rom.get_hash();
+        rom.bank1[0] = 0x01;
+        assert_ne!(rom.get_hash(), hash);
+
+        rom.load_gbs(&[0; 0xC000], 0x0000);
+        rom.select_bank(2);
+        let mut writer: StateWriter = StateWriter::new();
+        rom.save_state(&mut writer);
+        let bytes: Vec<u8> = writer.into_bytes();
+        rom.select_bank(1);
+        rom.load_state(&mut StateReader::new(&bytes)).unwrap();
+        assert_eq!(rom.get_bank(), 2);
+        let bank: [u8; 4] = 3u32.to_le_bytes();
+        assert!(matches!(rom.load_state(&mut StateReader::new(&bank)), Err(StateError::InvalidValue(_))));
+    }
+}
@@ -0,0 +1,261 @@
+use crate::constants::io_registers::{SB, SC};
+use crate::error::memory_error::MemoryError;
+use crate::error::state_error::StateError;
+use crate::memory_bus::bus::BUS;
+use crate::serial::disconnected::Disconnected;
+use crate::serial::serial_peer::SerialPeer;
+use crate::state::snapshot::Snapshot;
+use crate::state::state_reader::StateReader;
+use crate::state::state_writer::StateWriter;
+
+// T-cycles per bit with the internal clock: 8192 Hz, or 262144 Hz in CGB fast mode
+const BIT_CYCLES: u64 = 512;
+const FAST_BIT_CYCLES: u64 = 16;
+
+/// Serial port (SB and SC).
+///
+/// Writing SC with bit 7 set starts a transfer: on the internal clock (bit 0) the eight
+/// bits are shifted at 8192 Hz, or 32 times faster with the CGB fast clock (bit 1); on
+/// the external clock the transfer completes when the peer drives it. Both rates follow
+/// the CPU clock, so they double in CGB double speed mode. A completed byte clears SC
+/// bit 7 and raises the serial interrupt.
+pub struct Serial {
+    sb: u8,
+    sc: u8,
+    cgb: bool,
+    double_speed: bool,
+    peer: Box<dyn SerialPeer>,
+    // byte coming from the peer during an internal clock transfer
+    incoming: u8,
+    bits_left: u8,
+    // T-cycles until the next bit is shifted
+    bit_timer: u64,
+}
+
+impl Serial {
+    pub fn new() -> Serial {
+        Serial {
+            sb: 0,
+            sc: 0,
+            cgb: false,
+            double_speed: false,
+            peer: Box::new(Disconnected),
+            incoming: 0xFF,
+            bits_left: 0,
+            bit_timer: 0,
+        }
+    }
+
+    pub fn set_cgb_mode(&mut self, cgb: bool) {
+        self.cgb = cgb;
+    }
+
+    /// Peers count time at normal speed, while the bit clock follows the CPU.
+    pub fn set_double_speed(&mut self, double_speed: bool) {
+        self.double_speed = double_speed;
+    }
+
+    fn to_real_time(&self, cycles: u64) -> u64 {
+        if self.double_speed { cycles / 2 } else { cycles }
+    }
+
+    /// Plugs `peer` into the link port, replacing the current one.
+    pub fn set_peer(&mut self, peer: Box<dyn SerialPeer>) {
+        self.peer = peer;
+    }
+
+    /// Returns the error that broke the connection to the peer, once.
+    pub fn take_peer_error(&mut self) -> Option<std::io::Error> {
+        self.peer.take_error()
+    }
+
+    /// Returns `true` while a transfer is requested (SC bit 7).
+    pub fn is_transferring(&self) -> bool {
+        self.sc & 0x80 != 0
+    }
+
+    fn is_internal_clock(&self) -> bool {
+        self.sc & 0x01 != 0
+    }
+
+    fn bit_cycles(&self) -> u64 {
+        if self.cgb && self.sc & 0x02 != 0 { FAST_BIT_CYCLES } else { BIT_CYCLES }
+    }
+
+    fn start(&mut self) {
+        if self.is_internal_clock() {
+            let duration: u64 = self.to_real_time(self.bit_cycles() * 8);
+            self.incoming = self.peer.transfer(self.sb, duration);
+            self.bits_left = 8;
+            self.bit_timer = self.bit_cycles();
+        }
+    }
+
+    fn complete(&mut self, incoming: u8) {
+        self.sb = incoming;
+        self.sc &= 0x7F;
+        self.bits_left = 0;
+    }
+
+    /// Advances the port by `cycles` T-cycles.
+    ///
+    /// # Returns
+    /// `true` when a transfer completed and the serial interrupt must be requested.
+    pub fn tick(&mut self, cycles: u64) -> bool {
+        let elapsed: u64 = self.to_real_time(cycles);
+        self.peer.tick(elapsed);
+        if !self.is_transferring() {
+            return false;
+        }
+        if !self.is_internal_clock() {
+            return match self.peer.external_clock(self.sb, elapsed) {
+                Some(incoming) => {
+                    self.complete(incoming);
+                    true
+                }
+                None => false,
+            };
+        }
+        let mut cycles: u64 = cycles;
+        while cycles > 0 && self.bits_left > 0 {
+            let step: u64 = cycles.min(self.bit_timer);
+            cycles -= step;
+            self.bit_timer -= step;
+            if self.bit_timer > 0 {
+                continue;
+            }
+            // the top bit goes out, the next bit of the peer's byte comes in
+            self.bits_left -= 1;
+            self.sb = (self.sb << 1) | ((self.incoming >> self.bits_left) & 0x01);
+            self.bit_timer = self.bit_cycles();
+        }
+        if self.bits_left == 0 {
+            self.complete(self.sb);
+            return true;
+        }
+        false
+    }
+}
+
+impl BUS for Serial {
+    fn read(&self, addr: u16) -> Result<u8, MemoryError> {
+        match addr {
+            SB => Ok(self.sb),
+            SC => Ok(self.sc),
+            _ => Err(MemoryError::InvalidAddress(addr))
+        }
+    }
+
+    fn write(&mut self, addr: u16, data: u8) -> Result<(), MemoryError> {
+        match addr {
+            SB => self.sb = data,
+            SC => {
+                let starting: bool = !self.is_transferring() && data & 0x80 != 0;
+                self.sc = data & 0x83;
+                if starting {
+                    self.start();
+                } else if !self.is_transferring() {
+                    // clearing bit 7 aborts the transfer
+                    self.bits_left = 0;
+                }
+            }
+            _ => return Err(MemoryError::InvalidAddress(addr))
+        }
+        Ok(())
+    }
+}
+
+/// The peer is not part of the state: whatever is plugged in stays plugged in.
+impl Snapshot for Serial {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.sb);
+        writer.write_u8(self.sc);
+        writer.write_bool(self.cgb);
+        writer.write_bool(self.double_speed);
+        writer.write_u8(self.incoming);
+        writer.write_u8(self.bits_left);
+        writer.write_u64(self.bit_timer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.sb = reader.read_u8()?;
+        self.sc = reader.read_u8()?;
+        self.cgb = reader.read_bool()?;
+        self.double_speed = reader.read_bool()?;
+        self.incoming = reader.read_u8()?;
+        self.bits_left = reader.read_u8()?;
+        self.bit_timer = reader.read_u64()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serial::capture::SerialCapture;
+    use crate::serial::loopback::Loopback;
+
+    fn send(serial: &mut Serial, data: u8, sc: u8) {
+        serial.write(SB, data).unwrap();
+        serial.write(SC, sc).unwrap();
+    }
+
+    #[test]
+    fn loopback_echoes_sb_after_eight_bits() {
+        let mut serial: Serial = Serial::new();
+        serial.set_peer(Box::new(Loopback));
+        send(&mut serial, 0xA5, 0x81);
+        assert!(!serial.tick(BIT_CYCLES * 8 - 1));
+        assert!(serial.is_transferring());
+        assert!(serial.tick(1));
+        assert_eq!(serial.read(SB).unwrap(), 0xA5);
+        assert_eq!(serial.read(SC).unwrap(), 0x01);
+        // nothing more until the next transfer
+        assert!(!serial.tick(BIT_CYCLES * 8));
+    }
+
+    #[test]
+    fn bits_shift_in_one_at_a_time() {
+        let mut serial: Serial = Serial::new();
+        send(&mut serial, 0x00, 0x81);
+        serial.tick(BIT_CYCLES * 3);
+        // three 1 bits of the disconnected line came in
+        assert_eq!(serial.read(SB).unwrap(), 0x07);
+    }
+
+    #[test]
+    fn capture_records_the_bytes_sent() {
+        let capture: SerialCapture = SerialCapture::new();
+        let mut serial: Serial = Serial::new();
+        serial.set_peer(Box::new(capture.clone()));
+        for byte in b"Passed" {
+            send(&mut serial, *byte, 0x81);
+            assert!(serial.tick(BIT_CYCLES * 8));
+            assert_eq!(serial.read(SB).unwrap(), 0xFF);
+        }
+        assert_eq!(capture.get_text(), "Passed");
+    }
+
+    #[test]
+    fn fast_clock_only_on_cgb() {
+        let mut serial: Serial = Serial::new();
+        send(&mut serial, 0x12, 0x83);
+        assert!(!serial.tick(FAST_BIT_CYCLES * 8));
+        serial.write(SC, 0x00).unwrap();
+        serial.set_cgb_mode(true);
+        send(&mut serial, 0x12, 0x83);
+        assert!(serial.tick(FAST_BIT_CYCLES * 8));
+    }
+
+    #[test]
+    fn external_clock_waits_and_can_be_aborted() {
+        let mut serial: Serial = Serial::new();
+        serial.set_peer(Box::new(Loopback));
+        send(&mut serial, 0x42, 0x80);
+        assert!(!serial.tick(BIT_CYCLES * 64));
+        assert!(serial.is_transferring());
+        serial.write(SC, 0x00).unwrap();
+        assert!(!serial.is_transferring());
+        assert_eq!(serial.read(SB).unwrap(), 0x42);
+    }
+}
@@ -0,0 +1,290 @@
+use crate::constants::io_registers::{DIV, TAC, TIMA, TMA};
+use crate::error::memory_error::MemoryError;
+use crate::error::state_error::StateError;
+use crate::memory_bus::bus::BUS;
+use crate::state::snapshot::Snapshot;
+use crate::state::state_reader::StateReader;
+use crate::state::state_writer::StateWriter;
+
+/// DMG/CGB timer (DIV, TIMA, TMA and TAC).
+///
+/// DIV is the upper byte of a 16-bit system counter that advances every T-cycle. TIMA is
+/// not driven by its own clock: it increments on the falling edge of one counter bit
+/// (selected by TAC) AND'ed with the TAC enable bit. Because of that, resetting DIV or
+/// changing TAC can produce an extra increment, exactly like the hardware does.
+pub struct Timer {
+    counter: u16,
+    tima: u8,
+    tma: u8,
+    tac: u8,
+    // TIMA overflowed during the last M-cycle; TMA is loaded during the next one
+    overflow: bool,
+    // current M-cycle is the one where TMA is copied into TIMA
+    reloading: bool,
+    double_speed: bool,
+    // frame sequencer clocks produced since the last `take_apu_events`
+    apu_events: u32,
+}
+
+impl Timer {
+    pub fn new() -> Timer {
+        Timer {
+            counter: 0,
+            tima: 0,
+            tma: 0,
+            tac: 0,
+            overflow: false,
+            reloading: false,
+            double_speed: false,
+            apu_events: 0,
+        }
+    }
+
+    /// The APU frame sequencer follows a different counter bit in double speed mode, so it
+    /// keeps running at 512 Hz.
+    pub fn set_double_speed(&mut self, double_speed: bool) {
+        self.double_speed = double_speed;
+    }
+
+    /// Returns how many times the APU frame sequencer must be clocked since the last call.
+    pub fn take_apu_events(&mut self) -> u32 {
+        let events: u32 = self.apu_events;
+        self.apu_events = 0;
+        events
+    }
+
+    fn apu_bit(&self) -> u16 {
+        if self.double_speed { 13 } else { 12 }
+    }
+
+    /// Sets DIV as other emulators save it, with the lower bits of the counter cleared.
+    pub fn set_div(&mut self, div: u8) {
+        self.counter = (div as u16) << 8;
+    }
+
+    /// Bit of the system counter multiplexed into TIMA for the current TAC.
+    fn selected_bit(&self) -> u16 {
+        match self.tac & 0x03 {
+            0b00 => 9, // 4096 Hz
+            0b01 => 3, // 262144 Hz
+            0b10 => 5, // 65536 Hz
+            _ => 7,    // 16384 Hz
+        }
+    }
+
+    /// Output of the AND gate between the TAC enable bit and the selected counter bit.
+    fn signal(&self) -> bool {
+        self.tac & 0x04 != 0 && (self.counter >> self.selected_bit()) & 1 != 0
+    }
+
+    fn increment_tima(&mut self) {
+        let (r, overflow) = self.tima.overflowing_add(1);
+        self.tima = r;
+        if overflow {
+            self.overflow = true;
+        }
+    }
+
+    /// Changes the system counter or TAC, incrementing TIMA if the multiplexer output
+    /// goes from high to low as a result. A falling edge on the APU bit of the counter
+    /// (DIV bit 4, or bit 5 in double speed) clocks the frame sequencer.
+    fn update_signal<F: FnOnce(&mut Timer)>(&mut self, change: F) {
+        let before: bool = self.signal();
+        let apu_before: bool = (self.counter >> self.apu_bit()) & 1 != 0;
+        change(self);
+        if before && !self.signal() {
+            self.increment_tima();
+        }
+        if apu_before && (self.counter >> self.apu_bit()) & 1 == 0 {
+            self.apu_events += 1;
+        }
+    }
+
+    /// Advances the timer by a single M-cycle (4 T-cycles).
+    ///
+    /// # Returns
+    /// `true` when the timer interrupt must be requested.
+    fn step(&mut self) -> bool {
+        let mut interrupt: bool = false;
+        self.reloading = false;
+        if self.overflow {
+            self.overflow = false;
+            self.reloading = true;
+            self.tima = self.tma;
+            interrupt = true;
+        }
+        self.update_signal(|t| t.counter = t.counter.wrapping_add(4));
+        interrupt
+    }
+
+    /// Advances the timer by `cycles` T-cycles.
+    ///
+    /// # Returns
+    /// `true` when the timer interrupt must be requested.
+    pub fn tick(&mut self, cycles: u64) -> bool {
+        let mut interrupt: bool = false;
+        for _ in 0..cycles / 4 {
+            interrupt |= self.step();
+        }
+        interrupt
+    }
+}
+
+impl BUS for Timer {
+    fn read(&self, addr: u16) -> Result<u8, MemoryError> {
+        match addr {
+            DIV => Ok((self.counter >> 8) as u8),
+            TIMA => Ok(self.tima),
+            TMA => Ok(self.tma),
+            TAC => Ok(self.tac),
+            _ => Err(MemoryError::InvalidAddress(addr))
+        }
+    }
+
+    fn write(&mut self, addr: u16, data: u8) -> Result<(), MemoryError> {
+        match addr {
+            DIV => self.update_signal(|t| t.counter = 0),
+            TIMA => {
+                // during the reload cycle the value coming from TMA wins
+                if !self.reloading {
+                    self.tima = data;
+                    // writing in the cycle after the overflow cancels the reload and interrupt
+                    self.overflow = false;
+                }
+            }
+            TMA => {
+                self.tma = data;
+                if self.reloading {
+                    self.tima = data;
+                }
+            }
+            TAC => self.update_signal(|t| t.tac = data & 0x07),
+            _ => return Err(MemoryError::InvalidAddress(addr))
+        }
+        Ok(())
+    }
+}
+
+impl Snapshot for Timer {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u16(self.counter);
+        writer.write_u8(self.tima);
+        writer.write_u8(self.tma);
+        writer.write_u8(self.tac);
+        writer.write_bool(self.overflow);
+        writer.write_bool(self.reloading);
+        writer.write_bool(self.double_speed);
+        writer.write_u32(self.apu_events);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.counter = reader.read_u16()?;
+        self.tima = reader.read_u8()?;
+        self.tma = reader.read_u8()?;
+        self.tac = reader.read_u8()? & 0x07;
+        self.overflow = reader.read_bool()?;
+        self.reloading = reader.read_bool()?;
+        self.double_speed = reader.read_bool()?;
+        self.apu_events = reader.read_u32()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::io_registers::{IF, INT_TIMER};
+    use crate::cpu::cpu::CPU;
+    use crate::memory_bus::memory_bus::MemoryBus;
+
+    /// Timer at 262144 Hz (TIMA increments every 16 T-cycles) with TIMA about to overflow.
+    fn overflowing_timer() -> Timer {
+        let mut timer: Timer = Timer::new();
+        timer.write(TMA, 0x42).unwrap();
+        timer.write(TIMA, 0xFF).unwrap();
+        timer.write(TAC, 0x05).unwrap();
+        timer
+    }
+
+    #[test]
+    fn tima_follows_the_selected_bit() {
+        let mut timer: Timer = Timer::new();
+        timer.write(TAC, 0x05).unwrap();
+        timer.tick(64);
+        assert_eq!(timer.read(TIMA).unwrap(), 4);
+        timer.write(TAC, 0x04).unwrap();
+        timer.tick(1024);
+        assert_eq!(timer.read(TIMA).unwrap(), 5);
+    }
+
+    #[test]
+    fn reload_happens_one_cycle_after_the_overflow() {
+        let mut timer: Timer = overflowing_timer();
+        assert!(!timer.tick(16));
+        assert_eq!(timer.read(TIMA).unwrap(), 0x00);
+        assert!(timer.tick(4));
+        assert_eq!(timer.read(TIMA).unwrap(), 0x42);
+    }
+
+    #[test]
+    fn tima_write_after_overflow_cancels_the_reload() {
+        let mut timer: Timer = overflowing_timer();
+        timer.tick(16);
+        timer.write(TIMA, 0x10).unwrap();
+        assert!(!timer.tick(4));
+        assert_eq!(timer.read(TIMA).unwrap(), 0x10);
+    }
+
+    #[test]
+    fn tima_write_during_the_reload_is_ignored() {
+        let mut timer: Timer = overflowing_timer();
+        timer.tick(20);
+        timer.write(TIMA, 0x10).unwrap();
+        assert_eq!(timer.read(TIMA).unwrap(), 0x42);
+    }
+
+    #[test]
+    fn tma_write_during_the_reload_reaches_tima() {
+        let mut timer: Timer = overflowing_timer();
+        timer.tick(20);
+        timer.write(TMA, 0x77).unwrap();
+        assert_eq!(timer.read(TIMA).unwrap(), 0x77);
+        timer.tick(4);
+        timer.write(TMA, 0x01).unwrap();
+        assert_eq!(timer.read(TIMA).unwrap(), 0x77);
+    }
+
+    #[test]
+    fn div_reset_and_tac_change_glitches() {
+        let mut timer: Timer = Timer::new();
+        timer.write(TAC, 0x05).unwrap();
+        // counter = 8, bit 3 high: resetting DIV is a falling edge
+        timer.tick(8);
+        timer.write(DIV, 0x00).unwrap();
+        assert_eq!(timer.read(TIMA).unwrap(), 1);
+        // same with bit 3 high and the timer turned off
+        timer.tick(8);
+        timer.write(TAC, 0x01).unwrap();
+        assert_eq!(timer.read(TIMA).unwrap(), 2);
+    }
+
+    #[test]
+    fn cpu_write_in_the_overflow_cycle_cancels_the_reload() {
+        let mut bus: MemoryBus = MemoryBus::new();
+        let mut cpu: CPU = CPU::new();
+        bus.write(TMA, 0x42);
+        bus.write(TIMA, 0xFF);
+        bus.write(TAC, 0x05);
+        bus.write(DIV, 0x00);
+        // LDH (0x05),A with A = 0x10, its write lands in the M-cycle TIMA overflows
+        bus.write(0xC000, 0xE0);
+        bus.write(0xC001, 0x05);
+        cpu.get_registers().set_a(0x10);
+        cpu.change_pc(0xC000);
+        bus.tick(4);
+        cpu.step(&mut bus);
+        bus.tick(8);
+        assert_eq!(bus.read(TIMA), 0x10);
+        assert_eq!(bus.read(IF) & INT_TIMER, 0);
+    }
+}
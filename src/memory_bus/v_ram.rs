@@ -1,23 +1,51 @@
 use crate::error::memory_error::MemoryError;
+use crate::error::state_error::StateError;
 use crate::memory_bus::bus::BUS;
+use crate::state::snapshot::Snapshot;
+use crate::state::state_reader::StateReader;
+use crate::state::state_writer::StateWriter;
 
 pub struct VRAM{
-    r: [u8; 0x2000] //8 kiB = 8192 bytes = 0x2000 
+    r: [[u8; 0x2000]; 2], //8 kiB = 8192 bytes = 0x2000 per bank, bank 1 only on CGB
+    bank: usize,
 }
 
 impl VRAM{
     pub fn new() -> VRAM{
         VRAM{
-            r: [0; 0x2000]
+            r: [[0; 0x2000]; 2],
+            bank: 0,
         }
     }
+
+    /// Selects the bank seen by the CPU (VBK bit 0).
+    pub fn set_bank(&mut self, bank: u8) {
+        self.bank = (bank & 0x01) as usize;
+    }
+
+    /// Returns both banks in order, or only bank 0 when `cgb` is `false`.
+    pub fn get_bytes(&self, cgb: bool) -> Vec<u8> {
+        let banks: usize = if cgb { 2 } else { 1 };
+        self.r[..banks].concat()
+    }
+
+    /// Fills the banks in order from `bytes`.
+    pub fn set_bytes(&mut self, bytes: &[u8]) {
+        for (bank, chunk) in self.r.iter_mut().zip(bytes.chunks(0x2000)) {
+            bank[..chunk.len()].copy_from_slice(chunk);
+        }
+    }
+
+    /// Returns the byte at `addr` in the given bank, as the PPU fetches it.
+    pub fn read_bank(&self, bank: u8, addr: u16) -> u8 {
+        self.r[(bank & 0x01) as usize][(addr - 0x8000) as usize]
+    }
 }
 
 impl BUS for VRAM {
     fn read(&self, addr: u16) -> Result<u8, MemoryError> {
-        dbg!("Reading from VRAM");
         match addr { 
-            0x8000..=0x9FFF => Ok(self.r[(addr - 0x8000) as usize]),
+            0x8000..=0x9FFF => Ok(self.r[self.bank][(addr - 0x8000) as usize]),
             _ => Err(MemoryError::InvalidAddress(addr))
         }
     }
@@ -25,10 +53,43 @@ impl BUS for VRAM {
     fn write(&mut self, addr: u16, data: u8) -> Result<(), MemoryError> {
         match addr { 
             0x8000..=0x9FFF => {
-                self.r[(addr - 0x8000) as usize] = data;
+                self.r[self.bank][(addr - 0x8000) as usize] = data;
                 Ok(())
             },
             _ => Err(MemoryError::InvalidAddress(addr))
         }
     }
-}
\ No newline at end of file
+}
+
+impl Snapshot for VRAM {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.r.iter().for_each(|bank| writer.write_bytes(bank));
+        writer.write_u8(self.bank as u8);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        for bank in self.r.iter_mut() {
+            reader.read_bytes(bank)?;
+        }
+        self.bank = (reader.read_u8()? & 0x01) as usize;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cpu_sees_the_selected_bank() {
+        let mut v_ram: VRAM = VRAM::new();
+        v_ram.write(0x9800, 0x01).unwrap();
+        v_ram.set_bank(0xFF);
+        assert_eq!(v_ram.bank, 1);
+        v_ram.write(0x9800, 0x80).unwrap();
+        assert_eq!(v_ram.read(0x9800).unwrap(), 0x80);
+        assert_eq!(v_ram.read_bank(0, 0x9800), 0x01);
+        assert_eq!(v_ram.read_bank(1, 0x9800), 0x80);
+        assert_eq!(v_ram.get_bytes(false).len(), 0x2000);
+    }
+}
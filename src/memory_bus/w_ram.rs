@@ -1,26 +1,57 @@
 use crate::error::memory_error::MemoryError;
+use crate::error::state_error::StateError;
 use crate::memory_bus::bus::BUS;
+use crate::state::snapshot::Snapshot;
+use crate::state::state_reader::StateReader;
+use crate::state::state_writer::StateWriter;
 
 pub struct WRAM  {
     r1: [u8; 0x1000],
-    r2: [u8; 0x1000],
+    r2: [[u8; 0x1000]; 7], // banks 1-7, only bank 1 is reachable on DMG
+    bank: usize,
 }
 
 impl WRAM {
     pub fn new() -> Self {
         WRAM {
             r1: [0; 0x1000],
-            r2: [0; 0x1000],
+            r2: [[0; 0x1000]; 7],
+            bank: 1,
+        }
+    }
+
+    /// Selects the bank mapped at 0xD000–0xDFFF (SVBK bits 0-2, 0 selects bank 1).
+    pub fn set_bank(&mut self, bank: u8) {
+        self.bank = match bank & 0x07 {
+            0 => 1,
+            b => b as usize,
+        };
+    }
+
+    /// Returns every bank in order, bank 0 first.
+    pub fn get_bytes(&self) -> Vec<u8> {
+        let mut bytes: Vec<u8> = self.r1.to_vec();
+        self.r2.iter().for_each(|bank| bytes.extend_from_slice(bank));
+        bytes
+    }
+
+    /// Fills the banks in order from `bytes`, which may stop after bank 1 as on DMG.
+    pub fn set_bytes(&mut self, bytes: &[u8]) {
+        let mut chunks = bytes.chunks(0x1000);
+        for bank in std::iter::once(&mut self.r1).chain(self.r2.iter_mut()) {
+            let Some(chunk) = chunks.next() else {
+                return;
+            };
+            bank[..chunk.len()].copy_from_slice(chunk);
         }
     }
 }
 
 impl BUS for WRAM {
     fn read(&self, address: u16) -> Result<u8, MemoryError> {
-        dbg!("Reading from WRAM");
         match address {
             0xC000..=0xCFFF => Ok(self.r1[(address - 0xC000) as usize]),
-            0xD000..=0xDFFF => Ok(self.r2[(address - 0xD000) as usize]),
+            0xD000..=0xDFFF => Ok(self.r2[self.bank - 1][(address - 0xD000) as usize]),
             _ => Err(MemoryError::InvalidAddress(address)),
         }
     }
@@ -32,10 +63,63 @@ impl BUS for WRAM {
                 Ok(())
             }
             0xD000..=0xDFFF => {
-                self.r2[(address - 0xD000) as usize] = value;
+                self.r2[self.bank - 1][(address - 0xD000) as usize] = value;
                 Ok(())
             }
             _ => Err(MemoryError::InvalidAddress(address)),
         }
     }
-}
\ No newline at end of file
+}
+
+impl Snapshot for WRAM {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.r1);
+        self.r2.iter().for_each(|bank| writer.write_bytes(bank));
+        writer.write_u8(self.bank as u8);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        reader.read_bytes(&mut self.r1)?;
+        for bank in self.r2.iter_mut() {
+            reader.read_bytes(bank)?;
+        }
+        self.bank = reader.read_u8()? as usize;
+        if !(1..=self.r2.len()).contains(&self.bank) {
+            return Err(StateError::InvalidValue("WRAM bank"));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bank_0_selects_bank_1() {
+        let mut w_ram: WRAM = WRAM::new();
+        w_ram.write(0xD000, 0x11).unwrap();
+        w_ram.set_bank(7);
+        w_ram.write(0xD000, 0x77).unwrap();
+        w_ram.set_bank(0);
+        assert_eq!(w_ram.bank, 1);
+        assert_eq!(w_ram.read(0xD000).unwrap(), 0x11);
+        w_ram.set_bank(0x0F);
+        assert_eq!(w_ram.read(0xD000).unwrap(), 0x77);
+    }
+
+    #[test]
+    fn state_keeps_the_last_bank() {
+        let mut w_ram: WRAM = WRAM::new();
+        w_ram.set_bank(7);
+        let mut writer: StateWriter = StateWriter::new();
+        w_ram.save_state(&mut writer);
+        let mut bytes: Vec<u8> = writer.into_bytes();
+        let mut restored: WRAM = WRAM::new();
+        restored.load_state(&mut StateReader::new(&bytes)).unwrap();
+        assert_eq!(restored.bank, 7);
+        // bank 0 is never selected and can't come from a valid state
+        *bytes.last_mut().unwrap() = 0;
+        assert!(restored.load_state(&mut StateReader::new(&bytes)).is_err());
+    }
+}
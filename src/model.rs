@@ -0,0 +1,12 @@
+/// Hardware model being emulated.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Model {
+    /// Original Game Boy. CGB features are never enabled.
+    DMG,
+    /// Game Boy Color. CGB cartridges run in CGB mode, DMG-only cartridges in the
+    /// DMG compatibility mode with a colorization palette.
+    CGB,
+    /// Super Game Boy. Cartridges flagged for it get colorization and a border, the
+    /// others run as on DMG.
+    SGB,
+}
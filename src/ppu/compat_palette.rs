@@ -0,0 +1,318 @@
+use crate::memory_bus::joypad::Button;
+use crate::memory_bus::rom::ROM;
+
+/// Colorization the CGB boot ROM applies to DMG-only cartridges: one of its 51 palette
+/// combinations, each giving 4 colors for the background, OBJ0 and OBJ1.
+///
+/// The combination is looked up from the title of games published by Nintendo, or picked
+/// by the button combination held while the boot logo is shown. The tables are those of
+/// the boot ROM, as transcribed in SameBoy's `cgb_boot.asm`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct CompatPalette {
+    combination: u8,
+}
+
+/// RGB555 colors of the boot ROM, 4 per palette. Combinations index them by palette, and
+/// a few start in the middle of one.
+const COLORS: [u16; 120] = [
+    0x7FFF, 0x32BF, 0x00D0, 0x0000,
+    0x639F, 0x4279, 0x15B0, 0x04CB,
+    0x7FFF, 0x6E31, 0x454A, 0x0000,
+    0x7FFF, 0x1BEF, 0x0200, 0x0000,
+    0x7FFF, 0x421F, 0x1CF2, 0x0000,
+    0x7FFF, 0x5294, 0x294A, 0x0000,
+    0x7FFF, 0x03FF, 0x012F, 0x0000,
+    0x7FFF, 0x03EF, 0x01D6, 0x0000,
+    0x7FFF, 0x42B5, 0x3DC8, 0x0000,
+    0x7E74, 0x03FF, 0x0180, 0x0000,
+    0x67FF, 0x77AC, 0x1A13, 0x2D6B,
+    0x7ED6, 0x4BFF, 0x2175, 0x0000,
+    0x53FF, 0x4A5F, 0x7E52, 0x0000,
+    0x4FFF, 0x7ED2, 0x3A4C, 0x1CE0,
+    0x03ED, 0x7FFF, 0x255F, 0x0000,
+    0x036A, 0x021F, 0x03FF, 0x7FFF,
+    0x7FFF, 0x01DF, 0x0112, 0x0000,
+    0x231F, 0x035F, 0x00F2, 0x0009,
+    0x7FFF, 0x03EA, 0x011F, 0x0000,
+    0x299F, 0x001A, 0x000C, 0x0000,
+    0x7FFF, 0x027F, 0x001F, 0x0000,
+    0x7FFF, 0x03E0, 0x0206, 0x0120,
+    0x7FFF, 0x7EEB, 0x001F, 0x7C00,
+    0x7FFF, 0x3FFF, 0x7E00, 0x001F,
+    0x7FFF, 0x03FF, 0x001F, 0x0000,
+    0x03FF, 0x001F, 0x000C, 0x0000,
+    0x7FFF, 0x033F, 0x0193, 0x0000,
+    0x0000, 0x4200, 0x037F, 0x7FFF,
+    0x7FFF, 0x7E8C, 0x7C00, 0x0000,
+    0x7FFF, 0x1BEF, 0x6180, 0x0000,
+];
+
+// a combination made of whole palettes
+const fn palettes(obj0: usize, obj1: usize, bg: usize) -> [usize; 3] {
+    [obj0 * 4, obj1 * 4, bg * 4]
+}
+
+/// First color in `COLORS` of OBJ0, OBJ1 and the background, for each combination.
+const COMBINATIONS: [[usize; 3]; 51] = [
+    palettes(4, 4, 29),
+    palettes(18, 18, 18),
+    palettes(20, 20, 20),
+    palettes(24, 24, 24),
+    palettes(9, 9, 9),
+    palettes(0, 0, 0),
+    palettes(27, 27, 27),
+    palettes(5, 5, 5),
+    palettes(12, 12, 12),
+    palettes(26, 26, 26),
+    palettes(16, 8, 8),
+    palettes(4, 28, 28),
+    palettes(4, 2, 2),
+    palettes(3, 4, 4),
+    palettes(4, 29, 29),
+    palettes(28, 4, 28),
+    palettes(2, 17, 2),
+    palettes(16, 16, 8),
+    palettes(4, 4, 7),
+    palettes(4, 4, 18),
+    palettes(4, 4, 20),
+    palettes(19, 19, 9),
+    [4 * 4 - 1, 4 * 4 - 1, 11 * 4],
+    palettes(17, 17, 2),
+    palettes(4, 4, 2),
+    palettes(4, 4, 3),
+    palettes(28, 28, 0),
+    palettes(3, 3, 0),
+    palettes(0, 0, 1),
+    palettes(18, 22, 18),
+    palettes(20, 22, 20),
+    palettes(24, 22, 24),
+    palettes(16, 22, 8),
+    palettes(17, 4, 13),
+    [28 * 4 - 1, 0, 14 * 4],
+    [28 * 4 - 1, 4 * 4, 15 * 4],
+    palettes(19, 22, 9),
+    palettes(16, 28, 10),
+    palettes(4, 23, 28),
+    palettes(17, 22, 2),
+    palettes(4, 0, 2),
+    palettes(4, 28, 3),
+    palettes(28, 3, 0),
+    palettes(3, 28, 4),
+    palettes(21, 28, 4),
+    palettes(3, 28, 0),
+    palettes(25, 3, 28),
+    palettes(0, 28, 8),
+    palettes(4, 3, 28),
+    palettes(28, 3, 6),
+    palettes(4, 28, 29),
+];
+
+/// Title checksums of the games with a dedicated combination. Entry 0 is the default.
+const CHECKSUMS: [u8; 94] = [
+    0x00, 0x88, 0x16, 0x36, 0xD1, 0xDB, 0xF2, 0x3C, 0x8C, 0x92, 0x3D, 0x5C, 0x58, 0xC9, 0x3E, 0x70,
+    0x1D, 0x59, 0x69, 0x19, 0x35, 0xA8, 0x14, 0xAA, 0x75, 0x95, 0x99, 0x34, 0x6F, 0x15, 0xFF, 0x97,
+    0x4B, 0x90, 0x17, 0x10, 0x39, 0xF7, 0xF6, 0xA2, 0x49, 0x4E, 0xC3, 0x68, 0xE0, 0x8B, 0xF0, 0xCE,
+    0x0C, 0x29, 0xE8, 0xB7, 0x86, 0x9A, 0x52, 0x01, 0x9D, 0x71, 0x9C, 0xBD, 0x5D, 0x6D, 0x67, 0x3F,
+    0x6B,
+    // shared by several titles, told apart by `FOURTH_LETTERS`
+    0xB3, 0x46, 0x28, 0xA5, 0xC6, 0xD3, 0x27, 0x61, 0x18, 0x66, 0x6A, 0xBF, 0x0D, 0xF4,
+    0xB3, 0x46, 0x28, 0xA5, 0xC6, 0xD3, 0x27, 0x61, 0x18, 0x66, 0x6A, 0xBF, 0x0D, 0xF4,
+    0xB3,
+];
+
+// index in `CHECKSUMS` of the first entry that also checks the 4th letter of the title
+const FIRST_SHARED: usize = 65;
+
+/// 4th letter of the title for each entry of `CHECKSUMS` from `FIRST_SHARED` on.
+const FOURTH_LETTERS: &[u8; 29] = b"BEFAARBEKEK R-URAR INAILICE R";
+
+/// Combination of each entry of `CHECKSUMS`.
+const COMBINATION_PER_CHECKSUM: [u8; 94] = [
+    0, 4, 5, 35, 34, 3, 31, 15, 10, 5, 19, 36, 7, 37, 30, 44,
+    21, 32, 31, 20, 5, 33, 13, 14, 5, 29, 5, 18, 9, 3, 2, 26,
+    25, 25, 41, 42, 26, 45, 42, 45, 36, 38, 26, 42, 30, 41, 34, 34,
+    5, 42, 6, 5, 33, 25, 42, 42, 40, 2, 16, 25, 42, 42, 5, 0,
+    39,
+    36, 22, 25, 6, 32, 12, 36, 11, 39, 18, 39, 24, 31, 50,
+    17, 46, 6, 27, 0, 47, 41, 41, 0, 0, 19, 34, 23, 18,
+    29,
+];
+
+/// Combination picked by holding a direction while the logo is shown: Right, Left, Up,
+/// Down alone, then with A, then with B.
+const BUTTON_COMBINATIONS: [u8; 12] = [1, 48, 5, 8, 0, 40, 43, 3, 6, 7, 28, 49];
+
+impl CompatPalette {
+    /// # Parameters
+    /// - `combination`: Index of the palette combination in the boot ROM, 0 to 50.
+    pub fn new(combination: u8) -> CompatPalette {
+        assert!((combination as usize) < COMBINATIONS.len(), "Invalid palette combination {}", combination);
+        CompatPalette { combination }
+    }
+
+    pub fn get_combination(&self) -> u8 {
+        self.combination
+    }
+
+    /// Returns the BG, OBJ0 and OBJ1 colors as RGB555.
+    pub fn colors(&self) -> [[u16; 4]; 3] {
+        let [obj0, obj1, bg] = COMBINATIONS[self.combination as usize];
+        let palette = |start: usize| -> [u16; 4] { COLORS[start..start + 4].try_into().expect("4 colors") };
+        [palette(bg), palette(obj0), palette(obj1)]
+    }
+
+    /// Returns the combination the boot ROM picks for buttons held during the logo.
+    ///
+    /// # Parameters
+    /// - `pressed`: The buttons held down, see [`Button::mask`].
+    ///
+    /// # Returns
+    /// `None` without a direction. When several are held, the first of Right, Left, Up
+    /// and Down wins, and A wins over B.
+    pub fn from_buttons(pressed: u8) -> Option<CompatPalette> {
+        let direction: usize = [Button::Right, Button::Left, Button::Up, Button::Down].iter()
+            .position(|button| pressed & button.mask() != 0)?;
+        let modifier: usize = if pressed & Button::A.mask() != 0 {
+            1
+        } else if pressed & Button::B.mask() != 0 {
+            2
+        } else {
+            0
+        };
+        Some(CompatPalette::new(BUTTON_COMBINATIONS[modifier * 4 + direction]))
+    }
+
+    /// Picks the combination the CGB boot ROM would use for `rom` when no buttons are held.
+    ///
+    /// Only games published by Nintendo (old licensee 0x01, or 0x33 with new licensee
+    /// "01") are looked up by the checksum of their title; everything else, and any title
+    /// not in the table, gets the default combination 0. The last entries share their
+    /// checksum with other titles and also need the 4th letter of the title to match.
+    pub fn select(rom: &ROM) -> CompatPalette {
+        let old_licensee: u8 = rom.read_byte(0x014B);
+        let nintendo: bool = old_licensee == 0x01
+            || (old_licensee == 0x33 && rom.read_byte(0x0144) == b'0' && rom.read_byte(0x0145) == b'1');
+        if !nintendo {
+            return CompatPalette::new(0);
+        }
+        let checksum: u8 = (0x0134..=0x0143).fold(0u8, |sum, addr| sum.wrapping_add(rom.read_byte(addr)));
+        let fourth_letter: u8 = rom.read_byte(0x0137);
+        let index: Option<usize> = CHECKSUMS.iter()
+            .enumerate()
+            .position(|(i, c)| *c == checksum && (i < FIRST_SHARED || FOURTH_LETTERS[i - FIRST_SHARED] == fourth_letter));
+        CompatPalette::new(index.map(|i| COMBINATION_PER_CHECKSUM[i]).unwrap_or(0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a cartridge published by Nintendo with `title` in its header
+    fn nintendo_rom(title: &[u8]) -> ROM {
+        let mut rom: ROM = ROM::new();
+        rom.bank0[0x0134..0x0134 + title.len()].copy_from_slice(title);
+        rom.bank0[0x014B] = 0x01;
+        rom
+    }
+
+    // a title summing to `checksum` with `letter` in 4th position
+    fn title_with(checksum: u8, letter: u8) -> Vec<u8> {
+        vec![checksum.wrapping_sub(letter), 0, 0, letter]
+    }
+
+    // a title with the same checksum as `title` but another 4th letter
+    fn other_letter(title: &[u8]) -> Vec<u8> {
+        let mut title: Vec<u8> = title.to_vec();
+        title[0] = title[0].wrapping_add(title[3]).wrapping_sub(b'Z');
+        title[3] = b'Z';
+        title
+    }
+
+    macro_rules! tie_break {
+        ($name:ident, $title:expr, $combination:expr) => {
+            #[test]
+            fn $name() {
+                let title: Vec<u8> = $title.to_vec();
+                assert_eq!(CompatPalette::select(&nintendo_rom(&title)).get_combination(), $combination);
+                assert_eq!(CompatPalette::select(&nintendo_rom(&other_letter(&title))).get_combination(), 0);
+            }
+        };
+    }
+
+    tie_break!(unknown_b_0xb3, title_with(0xB3, b'B'), 36);
+    tie_break!(super_marioland, b"SUPER MARIOLAND", 22);
+    tie_break!(golf, b"GOLF", 25);
+    tie_break!(solarstriker, b"SOLARSTRIKER", 6);
+    tie_break!(gbwars, b"GBWARS", 32);
+    tie_break!(kaerunotameni, b"KAERUNOTAMENI", 12);
+    tie_break!(unknown_b_0x27, title_with(0x27, b'B'), 36);
+    tie_break!(pokemon_blue, b"POKEMON BLUE", 11);
+    tie_break!(donkeykongland, b"DONKEYKONGLAND", 39);
+    tie_break!(gameboy_gallery2, b"GAMEBOY GALLERY2", 18);
+    tie_break!(donkeykongland_2, b"DONKEYKONGLAND 2", 39);
+    tie_break!(kid_icarus, b"KID ICARUS", 24);
+    tie_break!(tetris2, b"TETRIS2", 31);
+    tie_break!(unknown_dash_0xf4, title_with(0xF4, b'-'), 50);
+    tie_break!(moguranya, b"MOGURANYA", 17);
+    tie_break!(unknown_r_0x46, title_with(0x46, b'R'), 46);
+    tie_break!(galaga_galaxian, title_with(0x28, b'A'), 6);
+    tie_break!(bt2ragnarokworld, b"BT2RAGNAROKWORLD", 27);
+    tie_break!(ken_griffey_jr, b"KEN GRIFFEY JR", 0);
+    tie_break!(unknown_i_0xd3, title_with(0xD3, b'I'), 47);
+    tie_break!(magnetic_soccer, b"MAGNETIC SOCCER", 41);
+    tie_break!(vegas_stakes, b"VEGAS STAKES", 41);
+    tie_break!(unknown_i_0x18, title_with(0x18, b'I'), 0);
+    tie_break!(milli_centi_pede, b"MILLI/CENTI/PEDE", 0);
+    tie_break!(mario_and_yoshi, b"MARIO & YOSHI", 19);
+    tie_break!(soccer, b"SOCCER", 34);
+    tie_break!(pokebom, b"POKEBOM", 23);
+    tie_break!(gw_gallery, b"G&W GALLERY", 18);
+    tie_break!(tetris_attack, b"TETRIS ATTACK", 29);
+
+    #[test]
+    fn unique_checksums_ignore_the_fourth_letter() {
+        assert_eq!(CompatPalette::select(&nintendo_rom(b"POKEMON RED")).get_combination(), 13);
+        assert_eq!(CompatPalette::select(&nintendo_rom(b"TETRIS")).get_combination(), 3);
+        assert_eq!(CompatPalette::select(&nintendo_rom(&other_letter(b"TETRIS"))).get_combination(), 3);
+    }
+
+    #[test]
+    fn other_publishers_get_the_default() {
+        let mut rom: ROM = nintendo_rom(b"POKEMON RED");
+        rom.bank0[0x014B] = 0x33;
+        assert_eq!(CompatPalette::select(&rom).get_combination(), 0);
+        rom.bank0[0x0144..0x0146].copy_from_slice(b"01");
+        assert_eq!(CompatPalette::select(&rom).get_combination(), 13);
+        assert_eq!(CompatPalette::select(&nintendo_rom(b"NOT IN THE TABLE")).get_combination(), 0);
+    }
+
+    #[test]
+    fn buttons_pick_a_combination() {
+        let combination = |buttons: &[Button]| {
+            CompatPalette::from_buttons(buttons.iter().fold(0, |mask, button| mask | button.mask()))
+                .map(|palette| palette.get_combination())
+        };
+        assert_eq!(combination(&[]), None);
+        assert_eq!(combination(&[Button::A, Button::Start]), None);
+        assert_eq!(combination(&[Button::Right]), Some(1));
+        assert_eq!(combination(&[Button::Up, Button::A]), Some(43));
+        assert_eq!(combination(&[Button::Down, Button::B]), Some(49));
+        assert_eq!(combination(&[Button::Left, Button::A, Button::B]), Some(40));
+    }
+
+    #[test]
+    fn colors_follow_the_combination() {
+        // Right + A: OBJ0 and OBJ1 from palette 4, the background from palette 29
+        let [bg, obj0, obj1] = CompatPalette::new(0).colors();
+        assert_eq!(bg, [0x7FFF, 0x1BEF, 0x6180, 0x0000]);
+        assert_eq!(obj0, [0x7FFF, 0x421F, 0x1CF2, 0x0000]);
+        assert_eq!(obj1, obj0);
+        // combinations starting in the middle of a palette
+        let [bg, obj0, _] = CompatPalette::new(22).colors();
+        assert_eq!(obj0, [0x0000, 0x7FFF, 0x421F, 0x1CF2]);
+        assert_eq!(bg, [0x7ED6, 0x4BFF, 0x2175, 0x0000]);
+        let [_, obj0, _] = CompatPalette::new(34).colors();
+        assert_eq!(obj0, [0x7FFF, 0x7FFF, 0x7E8C, 0x7C00]);
+    }
+}
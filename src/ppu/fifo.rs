@@ -0,0 +1,398 @@
+use std::collections::VecDeque;
+use crate::constants::io_registers::{LCDC, LY, OPRI, SCX, SCY, WX};
+use crate::constants::lcd::{BG_ATTR_PALETTE, BG_ATTR_PRIORITY, LCDC_BG_ENABLE, LCDC_BG_MAP, LCDC_OBJ_ENABLE, LCDC_OBJ_SIZE, LCDC_WINDOW_ENABLE, LCDC_WINDOW_MAP, OBJ_PRIORITY, SCREEN_WIDTH};
+use crate::error::state_error::StateError;
+use crate::memory_bus::io::IO;
+use crate::memory_bus::v_ram::VRAM;
+use crate::ppu::mixer::Mixer;
+use crate::ppu::pixel::Pixel;
+use crate::ppu::sprite::Sprite;
+use crate::ppu::tile::Tile;
+use crate::state::snapshot::Snapshot;
+use crate::state::state_reader::StateReader;
+use crate::state::state_writer::StateWriter;
+
+// dots spent by the fetcher reading the tile number, the low and the high bit plane
+const FETCH_TILE: u8 = 2;
+const FETCH_LOW: u8 = 4;
+const FETCH_HIGH: u8 = 6;
+
+// base cost of fetching a sprite, on top of waiting for the BG fetcher
+const SPRITE_FETCH_DOTS: u8 = 6;
+
+/// Dot-by-dot model of mode 3.
+///
+/// A BG/window fetcher fills the background FIFO 8 pixels at a time while one pixel per
+/// dot is shifted out to the LCD. Sprites pause the fetcher and are merged into their own
+/// FIFO. Registers are read when the hardware reads them, so writes to SCX, BGP, LCDC...
+/// in the middle of the line show up from the next affected pixel on.
+pub struct PixelFifo {
+    bg: VecDeque<Pixel>,
+    obj: VecDeque<Pixel>,
+    // dots spent in the current fetch (0..=FETCH_HIGH, then waiting to push)
+    fetch_dots: u8,
+    fetch_x: u8,
+    tile_index: u8,
+    tile_attributes: u8,
+    tile_low: u8,
+    tile_high: u8,
+    // the first fetch of every line is thrown away by the hardware
+    first_fetch: bool,
+    in_window: bool,
+    window_drawn: bool,
+    window_line: u8,
+    window_y_triggered: bool,
+    discard: u8,
+    lx: u8,
+    sprites: Vec<Sprite>,
+    sprite_penalty: u8,
+    pending_sprite: Option<Sprite>,
+}
+
+impl PixelFifo {
+    pub fn new() -> PixelFifo {
+        PixelFifo {
+            bg: VecDeque::with_capacity(16),
+            obj: VecDeque::with_capacity(8),
+            fetch_dots: 0,
+            fetch_x: 0,
+            tile_index: 0,
+            tile_attributes: 0,
+            tile_low: 0,
+            tile_high: 0,
+            first_fetch: true,
+            in_window: false,
+            window_drawn: false,
+            window_line: 0,
+            window_y_triggered: false,
+            discard: 0,
+            lx: 0,
+            sprites: Vec::new(),
+            sprite_penalty: 0,
+            pending_sprite: None,
+        }
+    }
+
+    /// Resets the FIFO state at the beginning of mode 3.
+    ///
+    /// # Parameters
+    /// - `sprites`: Result of the OAM scan for this line.
+    /// - `window_line`: Internal window line counter.
+    /// - `window_y_triggered`: Whether WY matched LY at some point during this frame.
+    pub fn start_line(&mut self, io: &IO, sprites: &[Sprite], window_line: u8, window_y_triggered: bool) {
+        self.bg.clear();
+        self.obj.clear();
+        self.fetch_dots = 0;
+        self.fetch_x = 0;
+        self.first_fetch = true;
+        self.in_window = false;
+        self.window_drawn = false;
+        self.window_line = window_line;
+        self.window_y_triggered = window_y_triggered;
+        // fine scroll is latched once per line, only the coarse part follows SCX writes
+        self.discard = io.get_register(SCX) & 0x07;
+        self.lx = 0;
+        self.sprites = sprites.to_vec();
+        Sprite::sort_by_priority(&mut self.sprites, false);
+        self.sprites.reverse();
+        self.sprite_penalty = 0;
+        self.pending_sprite = None;
+    }
+
+    /// Returns whether the window was drawn on the line, so the PPU can advance its
+    /// internal window line counter.
+    pub fn window_drawn(&self) -> bool {
+        self.window_drawn
+    }
+
+    /// Runs one dot of mode 3, writing at most one pixel into `shades` and `colors`.
+    ///
+    /// # Returns
+    /// `true` once the 160 pixels of the line have been output and mode 3 is over.
+    pub fn step(&mut self, io: &IO, v_ram: &VRAM, shades: &mut [u8], colors: &mut [u16]) -> bool {
+        let lcdc: u8 = io.get_register(LCDC);
+
+        if let Some(sprite) = self.pending_sprite {
+            self.sprite_penalty -= 1;
+            if self.sprite_penalty == 0 {
+                self.merge_sprite(io, v_ram, sprite);
+                self.pending_sprite = None;
+            }
+            return false;
+        }
+
+        if lcdc & LCDC_OBJ_ENABLE != 0 && self.discard == 0 {
+            if let Some(sprite) = self.next_sprite() {
+                // the fetch can't start until the BG fetcher is done with its current tile,
+                // and this dot already counts toward it
+                let wait: u8 = FETCH_HIGH.saturating_sub(self.fetch_dots).min(5);
+                self.sprite_penalty = SPRITE_FETCH_DOTS + wait - 1;
+                self.pending_sprite = Some(sprite);
+                return false;
+            }
+        }
+
+        self.check_window(io);
+        self.fetch(io, v_ram);
+
+        let Some(bg) = self.bg.pop_front() else {
+            return false;
+        };
+        if self.discard > 0 {
+            self.discard -= 1;
+            return false;
+        }
+        let obj: Option<Pixel> = self.obj.pop_front().filter(|_| lcdc & LCDC_OBJ_ENABLE != 0);
+        let (shade, color) = Mixer::resolve(io, lcdc, bg, obj);
+        shades[self.lx as usize] = shade;
+        colors[self.lx as usize] = color;
+        self.lx += 1;
+        self.lx as usize == SCREEN_WIDTH
+    }
+
+    /// Pops the next sprite starting at the current output position, if any.
+    fn next_sprite(&mut self) -> Option<Sprite> {
+        match self.sprites.last() {
+            Some(sprite) if sprite.x <= self.lx + 8 => self.sprites.pop(),
+            _ => None
+        }
+    }
+
+    /// Switches the fetcher to the window when the output reaches WX - 7.
+    fn check_window(&mut self, io: &IO) {
+        let lcdc: u8 = io.get_register(LCDC);
+        // on DMG LCDC bit 0 turns the window off too
+        let enabled: bool = lcdc & LCDC_WINDOW_ENABLE != 0 && (io.is_cgb_mode() || lcdc & LCDC_BG_ENABLE != 0);
+        if self.in_window || !enabled || !self.window_y_triggered {
+            return;
+        }
+        let wx: u8 = io.get_register(WX);
+        let reached: bool = self.lx as u16 + 7 == wx as u16 || (wx < 7 && self.lx == 0);
+        if wx > 166 || !reached {
+            return;
+        }
+        self.in_window = true;
+        self.window_drawn = true;
+        self.bg.clear();
+        self.fetch_dots = 0;
+        self.fetch_x = 0;
+        self.discard = 7u8.saturating_sub(wx);
+    }
+
+    fn fetch(&mut self, io: &IO, v_ram: &VRAM) {
+        let lcdc: u8 = io.get_register(LCDC);
+        if self.fetch_dots < FETCH_HIGH {
+            self.fetch_dots += 1;
+            match self.fetch_dots {
+                FETCH_TILE => self.fetch_tile_index(io, v_ram, lcdc),
+                FETCH_LOW => self.tile_low = self.fetch_tile_row(io, v_ram, lcdc).0,
+                FETCH_HIGH => self.tile_high = self.fetch_tile_row(io, v_ram, lcdc).1,
+                _ => {}
+            }
+        }
+        if self.fetch_dots == FETCH_HIGH {
+            self.push();
+        }
+    }
+
+    fn fetch_tile_index(&mut self, io: &IO, v_ram: &VRAM, lcdc: u8) {
+        let address: u16 = if self.in_window {
+            let map: u16 = if lcdc & LCDC_WINDOW_MAP != 0 { 0x9C00 } else { 0x9800 };
+            map + (self.window_line as u16 / 8) * 32 + (self.fetch_x as u16 & 0x1F)
+        } else {
+            let map: u16 = if lcdc & LCDC_BG_MAP != 0 { 0x9C00 } else { 0x9800 };
+            let y: u8 = io.get_register(SCY).wrapping_add(io.get_register(LY));
+            let x: u8 = (io.get_register(SCX) / 8).wrapping_add(self.fetch_x) & 0x1F;
+            map + (y as u16 / 8) * 32 + x as u16
+        };
+        self.tile_index = v_ram.read_bank(0, address);
+        self.tile_attributes = if io.is_cgb_mode() { v_ram.read_bank(1, address) } else { 0 };
+    }
+
+    fn fetch_tile_row(&self, io: &IO, v_ram: &VRAM, lcdc: u8) -> (u8, u8) {
+        let row: u8 = if self.in_window {
+            self.window_line % 8
+        } else {
+            io.get_register(SCY).wrapping_add(io.get_register(LY)) % 8
+        };
+        Tile::fetch_bg_row(v_ram, lcdc, self.tile_index, self.tile_attributes, row)
+    }
+
+    /// Pushes the fetched tile row once the background FIFO has room for it. Until then
+    /// the fetcher keeps retrying every dot.
+    fn push(&mut self) {
+        if !self.bg.is_empty() {
+            return;
+        }
+        self.fetch_dots = 0;
+        if self.first_fetch {
+            self.first_fetch = false;
+            return;
+        }
+        let palette: u8 = self.tile_attributes & BG_ATTR_PALETTE;
+        let priority: bool = self.tile_attributes & BG_ATTR_PRIORITY != 0;
+        for column in 0..8 {
+            let bit: u8 = Tile::bg_bit_for_column(self.tile_attributes, column);
+            let color: u8 = Tile::color_index(self.tile_low, self.tile_high, bit);
+            self.bg.push_back(Pixel::new(color, palette, priority));
+        }
+        self.fetch_x = self.fetch_x.wrapping_add(1);
+    }
+
+    /// Loads the sprite row into the object FIFO. Pixels already owned by a sprite with
+    /// higher priority are kept: an earlier one on DMG, a lower OAM index in CGB mode.
+    fn merge_sprite(&mut self, io: &IO, v_ram: &VRAM, sprite: Sprite) {
+        let lcdc: u8 = io.get_register(LCDC);
+        let height: u8 = if lcdc & LCDC_OBJ_SIZE != 0 { 16 } else { 8 };
+        let cgb: bool = io.is_cgb_mode();
+        let by_index: bool = cgb && io.get_register(OPRI) & 0x01 == 0;
+        let (low, high) = sprite.fetch_row(v_ram, io.get_register(LY), height, cgb);
+
+        while self.obj.len() < 8 {
+            self.obj.push_back(Pixel::transparent());
+        }
+        // sprites partially hidden on the left only show their rightmost columns
+        let skip: u8 = (self.lx + 8).saturating_sub(sprite.x).min(8);
+        for column in skip..8 {
+            let color: u8 = Tile::color_index(low, high, sprite.bit_for_column(column));
+            let slot: &mut Pixel = &mut self.obj[(column - skip) as usize];
+            let wins: bool = slot.color == 0 || (by_index && sprite.index < slot.index);
+            if color != 0 && wins {
+                *slot = Pixel::new(color, sprite.palette(cgb), sprite.attributes & OBJ_PRIORITY != 0);
+                slot.index = sprite.index;
+            }
+        }
+    }
+}
+
+impl Snapshot for PixelFifo {
+    fn save_state(&self, writer: &mut StateWriter) {
+        Pixel::save_queue(writer, &self.bg);
+        Pixel::save_queue(writer, &self.obj);
+        writer.write_bytes(&[self.fetch_dots, self.fetch_x, self.tile_index, self.tile_attributes, self.tile_low, self.tile_high]);
+        writer.write_bool(self.first_fetch);
+        writer.write_bool(self.in_window);
+        writer.write_bool(self.window_drawn);
+        writer.write_u8(self.window_line);
+        writer.write_bool(self.window_y_triggered);
+        writer.write_u8(self.discard);
+        writer.write_u8(self.lx);
+        Sprite::save_list(writer, &self.sprites);
+        writer.write_u8(self.sprite_penalty);
+        writer.write_bool(self.pending_sprite.is_some());
+        if let Some(sprite) = self.pending_sprite.as_ref() {
+            sprite.save_state(writer);
+        }
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.bg = Pixel::load_queue(reader)?;
+        self.obj = Pixel::load_queue(reader)?;
+        let mut fetch: [u8; 6] = [0; 6];
+        reader.read_bytes(&mut fetch)?;
+        [self.fetch_dots, self.fetch_x, self.tile_index, self.tile_attributes, self.tile_low, self.tile_high] = fetch;
+        self.first_fetch = reader.read_bool()?;
+        self.in_window = reader.read_bool()?;
+        self.window_drawn = reader.read_bool()?;
+        self.window_line = reader.read_u8()?;
+        self.window_y_triggered = reader.read_bool()?;
+        self.discard = reader.read_u8()?;
+        self.lx = reader.read_u8()?;
+        self.sprites = Sprite::load_list(reader)?;
+        self.sprite_penalty = reader.read_u8()?;
+        self.pending_sprite = if reader.read_bool()? { Some(Sprite::read_state(reader)?) } else { None };
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::io_registers::{BGP, OBP0};
+    use crate::constants::lcd::{LCDC_ENABLE, LCDC_TILE_DATA};
+    use crate::memory_bus::bus::BUS;
+    use crate::ppu::scanline::Scanline;
+
+    const LCDC_ON: u8 = LCDC_ENABLE | LCDC_TILE_DATA | LCDC_BG_ENABLE | LCDC_OBJ_ENABLE;
+
+    /// Tile 1 is a vertical stripe pattern, used on every other map entry.
+    fn setup() -> (IO, VRAM) {
+        let mut io: IO = IO::new();
+        io.set_register(LCDC, LCDC_ON);
+        io.set_register(BGP, 0xE4);
+        io.set_register(OBP0, 0xE4);
+        let mut v_ram: VRAM = VRAM::new();
+        for row in 0..8 {
+            v_ram.write(0x8010 + row * 2, 0b1100_1010).unwrap();
+            v_ram.write(0x8011 + row * 2, 0b1010_0110).unwrap();
+        }
+        for x in (0..32).step_by(2) {
+            v_ram.write(0x9800 + x, 1).unwrap();
+        }
+        (io, v_ram)
+    }
+
+    /// Runs mode 3 to the end, returning the dots it took and the line.
+    fn run_line(io: &IO, v_ram: &VRAM, sprites: &[Sprite]) -> (u32, [u8; SCREEN_WIDTH]) {
+        let mut fifo: PixelFifo = PixelFifo::new();
+        let mut shades: [u8; SCREEN_WIDTH] = [0; SCREEN_WIDTH];
+        let mut colors: [u16; SCREEN_WIDTH] = [0; SCREEN_WIDTH];
+        fifo.start_line(io, sprites, 0, false);
+        let mut dots: u32 = 1;
+        while !fifo.step(io, v_ram, &mut shades, &mut colors) {
+            dots += 1;
+        }
+        (dots, shades)
+    }
+
+    fn scanline(io: &IO, v_ram: &VRAM, sprites: &[Sprite]) -> [u8; SCREEN_WIDTH] {
+        let mut shades: [u8; SCREEN_WIDTH] = [0; SCREEN_WIDTH];
+        let mut colors: [u16; SCREEN_WIDTH] = [0; SCREEN_WIDTH];
+        Scanline::render(io, v_ram, sprites, 0, false, &mut shades, &mut colors);
+        shades
+    }
+
+    #[test]
+    fn matches_the_scanline_renderer() {
+        let (mut io, v_ram) = setup();
+        let sprites: [Sprite; 2] = [
+            Sprite { y: 16, x: 30, tile: 1, attributes: 0, index: 0 },
+            Sprite { y: 16, x: 34, tile: 1, attributes: OBJ_PRIORITY, index: 1 },
+        ];
+        for scx in [0, 3, 13] {
+            io.set_register(SCX, scx);
+            assert_eq!(run_line(&io, &v_ram, &sprites).1, scanline(&io, &v_ram, &sprites));
+        }
+    }
+
+    #[test]
+    fn mode3_grows_with_fine_scroll_and_sprites() {
+        let (mut io, v_ram) = setup();
+        let (base, _) = run_line(&io, &v_ram, &[]);
+        let sprite: Sprite = Sprite { y: 16, x: 0, tile: 1, attributes: 0, index: 0 };
+        // a sprite at X = 0 costs the full 11 dots (Pan Docs), later ones catch the
+        // fetcher partway through a tile and wait less
+        assert_eq!(run_line(&io, &v_ram, &[sprite]).0, base + 11);
+        let later: u32 = run_line(&io, &v_ram, &[Sprite { x: 20, ..sprite }]).0 - base;
+        assert!((SPRITE_FETCH_DOTS as u32..11).contains(&later));
+        io.set_register(SCX, 3);
+        assert_eq!(run_line(&io, &v_ram, &[]).0, base + 3);
+    }
+
+    #[test]
+    fn mid_line_palette_write_shows_up_at_once() {
+        let (mut io, v_ram) = setup();
+        let mut fifo: PixelFifo = PixelFifo::new();
+        let mut shades: [u8; SCREEN_WIDTH] = [0; SCREEN_WIDTH];
+        let mut colors: [u16; SCREEN_WIDTH] = [0; SCREEN_WIDTH];
+        fifo.start_line(&io, &[], 0, false);
+        while fifo.lx < 80 {
+            fifo.step(&io, &v_ram, &mut shades, &mut colors);
+        }
+        io.set_register(BGP, 0x00);
+        while !fifo.step(&io, &v_ram, &mut shades, &mut colors) {}
+        let reference: [u8; SCREEN_WIDTH] = scanline(&io, &v_ram, &[]);
+        assert!(shades[..80].iter().any(|&shade| shade != 0));
+        assert_eq!(&shades[80..], &reference[80..]);
+    }
+}
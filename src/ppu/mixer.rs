@@ -0,0 +1,103 @@
+use crate::constants::io_registers::{BGP, OBP0, OBP1};
+use crate::constants::lcd::{DMG_GREYS, LCDC_BG_ENABLE};
+use crate::memory_bus::color_palette::ColorPalette;
+use crate::memory_bus::io::IO;
+use crate::ppu::pixel::Pixel;
+use crate::ppu::tile::Tile;
+
+/// Decides which of the background or sprite pixel ends up on screen and resolves its color.
+pub struct Mixer;
+
+impl Mixer {
+    /// Resolves the final output of a pixel with the palettes currently in the registers.
+    ///
+    /// # Parameters
+    /// - `bg`: Background/window pixel.
+    /// - `obj`: Sprite pixel, `None` when no sprite covers it or sprites are disabled.
+    ///
+    /// # Returns
+    /// The DMG shade (0–3, or the raw color index in CGB mode) and the RGB555 color.
+    pub fn resolve(io: &IO, lcdc: u8, bg: Pixel, obj: Option<Pixel>) -> (u8, u16) {
+        if io.is_cgb_mode() {
+            Mixer::resolve_cgb(io, lcdc, bg, obj)
+        } else {
+            Mixer::resolve_dmg(io, lcdc, bg, obj)
+        }
+    }
+
+    fn resolve_dmg(io: &IO, lcdc: u8, bg: Pixel, obj: Option<Pixel>) -> (u8, u16) {
+        // LCDC bit 0 blanks both background and window on DMG
+        let bg_color: u8 = if lcdc & LCDC_BG_ENABLE != 0 { bg.color } else { 0 };
+        match obj {
+            Some(obj) if obj.color != 0 && !(obj.bg_priority && bg_color != 0) => {
+                let palette: u8 = if obj.palette == 1 { io.get_register(OBP1) } else { io.get_register(OBP0) };
+                let shade: u8 = Tile::apply_palette(palette, obj.color);
+                (shade, Mixer::dmg_color(io, io.get_obj_palette(), obj.palette, shade))
+            }
+            _ => {
+                let shade: u8 = Tile::apply_palette(io.get_register(BGP), bg_color);
+                (shade, Mixer::dmg_color(io, io.get_bg_palette(), 0, shade))
+            }
+        }
+    }
+
+    /// On a CGB running a DMG cartridge the shade picks a color of the CGB palette set up
+    /// by the boot ROM (BG palette 0, OBJ palettes 0 and 1). On DMG it's just a grey.
+    fn dmg_color(io: &IO, palettes: &ColorPalette, palette: u8, shade: u8) -> u16 {
+        if io.is_dmg_compat() {
+            palettes.get_color(palette, shade)
+        } else {
+            DMG_GREYS[shade as usize]
+        }
+    }
+
+    fn resolve_cgb(io: &IO, lcdc: u8, bg: Pixel, obj: Option<Pixel>) -> (u8, u16) {
+        // on CGB LCDC bit 0 is the master priority: when clear sprites are always on top
+        let master: bool = lcdc & LCDC_BG_ENABLE != 0;
+        if let Some(obj) = obj {
+            let bg_wins: bool = master && bg.color != 0 && (bg.bg_priority || obj.bg_priority);
+            if obj.color != 0 && !bg_wins {
+                return (obj.color, io.get_obj_palette().get_color(obj.palette, obj.color));
+            }
+        }
+        (bg.color, io.get_bg_palette().get_color(bg.palette, bg.color))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::io_registers::BGP;
+
+    fn cgb_io() -> IO {
+        let mut io: IO = IO::new();
+        io.set_cgb_mode(true);
+        io.get_bg_palette_mut().set_color(2, 1, 0x001F);
+        io.get_obj_palette_mut().set_color(5, 3, 0x03E0);
+        io
+    }
+
+    #[test]
+    fn cgb_priority() {
+        let io: IO = cgb_io();
+        let bg: Pixel = Pixel::new(1, 2, false);
+        let obj: Pixel = Pixel::new(3, 5, false);
+        assert_eq!(Mixer::resolve(&io, LCDC_BG_ENABLE, bg, Some(obj)), (3, 0x03E0));
+        // the BG attribute or the OBJ flag puts a non-zero background on top
+        let bg_on_top: Pixel = Pixel::new(1, 2, true);
+        assert_eq!(Mixer::resolve(&io, LCDC_BG_ENABLE, bg_on_top, Some(obj)), (1, 0x001F));
+        let behind: Pixel = Pixel::new(3, 5, true);
+        assert_eq!(Mixer::resolve(&io, LCDC_BG_ENABLE, bg, Some(behind)), (1, 0x001F));
+        // ...unless LCDC bit 0 clears the master priority
+        assert_eq!(Mixer::resolve(&io, 0, bg_on_top, Some(obj)), (3, 0x03E0));
+    }
+
+    #[test]
+    fn dmg_bg_disable_blanks_the_background() {
+        let mut io: IO = IO::new();
+        io.set_register(BGP, 0xE4);
+        let bg: Pixel = Pixel::new(3, 0, false);
+        assert_eq!(Mixer::resolve(&io, LCDC_BG_ENABLE, bg, None), (3, DMG_GREYS[3]));
+        assert_eq!(Mixer::resolve(&io, 0, bg, None), (0, DMG_GREYS[0]));
+    }
+}
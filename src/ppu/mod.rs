@@ -0,0 +1,8 @@
+pub mod compat_palette;
+pub mod fifo;
+pub mod mixer;
+pub mod pixel;
+pub mod ppu;
+pub mod scanline;
+pub mod sprite;
+pub mod tile;
\ No newline at end of file
@@ -0,0 +1,98 @@
+use std::collections::VecDeque;
+
+use crate::error::state_error::StateError;
+use crate::state::snapshot::Snapshot;
+use crate::state::state_reader::StateReader;
+use crate::state::state_writer::StateWriter;
+
+// the FIFOs never hold more than two tiles
+const FIFO_CAPACITY: usize = 16;
+
+/// A pixel waiting in one of the PPU FIFOs.
+///
+/// `palette` is the CGB palette number (0–7), or for DMG sprites 0 for OBP0 and 1 for
+/// OBP1. `index` is the OAM index of the sprite that produced the pixel, used by the CGB
+/// priority rules.
+#[derive(Clone, Copy)]
+pub struct Pixel {
+    pub color: u8,
+    pub palette: u8,
+    pub bg_priority: bool,
+    pub index: u8,
+}
+
+impl Pixel {
+    pub fn new(color: u8, palette: u8, bg_priority: bool) -> Pixel {
+        Pixel { color, palette, bg_priority, index: 0 }
+    }
+
+    pub fn transparent() -> Pixel {
+        Pixel::new(0, 0, false)
+    }
+
+    /// Writes the pixels of a FIFO, preceded by their count.
+    pub fn save_queue(writer: &mut StateWriter, pixels: &VecDeque<Pixel>) {
+        writer.write_u8(pixels.len() as u8);
+        pixels.iter().for_each(|pixel| pixel.save_state(writer));
+    }
+
+    pub fn load_queue(reader: &mut StateReader) -> Result<VecDeque<Pixel>, StateError> {
+        let count: usize = reader.read_u8()? as usize;
+        if count > FIFO_CAPACITY {
+            return Err(StateError::InvalidValue("pixels in a FIFO"));
+        }
+        let mut pixels: VecDeque<Pixel> = VecDeque::with_capacity(count);
+        for _ in 0..count {
+            let mut pixel: Pixel = Pixel::transparent();
+            pixel.load_state(reader)?;
+            pixels.push_back(pixel);
+        }
+        Ok(pixels)
+    }
+}
+
+impl Snapshot for Pixel {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.color);
+        writer.write_u8(self.palette);
+        writer.write_bool(self.bg_priority);
+        writer.write_u8(self.index);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.color = reader.read_u8()? & 0x03;
+        self.palette = reader.read_u8()? & 0x07;
+        self.bg_priority = reader.read_bool()?;
+        self.index = reader.read_u8()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn queues_round_trip() {
+        let mut pixels: VecDeque<Pixel> = VecDeque::new();
+        pixels.push_back(Pixel::new(3, 7, true));
+        pixels.push_back(Pixel { index: 39, ..Pixel::new(1, 1, false) });
+        let mut writer: StateWriter = StateWriter::new();
+        Pixel::save_queue(&mut writer, &pixels);
+        let bytes: Vec<u8> = writer.into_bytes();
+        let loaded: VecDeque<Pixel> = Pixel::load_queue(&mut StateReader::new(&bytes)).unwrap();
+        let fields = |pixel: &Pixel| (pixel.color, pixel.palette, pixel.bg_priority, pixel.index);
+        assert_eq!(loaded.iter().map(fields).collect::<Vec<_>>(), pixels.iter().map(fields).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn oversized_queues_are_refused() {
+        let mut bytes: Vec<u8> = vec![FIFO_CAPACITY as u8 + 1];
+        bytes.resize(1 + (FIFO_CAPACITY + 1) * 4, 0);
+        assert!(matches!(Pixel::load_queue(&mut StateReader::new(&bytes)), Err(StateError::InvalidValue(_))));
+        // out of range colors and palettes are masked
+        let mut pixel: Pixel = Pixel::transparent();
+        pixel.load_state(&mut StateReader::new(&[0xFF, 0xFF, 0x01, 0x05])).unwrap();
+        assert_eq!((pixel.color, pixel.palette, pixel.bg_priority, pixel.index), (3, 7, true, 5));
+    }
+}
@@ -0,0 +1,489 @@
+use crate::constants::io_registers::{INT_STAT, INT_VBLANK, LCDC, LY, LYC, STAT, WY};
+use crate::constants::lcd::{DMG_GREYS, DOTS_PER_LINE, DRAWING_DOTS, LCDC_ENABLE, LCDC_OBJ_SIZE, LINES_PER_FRAME, OAM_SCAN_DOTS, SCREEN_HEIGHT, SCREEN_WIDTH, STAT_LYC_EQUAL, STAT_LYC_INT, STAT_MODE, STAT_MODE0_INT, STAT_MODE1_INT, STAT_MODE2_INT};
+use crate::error::state_error::StateError;
+use crate::memory_bus::io::IO;
+use crate::memory_bus::oam::OAM;
+use crate::memory_bus::v_ram::VRAM;
+use crate::ppu::fifo::PixelFifo;
+use crate::ppu::scanline::Scanline;
+use crate::ppu::sprite::Sprite;
+use crate::state::snapshot::Snapshot;
+use crate::state::state_reader::StateReader;
+use crate::state::state_writer::StateWriter;
+
+pub const MODE_HBLANK: u8 = 0;
+pub const MODE_VBLANK: u8 = 1;
+pub const MODE_OAM_SCAN: u8 = 2;
+pub const MODE_DRAWING: u8 = 3;
+
+/// How mode 3 is emulated.
+#[derive(Clone, Copy, PartialEq)]
+pub enum RenderMode {
+    /// Whole line rendered at once, fixed 172-dot mode 3. Cheap and good enough for most games.
+    Scanline,
+    /// Fetchers and pixel FIFO modelled dot by dot, with variable mode 3 length.
+    PixelFifo,
+}
+
+/// Picture processing unit.
+///
+/// Walks the 154 lines of a frame (144 visible + 10 of VBlank), each one 456 dots long,
+/// going through modes 2 (OAM scan), 3 (drawing) and 0 (HBlank) on visible lines and
+/// staying in mode 1 during VBlank. The output is a 160×144 buffer of DMG shades (0–3)
+/// plus the same picture as RGB555 colors, which is the one to show in CGB mode.
+pub struct PPU {
+    dot: u32,
+    line: u8,
+    mode: u8,
+    window_line: u8,
+    window_y_triggered: bool,
+    sprites: Vec<Sprite>,
+    render_mode: RenderMode,
+    fifo: PixelFifo,
+    framebuffer: [u8; SCREEN_WIDTH * SCREEN_HEIGHT],
+    color_framebuffer: [u16; SCREEN_WIDTH * SCREEN_HEIGHT],
+    frame_ready: bool,
+    // OR of every enabled STAT source; the interrupt fires on its rising edge only
+    stat_line: bool,
+    lcd_on: bool,
+    // the first line after turning the LCD on skips mode 2
+    first_line: bool,
+    // the first frame after turning the LCD on is not shown
+    skip_frame: bool,
+    // HBlank periods started on visible lines since the last call to take_hblank_count
+    hblank_count: u32,
+}
+
+impl PPU {
+    pub fn new() -> PPU {
+        PPU {
+            dot: 0,
+            line: 0,
+            mode: MODE_OAM_SCAN,
+            window_line: 0,
+            window_y_triggered: false,
+            sprites: Vec::new(),
+            render_mode: RenderMode::Scanline,
+            fifo: PixelFifo::new(),
+            framebuffer: [0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            color_framebuffer: [DMG_GREYS[0]; SCREEN_WIDTH * SCREEN_HEIGHT],
+            frame_ready: false,
+            stat_line: false,
+            lcd_on: false,
+            first_line: false,
+            skip_frame: false,
+            hblank_count: 0,
+        }
+    }
+
+    pub fn get_mode(&self) -> u8 {
+        self.mode
+    }
+
+    pub fn set_render_mode(&mut self, render_mode: RenderMode) {
+        self.render_mode = render_mode;
+    }
+
+    pub fn get_framebuffer(&self) -> &[u8] {
+        &self.framebuffer
+    }
+
+    /// Returns the frame as RGB555 colors (bits 0-4 red, 5-9 green, 10-14 blue).
+    pub fn get_color_framebuffer(&self) -> &[u16] {
+        &self.color_framebuffer
+    }
+
+    /// Returns `true` once per frame, when VBlank starts.
+    pub fn take_frame_ready(&mut self) -> bool {
+        let ready: bool = self.frame_ready;
+        self.frame_ready = false;
+        ready
+    }
+
+    /// Returns how many HBlank periods started on visible lines since the last call. Used
+    /// to drive the CGB HBlank DMA.
+    pub fn take_hblank_count(&mut self) -> u32 {
+        let count: u32 = self.hblank_count;
+        self.hblank_count = 0;
+        count
+    }
+
+    /// Returns `true` while the LCD is on.
+    pub fn is_lcd_on(&self) -> bool {
+        self.lcd_on
+    }
+
+    /// Advances the PPU by `cycles` dots.
+    ///
+    /// The CPU ticks the bus before each of its memory accesses, so a STAT write flagged
+    /// here happened right before the first of these dots and is seen by it.
+    pub fn tick(&mut self, cycles: u64, io: &mut IO, v_ram: &VRAM, oam: &OAM) {
+        let stat_written: bool = io.take_stat_written();
+        if io.get_register(LCDC) & LCDC_ENABLE == 0 {
+            if self.lcd_on {
+                self.turn_off(io);
+            }
+            return;
+        }
+        if !self.lcd_on {
+            self.turn_on();
+        }
+        if stat_written && !io.is_cgb_mode() {
+            self.stat_write_bug(io);
+        }
+        for _ in 0..cycles {
+            self.step(io, v_ram, oam);
+            self.update_stat(io);
+        }
+    }
+
+    /// Restarts the PPU at the first dot of `line`, for states that only record LY.
+    /// The window line counter starts over, so a window already on screen may shift.
+    pub fn restore_line(&mut self, io: &mut IO, line: u8) {
+        self.lcd_on = io.get_register(LCDC) & LCDC_ENABLE != 0;
+        self.dot = 0;
+        self.line = if self.lcd_on { line % LINES_PER_FRAME } else { 0 };
+        self.window_line = 0;
+        self.window_y_triggered = false;
+        self.first_line = false;
+        self.skip_frame = false;
+        let mode: u8 = if !self.lcd_on {
+            MODE_HBLANK
+        } else if self.line as usize >= SCREEN_HEIGHT {
+            MODE_VBLANK
+        } else {
+            MODE_OAM_SCAN
+        };
+        self.set_mode(io, mode);
+        io.set_register(LY, self.line);
+        self.stat_line = self.compute_stat_line(io, io.get_register(STAT));
+    }
+
+    /// Turning the LCD off resets LY to 0 and leaves STAT in mode 0 until it's enabled again.
+    fn turn_off(&mut self, io: &mut IO) {
+        self.lcd_on = false;
+        self.dot = 0;
+        self.line = 0;
+        self.window_line = 0;
+        self.window_y_triggered = false;
+        self.stat_line = false;
+        self.clear_frame();
+        self.set_mode(io, MODE_HBLANK);
+        io.set_register(LY, 0);
+    }
+
+    fn turn_on(&mut self) {
+        self.lcd_on = true;
+        self.first_line = true;
+        self.skip_frame = true;
+    }
+
+    /// On DMG, writing STAT behaves as if every source was enabled for one cycle, so a
+    /// write during HBlank, VBlank or with LY=LYC raises a spurious STAT interrupt.
+    fn stat_write_bug(&mut self, io: &mut IO) {
+        let stat: u8 = io.get_register(STAT);
+        let line: bool = self.compute_stat_line(io, stat | STAT_MODE0_INT | STAT_MODE1_INT | STAT_LYC_INT);
+        if line && !self.stat_line {
+            io.request_interrupt(INT_STAT);
+        }
+    }
+
+    fn compute_stat_line(&self, io: &IO, stat: u8) -> bool {
+        let lyc_equal: bool = io.get_register(LY) == io.get_register(LYC);
+        // the mode 2 source also sees the first dot of VBlank
+        let oam_source: bool = self.mode == MODE_OAM_SCAN
+            || (self.mode == MODE_VBLANK && self.line as usize == SCREEN_HEIGHT && self.dot == 0);
+        (stat & STAT_LYC_INT != 0 && lyc_equal)
+            || (stat & STAT_MODE0_INT != 0 && self.mode == MODE_HBLANK)
+            || (stat & STAT_MODE1_INT != 0 && self.mode == MODE_VBLANK)
+            || (stat & STAT_MODE2_INT != 0 && oam_source)
+    }
+
+    /// Refreshes the LY=LYC flag and raises the STAT interrupt on a rising edge of the
+    /// combined STAT line.
+    fn update_stat(&mut self, io: &mut IO) {
+        let mut stat: u8 = io.get_register(STAT);
+        if io.get_register(LY) == io.get_register(LYC) {
+            stat |= STAT_LYC_EQUAL;
+        } else {
+            stat &= !STAT_LYC_EQUAL;
+        }
+        io.set_register(STAT, stat);
+        let line: bool = self.compute_stat_line(io, stat);
+        if line && !self.stat_line {
+            io.request_interrupt(INT_STAT);
+        }
+        self.stat_line = line;
+    }
+
+    fn step(&mut self, io: &mut IO, v_ram: &VRAM, oam: &OAM) {
+        if (self.line as usize) < SCREEN_HEIGHT {
+            match self.dot {
+                0 => {
+                    if self.line == io.get_register(WY) {
+                        self.window_y_triggered = true;
+                    }
+                    let mode: u8 = if self.first_line { MODE_HBLANK } else { MODE_OAM_SCAN };
+                    self.set_mode(io, mode);
+                    let height: u8 = if io.get_register(LCDC) & LCDC_OBJ_SIZE != 0 { 16 } else { 8 };
+                    self.sprites = Sprite::scan(oam, self.line, height);
+                }
+                OAM_SCAN_DOTS => {
+                    self.set_mode(io, MODE_DRAWING);
+                    match self.render_mode {
+                        RenderMode::Scanline => self.render_line(io, v_ram),
+                        RenderMode::PixelFifo => self.fifo.start_line(io, &self.sprites, self.window_line, self.window_y_triggered),
+                    }
+                }
+                d if d == OAM_SCAN_DOTS + DRAWING_DOTS && self.render_mode == RenderMode::Scanline => {
+                    self.enter_hblank(io);
+                }
+                _ if self.mode == MODE_DRAWING && self.render_mode == RenderMode::PixelFifo => {
+                    self.step_fifo(io, v_ram);
+                }
+                _ => {}
+            }
+        }
+
+        self.dot += 1;
+        // on line 153 LY already reads 0 after the first M-cycle
+        if self.line == LINES_PER_FRAME - 1 && self.dot == 4 {
+            io.set_register(LY, 0);
+        }
+        if self.dot == DOTS_PER_LINE {
+            self.dot = 0;
+            self.first_line = false;
+            self.line += 1;
+            if self.line == LINES_PER_FRAME {
+                self.line = 0;
+                self.window_line = 0;
+                self.window_y_triggered = false;
+            }
+            io.set_register(LY, self.line);
+            if self.line as usize == SCREEN_HEIGHT {
+                self.set_mode(io, MODE_VBLANK);
+                io.request_interrupt(INT_VBLANK);
+                if self.skip_frame {
+                    self.skip_frame = false;
+                    self.clear_frame();
+                } else {
+                    self.frame_ready = true;
+                }
+            }
+        }
+    }
+
+    fn clear_frame(&mut self) {
+        self.framebuffer.fill(0);
+        self.color_framebuffer.fill(DMG_GREYS[0]);
+    }
+
+    fn render_line(&mut self, io: &IO, v_ram: &VRAM) {
+        let start: usize = self.line as usize * SCREEN_WIDTH;
+        let shades: &mut [u8] = &mut self.framebuffer[start..start + SCREEN_WIDTH];
+        let colors: &mut [u16] = &mut self.color_framebuffer[start..start + SCREEN_WIDTH];
+        if Scanline::render(io, v_ram, &self.sprites, self.window_line, self.window_y_triggered, shades, colors) {
+            self.window_line += 1;
+        }
+    }
+
+    fn step_fifo(&mut self, io: &mut IO, v_ram: &VRAM) {
+        let start: usize = self.line as usize * SCREEN_WIDTH;
+        let shades: &mut [u8] = &mut self.framebuffer[start..start + SCREEN_WIDTH];
+        let colors: &mut [u16] = &mut self.color_framebuffer[start..start + SCREEN_WIDTH];
+        if self.fifo.step(io, v_ram, shades, colors) {
+            if self.fifo.window_drawn() {
+                self.window_line += 1;
+            }
+            self.enter_hblank(io);
+        }
+    }
+
+    fn enter_hblank(&mut self, io: &mut IO) {
+        self.hblank_count += 1;
+        self.set_mode(io, MODE_HBLANK);
+    }
+
+    fn set_mode(&mut self, io: &mut IO, mode: u8) {
+        self.mode = mode;
+        let stat: u8 = io.get_register(STAT);
+        io.set_register(STAT, (stat & !STAT_MODE) | mode);
+    }
+}
+
+/// The render mode is a host setting and is kept.
+impl Snapshot for PPU {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u32(self.dot);
+        writer.write_u8(self.line);
+        writer.write_u8(self.mode);
+        writer.write_u8(self.window_line);
+        writer.write_bool(self.window_y_triggered);
+        Sprite::save_list(writer, &self.sprites);
+        self.fifo.save_state(writer);
+        writer.write_bytes(&self.framebuffer);
+        writer.write_u16_slice(&self.color_framebuffer);
+        writer.write_bool(self.frame_ready);
+        writer.write_bool(self.stat_line);
+        writer.write_bool(self.lcd_on);
+        writer.write_bool(self.first_line);
+        writer.write_bool(self.skip_frame);
+        writer.write_u32(self.hblank_count);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.dot = reader.read_u32()?;
+        self.line = reader.read_u8()?;
+        self.mode = reader.read_u8()? & 0x03;
+        if self.dot >= DOTS_PER_LINE || self.line >= LINES_PER_FRAME {
+            return Err(StateError::InvalidValue("PPU position"));
+        }
+        self.window_line = reader.read_u8()?;
+        self.window_y_triggered = reader.read_bool()?;
+        self.sprites = Sprite::load_list(reader)?;
+        self.fifo.load_state(reader)?;
+        reader.read_bytes(&mut self.framebuffer)?;
+        reader.read_u16_slice(&mut self.color_framebuffer)?;
+        self.frame_ready = reader.read_bool()?;
+        self.stat_line = reader.read_bool()?;
+        self.lcd_on = reader.read_bool()?;
+        self.first_line = reader.read_bool()?;
+        self.skip_frame = reader.read_bool()?;
+        self.hblank_count = reader.read_u32()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::io_registers::IF;
+    use crate::memory_bus::bus::BUS;
+
+    struct Lcd {
+        ppu: PPU,
+        io: IO,
+        v_ram: VRAM,
+        oam: OAM,
+    }
+
+    impl Lcd {
+        /// Turns the LCD on and runs the shortened first line, stopping on the first dot
+        /// of line 1.
+        fn new(cgb: bool, stat: u8) -> Lcd {
+            let mut lcd: Lcd = Lcd { ppu: PPU::new(), io: IO::new(), v_ram: VRAM::new(), oam: OAM::new() };
+            lcd.io.set_cgb_mode(cgb);
+            lcd.io.set_register(LCDC, LCDC_ENABLE);
+            lcd.io.set_register(STAT, stat);
+            lcd.tick(DOTS_PER_LINE as u64);
+            lcd.io.set_register(IF, 0);
+            lcd
+        }
+
+        fn tick(&mut self, dots: u64) {
+            self.ppu.tick(dots, &mut self.io, &self.v_ram, &self.oam);
+        }
+
+        fn take_stat_interrupt(&mut self) -> bool {
+            let requested: bool = self.io.get_register(IF) & INT_STAT != 0;
+            self.io.set_register(IF, 0);
+            requested
+        }
+    }
+
+    #[test]
+    fn modes_follow_the_dots() {
+        let mut lcd: Lcd = Lcd::new(false, 0);
+        assert_eq!(lcd.io.get_register(LY), 1);
+        lcd.tick(1);
+        assert_eq!(lcd.ppu.get_mode(), MODE_OAM_SCAN);
+        lcd.tick(OAM_SCAN_DOTS as u64 - 1);
+        assert_eq!(lcd.ppu.get_mode(), MODE_OAM_SCAN);
+        lcd.tick(1);
+        assert_eq!(lcd.io.get_register(STAT) & STAT_MODE, MODE_DRAWING);
+        lcd.tick(DRAWING_DOTS as u64 - 1);
+        assert_eq!(lcd.ppu.get_mode(), MODE_DRAWING);
+        lcd.tick(1);
+        assert_eq!(lcd.io.get_register(STAT) & STAT_MODE, MODE_HBLANK);
+    }
+
+    #[test]
+    fn lyc_interrupt_fires_once_per_match() {
+        let mut lcd: Lcd = Lcd::new(false, STAT_LYC_INT);
+        lcd.io.set_register(LYC, 2);
+        lcd.tick(DOTS_PER_LINE as u64 - 1);
+        assert!(!lcd.take_stat_interrupt());
+        assert_eq!(lcd.io.get_register(STAT) & STAT_LYC_EQUAL, 0);
+        // the last dot of line 1 moves LY to 2
+        lcd.tick(1);
+        assert!(lcd.take_stat_interrupt());
+        assert_ne!(lcd.io.get_register(STAT) & STAT_LYC_EQUAL, 0);
+        lcd.tick(DOTS_PER_LINE as u64 - 1);
+        assert!(!lcd.take_stat_interrupt());
+        lcd.tick(1);
+        assert_eq!(lcd.io.get_register(STAT) & STAT_LYC_EQUAL, 0);
+    }
+
+    #[test]
+    fn hblank_into_oam_scan_blocks_the_second_interrupt() {
+        let mut lcd: Lcd = Lcd::new(false, STAT_MODE0_INT | STAT_MODE2_INT);
+        // the line stays high from the HBlank of line 0 into the OAM scan of line 1
+        lcd.tick(1);
+        assert!(!lcd.take_stat_interrupt());
+        lcd.tick((OAM_SCAN_DOTS + DRAWING_DOTS) as u64);
+        assert!(lcd.take_stat_interrupt());
+        lcd.tick(DOTS_PER_LINE as u64 - (OAM_SCAN_DOTS + DRAWING_DOTS) as u64);
+        assert_eq!(lcd.ppu.get_mode(), MODE_OAM_SCAN);
+        assert!(!lcd.take_stat_interrupt());
+    }
+
+    #[test]
+    fn mode2_source_sees_the_first_dot_of_vblank() {
+        let mut lcd: Lcd = Lcd::new(false, STAT_MODE2_INT);
+        lcd.tick(DOTS_PER_LINE as u64 * (SCREEN_HEIGHT as u64 - 1) - 1);
+        assert_eq!(lcd.io.get_register(LY), SCREEN_HEIGHT as u8 - 1);
+        lcd.take_stat_interrupt();
+        lcd.tick(1);
+        assert_eq!(lcd.ppu.get_mode(), MODE_VBLANK);
+        assert_ne!(lcd.io.get_register(IF) & INT_VBLANK, 0);
+        assert!(lcd.take_stat_interrupt());
+        lcd.tick(DOTS_PER_LINE as u64);
+        assert!(!lcd.take_stat_interrupt());
+    }
+
+    #[test]
+    fn dmg_stat_write_raises_a_spurious_interrupt() {
+        for cgb in [false, true] {
+            let mut lcd: Lcd = Lcd::new(cgb, 0);
+            // a write during mode 3 on a line where LY != LYC is harmless
+            lcd.tick(OAM_SCAN_DOTS as u64 + 1);
+            lcd.io.write(STAT, 0).unwrap();
+            lcd.tick(4);
+            assert!(!lcd.take_stat_interrupt());
+            lcd.tick(DRAWING_DOTS as u64);
+            assert_eq!(lcd.ppu.get_mode(), MODE_HBLANK);
+            lcd.io.write(STAT, 0).unwrap();
+            lcd.tick(4);
+            assert_eq!(lcd.take_stat_interrupt(), !cgb);
+        }
+    }
+
+    #[test]
+    fn stat_write_applies_from_the_next_dot() {
+        let mut lcd: Lcd = Lcd::new(true, 0);
+        // one dot before HBlank: enabling the mode 0 source waits for the mode change
+        lcd.tick((OAM_SCAN_DOTS + DRAWING_DOTS) as u64);
+        assert_eq!(lcd.ppu.get_mode(), MODE_DRAWING);
+        lcd.io.write(STAT, STAT_MODE0_INT).unwrap();
+        assert!(!lcd.take_stat_interrupt());
+        lcd.tick(1);
+        assert_eq!(lcd.ppu.get_mode(), MODE_HBLANK);
+        assert!(lcd.take_stat_interrupt());
+        // enabling a source whose condition already holds is also a rising edge
+        let mut lcd: Lcd = Lcd::new(true, 0);
+        lcd.tick((OAM_SCAN_DOTS + DRAWING_DOTS + 1) as u64);
+        lcd.io.write(STAT, STAT_MODE0_INT).unwrap();
+        lcd.tick(1);
+        assert!(lcd.take_stat_interrupt());
+    }
+}
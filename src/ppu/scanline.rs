@@ -0,0 +1,174 @@
+use crate::constants::io_registers::{LCDC, LY, OPRI, SCX, SCY, WX};
+use crate::constants::lcd::{BG_ATTR_PALETTE, BG_ATTR_PRIORITY, LCDC_BG_ENABLE, LCDC_BG_MAP, LCDC_OBJ_ENABLE, LCDC_OBJ_SIZE, LCDC_WINDOW_ENABLE, LCDC_WINDOW_MAP, OBJ_PRIORITY, SCREEN_WIDTH};
+use crate::memory_bus::io::IO;
+use crate::memory_bus::v_ram::VRAM;
+use crate::ppu::mixer::Mixer;
+use crate::ppu::pixel::Pixel;
+use crate::ppu::sprite::Sprite;
+use crate::ppu::tile::Tile;
+
+/// Renders a whole line at once using the register values at the start of mode 3.
+///
+/// This is the fast renderer: mid-line register writes are not visible, and mode 3 always
+/// lasts the same amount of dots.
+pub struct Scanline;
+
+impl Scanline {
+    /// Renders the current LY into `shades` (160 DMG shades) and `colors` (160 RGB555 colors).
+    ///
+    /// # Parameters
+    /// - `sprites`: Result of the OAM scan for this line.
+    /// - `window_line`: Internal window line counter.
+    /// - `window_y_triggered`: Whether WY matched LY at some point during this frame.
+    ///
+    /// # Returns
+    /// `true` when the window was drawn, so the caller advances its line counter.
+    pub fn render(io: &IO, v_ram: &VRAM, sprites: &[Sprite], window_line: u8, window_y_triggered: bool, shades: &mut [u8], colors: &mut [u16]) -> bool {
+        let lcdc: u8 = io.get_register(LCDC);
+        let mut bg: [Pixel; SCREEN_WIDTH] = [Pixel::transparent(); SCREEN_WIDTH];
+        let window_drawn: bool = Scanline::render_background(io, v_ram, window_line, window_y_triggered, &mut bg);
+
+        let mut obj: [Option<Pixel>; SCREEN_WIDTH] = [None; SCREEN_WIDTH];
+        if lcdc & LCDC_OBJ_ENABLE != 0 {
+            Scanline::render_sprites(io, v_ram, sprites, &mut obj);
+        }
+
+        for x in 0..SCREEN_WIDTH {
+            let (shade, color) = Mixer::resolve(io, lcdc, bg[x], obj[x]);
+            shades[x] = shade;
+            colors[x] = color;
+        }
+        window_drawn
+    }
+
+    fn render_background(io: &IO, v_ram: &VRAM, window_line: u8, window_y_triggered: bool, bg: &mut [Pixel; SCREEN_WIDTH]) -> bool {
+        let lcdc: u8 = io.get_register(LCDC);
+        let ly: u8 = io.get_register(LY);
+        let scx: u8 = io.get_register(SCX);
+        let scy: u8 = io.get_register(SCY);
+        let wx: u8 = io.get_register(WX);
+        let cgb: bool = io.is_cgb_mode();
+
+        // on DMG LCDC bit 0 turns the window off too
+        let window_enabled: bool = lcdc & LCDC_WINDOW_ENABLE != 0 && (cgb || lcdc & LCDC_BG_ENABLE != 0);
+        let window_visible: bool = window_enabled && window_y_triggered && wx <= 166;
+        let mut window_drawn: bool = false;
+
+        for (x, pixel) in bg.iter_mut().enumerate() {
+            let in_window: bool = window_visible && x as u16 + 7 >= wx as u16;
+            let (map, map_x, map_y) = if in_window {
+                window_drawn = true;
+                let map: u16 = if lcdc & LCDC_WINDOW_MAP != 0 { 0x9C00 } else { 0x9800 };
+                (map, (x as u16 + 7 - wx as u16) as u8, window_line)
+            } else {
+                let map: u16 = if lcdc & LCDC_BG_MAP != 0 { 0x9C00 } else { 0x9800 };
+                (map, scx.wrapping_add(x as u8), scy.wrapping_add(ly))
+            };
+            let map_address: u16 = map + (map_y as u16 / 8) * 32 + map_x as u16 / 8;
+            let tile_index: u8 = v_ram.read_bank(0, map_address);
+            let attributes: u8 = if cgb { v_ram.read_bank(1, map_address) } else { 0 };
+            let (low, high) = Tile::fetch_bg_row(v_ram, lcdc, tile_index, attributes, map_y % 8);
+            let color: u8 = Tile::color_index(low, high, Tile::bg_bit_for_column(attributes, map_x % 8));
+            *pixel = Pixel::new(color, attributes & BG_ATTR_PALETTE, attributes & BG_ATTR_PRIORITY != 0);
+        }
+        window_drawn
+    }
+
+    fn render_sprites(io: &IO, v_ram: &VRAM, sprites: &[Sprite], obj: &mut [Option<Pixel>; SCREEN_WIDTH]) {
+        let lcdc: u8 = io.get_register(LCDC);
+        let ly: u8 = io.get_register(LY);
+        let height: u8 = if lcdc & LCDC_OBJ_SIZE != 0 { 16 } else { 8 };
+        let cgb: bool = io.is_cgb_mode();
+
+        let mut ordered: Vec<Sprite> = sprites.to_vec();
+        Sprite::sort_by_priority(&mut ordered, cgb && io.get_register(OPRI) & 0x01 == 0);
+
+        // a pixel belongs to the highest priority sprite with an opaque color there
+        for sprite in ordered.iter() {
+            let (low, high) = sprite.fetch_row(v_ram, ly, height, cgb);
+            for column in 0..8u8 {
+                let x: i16 = sprite.x as i16 - 8 + column as i16;
+                if !(0..SCREEN_WIDTH as i16).contains(&x) || obj[x as usize].is_some() {
+                    continue;
+                }
+                let color: u8 = Tile::color_index(low, high, sprite.bit_for_column(column));
+                if color != 0 {
+                    obj[x as usize] = Some(Pixel::new(color, sprite.palette(cgb), sprite.attributes & OBJ_PRIORITY != 0));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::io_registers::{BGP, OBP0, WY};
+    use crate::constants::lcd::{LCDC_ENABLE, LCDC_TILE_DATA};
+    use crate::memory_bus::bus::BUS;
+
+    const LCDC_ON: u8 = LCDC_ENABLE | LCDC_TILE_DATA | LCDC_BG_ENABLE;
+
+    /// Tile 1 is solid color 3, tile 2 solid color 1, everything else color 0.
+    fn setup(lcdc: u8) -> (IO, VRAM) {
+        let mut io: IO = IO::new();
+        io.set_register(LCDC, lcdc);
+        io.set_register(BGP, 0xE4);
+        io.set_register(OBP0, 0xE4);
+        let mut v_ram: VRAM = VRAM::new();
+        for row in 0..16 {
+            v_ram.write(0x8010 + row, 0xFF).unwrap();
+            v_ram.write(0x8020 + row, if row % 2 == 0 { 0xFF } else { 0x00 }).unwrap();
+        }
+        v_ram.write(0x9800, 1).unwrap();
+        (io, v_ram)
+    }
+
+    fn render(io: &IO, v_ram: &VRAM, sprites: &[Sprite], window_y_triggered: bool) -> ([u8; SCREEN_WIDTH], bool) {
+        let mut shades: [u8; SCREEN_WIDTH] = [0; SCREEN_WIDTH];
+        let mut colors: [u16; SCREEN_WIDTH] = [0; SCREEN_WIDTH];
+        let window_drawn: bool = Scanline::render(io, v_ram, sprites, 0, window_y_triggered, &mut shades, &mut colors);
+        (shades, window_drawn)
+    }
+
+    #[test]
+    fn background_scrolls() {
+        let (mut io, v_ram) = setup(LCDC_ON);
+        let (shades, _) = render(&io, &v_ram, &[], false);
+        assert_eq!(&shades[..9], &[3, 3, 3, 3, 3, 3, 3, 3, 0]);
+        io.set_register(SCX, 4);
+        let (shades, _) = render(&io, &v_ram, &[], false);
+        assert_eq!(&shades[..5], &[3, 3, 3, 3, 0]);
+        // the map wraps around: x = 252 shows tile 0 of the row
+        io.set_register(SCX, 252);
+        let (shades, _) = render(&io, &v_ram, &[], false);
+        assert_eq!(&shades[..5], &[0, 0, 0, 0, 3]);
+    }
+
+    #[test]
+    fn window_starts_at_wx_minus_7() {
+        let (mut io, mut v_ram) = setup(LCDC_ON | LCDC_WINDOW_ENABLE | LCDC_WINDOW_MAP);
+        v_ram.write(0x9C00, 2).unwrap();
+        io.set_register(WY, 0);
+        io.set_register(WX, 7 + 80);
+        let (shades, window_drawn) = render(&io, &v_ram, &[], true);
+        assert!(window_drawn);
+        assert_eq!(&shades[78..90], &[0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0]);
+        // not drawn before WY matched LY
+        let (shades, window_drawn) = render(&io, &v_ram, &[], false);
+        assert!(!window_drawn);
+        assert_eq!(shades[80], 0);
+    }
+
+    #[test]
+    fn sprites_mix_with_the_background() {
+        let (io, v_ram) = setup(LCDC_ON | LCDC_OBJ_ENABLE);
+        // a color 1 sprite overlapping the edge of the solid tile, one behind the background
+        let front: Sprite = Sprite { y: 16, x: 8 + 4, tile: 2, attributes: 0, index: 0 };
+        let (shades, _) = render(&io, &v_ram, &[front], false);
+        assert_eq!(&shades[2..14], &[3, 3, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0]);
+        let behind: Sprite = Sprite { attributes: OBJ_PRIORITY, ..front };
+        let (shades, _) = render(&io, &v_ram, &[behind], false);
+        assert_eq!(&shades[2..14], &[3, 3, 3, 3, 3, 3, 1, 1, 1, 1, 0, 0]);
+    }
+}
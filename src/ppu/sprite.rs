@@ -0,0 +1,184 @@
+use crate::constants::lcd::{MAX_SPRITES_PER_LINE, OBJ_BANK, OBJ_CGB_PALETTE, OBJ_PALETTE, OBJ_X_FLIP, OBJ_Y_FLIP};
+use crate::error::state_error::StateError;
+use crate::memory_bus::oam::OAM;
+use crate::memory_bus::v_ram::VRAM;
+use crate::state::snapshot::Snapshot;
+use crate::state::state_reader::StateReader;
+use crate::state::state_writer::StateWriter;
+
+/// One of the 40 object entries stored in OAM (0xFE00–0xFE9F).
+#[derive(Clone, Copy)]
+pub struct Sprite {
+    pub y: u8,
+    pub x: u8,
+    pub tile: u8,
+    pub attributes: u8,
+    pub index: u8,
+}
+
+impl Sprite {
+    /// Reads a sprite written by [`Snapshot::save_state`].
+    pub fn read_state(reader: &mut StateReader) -> Result<Sprite, StateError> {
+        let mut sprite: Sprite = Sprite { y: 0, x: 0, tile: 0, attributes: 0, index: 0 };
+        sprite.load_state(reader)?;
+        Ok(sprite)
+    }
+
+    /// Writes the sprites selected for a line, preceded by their count.
+    pub fn save_list(writer: &mut StateWriter, sprites: &[Sprite]) {
+        writer.write_u8(sprites.len() as u8);
+        sprites.iter().for_each(|sprite| sprite.save_state(writer));
+    }
+
+    pub fn load_list(reader: &mut StateReader) -> Result<Vec<Sprite>, StateError> {
+        let count: usize = reader.read_u8()? as usize;
+        if count > MAX_SPRITES_PER_LINE {
+            return Err(StateError::InvalidValue("sprites per line"));
+        }
+        (0..count).map(|_| Sprite::read_state(reader)).collect()
+    }
+
+    /// Reads the OAM entry number `index` (0..40).
+    pub fn from_oam(oam: &OAM, index: u8) -> Sprite {
+        let base: u16 = 0xFE00 + index as u16 * 4;
+        Sprite {
+            y: oam.read_byte(base),
+            x: oam.read_byte(base + 1),
+            tile: oam.read_byte(base + 2),
+            attributes: oam.read_byte(base + 3),
+            index,
+        }
+    }
+
+    /// Performs the mode 2 OAM scan for `ly`.
+    ///
+    /// Sprites are taken in OAM order and the scan stops after `MAX_SPRITES_PER_LINE`
+    /// hits, no matter their X position (off-screen sprites still count).
+    ///
+    /// # Parameters
+    /// - `height`: 8 or 16 depending on LCDC bit 2.
+    pub fn scan(oam: &OAM, ly: u8, height: u8) -> Vec<Sprite> {
+        let mut sprites: Vec<Sprite> = Vec::with_capacity(MAX_SPRITES_PER_LINE);
+        let line: u16 = ly as u16 + 16;
+        for index in 0..40 {
+            let sprite: Sprite = Sprite::from_oam(oam, index);
+            let top: u16 = sprite.y as u16;
+            if line >= top && line < top + height as u16 {
+                sprites.push(sprite);
+                if sprites.len() == MAX_SPRITES_PER_LINE {
+                    break;
+                }
+            }
+        }
+        sprites
+    }
+
+    /// Sorts sprites by drawing priority, highest first.
+    ///
+    /// On DMG (and on CGB with OPRI bit 0 set) smaller X wins and OAM order breaks ties.
+    /// In CGB mode only the OAM order matters.
+    pub fn sort_by_priority(sprites: &mut [Sprite], by_index: bool) {
+        if by_index {
+            sprites.sort_by_key(|s| s.index);
+        } else {
+            sprites.sort_by_key(|s| (s.x, s.index));
+        }
+    }
+
+    /// Returns the address of the tile row this sprite shows on `ly`, taking Y flip and
+    /// 8×16 mode into account.
+    pub fn row_address(&self, ly: u8, height: u8) -> u16 {
+        let mut row: u8 = (ly as u16 + 16 - self.y as u16) as u8;
+        if self.attributes & OBJ_Y_FLIP != 0 {
+            row = height - 1 - row;
+        }
+        let tile: u8 = if height == 16 { self.tile & 0xFE } else { self.tile };
+        0x8000 + tile as u16 * 16 + row as u16 * 2
+    }
+
+    /// Fetches the two bit planes of the row shown on `ly`. In CGB mode the tile may come
+    /// from VRAM bank 1.
+    pub fn fetch_row(&self, v_ram: &VRAM, ly: u8, height: u8, cgb: bool) -> (u8, u8) {
+        let address: u16 = self.row_address(ly, height);
+        let bank: u8 = if cgb && self.attributes & OBJ_BANK != 0 { 1 } else { 0 };
+        (v_ram.read_bank(bank, address), v_ram.read_bank(bank, address + 1))
+    }
+
+    /// Palette used by the sprite: OBP0/OBP1 (0/1) on DMG, palette 0–7 on CGB.
+    pub fn palette(&self, cgb: bool) -> u8 {
+        if cgb {
+            self.attributes & OBJ_CGB_PALETTE
+        } else if self.attributes & OBJ_PALETTE != 0 {
+            1
+        } else {
+            0
+        }
+    }
+
+    /// Returns the bit of the tile row bytes that holds the pixel `column` (0 = leftmost)
+    /// of the sprite, taking X flip into account.
+    pub fn bit_for_column(&self, column: u8) -> u8 {
+        if self.attributes & OBJ_X_FLIP != 0 { column } else { 7 - column }
+    }
+}
+
+impl Snapshot for Sprite {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&[self.y, self.x, self.tile, self.attributes, self.index]);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        let mut values: [u8; 5] = [0; 5];
+        reader.read_bytes(&mut values)?;
+        [self.y, self.x, self.tile, self.attributes, self.index] = values;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory_bus::bus::BUS;
+
+    fn place(oam: &mut OAM, index: u16, y: u8, x: u8) {
+        oam.write(0xFE00 + index * 4, y).unwrap();
+        oam.write(0xFE01 + index * 4, x).unwrap();
+    }
+
+    #[test]
+    fn scan_stops_after_ten_sprites() {
+        let mut oam: OAM = OAM::new();
+        for index in 0..12 {
+            // off-screen X still takes a slot
+            place(&mut oam, index, 16, if index == 0 { 0 } else { 8 + index as u8 });
+        }
+        let sprites: Vec<Sprite> = Sprite::scan(&oam, 0, 8);
+        assert_eq!(sprites.len(), MAX_SPRITES_PER_LINE);
+        assert_eq!(sprites[0].index, 0);
+        assert!(Sprite::scan(&oam, 8, 8).is_empty());
+        assert_eq!(Sprite::scan(&oam, 15, 16).len(), MAX_SPRITES_PER_LINE);
+    }
+
+    #[test]
+    fn priority_by_x_then_index() {
+        let mut oam: OAM = OAM::new();
+        place(&mut oam, 0, 16, 20);
+        place(&mut oam, 1, 16, 10);
+        place(&mut oam, 2, 16, 10);
+        let mut sprites: Vec<Sprite> = Sprite::scan(&oam, 0, 8);
+        Sprite::sort_by_priority(&mut sprites, false);
+        assert_eq!(sprites.iter().map(|s| s.index).collect::<Vec<u8>>(), vec![1, 2, 0]);
+        Sprite::sort_by_priority(&mut sprites, true);
+        assert_eq!(sprites.iter().map(|s| s.index).collect::<Vec<u8>>(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn rows_follow_flips_and_tall_sprites() {
+        let sprite: Sprite = Sprite { y: 16, x: 8, tile: 0x03, attributes: OBJ_Y_FLIP | OBJ_X_FLIP, index: 0 };
+        // 8x16 ignores bit 0 of the tile, Y flip reads the last row first
+        assert_eq!(sprite.row_address(0, 16), 0x8000 + 0x02 * 16 + 15 * 2);
+        assert_eq!(sprite.row_address(0, 8), 0x8000 + 0x03 * 16 + 7 * 2);
+        assert_eq!(sprite.bit_for_column(0), 0);
+        assert_eq!(sprite.palette(false), 0);
+    }
+}
@@ -0,0 +1,72 @@
+use crate::constants::lcd::{BG_ATTR_BANK, BG_ATTR_X_FLIP, BG_ATTR_Y_FLIP, LCDC_TILE_DATA};
+use crate::memory_bus::v_ram::VRAM;
+
+/// Helpers to address and decode the 2bpp tile data stored in VRAM.
+pub struct Tile;
+
+impl Tile {
+    /// Returns the address of row `row` of BG/window tile `index`.
+    ///
+    /// With LCDC bit 4 set tiles are addressed from 0x8000 with an unsigned index,
+    /// otherwise from 0x9000 with a signed one (the "8800 method").
+    pub fn bg_row_address(lcdc: u8, index: u8, row: u8) -> u16 {
+        let base: u16 = if lcdc & LCDC_TILE_DATA != 0 {
+            0x8000 + index as u16 * 16
+        } else {
+            (0x9000i32 + (index as i8) as i32 * 16) as u16
+        };
+        base + row as u16 * 2
+    }
+
+    /// Fetches the two bit planes of row `row` of BG/window tile `index`.
+    ///
+    /// `attributes` is the CGB BG map attribute byte (0 on DMG), which selects the VRAM
+    /// bank and may flip the tile vertically.
+    pub fn fetch_bg_row(v_ram: &VRAM, lcdc: u8, index: u8, attributes: u8, row: u8) -> (u8, u8) {
+        let row: u8 = if attributes & BG_ATTR_Y_FLIP != 0 { 7 - row } else { row };
+        let bank: u8 = if attributes & BG_ATTR_BANK != 0 { 1 } else { 0 };
+        let address: u16 = Tile::bg_row_address(lcdc, index, row);
+        (v_ram.read_bank(bank, address), v_ram.read_bank(bank, address + 1))
+    }
+
+    /// Returns the bit of a BG tile row that holds pixel `column` (0 = leftmost), taking
+    /// the CGB horizontal flip attribute into account.
+    pub fn bg_bit_for_column(attributes: u8, column: u8) -> u8 {
+        if attributes & BG_ATTR_X_FLIP != 0 { column } else { 7 - column }
+    }
+
+    /// Combines the low and high bit planes of a tile row into the 2-bit color index
+    /// stored at `bit` (7 = leftmost pixel).
+    pub fn color_index(low: u8, high: u8, bit: u8) -> u8 {
+        (((high >> bit) & 1) << 1) | ((low >> bit) & 1)
+    }
+
+    /// Maps a color index through a DMG palette register (BGP, OBP0 or OBP1).
+    pub fn apply_palette(palette: u8, color: u8) -> u8 {
+        (palette >> (color * 2)) & 0x03
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn addressing_methods() {
+        assert_eq!(Tile::bg_row_address(LCDC_TILE_DATA, 0x80, 1), 0x8802);
+        assert_eq!(Tile::bg_row_address(0, 0x00, 0), 0x9000);
+        assert_eq!(Tile::bg_row_address(0, 0x80, 7), 0x880E);
+    }
+
+    #[test]
+    fn decodes_2bpp_rows() {
+        // leftmost pixel color 2, next one color 1, then color 3
+        let (low, high): (u8, u8) = (0b0110_0000, 0b1010_0000);
+        assert_eq!(Tile::color_index(low, high, 7), 2);
+        assert_eq!(Tile::color_index(low, high, 6), 1);
+        assert_eq!(Tile::color_index(low, high, 5), 3);
+        assert_eq!(Tile::color_index(low, high, Tile::bg_bit_for_column(BG_ATTR_X_FLIP, 7)), 2);
+        assert_eq!(Tile::apply_palette(0xE4, 2), 2);
+        assert_eq!(Tile::apply_palette(0x1B, 0), 3);
+    }
+}
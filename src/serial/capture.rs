@@ -0,0 +1,46 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::serial::serial_peer::SerialPeer;
+
+/// Records every byte sent on the internal clock, like test ROMs printing their results
+/// through the link port. Reads 0xFF as a disconnected port does.
+///
+/// Clones share the same buffer: keep one to read the output after plugging the other in.
+#[derive(Clone)]
+pub struct SerialCapture {
+    output: Rc<RefCell<Vec<u8>>>,
+}
+
+impl SerialCapture {
+    pub fn new() -> SerialCapture {
+        SerialCapture { output: Rc::new(RefCell::new(Vec::new())) }
+    }
+
+    /// Returns the captured bytes as text.
+    pub fn get_text(&self) -> String {
+        String::from_utf8_lossy(&self.output.borrow()).into_owned()
+    }
+}
+
+impl SerialPeer for SerialCapture {
+    fn transfer(&mut self, data: u8, _duration: u64) -> u8 {
+        self.output.borrow_mut().push(data);
+        0xFF
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clones_share_the_output() {
+        let capture: SerialCapture = SerialCapture::new();
+        let mut plugged: SerialCapture = capture.clone();
+        assert_eq!(plugged.transfer(b'o', 4096), 0xFF);
+        plugged.transfer(b'k', 4096);
+        plugged.transfer(0xFF, 4096);
+        assert_eq!(capture.get_text(), "ok\u{FFFD}");
+    }
+}
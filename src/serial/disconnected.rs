@@ -0,0 +1,11 @@
+use crate::serial::serial_peer::SerialPeer;
+
+/// Nothing plugged in: the input line is pulled up, so every transfer reads 0xFF, and
+/// nobody ever drives the external clock.
+pub struct Disconnected;
+
+impl SerialPeer for Disconnected {
+    fn transfer(&mut self, _data: u8, _duration: u64) -> u8 {
+        0xFF
+    }
+}
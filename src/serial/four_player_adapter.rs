@@ -0,0 +1,457 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::apu::apu::APU_CLOCK;
+use crate::gameboy::Gameboy;
+use crate::serial::disconnected::Disconnected;
+use crate::serial::serial_peer::SerialPeer;
+
+pub const PLAYERS: usize = 4;
+
+const PING_HEADER: u8 = 0xFE;
+const PING_ACK: u8 = 0x88;
+const START_REQUEST: u8 = 0xAA;
+const START_REPLY: u8 = 0xCC;
+const RESTART_REQUEST: u8 = 0xFF;
+// a bit takes 6 * RR + 512 T-cycles, RR being the rate chosen by player 1: 8192 Hz at 0
+const BIT_CYCLES: u64 = 512;
+const RATE_CYCLES: u64 = 6;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Phase {
+    // the adapter sends FE and the status of the players, waiting for player 1 to start
+    Ping,
+    // four CC bytes announce the transmission phase
+    Start,
+    // packets of every player are buffered and sent back to everybody
+    Transmission,
+}
+
+#[derive(Clone, Copy)]
+struct Port {
+    // byte offered while the Game Boy waits on the external clock
+    waiting: Option<u8>,
+    // byte on its way to the Game Boy, with the time left until its transfer completes
+    delivery: Option<(u8, u64)>,
+    // whether the Game Boy polled the external clock since its previous tick
+    listening: bool,
+}
+
+struct AdapterState {
+    phase: Phase,
+    ports: [Port; PLAYERS],
+    // players that acknowledged the last ping packet
+    connected: [bool; PLAYERS],
+    // position in the current ping packet, start sequence or transmission cycle
+    index: usize,
+    // bytes every player answered during the current ping packet
+    ping_replies: [[u8; 4]; PLAYERS],
+    rate: u8,
+    size: usize,
+    // packets received during the current transmission cycle
+    packets: [Vec<u8>; PLAYERS],
+    // packets of the previous cycle, sent back to every player
+    outgoing: Vec<u8>,
+}
+
+impl AdapterState {
+    fn new() -> AdapterState {
+        AdapterState {
+            phase: Phase::Ping,
+            ports: [Port { waiting: None, delivery: None, listening: false }; PLAYERS],
+            connected: [false; PLAYERS],
+            index: 0,
+            ping_replies: [[0; 4]; PLAYERS],
+            rate: 0,
+            size: 4,
+            packets: Default::default(),
+            outgoing: Vec::new(),
+        }
+    }
+
+    fn byte_cycles(&self) -> u64 {
+        8 * (BIT_CYCLES + RATE_CYCLES * self.rate as u64)
+    }
+
+    /// Status byte of the ping packets: connected players in the upper nibble, the
+    /// number of the receiving player in the lower bits.
+    fn status(&self, player: usize) -> u8 {
+        let connected: u8 = self.connected.iter()
+            .enumerate()
+            .fold(0, |mask, (i, connected)| if *connected { mask | (0x10 << i) } else { mask });
+        connected | (player as u8 + 1)
+    }
+
+    /// Byte the adapter sends to `player` at the current position.
+    fn output(&self, player: usize) -> u8 {
+        match self.phase {
+            Phase::Ping if self.index == 0 => PING_HEADER,
+            Phase::Ping => self.status(player),
+            Phase::Start => START_REPLY,
+            Phase::Transmission => self.outgoing.get(self.index).copied().unwrap_or(0),
+        }
+    }
+
+    /// Shifts one byte with every Game Boy at once and moves the protocol forward.
+    fn clock_byte(&mut self) {
+        let duration: u64 = self.byte_cycles();
+        let mut replies: [u8; PLAYERS] = [0xFF; PLAYERS];
+        for (player, reply) in replies.iter_mut().enumerate() {
+            let output: u8 = self.output(player);
+            let port: &mut Port = &mut self.ports[player];
+            // a Game Boy not waiting on the external clock misses the byte
+            if let Some(byte) = port.waiting.take() {
+                port.delivery = Some((output, duration));
+                *reply = byte;
+            }
+        }
+        match self.phase {
+            Phase::Ping => self.ping(replies),
+            Phase::Start => {
+                self.index += 1;
+                if self.index == 4 {
+                    self.start_transmission();
+                }
+            }
+            Phase::Transmission => self.transmit(replies),
+        }
+    }
+
+    fn ping(&mut self, replies: [u8; PLAYERS]) {
+        for (player, reply) in replies.iter().enumerate() {
+            self.ping_replies[player][self.index] = *reply;
+        }
+        self.index += 1;
+        if self.index < 4 {
+            return;
+        }
+        self.index = 0;
+        // the players connected during the previous packets keep their slots
+        let first: [u8; 4] = self.ping_replies[0];
+        if first == [START_REQUEST; 4] && self.connected[0] {
+            self.phase = Phase::Start;
+            return;
+        }
+        for player in 0..PLAYERS {
+            let reply: [u8; 4] = self.ping_replies[player];
+            self.connected[player] = reply[0] == PING_ACK && reply[1] == PING_ACK;
+        }
+        // player 1 chooses the speed and the size of the packets
+        if self.connected[0] {
+            self.rate = first[2];
+            self.size = (first[3] as usize).max(1);
+        }
+    }
+
+    fn start_transmission(&mut self) {
+        self.phase = Phase::Transmission;
+        self.index = 0;
+        self.packets = Default::default();
+        self.outgoing = vec![0; self.size * PLAYERS];
+    }
+
+    fn transmit(&mut self, replies: [u8; PLAYERS]) {
+        if self.index < self.size {
+            for (packet, reply) in self.packets.iter_mut().zip(replies) {
+                packet.push(reply);
+            }
+        }
+        self.index += 1;
+        if self.index < self.size * PLAYERS {
+            return;
+        }
+        self.index = 0;
+        // every connected player asking for it sends the adapter back to the ping phase
+        let connected: Vec<usize> = (0..PLAYERS).filter(|player| self.connected[*player]).collect();
+        let restart: bool = !connected.is_empty() && connected.iter()
+            .all(|player| self.packets[*player].iter().take(4).all(|byte| *byte == RESTART_REQUEST));
+        if restart {
+            self.phase = Phase::Ping;
+            return;
+        }
+        let mut outgoing: Vec<u8> = Vec::with_capacity(self.size * PLAYERS);
+        for (player, packet) in self.packets.iter_mut().enumerate() {
+            if self.connected[player] {
+                outgoing.append(packet);
+            } else {
+                outgoing.resize(outgoing.len() + self.size, 0);
+                packet.clear();
+            }
+        }
+        self.outgoing = outgoing;
+    }
+}
+
+/// One port of a [`FourPlayerAdapter`]. The adapter always drives the clock, so the Game
+/// Boy side only ever transfers on the external clock.
+pub struct AdapterPort {
+    state: Rc<RefCell<AdapterState>>,
+    player: usize,
+}
+
+impl SerialPeer for AdapterPort {
+    fn transfer(&mut self, _data: u8, _duration: u64) -> u8 {
+        // the adapter never listens to a Game Boy driving the clock
+        0xFF
+    }
+
+    /// Withdraws the byte offered, which `external_clock` renews while the Game Boy keeps
+    /// waiting, and drops the byte on its way to a Game Boy that stopped waiting.
+    fn tick(&mut self, _elapsed: u64) {
+        let mut state = self.state.borrow_mut();
+        let port: &mut Port = &mut state.ports[self.player];
+        port.waiting = None;
+        if !port.listening {
+            port.delivery = None;
+        }
+        port.listening = false;
+    }
+
+    fn external_clock(&mut self, data: u8, elapsed: u64) -> Option<u8> {
+        let mut state = self.state.borrow_mut();
+        let port: &mut Port = &mut state.ports[self.player];
+        port.listening = true;
+        match port.delivery {
+            Some((byte, left)) if left <= elapsed => {
+                port.delivery = None;
+                Some(byte)
+            }
+            Some((byte, left)) => {
+                port.delivery = Some((byte, left - elapsed));
+                None
+            }
+            None => {
+                port.waiting = Some(data);
+                None
+            }
+        }
+    }
+}
+
+/// DMG-07 four-player adapter linking up to four Game Boys in the same process.
+///
+/// In the ping phase the adapter repeatedly sends `FE` followed by three status bytes; the
+/// Game Boys acknowledge with `88 88` and player 1 adds the transmission rate and packet
+/// size. Player 1 sending `AA AA AA AA` starts the transmission phase (announced with
+/// `CC CC CC CC`): from then on the adapter collects one packet from each player per cycle
+/// and sends all four packets of the previous cycle back to everybody. Packets starting
+/// with `FF FF FF FF` from every player go back to the ping phase.
+///
+/// Like [`crate::serial::link_cable::LinkCable`], the Game Boys run in lockstep.
+pub struct FourPlayerAdapter {
+    // boxed, as the Game Boys are too large to be moved around on the stack
+    gameboys: Vec<Box<Gameboy>>,
+    // emulated time of each Game Boy, in 4194304 Hz cycles
+    time: Vec<u64>,
+    state: Rc<RefCell<AdapterState>>,
+    next_byte: u64,
+}
+
+impl FourPlayerAdapter {
+    /// Plugs the given Game Boys into ports 1 to 4, in order.
+    pub fn new(gameboys: Vec<Box<Gameboy>>) -> FourPlayerAdapter {
+        assert!(!gameboys.is_empty() && gameboys.len() <= PLAYERS, "The DMG-07 has four ports");
+        let state: Rc<RefCell<AdapterState>> = Rc::new(RefCell::new(AdapterState::new()));
+        let next_byte: u64 = state.borrow().byte_cycles();
+        let mut adapter: FourPlayerAdapter = FourPlayerAdapter {
+            time: vec![0; gameboys.len()],
+            gameboys,
+            state: state.clone(),
+            next_byte,
+        };
+        for (player, gameboy) in adapter.gameboys.iter_mut().enumerate() {
+            gameboy.set_serial_peer(Box::new(AdapterPort { state: state.clone(), player }));
+        }
+        adapter
+    }
+
+    pub fn get_mut(&mut self, player: usize) -> &mut Gameboy {
+        &mut self.gameboys[player]
+    }
+
+    /// Returns which players acknowledged the last ping packet.
+    pub fn get_connected(&self) -> [bool; PLAYERS] {
+        self.state.borrow().connected
+    }
+
+    /// Returns `true` once player 1 started the transmission phase.
+    pub fn is_transmitting(&self) -> bool {
+        self.state.borrow().phase == Phase::Transmission
+    }
+
+    fn is_stopped(&self, player: usize) -> bool {
+        !self.gameboys[player].cpu.is_running()
+    }
+
+    /// Runs one instruction on the Game Boy that is the furthest behind, and the adapter
+    /// up to the time every Game Boy reached.
+    ///
+    /// # Returns
+    /// `false` when no Game Boy can run because every CPU is stopped.
+    pub fn step(&mut self) -> bool {
+        let Some(player) = (0..self.gameboys.len())
+            .filter(|player| !self.is_stopped(*player))
+            .min_by_key(|player| self.time[*player]) else {
+            return false;
+        };
+        let gameboy: &mut Gameboy = &mut self.gameboys[player];
+        let cycles: u64 = gameboy.step();
+        self.time[player] += if gameboy.memory_bus.is_double_speed() { cycles / 2 } else { cycles };
+
+        let now: u64 = (0..self.gameboys.len())
+            .filter(|player| !self.is_stopped(*player))
+            .map(|player| self.time[player])
+            .min()
+            .unwrap_or(self.time[player]);
+        // stopped Game Boys don't hold the others back, and wake up in sync with them
+        for player in 0..self.gameboys.len() {
+            if self.is_stopped(player) {
+                self.time[player] = now;
+            }
+        }
+        while self.next_byte <= now {
+            let mut state = self.state.borrow_mut();
+            state.clock_byte();
+            self.next_byte += state.byte_cycles();
+        }
+        true
+    }
+
+    /// Runs every Game Boy for `seconds` of emulated time, or until all are stopped.
+    pub fn run_for(&mut self, seconds: f64) {
+        let start: u64 = self.time.iter().copied().min().unwrap_or(0);
+        let target: u64 = start + (seconds * APU_CLOCK as f64) as u64;
+        while self.time.iter().copied().min().unwrap_or(target) < target {
+            if !self.step() {
+                break;
+            }
+        }
+    }
+
+    /// Unplugs the adapter, returning the Game Boys with disconnected ports.
+    pub fn unplug(mut self) -> Vec<Box<Gameboy>> {
+        for gameboy in self.gameboys.iter_mut() {
+            gameboy.set_serial_peer(Box::new(Disconnected));
+        }
+        self.gameboys
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Plays the Game Boys on the ports: every byte, each player waiting on the external
+    /// clock offers a byte of its script and gets back what the adapter sent.
+    struct Script {
+        state: Rc<RefCell<AdapterState>>,
+        ports: Vec<AdapterPort>,
+    }
+
+    impl Script {
+        fn new() -> Script {
+            let state: Rc<RefCell<AdapterState>> = Rc::new(RefCell::new(AdapterState::new()));
+            let ports: Vec<AdapterPort> = (0..PLAYERS)
+                .map(|player| AdapterPort { state: state.clone(), player })
+                .collect();
+            Script { state, ports }
+        }
+
+        fn exchange(&mut self, offers: [Option<u8>; PLAYERS]) -> [Option<u8>; PLAYERS] {
+            for (port, offer) in self.ports.iter_mut().zip(offers) {
+                port.tick(0);
+                if let Some(byte) = offer {
+                    assert_eq!(port.external_clock(byte, 0), None);
+                }
+            }
+            let duration: u64 = self.state.borrow().byte_cycles();
+            self.state.borrow_mut().clock_byte();
+            let mut received: [Option<u8>; PLAYERS] = [None; PLAYERS];
+            for ((port, offer), received) in self.ports.iter_mut().zip(offers).zip(received.iter_mut()) {
+                port.tick(duration);
+                if let Some(byte) = offer {
+                    *received = port.external_clock(byte, duration);
+                }
+            }
+            received
+        }
+
+        /// Exchanges a packet where players 1 and 2 send `first` and `second`.
+        fn packet(&mut self, first: &[u8], second: &[u8]) -> (Vec<u8>, Vec<u8>) {
+            first.iter().zip(second)
+                .map(|(a, b)| {
+                    let received = self.exchange([Some(*a), Some(*b), None, None]);
+                    (received[0].unwrap(), received[1].unwrap())
+                })
+                .unzip()
+        }
+
+        fn get_phase(&self) -> Phase {
+            self.state.borrow().phase
+        }
+    }
+
+    #[test]
+    fn bytes_follow_the_rate_of_player_1() {
+        let mut state: AdapterState = AdapterState::new();
+        assert_eq!(state.byte_cycles(), 8 * 512);
+        state.rate = 0x10;
+        assert_eq!(state.byte_cycles(), 8 * (6 * 0x10 + 512));
+    }
+
+    #[test]
+    fn ping_start_transmission_and_restart() {
+        let mut script: Script = Script::new();
+        // ping: nobody is connected yet, players 1 and 2 acknowledge
+        let (first, second) = script.packet(&[0x88, 0x88, 0x10, 0x04], &[0x88, 0x88, 0x00, 0x00]);
+        assert_eq!(first, [PING_HEADER, 0x01, 0x01, 0x01]);
+        assert_eq!(second, [PING_HEADER, 0x02, 0x02, 0x02]);
+        assert_eq!(script.state.borrow().connected, [true, true, false, false]);
+        assert_eq!(script.state.borrow().rate, 0x10);
+        // player 1 asks for the transmission, which four CC announce
+        let (first, second) = script.packet(&[START_REQUEST; 4], &[0x88, 0x88, 0x00, 0x00]);
+        assert_eq!(first, [PING_HEADER, 0x31, 0x31, 0x31]);
+        assert_eq!(second, [PING_HEADER, 0x32, 0x32, 0x32]);
+        assert!(script.get_phase() == Phase::Start);
+        let (first, second) = script.packet(&[0; 4], &[0; 4]);
+        assert_eq!((first, second), (vec![START_REPLY; 4], vec![START_REPLY; 4]));
+        assert!(script.get_phase() == Phase::Transmission);
+        // packets of a cycle go back to everybody during the next one
+        let mut first: Vec<u8> = vec![1, 2, 3, 4];
+        let mut second: Vec<u8> = vec![5, 6, 7, 8];
+        first.resize(16, 0);
+        second.resize(16, 0);
+        let (received, _) = script.packet(&first, &second);
+        assert_eq!(received, [0; 16]);
+        let restart: Vec<u8> = vec![RESTART_REQUEST; 16];
+        let (received_1, received_2) = script.packet(&restart, &restart);
+        let expected: Vec<u8> = vec![1, 2, 3, 4, 5, 6, 7, 8, 0, 0, 0, 0, 0, 0, 0, 0];
+        assert_eq!((received_1, received_2), (expected.clone(), expected));
+        // FF FF FF FF from every connected player goes back to the ping phase
+        assert!(script.get_phase() == Phase::Ping);
+        let (first, _) = script.packet(&[0x88, 0x88, 0x10, 0x04], &[0x88, 0x88, 0x00, 0x00]);
+        assert_eq!(first, [PING_HEADER, 0x31, 0x31, 0x31]);
+    }
+
+    #[test]
+    fn restart_needs_a_connected_player() {
+        let mut script: Script = Script::new();
+        script.state.borrow_mut().start_transmission();
+        // the ports of nobody waiting read FF
+        for _ in 0..4 * PLAYERS {
+            assert_eq!(script.exchange([None; PLAYERS]), [None; PLAYERS]);
+        }
+        assert!(script.get_phase() == Phase::Transmission);
+    }
+
+    #[test]
+    fn byte_offered_is_withdrawn_when_the_game_boy_stops_waiting() {
+        let mut script: Script = Script::new();
+        let port: &mut AdapterPort = &mut script.ports[0];
+        assert_eq!(port.external_clock(0x88, 0), None);
+        port.tick(4);
+        script.state.borrow_mut().clock_byte();
+        assert_eq!(script.state.borrow().ping_replies[0][0], 0xFF);
+        assert!(script.state.borrow().ports[0].delivery.is_none());
+    }
+}
@@ -0,0 +1,226 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::apu::apu::APU_CLOCK;
+use crate::gameboy::Gameboy;
+use crate::serial::disconnected::Disconnected;
+use crate::serial::serial_peer::SerialPeer;
+
+/// State of the cable shared by both ends.
+struct Wire {
+    // byte each side offers while it waits for a transfer on the external clock
+    waiting: [Option<u8>; 2],
+    // byte on its way to each side, with the time left until its transfer completes
+    delivery: [Option<(u8, u64)>; 2],
+    // whether each side polled the external clock since its previous tick
+    listening: [bool; 2],
+}
+
+/// One end of a [`LinkCable`].
+///
+/// When this side starts a transfer on its internal clock, the other side must be waiting
+/// on the external clock: both bytes are swapped, and the other side completes its
+/// transfer after the time this side takes to shift the eight bits. Otherwise nobody
+/// answers and the line reads 0xFF.
+pub struct LinkPort {
+    wire: Rc<RefCell<Wire>>,
+    side: usize,
+}
+
+impl LinkPort {
+    /// Returns both ends of a new cable.
+    pub fn pair() -> (LinkPort, LinkPort) {
+        let wire: Rc<RefCell<Wire>> = Rc::new(RefCell::new(Wire {
+            waiting: [None; 2],
+            delivery: [None; 2],
+            listening: [false; 2],
+        }));
+        (LinkPort { wire: wire.clone(), side: 0 }, LinkPort { wire, side: 1 })
+    }
+}
+
+impl SerialPeer for LinkPort {
+    fn transfer(&mut self, data: u8, duration: u64) -> u8 {
+        let mut wire = self.wire.borrow_mut();
+        let other: usize = 1 - self.side;
+        match wire.waiting[other].take() {
+            Some(byte) => {
+                wire.delivery[other] = Some((data, duration));
+                byte
+            }
+            None => 0xFF,
+        }
+    }
+
+    /// The port calls `external_clock` right after every tick while this side waits, so
+    /// the byte offered is withdrawn here, and a side that stopped waiting (SC written with
+    /// bit 7 clear) drops the byte that was on its way.
+    fn tick(&mut self, _elapsed: u64) {
+        let mut wire = self.wire.borrow_mut();
+        wire.waiting[self.side] = None;
+        if !wire.listening[self.side] {
+            wire.delivery[self.side] = None;
+        }
+        wire.listening[self.side] = false;
+    }
+
+    fn external_clock(&mut self, data: u8, elapsed: u64) -> Option<u8> {
+        let mut wire = self.wire.borrow_mut();
+        wire.listening[self.side] = true;
+        match wire.delivery[self.side] {
+            Some((byte, left)) if left <= elapsed => {
+                wire.delivery[self.side] = None;
+                Some(byte)
+            }
+            Some((byte, left)) => {
+                wire.delivery[self.side] = Some((byte, left - elapsed));
+                None
+            }
+            None => {
+                wire.waiting[self.side] = Some(data);
+                None
+            }
+        }
+    }
+}
+
+/// Two Game Boys in the same process connected by a link cable.
+///
+/// They run in lockstep: every step executes one instruction on the one that is behind in
+/// emulated time, so a transfer started by one side always finds the other at the same
+/// point in time. Nothing depends on the host, which makes link sessions deterministic.
+pub struct LinkCable {
+    // boxed, as both machines are too large to be moved around on the stack
+    pub first: Box<Gameboy>,
+    pub second: Box<Gameboy>,
+    // emulated time of each side, in 4194304 Hz cycles
+    time: [u64; 2],
+}
+
+impl LinkCable {
+    /// Plugs both ends of a new cable into `first` and `second`.
+    pub fn new(first: Box<Gameboy>, second: Box<Gameboy>) -> LinkCable {
+        let (first_port, second_port) = LinkPort::pair();
+        let mut cable: LinkCable = LinkCable { first, second, time: [0; 2] };
+        cable.first.set_serial_peer(Box::new(first_port));
+        cable.second.set_serial_peer(Box::new(second_port));
+        cable
+    }
+
+    /// Runs one instruction on the Game Boy that is behind.
+    ///
+    /// # Returns
+    /// `false` when neither side can run because both CPUs are stopped.
+    pub fn step(&mut self) -> bool {
+        let behind: usize = if self.time[0] <= self.time[1] { 0 } else { 1 };
+        let side: usize = match (self.is_stopped(behind), self.is_stopped(1 - behind)) {
+            (false, _) => behind,
+            (true, false) => 1 - behind,
+            (true, true) => return false,
+        };
+        let gameboy: &mut Gameboy = if side == 0 { &mut self.first } else { &mut self.second };
+        let cycles: u64 = gameboy.step();
+        self.time[side] += if gameboy.memory_bus.is_double_speed() { cycles / 2 } else { cycles };
+        // a stopped side doesn't hold the other one back, and wakes up in sync with it
+        if self.is_stopped(1 - side) {
+            self.time[1 - side] = self.time[side];
+        }
+        true
+    }
+
+    fn is_stopped(&self, side: usize) -> bool {
+        let gameboy: &Gameboy = if side == 0 { &self.first } else { &self.second };
+        !gameboy.cpu.is_running()
+    }
+
+    /// Runs both Game Boys for `seconds` of emulated time, or until both are stopped.
+    pub fn run_for(&mut self, seconds: f64) {
+        let target: u64 = self.time[0].min(self.time[1]) + (seconds * APU_CLOCK as f64) as u64;
+        while self.time[0].min(self.time[1]) < target {
+            if !self.step() {
+                break;
+            }
+        }
+    }
+
+    /// Unplugs the cable, returning both Game Boys with disconnected ports.
+    pub fn unplug(mut self) -> (Box<Gameboy>, Box<Gameboy>) {
+        self.first.set_serial_peer(Box::new(Disconnected));
+        self.second.set_serial_peer(Box::new(Disconnected));
+        (self.first, self.second)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::io_registers::{SB, SC};
+    use crate::model::Model;
+
+    #[test]
+    fn transfer_reaches_the_waiting_side_after_the_duration() {
+        let (mut master, mut slave) = LinkPort::pair();
+        slave.tick(4);
+        assert_eq!(slave.external_clock(0x34, 4), None);
+        assert_eq!(master.transfer(0x12, 4096), 0x34);
+        slave.tick(4092);
+        assert_eq!(slave.external_clock(0x34, 4092), None);
+        slave.tick(4);
+        assert_eq!(slave.external_clock(0x34, 4), Some(0x12));
+    }
+
+    #[test]
+    fn nobody_answers_without_a_waiting_side() {
+        let (mut master, _slave) = LinkPort::pair();
+        assert_eq!(master.transfer(0x12, 4096), 0xFF);
+    }
+
+    #[test]
+    fn aborted_slave_withdraws_its_byte() {
+        let (mut master, mut slave) = LinkPort::pair();
+        slave.tick(4);
+        assert_eq!(slave.external_clock(0x34, 4), None);
+        // SC written with bit 7 clear: the port ticks without polling the clock
+        slave.tick(4);
+        assert_eq!(master.transfer(0x12, 4096), 0xFF);
+    }
+
+    #[test]
+    fn aborted_slave_drops_the_byte_on_its_way() {
+        let (mut master, mut slave) = LinkPort::pair();
+        slave.tick(4);
+        slave.external_clock(0x34, 4);
+        assert_eq!(master.transfer(0x12, 4096), 0x34);
+        slave.tick(4);
+        slave.tick(4);
+        // waiting again later doesn't complete with the old byte
+        slave.tick(4096);
+        assert_eq!(slave.external_clock(0x56, 4096), None);
+    }
+
+    /// A DMG running `program` from 0x0100.
+    fn gameboy(program: &[u8]) -> Box<Gameboy> {
+        let mut gameboy: Box<Gameboy> = Box::new(Gameboy::new());
+        gameboy.set_model(Model::DMG);
+        gameboy.memory_bus.rom.bank0[0x0100..0x0100 + program.len()].copy_from_slice(program);
+        gameboy.setup_model();
+        gameboy.skip_boot();
+        gameboy
+    }
+
+    #[test]
+    fn two_gameboys_swap_a_byte() {
+        // LD A,0x34; LDH (SB),A; LD A,0x80; LDH (SC),A; JR -2
+        let slave: Box<Gameboy> = gameboy(&[0x3E, 0x34, 0xE0, 0x01, 0x3E, 0x80, 0xE0, 0x02, 0x18, 0xFE]);
+        // the master waits a few NOPs for the slave to listen
+        let master: Box<Gameboy> = gameboy(&[0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x3E, 0x12, 0xE0, 0x01, 0x3E, 0x81, 0xE0, 0x02, 0x18, 0xFE]);
+        let mut cable: LinkCable = LinkCable::new(master, slave);
+        cable.run_for(0.01);
+        let (master, slave) = cable.unplug();
+        assert_eq!(master.memory_bus.read(SB), 0x34);
+        assert_eq!(slave.memory_bus.read(SB), 0x12);
+        assert_eq!(master.memory_bus.read(SC) & 0x80, 0);
+        assert_eq!(slave.memory_bus.read(SC) & 0x80, 0);
+    }
+}
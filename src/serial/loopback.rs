@@ -0,0 +1,10 @@
+use crate::serial::serial_peer::SerialPeer;
+
+/// Output line wired to the input line: every byte sent comes back.
+pub struct Loopback;
+
+impl SerialPeer for Loopback {
+    fn transfer(&mut self, data: u8, _duration: u64) -> u8 {
+        data
+    }
+}
@@ -0,0 +1,8 @@
+pub mod capture;
+pub mod disconnected;
+pub mod four_player_adapter;
+pub mod link_cable;
+pub mod loopback;
+pub mod printer;
+pub mod serial_peer;
+pub mod tcp_link;
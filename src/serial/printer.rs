@@ -0,0 +1,433 @@
+use std::cell::RefCell;
+use std::io;
+use std::rc::Rc;
+
+use crate::serial::serial_peer::SerialPeer;
+use crate::utils::bmp::write_bmp;
+
+const MAGIC: [u8; 2] = [0x88, 0x33];
+const DEVICE_ID: u8 = 0x81;
+
+const CMD_INIT: u8 = 0x01;
+const CMD_PRINT: u8 = 0x02;
+const CMD_DATA: u8 = 0x04;
+const CMD_STATUS: u8 = 0x0F;
+
+const STATUS_CHECKSUM_ERROR: u8 = 0x01;
+const STATUS_BUSY: u8 = 0x02;
+const STATUS_FULL: u8 = 0x04;
+const STATUS_UNPROCESSED: u8 = 0x08;
+
+pub const PAPER_WIDTH: usize = 160;
+// a DATA packet holds a band of 2 rows of 20 tiles, and the buffer up to 9 bands
+const BAND_SIZE: usize = 640;
+const MAX_BANDS: usize = 9;
+// paper fed for each margin unit of the PRINT command, in pixel lines
+const MARGIN_LINES: usize = 8;
+// STATUS packets answered busy after a PRINT, while the head "prints"
+const PRINT_POLLS: u8 = 4;
+// shades of the thermal paper, from white to black
+const PAPER_SHADES: [[u8; 3]; 4] = [[0xFF, 0xFF, 0xFF], [0xAA, 0xAA, 0xAA], [0x55, 0x55, 0x55], [0x00, 0x00, 0x00]];
+
+/// Position in the packet being received.
+#[derive(Clone, Copy, PartialEq)]
+enum PacketState {
+    Magic1,
+    Magic2,
+    Command,
+    Compression,
+    LengthLow,
+    LengthHigh,
+    Data,
+    ChecksumLow,
+    ChecksumHigh,
+    // the printer answers with its ID, then with its status
+    DeviceId,
+    Status,
+}
+
+/// A finished sheet of paper: one shade (0 white to 3 black) per pixel, 160 pixels wide.
+#[derive(Clone)]
+pub struct PrintedPage {
+    pub height: usize,
+    pub shades: Vec<u8>,
+    // BMP file the page was written to, if any
+    pub path: Option<String>,
+}
+
+struct PrinterState {
+    packet_state: PacketState,
+    command: u8,
+    compressed: bool,
+    length: u16,
+    data: Vec<u8>,
+    checksum: u16,
+    received_checksum: u16,
+    // decompressed tile data waiting for a PRINT
+    buffer: Vec<u8>,
+    status: u8,
+    busy_polls: u8,
+    // the sheet being printed, fed out on the next PRINT with a bottom margin
+    paper: Vec<u8>,
+    pages: Vec<PrintedPage>,
+    // files are written as `<prefix>_001.bmp`, `<prefix>_002.bmp`...
+    output_prefix: Option<String>,
+    // first page that could not be written
+    error: Option<io::Error>,
+}
+
+/// Game Boy Printer.
+///
+/// The Game Boy sends packets made of the magic bytes 0x88 0x33, a command, a compression
+/// flag, a little endian data length, the data and a checksum (the sum of every byte from
+/// the command on). The printer answers the two bytes that follow with 0x81 and its status.
+/// DATA packets fill the buffer with tiles (optionally run-length compressed) and PRINT
+/// renders them with the given palette and margins. A sheet is finished, and written to
+/// a file when an output prefix is set, once a PRINT feeds paper after the image.
+///
+/// Clones share the same printer: keep one to collect the pages after plugging the other.
+#[derive(Clone)]
+pub struct Printer {
+    state: Rc<RefCell<PrinterState>>,
+}
+
+impl Printer {
+    pub fn new() -> Printer {
+        Printer {
+            state: Rc::new(RefCell::new(PrinterState {
+                packet_state: PacketState::Magic1,
+                command: 0,
+                compressed: false,
+                length: 0,
+                data: Vec::new(),
+                checksum: 0,
+                received_checksum: 0,
+                buffer: Vec::new(),
+                status: 0,
+                busy_polls: 0,
+                paper: Vec::new(),
+                pages: Vec::new(),
+                output_prefix: None,
+                error: None,
+            })),
+        }
+    }
+
+    /// Writes every finished page as a BMP file named `<prefix>_<page number>.bmp`.
+    pub fn set_output_prefix(&self, prefix: &str) {
+        self.state.borrow_mut().output_prefix = Some(prefix.to_string());
+    }
+
+    /// Returns the pages printed so far.
+    pub fn get_pages(&self) -> Vec<PrintedPage> {
+        self.state.borrow().pages.clone()
+    }
+
+    /// Finishes the current sheet even if no bottom margin was fed, e.g. when closing the
+    /// emulator.
+    pub fn cut_paper(&self) {
+        self.state.borrow_mut().cut_paper();
+    }
+
+    /// Returns the error of the first page that could not be written, once. That page is
+    /// left out of [`Printer::get_pages`].
+    pub fn take_error(&self) -> Option<io::Error> {
+        self.state.borrow_mut().error.take()
+    }
+}
+
+impl PrinterState {
+    /// Handles one byte sent by the Game Boy.
+    ///
+    /// # Returns
+    /// The byte the printer shifts back at the same time.
+    fn receive(&mut self, byte: u8) -> u8 {
+        let mut reply: u8 = 0x00;
+        self.packet_state = match self.packet_state {
+            PacketState::Magic1 if byte == MAGIC[0] => PacketState::Magic2,
+            PacketState::Magic1 => PacketState::Magic1,
+            PacketState::Magic2 if byte == MAGIC[1] => PacketState::Command,
+            // resynchronize on a stray 0x88
+            PacketState::Magic2 if byte == MAGIC[0] => PacketState::Magic2,
+            PacketState::Magic2 => PacketState::Magic1,
+            PacketState::Command => {
+                self.command = byte;
+                self.checksum = byte as u16;
+                PacketState::Compression
+            }
+            PacketState::Compression => {
+                self.compressed = byte & 0x01 != 0;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                PacketState::LengthLow
+            }
+            PacketState::LengthLow => {
+                self.length = byte as u16;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                PacketState::LengthHigh
+            }
+            PacketState::LengthHigh => {
+                self.length |= (byte as u16) << 8;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                self.data.clear();
+                if self.length == 0 { PacketState::ChecksumLow } else { PacketState::Data }
+            }
+            PacketState::Data => {
+                self.data.push(byte);
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                if self.data.len() == self.length as usize { PacketState::ChecksumLow } else { PacketState::Data }
+            }
+            PacketState::ChecksumLow => {
+                self.received_checksum = byte as u16;
+                PacketState::ChecksumHigh
+            }
+            PacketState::ChecksumHigh => {
+                self.received_checksum |= (byte as u16) << 8;
+                PacketState::DeviceId
+            }
+            PacketState::DeviceId => {
+                reply = DEVICE_ID;
+                // the command runs between the ID and the status bytes
+                if self.received_checksum == self.checksum {
+                    self.status &= !STATUS_CHECKSUM_ERROR;
+                    self.execute();
+                } else {
+                    self.status |= STATUS_CHECKSUM_ERROR;
+                }
+                PacketState::Status
+            }
+            PacketState::Status => {
+                reply = self.status;
+                PacketState::Magic1
+            }
+        };
+        reply
+    }
+
+    fn execute(&mut self) {
+        match self.command {
+            CMD_INIT => {
+                self.buffer.clear();
+                self.status = 0;
+                self.busy_polls = 0;
+            }
+            CMD_DATA if self.data.is_empty() => {
+                // an empty DATA packet ends the transfer of the image
+                self.status |= STATUS_FULL;
+            }
+            CMD_DATA => {
+                let data: Vec<u8> = if self.compressed { decompress(&self.data) } else { self.data.clone() };
+                let room: usize = BAND_SIZE * MAX_BANDS - self.buffer.len();
+                self.buffer.extend_from_slice(&data[..data.len().min(room)]);
+                self.status |= STATUS_UNPROCESSED;
+                if self.buffer.len() == BAND_SIZE * MAX_BANDS {
+                    self.status |= STATUS_FULL;
+                }
+            }
+            CMD_PRINT if self.data.len() >= 4 => {
+                self.print(self.data[0], self.data[1], self.data[2]);
+                self.status = STATUS_BUSY;
+                self.busy_polls = PRINT_POLLS;
+            }
+            CMD_STATUS if self.busy_polls > 0 => {
+                self.busy_polls -= 1;
+                if self.busy_polls == 0 {
+                    self.status &= !STATUS_BUSY;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Prints the buffer on the current sheet.
+    ///
+    /// # Parameters
+    /// - `sheets`: Copies of the image (0 only feeds paper).
+    /// - `margins`: Feed before (upper nibble) and after (lower nibble) the image.
+    /// - `palette`: Maps color numbers to shades, like BGP.
+    fn print(&mut self, sheets: u8, margins: u8, palette: u8) {
+        // some games leave the palette at 0 and expect the default one
+        let palette: u8 = if palette == 0 { 0xE4 } else { palette };
+        self.feed((margins >> 4) as usize * MARGIN_LINES);
+        let height: usize = self.buffer.len() / BAND_SIZE * 16;
+        for _ in 0..sheets {
+            for y in 0..height {
+                for x in 0..PAPER_WIDTH {
+                    let color: u8 = self.pixel(x, y);
+                    self.paper.push((palette >> (color * 2)) & 0x03);
+                }
+            }
+        }
+        self.buffer.clear();
+        let after: usize = (margins & 0x0F) as usize;
+        if after > 0 {
+            self.feed(after * MARGIN_LINES);
+            self.cut_paper();
+        }
+    }
+
+    /// Color number of a pixel of the buffer: tiles are stored row after row, 20 per row.
+    fn pixel(&self, x: usize, y: usize) -> u8 {
+        let tile: usize = (y / 8) * 20 + x / 8;
+        let address: usize = tile * 16 + (y % 8) * 2;
+        let bit: usize = 7 - x % 8;
+        let low: u8 = (self.buffer[address] >> bit) & 0x01;
+        let high: u8 = (self.buffer[address + 1] >> bit) & 0x01;
+        (high << 1) | low
+    }
+
+    fn feed(&mut self, lines: usize) {
+        self.paper.resize(self.paper.len() + lines * PAPER_WIDTH, 0);
+    }
+
+    fn cut_paper(&mut self) {
+        if self.paper.is_empty() {
+            return;
+        }
+        let mut page: PrintedPage = PrintedPage {
+            height: self.paper.len() / PAPER_WIDTH,
+            shades: std::mem::take(&mut self.paper),
+            path: None,
+        };
+        if let Some(prefix) = self.output_prefix.as_ref() {
+            let path: String = format!("{}_{:03}.bmp", prefix, self.pages.len() + 1);
+            let pixels: Vec<[u8; 3]> = page.shades.iter().map(|shade| PAPER_SHADES[*shade as usize]).collect();
+            if let Err(e) = write_bmp(&path, PAPER_WIDTH, page.height, &pixels) {
+                self.error.get_or_insert(io::Error::new(e.kind(), format!("{}: {}", path, e)));
+                return;
+            }
+            page.path = Some(path);
+        }
+        self.pages.push(page);
+    }
+}
+
+/// Expands the run-length encoding of DATA packets: a control byte with bit 7 set repeats
+/// the next byte (control & 0x7F) + 2 times, otherwise (control + 1) bytes follow as is.
+fn decompress(data: &[u8]) -> Vec<u8> {
+    let mut output: Vec<u8> = Vec::new();
+    let mut i: usize = 0;
+    while i < data.len() {
+        let control: u8 = data[i];
+        i += 1;
+        if control & 0x80 != 0 {
+            let Some(byte) = data.get(i) else {
+                break;
+            };
+            output.extend(std::iter::repeat_n(*byte, (control & 0x7F) as usize + 2));
+            i += 1;
+        } else {
+            let end: usize = (i + control as usize + 1).min(data.len());
+            output.extend_from_slice(&data[i..end]);
+            i = end;
+        }
+    }
+    output
+}
+
+impl SerialPeer for Printer {
+    fn transfer(&mut self, data: u8, _duration: u64) -> u8 {
+        self.state.borrow_mut().receive(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    /// Sends a whole packet, returning the two bytes the printer answered at the end.
+    fn send(printer: &mut Printer, command: u8, compressed: bool, data: &[u8]) -> (u8, u8) {
+        let header: [u8; 4] = [command, compressed as u8, data.len() as u8, (data.len() >> 8) as u8];
+        let checksum: u16 = header.iter().chain(data).fold(0u16, |sum, byte| sum.wrapping_add(*byte as u16));
+        for byte in MAGIC.iter().chain(&header).chain(data).chain(&checksum.to_le_bytes()) {
+            assert_eq!(printer.transfer(*byte, 0), 0x00);
+        }
+        (printer.transfer(0, 0), printer.transfer(0, 0))
+    }
+
+    /// One band whose top-left tile is solid color 3, the rest color 0.
+    fn band() -> Vec<u8> {
+        let mut data: Vec<u8> = vec![0; BAND_SIZE];
+        data[..16].fill(0xFF);
+        data
+    }
+
+    #[test]
+    fn answers_id_and_status() {
+        let mut printer: Printer = Printer::new();
+        assert_eq!(send(&mut printer, CMD_INIT, false, &[]), (DEVICE_ID, 0x00));
+        assert_eq!(send(&mut printer, CMD_DATA, false, &band()), (DEVICE_ID, STATUS_UNPROCESSED));
+        // a corrupted checksum is reported and the packet ignored
+        for byte in [0x88, 0x33, CMD_INIT, 0, 0, 0, 0xFF, 0xFF] {
+            printer.transfer(byte, 0);
+        }
+        assert_eq!(printer.transfer(0, 0), DEVICE_ID);
+        assert_eq!(printer.transfer(0, 0), STATUS_UNPROCESSED | STATUS_CHECKSUM_ERROR);
+    }
+
+    #[test]
+    fn prints_a_band_with_margins() {
+        let printer: Printer = Printer::new();
+        let mut port: Printer = printer.clone();
+        send(&mut port, CMD_INIT, false, &[]);
+        send(&mut port, CMD_DATA, false, &band());
+        send(&mut port, CMD_DATA, false, &[]);
+        // one copy, 1 unit of margin before and 2 after, inverted palette
+        assert_eq!(send(&mut port, CMD_PRINT, false, &[1, 0x12, 0x1B, 0x40]).1, STATUS_BUSY);
+        for _ in 1..PRINT_POLLS {
+            assert_eq!(send(&mut port, CMD_STATUS, false, &[]).1, STATUS_BUSY);
+        }
+        assert_eq!(send(&mut port, CMD_STATUS, false, &[]).1, 0x00);
+
+        let pages: Vec<PrintedPage> = printer.get_pages();
+        assert_eq!(pages.len(), 1);
+        assert_eq!(pages[0].height, MARGIN_LINES + 16 + 2 * MARGIN_LINES);
+        let row = |y: usize| &pages[0].shades[y * PAPER_WIDTH..(y + 1) * PAPER_WIDTH];
+        assert!(row(0).iter().all(|&shade| shade == 0));
+        assert_eq!(&row(MARGIN_LINES)[..9], &[0, 0, 0, 0, 0, 0, 0, 0, 3]);
+    }
+
+    #[test]
+    fn compressed_data_matches_raw_data() {
+        // a run of 16 x 0xFF, then 624 literal zeros sent as 4 blocks of 128 and one of 112
+        let mut packet: Vec<u8> = vec![0x80 | 14, 0xFF];
+        for length in [128usize, 128, 128, 128, 112] {
+            packet.push(length as u8 - 1);
+            packet.extend(std::iter::repeat_n(0, length));
+        }
+        assert_eq!(decompress(&packet), band());
+        let compressed: Printer = Printer::new();
+        let mut port: Printer = compressed.clone();
+        send(&mut port, CMD_DATA, true, &packet);
+        send(&mut port, CMD_PRINT, false, &[1, 0x01, 0xE4, 0x40]);
+        assert_eq!(compressed.get_pages()[0].shades[..8], [3; 8]);
+    }
+
+    #[test]
+    fn cut_paper_finishes_the_sheet() {
+        let printer: Printer = Printer::new();
+        let mut port: Printer = printer.clone();
+        send(&mut port, CMD_DATA, false, &band());
+        send(&mut port, CMD_PRINT, false, &[1, 0x00, 0xE4, 0x40]);
+        assert!(printer.get_pages().is_empty());
+        printer.cut_paper();
+        assert_eq!(printer.get_pages()[0].height, 16);
+        assert_eq!(printer.get_pages()[0].path, None);
+    }
+
+    #[test]
+    fn failed_page_writes_are_reported() {
+        let printer: Printer = Printer::new();
+        let dir: PathBuf = std::env::temp_dir().join(format!("rustyboy-printer-{}", std::process::id()));
+        printer.set_output_prefix(dir.join("missing").join("page").to_str().unwrap());
+        let mut port: Printer = printer.clone();
+        send(&mut port, CMD_DATA, false, &band());
+        send(&mut port, CMD_PRINT, false, &[1, 0x00, 0xE4, 0x40]);
+        printer.cut_paper();
+        assert!(printer.get_pages().is_empty());
+        let e: io::Error = printer.take_error().unwrap();
+        assert!(e.to_string().contains("page_001.bmp"), "{}", e);
+        assert!(printer.take_error().is_none());
+    }
+}
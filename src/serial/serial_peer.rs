@@ -0,0 +1,48 @@
+use std::io;
+
+/// Whatever is plugged into the link port.
+///
+/// The Game Boy exchanges one byte per transfer: it shifts its SB out while shifting the
+/// peer's byte in. With the internal clock this Game Boy drives the transfer and calls
+/// [`SerialPeer::transfer`]; with the external clock it waits for the peer to drive it,
+/// polling [`SerialPeer::external_clock`]. Durations are counted in 4194304 Hz cycles, so
+/// they don't depend on the CGB speed mode of either side.
+pub trait SerialPeer {
+    /// A transfer started on the internal clock.
+    ///
+    /// # Parameters
+    /// - `data`: Byte shifted out by this Game Boy.
+    /// - `duration`: Time the eight bits take, for peers that clock another device.
+    ///
+    /// # Returns
+    /// The byte the peer shifts back.
+    fn transfer(&mut self, data: u8, duration: u64) -> u8;
+
+    /// Called on every tick, whatever the state of the port, for peers that keep their
+    /// own notion of time.
+    ///
+    /// # Parameters
+    /// - `elapsed`: Time since the previous call.
+    fn tick(&mut self, elapsed: u64) {
+        let _ = elapsed;
+    }
+
+    /// Called on every tick while this Game Boy waits for a transfer on the external
+    /// clock.
+    ///
+    /// # Parameters
+    /// - `data`: Byte this Game Boy would shift out.
+    /// - `elapsed`: Time since the previous call.
+    ///
+    /// # Returns
+    /// The byte shifted in when the peer clocked a whole transfer, `None` otherwise.
+    fn external_clock(&mut self, data: u8, elapsed: u64) -> Option<u8> {
+        let _ = (data, elapsed);
+        None
+    }
+
+    /// Returns the error that broke the connection to the peer, once.
+    fn take_error(&mut self) -> Option<io::Error> {
+        None
+    }
+}
@@ -0,0 +1,394 @@
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use crate::serial::serial_peer::SerialPeer;
+
+const MAGIC: &[u8; 4] = b"GBLK";
+const PROTOCOL_VERSION: u8 = 1;
+
+// messages: a type byte followed by a fixed size little endian payload
+const MSG_SYNC: u8 = 0x01; // time: u64
+const MSG_TRANSFER: u8 = 0x02; // time: u64, data: u8, duration: u64
+const MSG_REPLY: u8 = 0x03; // data: u8
+
+/// Default latency tolerance: one frame.
+pub const DEFAULT_TOLERANCE: u64 = 70224;
+// time between two reads of the socket while nothing forces it
+const POLL_CYCLES: u64 = 512;
+// how far a side waiting on the external clock may run ahead of the other one: a bit at
+// 8192 Hz, since the other side may start a transfer at any time
+const WAIT_WINDOW: u64 = 512;
+/// Default time to wait for the other side before giving up on it.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Link cable to another emulator process over TCP.
+///
+/// Each side tells the other how far it has run with `SYNC` messages, and stops as soon
+/// as it gets more than the latency tolerance ahead of the other, so neither Game Boy can
+/// run away from its partner. A side starting a transfer on its internal clock sends
+/// `TRANSFER` with its byte and waits for the `REPLY` holding the byte of the other side,
+/// which completes its own transfer on the external clock after the same duration.
+///
+/// A smaller tolerance keeps both sides closer in time but stalls more on slow networks.
+/// Times are in 4194304 Hz cycles.
+pub struct TcpLink {
+    stream: Option<TcpStream>,
+    tolerance: u64,
+    timeout: Duration,
+    error: Option<io::Error>,
+    // emulated time of this side and the latest one reported by the other side
+    time: u64,
+    peer_time: u64,
+    last_sync: u64,
+    last_poll: u64,
+    // bytes received that don't make up a whole message yet
+    inbox: Vec<u8>,
+    // byte this side offers while waiting on the external clock
+    waiting: Option<u8>,
+    // byte received from the other side, with the time its transfer completes
+    delivery: Option<(u8, u64)>,
+    // transfer started by the other side and not answered yet: time, byte and duration
+    pending: Option<(u64, u8, u64)>,
+    reply: Option<u8>,
+}
+
+impl TcpLink {
+    /// Waits for the other emulator to connect on `addr`.
+    pub fn listen<A: ToSocketAddrs>(addr: A) -> io::Result<TcpLink> {
+        TcpLink::accept(&TcpListener::bind(addr)?)
+    }
+
+    /// Waits for the other emulator to connect to `listener`.
+    pub fn accept(listener: &TcpListener) -> io::Result<TcpLink> {
+        let (stream, _) = listener.accept()?;
+        TcpLink::handshake(stream)
+    }
+
+    /// Connects to an emulator listening on `addr`.
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> io::Result<TcpLink> {
+        TcpLink::handshake(TcpStream::connect(addr)?)
+    }
+
+    fn handshake(mut stream: TcpStream) -> io::Result<TcpLink> {
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(DEFAULT_TIMEOUT))?;
+        let mut hello: [u8; 5] = [0; 5];
+        hello[..4].copy_from_slice(MAGIC);
+        hello[4] = PROTOCOL_VERSION;
+        stream.write_all(&hello)?;
+        let mut answer: [u8; 5] = [0; 5];
+        stream.read_exact(&mut answer)?;
+        if answer != hello {
+            return Err(io::Error::new(ErrorKind::InvalidData, "the other side doesn't speak the link protocol"));
+        }
+        // polled between ticks from now on, blocking only while waiting for the other side
+        stream.set_nonblocking(true)?;
+        Ok(TcpLink {
+            stream: Some(stream),
+            tolerance: DEFAULT_TOLERANCE,
+            timeout: DEFAULT_TIMEOUT,
+            error: None,
+            time: 0,
+            peer_time: 0,
+            last_sync: 0,
+            last_poll: 0,
+            inbox: Vec::new(),
+            waiting: None,
+            delivery: None,
+            pending: None,
+            reply: None,
+        })
+    }
+
+    /// Sets how far (in 4194304 Hz cycles) this side may run ahead of the other one.
+    pub fn set_tolerance(&mut self, tolerance: u64) {
+        self.tolerance = tolerance.max(POLL_CYCLES);
+    }
+
+    pub fn get_tolerance(&self) -> u64 {
+        self.tolerance
+    }
+
+    /// Sets how long to wait for the other side before disconnecting.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+        let result: io::Result<()> = match self.stream.as_ref() {
+            Some(stream) => stream.set_read_timeout(Some(timeout)),
+            None => Ok(()),
+        };
+        if let Err(e) = result {
+            self.disconnect(e);
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    /// Drops the connection after an error: from now on the port behaves as disconnected.
+    fn disconnect(&mut self, e: io::Error) {
+        self.error.get_or_insert(e);
+        self.stream = None;
+        self.delivery = None;
+        self.pending = None;
+    }
+
+    fn send(&mut self, message: &[u8]) {
+        let Some(stream) = self.stream.as_mut() else {
+            return;
+        };
+        if let Err(e) = stream.write_all(message) {
+            self.disconnect(e);
+        }
+    }
+
+    fn send_sync(&mut self) {
+        let mut message: Vec<u8> = vec![MSG_SYNC];
+        message.extend_from_slice(&self.time.to_le_bytes());
+        self.send(&message);
+        self.last_sync = self.time;
+    }
+
+    /// Reads what the other side sent, handles every complete message and answers the
+    /// transfer of the other side once this side has caught up with it.
+    ///
+    /// # Parameters
+    /// - `block`: Wait until at least some data arrives, or the timeout elapses.
+    fn pump(&mut self, block: bool) {
+        let Some(stream) = self.stream.as_mut() else {
+            return;
+        };
+        let timeout: Duration = self.timeout;
+        let mut buffer: [u8; 256] = [0; 256];
+        let result: io::Result<usize> = if block {
+            stream.set_nonblocking(false)
+                .and_then(|_| stream.read(&mut buffer))
+                .and_then(|count| stream.set_nonblocking(true).map(|_| count))
+        } else {
+            stream.read(&mut buffer)
+        };
+        match result {
+            Ok(0) => self.disconnect(io::Error::new(ErrorKind::UnexpectedEof, "closed by the other side")),
+            Ok(count) => self.inbox.extend_from_slice(&buffer[..count]),
+            Err(e) if !block && e.kind() == ErrorKind::WouldBlock => {}
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                let message: String = format!("no answer from the other side in {:?}", timeout);
+                self.disconnect(io::Error::new(ErrorKind::TimedOut, message));
+            }
+            Err(e) => self.disconnect(e),
+        }
+        while let Some(size) = TcpLink::message_size(&self.inbox) {
+            let message: Vec<u8> = self.inbox.drain(..size).collect();
+            self.handle(&message);
+        }
+        self.answer();
+    }
+
+    /// Answers the pending transfer of the other side if this side reached its time: the
+    /// byte offered on the external clock, or 0xFF when not waiting.
+    fn answer(&mut self) {
+        let Some((time, data, duration)) = self.pending else {
+            return;
+        };
+        if time > self.time {
+            return;
+        }
+        self.pending = None;
+        let answer: u8 = match self.waiting.take() {
+            Some(byte) => {
+                // both sides complete the transfer at the same time
+                self.delivery = Some((data, time + duration));
+                byte
+            }
+            None => 0xFF,
+        };
+        self.send(&[MSG_REPLY, answer]);
+    }
+
+    /// Size of the first message of `inbox`, if it was received completely.
+    fn message_size(inbox: &[u8]) -> Option<usize> {
+        let size: usize = match *inbox.first()? {
+            MSG_SYNC => 9,
+            MSG_TRANSFER => 18,
+            _ => 2,
+        };
+        if inbox.len() >= size { Some(size) } else { None }
+    }
+
+    fn handle(&mut self, message: &[u8]) {
+        let word = |offset: usize| -> u64 {
+            u64::from_le_bytes(message[offset..offset + 8].try_into().expect("Messages have fixed sizes"))
+        };
+        match message[0] {
+            MSG_SYNC => self.peer_time = self.peer_time.max(word(1)),
+            MSG_TRANSFER => {
+                self.peer_time = self.peer_time.max(word(1));
+                self.pending = Some((word(1), message[9], word(10)));
+            }
+            MSG_REPLY => self.reply = Some(message[1]),
+            _ => self.disconnect(io::Error::new(ErrorKind::InvalidData, "unknown link message")),
+        }
+    }
+}
+
+impl SerialPeer for TcpLink {
+    fn transfer(&mut self, data: u8, duration: u64) -> u8 {
+        let mut message: Vec<u8> = vec![MSG_TRANSFER];
+        message.extend_from_slice(&self.time.to_le_bytes());
+        message.push(data);
+        message.extend_from_slice(&duration.to_le_bytes());
+        self.send(&message);
+        self.reply = None;
+        while self.reply.is_none() && self.is_connected() {
+            self.pump(true);
+        }
+        self.reply.take().unwrap_or(0xFF)
+    }
+
+    fn tick(&mut self, elapsed: u64) {
+        if !self.is_connected() {
+            return;
+        }
+        self.time += elapsed;
+        if self.time >= self.last_sync + self.tolerance / 2 {
+            self.send_sync();
+        }
+        if self.time >= self.last_poll + POLL_CYCLES {
+            self.last_poll = self.time;
+            self.pump(false);
+        }
+        // never run ahead of the other side by more than the tolerance
+        while self.is_connected() && self.time > self.peer_time + self.tolerance {
+            self.pump(true);
+        }
+        // a transfer started while this side ran up to now
+        self.answer();
+        // `external_clock` renews it while the port keeps waiting
+        self.waiting = None;
+    }
+
+    fn external_clock(&mut self, data: u8, _elapsed: u64) -> Option<u8> {
+        match self.delivery {
+            Some((byte, time)) if time <= self.time => {
+                self.delivery = None;
+                Some(byte)
+            }
+            Some(_) => None,
+            None => {
+                self.waiting = Some(data);
+                if self.time > self.peer_time + WAIT_WINDOW {
+                    // the other side may be waiting for this one too
+                    self.send_sync();
+                    while self.is_connected() && self.delivery.is_none() && self.time > self.peer_time + WAIT_WINDOW {
+                        self.pump(true);
+                    }
+                }
+                None
+            }
+        }
+    }
+
+    fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Instant;
+
+    /// Ticks a side waiting on the external clock until the other side drives a transfer.
+    fn wait_for_transfer(link: &mut TcpLink, data: u8) -> u8 {
+        loop {
+            link.tick(64);
+            if let Some(byte) = link.external_clock(data, 64) {
+                return byte;
+            }
+            assert!(link.is_connected());
+        }
+    }
+
+    #[test]
+    fn swaps_a_byte_both_ways_over_localhost() {
+        let listener: TcpListener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (done, wait) = mpsc::channel::<()>();
+        let other = thread::spawn(move || {
+            let mut link: TcpLink = TcpLink::connect(addr).unwrap();
+            let received: u8 = wait_for_transfer(&mut link, 0x34);
+            let answer: u8 = link.transfer(0x56, 4096);
+            // hanging up now would cut the transfer short on the other side
+            wait.recv().unwrap();
+            (received, answer)
+        });
+        let mut link: TcpLink = TcpLink::accept(&listener).unwrap();
+        assert_eq!(link.transfer(0x12, 4096), 0x34);
+        assert_eq!(wait_for_transfer(&mut link, 0x78), 0x56);
+        done.send(()).unwrap();
+        assert_eq!(other.join().unwrap(), (0x12, 0x78));
+        assert!(link.take_error().is_none());
+    }
+
+    #[test]
+    fn side_not_waiting_answers_0xff() {
+        let listener: TcpListener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let other = thread::spawn(move || {
+            let mut link: TcpLink = TcpLink::connect(addr).unwrap();
+            // runs without waiting on the external clock until the other side hangs up
+            while link.is_connected() {
+                link.tick(64);
+            }
+        });
+        let mut link: TcpLink = TcpLink::accept(&listener).unwrap();
+        assert_eq!(link.transfer(0x12, 4096), 0xFF);
+        assert!(link.take_error().is_none());
+        drop(link);
+        other.join().unwrap();
+    }
+
+    #[test]
+    fn polls_of_a_quiet_link_do_not_wait() {
+        let listener: TcpListener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (done, wait) = mpsc::channel::<()>();
+        let other = thread::spawn(move || {
+            let _link: TcpLink = TcpLink::connect(addr).unwrap();
+            wait.recv().unwrap();
+        });
+        let mut link: TcpLink = TcpLink::accept(&listener).unwrap();
+        let start: Instant = Instant::now();
+        // well within the tolerance, so nothing forces a wait for the other side
+        for _ in 0..10 {
+            link.tick(POLL_CYCLES);
+        }
+        assert!(start.elapsed() < DEFAULT_TIMEOUT / 2);
+        assert!(link.is_connected());
+        done.send(()).unwrap();
+        other.join().unwrap();
+    }
+
+    #[test]
+    fn silent_side_times_out() {
+        let listener: TcpListener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (done, wait) = mpsc::channel::<()>();
+        let other = thread::spawn(move || {
+            // connected, but never reads or answers
+            let _link: TcpLink = TcpLink::connect(addr).unwrap();
+            wait.recv().unwrap();
+        });
+        let mut link: TcpLink = TcpLink::accept(&listener).unwrap();
+        link.set_timeout(Duration::from_millis(100));
+        assert_eq!(link.transfer(0x12, 4096), 0xFF);
+        assert!(!link.is_connected());
+        assert_eq!(link.take_error().map(|e| e.kind()), Some(ErrorKind::TimedOut));
+        assert!(link.take_error().is_none());
+        done.send(()).unwrap();
+        other.join().unwrap();
+    }
+}
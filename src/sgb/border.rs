@@ -0,0 +1,141 @@
+use crate::error::state_error::StateError;
+use crate::state::snapshot::Snapshot;
+use crate::state::state_reader::StateReader;
+use crate::state::state_writer::StateWriter;
+
+// size of the tile map and palettes sent by PCT_TRN
+const MAP_ENTRIES: usize = 32 * 32;
+const PALETTES_OFFSET: usize = MAP_ENTRIES * 2;
+// 256 tiles of 32 bytes, sent in two halves by CHR_TRN
+const TILES_SIZE: usize = 256 * 32;
+
+/// Picture frame drawn by the Super Game Boy around the game screen.
+///
+/// Tiles are in the SNES 4bpp format: bit planes 0-1 interleaved in the first 16 bytes,
+/// planes 2-3 in the next 16. Map entries hold the tile number (bits 0-7), the palette
+/// (bits 10-12, 4 to 7) and the horizontal (bit 14) and vertical (bit 15) flips.
+pub struct Border {
+    tiles: [u8; TILES_SIZE],
+    map: [u16; MAP_ENTRIES],
+    palettes: [[u16; 16]; 4],
+}
+
+impl Border {
+    pub fn new() -> Border {
+        Border {
+            tiles: [0; TILES_SIZE],
+            map: [0; MAP_ENTRIES],
+            palettes: [[0; 16]; 4],
+        }
+    }
+
+    /// CHR_TRN: stores 128 tiles, the first or the second half of the tile set.
+    pub fn load_tiles(&mut self, upper: bool, data: &[u8]) {
+        let start: usize = if upper { TILES_SIZE / 2 } else { 0 };
+        let size: usize = data.len().min(TILES_SIZE / 2);
+        self.tiles[start..start + size].copy_from_slice(&data[..size]);
+    }
+
+    /// PCT_TRN: stores the tile map followed by palettes 4 to 7.
+    pub fn load_map(&mut self, data: &[u8]) {
+        for (entry, bytes) in self.map.iter_mut().zip(data.chunks_exact(2)) {
+            *entry = u16::from_le_bytes([bytes[0], bytes[1]]);
+        }
+        let colors = data.get(PALETTES_OFFSET..).unwrap_or(&[]).chunks_exact(2);
+        for (i, bytes) in colors.take(64).enumerate() {
+            self.palettes[i / 16][i % 16] = u16::from_le_bytes([bytes[0], bytes[1]]) & 0x7FFF;
+        }
+    }
+
+    /// Returns the RGB555 color of the border at (`x`, `y`), `None` where it's transparent.
+    pub fn get_color(&self, x: usize, y: usize) -> Option<u16> {
+        let entry: u16 = self.map[(y / 8) * 32 + x / 8];
+        let tile: usize = (entry & 0xFF) as usize;
+        let row: usize = if entry & 0x8000 != 0 { 7 - y % 8 } else { y % 8 };
+        let column: usize = if entry & 0x4000 != 0 { 7 - x % 8 } else { x % 8 };
+        let base: usize = tile * 32 + row * 2;
+        let planes: [u8; 4] = [self.tiles[base], self.tiles[base + 1], self.tiles[base + 16], self.tiles[base + 17]];
+        let color: usize = planes.iter()
+            .enumerate()
+            .fold(0, |color, (plane, bits)| color | ((((bits >> (7 - column)) & 0x01) as usize) << plane));
+        if color == 0 {
+            return None;
+        }
+        Some(self.palettes[((entry >> 10) & 0x03) as usize][color])
+    }
+}
+
+impl Snapshot for Border {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.tiles);
+        writer.write_u16_slice(&self.map);
+        self.palettes.iter().for_each(|palette| writer.write_u16_slice(palette));
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        reader.read_bytes(&mut self.tiles)?;
+        reader.read_u16_slice(&mut self.map)?;
+        for palette in self.palettes.iter_mut() {
+            reader.read_u16_slice(palette)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a tile whose top-left pixel has color 1 and top-right pixel color 15
+    fn corner_tile() -> Vec<u8> {
+        let mut tile: Vec<u8> = vec![0; 32];
+        tile[0] = 0x81;
+        tile[1] = 0x01;
+        tile[16] = 0x01;
+        tile[17] = 0x01;
+        tile
+    }
+
+    fn map_with(entry: u16) -> Vec<u8> {
+        let mut data: Vec<u8> = vec![0; PALETTES_OFFSET + 4 * 16 * 2];
+        data[0..2].copy_from_slice(&entry.to_le_bytes());
+        // color 1 and 15 of palettes 4 and 5
+        for (palette, color, value) in [(0, 1, 0x0011u16), (0, 15, 0x00FF), (1, 1, 0x8022)] {
+            let offset: usize = PALETTES_OFFSET + (palette * 16 + color) * 2;
+            data[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+        }
+        data
+    }
+
+    #[test]
+    fn tiles_are_drawn_with_their_palette() {
+        let mut border: Border = Border::new();
+        let mut tiles: Vec<u8> = vec![0; 32];
+        tiles.extend(corner_tile());
+        border.load_tiles(false, &tiles);
+        // tile 1, palette 4
+        border.load_map(&map_with(0x0001));
+        assert_eq!(border.get_color(0, 0), Some(0x0011));
+        assert_eq!(border.get_color(7, 0), Some(0x00FF));
+        assert_eq!(border.get_color(1, 0), None);
+        assert_eq!(border.get_color(0, 1), None);
+        // palette 5, the top bit of the color is dropped
+        border.load_map(&map_with(0x0401));
+        assert_eq!(border.get_color(0, 0), Some(0x0022));
+    }
+
+    #[test]
+    fn flips_mirror_the_tile() {
+        let mut border: Border = Border::new();
+        let mut tiles: Vec<u8> = vec![0; TILES_SIZE / 2];
+        tiles[..32].copy_from_slice(&corner_tile());
+        // the upper half starts at tile 128
+        border.load_tiles(true, &tiles);
+        border.load_map(&map_with(0x4080));
+        assert_eq!(border.get_color(0, 0), Some(0x00FF));
+        assert_eq!(border.get_color(7, 0), Some(0x0011));
+        border.load_map(&map_with(0x8080));
+        assert_eq!(border.get_color(0, 7), Some(0x0011));
+        assert_eq!(border.get_color(0, 0), None);
+    }
+}
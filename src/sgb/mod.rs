@@ -0,0 +1,3 @@
+pub mod border;
+pub mod packet;
+pub mod sgb;
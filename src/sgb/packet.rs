@@ -0,0 +1,129 @@
+use crate::error::state_error::StateError;
+use crate::state::snapshot::Snapshot;
+use crate::state::state_reader::StateReader;
+use crate::state::state_writer::StateWriter;
+
+pub const PACKET_SIZE: usize = 16;
+const PACKET_BITS: usize = PACKET_SIZE * 8;
+
+/// Decodes the 16-byte packets a game sends to the Super Game Boy through P1.
+///
+/// Writing 0 to both select bits (P14 and P15 low) resets the transfer. Then every bit is
+/// a pulse on one line, starting from both lines high: P14 low sends a 0, P15 low a 1.
+/// Bits come least significant first, and the packet ends with a 0 stop bit.
+pub struct PacketReceiver {
+    // select bits of the previous P1 write
+    previous: u8,
+    receiving: bool,
+    bits: usize,
+    packet: [u8; PACKET_SIZE],
+}
+
+impl PacketReceiver {
+    pub fn new() -> PacketReceiver {
+        PacketReceiver {
+            previous: 0x30,
+            receiving: false,
+            bits: 0,
+            packet: [0; PACKET_SIZE],
+        }
+    }
+
+    /// Follows a write to P1.
+    ///
+    /// # Returns
+    /// The packet, once its stop bit is received.
+    pub fn write(&mut self, value: u8) -> Option<[u8; PACKET_SIZE]> {
+        let select: u8 = value & 0x30;
+        let previous: u8 = self.previous;
+        self.previous = select;
+        if select == 0x00 {
+            self.receiving = true;
+            self.bits = 0;
+            self.packet = [0; PACKET_SIZE];
+            return None;
+        }
+        if !self.receiving || previous != 0x30 || select == 0x30 {
+            return None;
+        }
+        let bit: bool = select == 0x10;
+        if self.bits == PACKET_BITS {
+            self.receiving = false;
+            return if bit { None } else { Some(self.packet) };
+        }
+        if bit {
+            self.packet[self.bits / 8] |= 1 << (self.bits % 8);
+        }
+        self.bits += 1;
+        None
+    }
+}
+
+impl Snapshot for PacketReceiver {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.previous);
+        writer.write_bool(self.receiving);
+        writer.write_u8(self.bits as u8);
+        writer.write_bytes(&self.packet);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.previous = reader.read_u8()? & 0x30;
+        self.receiving = reader.read_bool()?;
+        self.bits = reader.read_u8()? as usize;
+        if self.bits > PACKET_BITS {
+            return Err(StateError::InvalidValue("SGB packet bits"));
+        }
+        reader.read_bytes(&mut self.packet)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// P1 writes sending `packet` with the given stop bit.
+    fn pulses(packet: &[u8; PACKET_SIZE], stop: bool) -> Vec<u8> {
+        let mut writes: Vec<u8> = vec![0x00, 0x30];
+        let bits = (0..PACKET_BITS).map(|bit| packet[bit / 8] & (1 << (bit % 8)) != 0).chain([stop]);
+        for bit in bits {
+            writes.push(if bit { 0x10 } else { 0x20 });
+            writes.push(0x30);
+        }
+        writes
+    }
+
+    fn send(receiver: &mut PacketReceiver, writes: &[u8]) -> Vec<[u8; PACKET_SIZE]> {
+        writes.iter().filter_map(|value| receiver.write(*value)).collect()
+    }
+
+    #[test]
+    fn packet_ends_on_a_zero_stop_bit() {
+        let mut receiver: PacketReceiver = PacketReceiver::new();
+        let packet: [u8; PACKET_SIZE] = [0x89, 0x01, 0x80, 0xFF, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x5A];
+        assert_eq!(send(&mut receiver, &pulses(&packet, false)), [packet]);
+        assert!(send(&mut receiver, &pulses(&packet, true)).is_empty());
+    }
+
+    #[test]
+    fn reset_restarts_the_packet() {
+        let mut receiver: PacketReceiver = PacketReceiver::new();
+        let packet: [u8; PACKET_SIZE] = [0xA5; PACKET_SIZE];
+        let mut writes: Vec<u8> = pulses(&[0xFF; PACKET_SIZE], false);
+        writes.truncate(40);
+        writes.extend(pulses(&packet, false));
+        assert_eq!(send(&mut receiver, &writes), [packet]);
+    }
+
+    #[test]
+    fn pulses_need_both_lines_high_in_between() {
+        let mut receiver: PacketReceiver = PacketReceiver::new();
+        let packet: [u8; PACKET_SIZE] = [0x01; PACKET_SIZE];
+        let mut writes: Vec<u8> = pulses(&packet, false);
+        // going from one line low to the other is not a pulse
+        writes.insert(3, 0x10);
+        assert_eq!(send(&mut receiver, &writes), [packet]);
+        // nothing is received before the reset
+        assert!(send(&mut receiver, &writes[2..]).is_empty());
+    }
+}
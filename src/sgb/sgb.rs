@@ -0,0 +1,462 @@
+use crate::constants::io_registers::LCDC;
+use crate::constants::lcd::{DMG_GREYS, LCDC_BG_MAP, SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::error::state_error::StateError;
+use crate::memory_bus::io::IO;
+use crate::memory_bus::v_ram::VRAM;
+use crate::ppu::tile::Tile;
+use crate::sgb::border::Border;
+use crate::sgb::packet::{PacketReceiver, PACKET_SIZE};
+use crate::state::snapshot::Snapshot;
+use crate::state::state_reader::StateReader;
+use crate::state::state_writer::StateWriter;
+
+/// Size of the picture sent to the TV, border included.
+pub const SGB_WIDTH: usize = 256;
+pub const SGB_HEIGHT: usize = 224;
+// position of the game screen inside the border
+const SCREEN_X: usize = 48;
+const SCREEN_Y: usize = 40;
+
+// the attribute map gives a palette to every 8×8 cell of the screen
+const ATTR_WIDTH: usize = SCREEN_WIDTH / 8;
+const ATTR_HEIGHT: usize = SCREEN_HEIGHT / 8;
+
+// size of the VRAM transfers of CHR_TRN and PCT_TRN
+const TRANSFER_SIZE: usize = 0x1000;
+
+const PAL01: u8 = 0x00;
+const PAL23: u8 = 0x01;
+const PAL03: u8 = 0x02;
+const PAL12: u8 = 0x03;
+const ATTR_BLK: u8 = 0x04;
+const ATTR_LIN: u8 = 0x05;
+const ATTR_DIV: u8 = 0x06;
+const ATTR_CHR: u8 = 0x07;
+const MLT_REQ: u8 = 0x11;
+const CHR_TRN: u8 = 0x13;
+const PCT_TRN: u8 = 0x14;
+const MASK_EN: u8 = 0x17;
+
+/// What MASK_EN shows instead of the game screen.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Mask {
+    None,
+    /// Keeps the picture of the moment the mask was set.
+    Freeze,
+    Black,
+    /// Fills the screen with color 0.
+    Color0,
+}
+
+/// Super Game Boy: receives command packets through P1, colorizes the DMG picture with
+/// four palettes chosen per 8×8 cell and surrounds it with a border.
+///
+/// Commands that need more than a packet (CHR_TRN, PCT_TRN) copy 4 KiB from what the
+/// background shows: the tiles of the first 256 cells of the BG map, 20 per row.
+pub struct SGB {
+    receiver: PacketReceiver,
+    // packets of the command being received
+    command: Vec<u8>,
+    palettes: [[u16; 4]; 4],
+    attributes: [u8; ATTR_WIDTH * ATTR_HEIGHT],
+    mask: Mask,
+    frozen: Vec<u8>,
+    border: Border,
+}
+
+impl SGB {
+    pub fn new() -> SGB {
+        SGB {
+            receiver: PacketReceiver::new(),
+            command: Vec::new(),
+            palettes: [DMG_GREYS; 4],
+            attributes: [0; ATTR_WIDTH * ATTR_HEIGHT],
+            mask: Mask::None,
+            frozen: Vec::new(),
+            border: Border::new(),
+        }
+    }
+
+    /// Returns the palette of the 8×8 cell at (`x`, `y`).
+    fn get_attribute(&self, x: usize, y: usize) -> u8 {
+        self.attributes[y * ATTR_WIDTH + x]
+    }
+
+    /// Follows a CPU write to P1, running the command once all its packets arrived.
+    ///
+    /// # Parameters
+    /// - `io`: I/O registers, for LCDC and the joypad of MLT_REQ.
+    /// - `v_ram`: Source of the CHR_TRN and PCT_TRN transfers.
+    /// - `screen`: Current DMG shades, kept when MASK_EN freezes the screen.
+    pub fn write_joypad(&mut self, value: u8, io: &mut IO, v_ram: &VRAM, screen: &[u8]) {
+        let Some(packet) = self.receiver.write(value) else {
+            return;
+        };
+        // the first byte holds the command and how many packets it takes
+        if self.command.is_empty() && packet[0] & 0x07 == 0 {
+            return;
+        }
+        self.command.extend_from_slice(&packet);
+        if self.command.len() < (self.command[0] & 0x07) as usize * PACKET_SIZE {
+            return;
+        }
+        let command: Vec<u8> = std::mem::take(&mut self.command);
+        self.execute(&command, io, v_ram, screen);
+    }
+
+    fn execute(&mut self, data: &[u8], io: &mut IO, v_ram: &VRAM, screen: &[u8]) {
+        match data[0] >> 3 {
+            PAL01 => self.set_palettes(0, 1, data),
+            PAL23 => self.set_palettes(2, 3, data),
+            PAL03 => self.set_palettes(0, 3, data),
+            PAL12 => self.set_palettes(1, 2, data),
+            ATTR_BLK => self.attr_blk(data),
+            ATTR_LIN => self.attr_lin(data),
+            ATTR_DIV => self.attr_div(data),
+            ATTR_CHR => self.attr_chr(data),
+            MLT_REQ => {
+                let players: usize = match data[1] & 0x03 {
+                    0x01 => 2,
+                    0x03 => 4,
+                    _ => 1,
+                };
+                io.set_joypad_players(players);
+            }
+            CHR_TRN => self.border.load_tiles(data[1] & 0x01 != 0, &SGB::read_transfer(io, v_ram)),
+            PCT_TRN => self.border.load_map(&SGB::read_transfer(io, v_ram)),
+            MASK_EN => {
+                self.mask = match data[1] & 0x03 {
+                    0x01 => Mask::Freeze,
+                    0x02 => Mask::Black,
+                    0x03 => Mask::Color0,
+                    _ => Mask::None,
+                };
+                if self.mask == Mask::Freeze {
+                    self.frozen = screen.to_vec();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Copies the 2bpp tiles shown by the first 256 cells of the background.
+    fn read_transfer(io: &IO, v_ram: &VRAM) -> Vec<u8> {
+        let lcdc: u8 = io.get_register(LCDC);
+        let map: u16 = if lcdc & LCDC_BG_MAP != 0 { 0x9C00 } else { 0x9800 };
+        let mut data: Vec<u8> = Vec::with_capacity(TRANSFER_SIZE);
+        for cell in 0..(TRANSFER_SIZE / 16) as u16 {
+            let index: u8 = v_ram.read_bank(0, map + (cell / 20) * 32 + cell % 20);
+            let address: u16 = Tile::bg_row_address(lcdc, index, 0);
+            data.extend((0..16).map(|offset| v_ram.read_bank(0, address + offset)));
+        }
+        data
+    }
+
+    /// PAL01, PAL23, PAL03 and PAL12: color 0, shared by every palette, then colors 1-3 of
+    /// both palettes.
+    fn set_palettes(&mut self, first: usize, second: usize, data: &[u8]) {
+        let color = |i: usize| u16::from_le_bytes([data[1 + i * 2], data[2 + i * 2]]) & 0x7FFF;
+        for palette in self.palettes.iter_mut() {
+            palette[0] = color(0);
+        }
+        for i in 1..4 {
+            self.palettes[first][i] = color(i);
+            self.palettes[second][i] = color(i + 3);
+        }
+    }
+
+    fn set_attribute(&mut self, x: usize, y: usize, palette: u8) {
+        if x < ATTR_WIDTH && y < ATTR_HEIGHT {
+            self.attributes[y * ATTR_WIDTH + x] = palette & 0x03;
+        }
+    }
+
+    /// ATTR_BLK: rectangles with a palette for the cells inside, on the edge and outside.
+    fn attr_blk(&mut self, data: &[u8]) {
+        let count: usize = data[1] as usize;
+        for set in data[2..].chunks_exact(6).take(count) {
+            let (inside, edge, outside) = (set[1] & 0x03, (set[1] >> 2) & 0x03, (set[1] >> 4) & 0x03);
+            // with only the inside or the outside changed, the edge follows it
+            let (control, edge) = match set[0] & 0x07 {
+                0x01 => (0x03, inside),
+                0x04 => (0x06, outside),
+                control => (control, edge),
+            };
+            let (x1, y1, x2, y2) = (set[2] as usize, set[3] as usize, set[4] as usize, set[5] as usize);
+            for y in 0..ATTR_HEIGHT {
+                for x in 0..ATTR_WIDTH {
+                    let within: bool = (x1..=x2).contains(&x) && (y1..=y2).contains(&y);
+                    let on_edge: bool = within && (x == x1 || x == x2 || y == y1 || y == y2);
+                    let palette: Option<u8> = if on_edge {
+                        (control & 0x02 != 0).then_some(edge)
+                    } else if within {
+                        (control & 0x01 != 0).then_some(inside)
+                    } else {
+                        (control & 0x04 != 0).then_some(outside)
+                    };
+                    if let Some(palette) = palette {
+                        self.set_attribute(x, y, palette);
+                    }
+                }
+            }
+        }
+    }
+
+    /// ATTR_LIN: whole rows (bit 7 set) or columns of cells, one byte each.
+    fn attr_lin(&mut self, data: &[u8]) {
+        let count: usize = data[1] as usize;
+        for line in data[2..].iter().take(count) {
+            let index: usize = (line & 0x1F) as usize;
+            let palette: u8 = (line >> 5) & 0x03;
+            if line & 0x80 != 0 {
+                (0..ATTR_WIDTH).for_each(|x| self.set_attribute(x, index, palette));
+            } else {
+                (0..ATTR_HEIGHT).for_each(|y| self.set_attribute(index, y, palette));
+            }
+        }
+    }
+
+    /// ATTR_DIV: splits the screen in two at a row (bit 6 set) or a column, with a third
+    /// palette for the dividing line.
+    fn attr_div(&mut self, data: &[u8]) {
+        let (after, before, on_line) = (data[1] & 0x03, (data[1] >> 2) & 0x03, (data[1] >> 4) & 0x03);
+        let horizontal: bool = data[1] & 0x40 != 0;
+        let split: usize = data[2] as usize;
+        for y in 0..ATTR_HEIGHT {
+            for x in 0..ATTR_WIDTH {
+                let position: usize = if horizontal { y } else { x };
+                let palette: u8 = match position.cmp(&split) {
+                    std::cmp::Ordering::Less => before,
+                    std::cmp::Ordering::Equal => on_line,
+                    std::cmp::Ordering::Greater => after,
+                };
+                self.set_attribute(x, y, palette);
+            }
+        }
+    }
+
+    /// ATTR_CHR: a palette for each cell, 2 bits each (high bits first), from a starting
+    /// cell going right (or down when byte 5 is 1).
+    fn attr_chr(&mut self, data: &[u8]) {
+        let (mut x, mut y) = (data[1] as usize, data[2] as usize);
+        let count: usize = (u16::from_le_bytes([data[3], data[4]]) as usize).min(ATTR_WIDTH * ATTR_HEIGHT);
+        let vertical: bool = data[5] & 0x01 != 0;
+        for i in 0..count {
+            let Some(byte) = data.get(6 + i / 4) else {
+                break;
+            };
+            self.set_attribute(x, y, byte >> (6 - (i % 4) * 2));
+            if vertical {
+                y += 1;
+                if y >= ATTR_HEIGHT {
+                    y = 0;
+                    x = (x + 1) % ATTR_WIDTH;
+                }
+            } else {
+                x += 1;
+                if x >= ATTR_WIDTH {
+                    x = 0;
+                    y = (y + 1) % ATTR_HEIGHT;
+                }
+            }
+        }
+    }
+
+    /// Builds the 256×224 RGB555 picture the Super Game Boy sends to the TV.
+    ///
+    /// # Parameters
+    /// - `screen`: DMG shades (0–3) of the last frame, 160×144.
+    pub fn compose(&self, screen: &[u8]) -> Vec<u16> {
+        let backdrop: u16 = self.palettes[0][0];
+        let mut frame: Vec<u16> = vec![backdrop; SGB_WIDTH * SGB_HEIGHT];
+        let screen: &[u8] = if self.mask == Mask::Freeze && !self.frozen.is_empty() { &self.frozen } else { screen };
+        for y in 0..SCREEN_HEIGHT {
+            for x in 0..SCREEN_WIDTH {
+                let color: u16 = match self.mask {
+                    Mask::Black => 0x0000,
+                    Mask::Color0 => backdrop,
+                    _ => {
+                        let palette: usize = self.get_attribute(x / 8, y / 8) as usize;
+                        self.palettes[palette][(screen[y * SCREEN_WIDTH + x] & 0x03) as usize]
+                    }
+                };
+                frame[(y + SCREEN_Y) * SGB_WIDTH + x + SCREEN_X] = color;
+            }
+        }
+        for y in 0..SGB_HEIGHT {
+            for x in 0..SGB_WIDTH {
+                if let Some(color) = self.border.get_color(x, y) {
+                    frame[y * SGB_WIDTH + x] = color;
+                }
+            }
+        }
+        frame
+    }
+}
+
+impl Snapshot for SGB {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.receiver.save_state(writer);
+        writer.write_vec(&self.command);
+        self.palettes.iter().for_each(|palette| writer.write_u16_slice(palette));
+        writer.write_bytes(&self.attributes);
+        writer.write_u8(match self.mask {
+            Mask::None => 0,
+            Mask::Freeze => 1,
+            Mask::Black => 2,
+            Mask::Color0 => 3,
+        });
+        writer.write_vec(&self.frozen);
+        self.border.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.receiver.load_state(reader)?;
+        self.command = reader.read_vec()?;
+        for palette in self.palettes.iter_mut() {
+            reader.read_u16_slice(palette)?;
+        }
+        reader.read_bytes(&mut self.attributes)?;
+        self.mask = match reader.read_u8()? {
+            0 => Mask::None,
+            1 => Mask::Freeze,
+            2 => Mask::Black,
+            3 => Mask::Color0,
+            _ => return Err(StateError::InvalidValue("SGB mask")),
+        };
+        self.frozen = reader.read_vec()?;
+        self.border.load_state(reader)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::io_registers::P1;
+    use crate::memory_bus::bus::BUS;
+
+    /// Bit-bangs `data`, one command of 16-byte packets, through P1.
+    fn send(sgb: &mut SGB, io: &mut IO, data: &[u8]) {
+        let v_ram: VRAM = VRAM::new();
+        let screen: Vec<u8> = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT];
+        for packet in data.chunks(PACKET_SIZE) {
+            let mut writes: Vec<u8> = vec![0x00, 0x30];
+            for bit in 0..PACKET_SIZE * 8 + 1 {
+                let one: bool = packet.get(bit / 8).is_some_and(|byte| byte & (1 << (bit % 8)) != 0);
+                writes.extend([if one { 0x10 } else { 0x20 }, 0x30]);
+            }
+            for value in writes {
+                io.write(P1, value).unwrap();
+                sgb.write_joypad(value, io, &v_ram, &screen);
+            }
+        }
+    }
+
+    fn attr_blk(sets: &[[u8; 6]]) -> Vec<u8> {
+        let mut data: Vec<u8> = vec![(ATTR_BLK << 3) | 0x01, sets.len() as u8];
+        sets.iter().for_each(|set| data.extend_from_slice(set));
+        data.resize(PACKET_SIZE, 0);
+        data
+    }
+
+    #[test]
+    fn pal_commands_share_color_0() {
+        let mut sgb: SGB = SGB::new();
+        let mut io: IO = IO::new();
+        let mut pal12: Vec<u8> = vec![(PAL12 << 3) | 0x01];
+        for color in [0x0001u16, 0x0002, 0x0003, 0x0004, 0x0005, 0x0006, 0xFFFF] {
+            pal12.extend_from_slice(&color.to_le_bytes());
+        }
+        pal12.resize(PACKET_SIZE, 0);
+        send(&mut sgb, &mut io, &pal12);
+        assert_eq!(sgb.palettes, [
+            [0x0001, 0x56B5, 0x294A, 0x0000],
+            [0x0001, 0x0002, 0x0003, 0x0004],
+            [0x0001, 0x0005, 0x0006, 0x7FFF],
+            [0x0001, 0x56B5, 0x294A, 0x0000],
+        ]);
+    }
+
+    #[test]
+    fn attr_blk_edges() {
+        let mut sgb: SGB = SGB::new();
+        let mut io: IO = IO::new();
+        // inside 1, edge 2, outside 3
+        send(&mut sgb, &mut io, &attr_blk(&[[0x07, 0x39, 2, 2, 5, 4]]));
+        assert_eq!(sgb.get_attribute(3, 3), 1);
+        assert_eq!(sgb.get_attribute(2, 3), 2);
+        assert_eq!(sgb.get_attribute(5, 4), 2);
+        assert_eq!(sgb.get_attribute(6, 3), 3);
+        assert_eq!(sgb.get_attribute(0, 0), 3);
+        // only the inside: the edge takes the same palette, the outside is kept
+        send(&mut sgb, &mut io, &attr_blk(&[[0x01, 0x00, 0, 0, 1, 1]]));
+        assert_eq!(sgb.get_attribute(0, 0), 0);
+        assert_eq!(sgb.get_attribute(1, 1), 0);
+        assert_eq!(sgb.get_attribute(2, 2), 2);
+        // only the outside: the edge takes the outside palette, the inside is kept
+        send(&mut sgb, &mut io, &attr_blk(&[[0x04, 0x10, 10, 10, 12, 12]]));
+        assert_eq!(sgb.get_attribute(10, 10), 1);
+        assert_eq!(sgb.get_attribute(11, 11), 3);
+        assert_eq!(sgb.get_attribute(0, 0), 1);
+    }
+
+    #[test]
+    fn mlt_req_reads_the_joypads_in_turn() {
+        let mut sgb: SGB = SGB::new();
+        let mut io: IO = IO::new();
+        let mut mlt_req: Vec<u8> = vec![(MLT_REQ << 3) | 0x01, 0x03];
+        mlt_req.resize(PACKET_SIZE, 0);
+        send(&mut sgb, &mut io, &mlt_req);
+        let mut ids: Vec<u8> = Vec::new();
+        for _ in 0..5 {
+            ids.push(io.read(P1).unwrap() & 0x0F);
+            io.write(P1, 0x10).unwrap();
+            io.write(P1, 0x30).unwrap();
+        }
+        assert_eq!(ids, [0x0F, 0x0E, 0x0D, 0x0C, 0x0F]);
+
+        mlt_req[1] = 0x00;
+        send(&mut sgb, &mut io, &mlt_req);
+        io.write(P1, 0x10).unwrap();
+        io.write(P1, 0x30).unwrap();
+        assert_eq!(io.read(P1).unwrap() & 0x0F, 0x0F);
+    }
+
+    #[test]
+    fn border_covers_the_screen_but_not_where_transparent() {
+        let mut sgb: SGB = SGB::new();
+        // tile 1: color 1 on the leftmost pixel of every row
+        let mut tiles: Vec<u8> = vec![0; TRANSFER_SIZE];
+        for row in 0..8 {
+            tiles[32 + row * 2] = 0x80;
+        }
+        sgb.border.load_tiles(false, &tiles);
+        // palettes 4 to 7 follow the 32×32 map
+        const PALETTES_OFFSET: usize = 32 * 32 * 2;
+        let mut map: Vec<u8> = vec![0; TRANSFER_SIZE];
+        // cell (0, 0): tile 1, palette 4; cell (1, 0): flipped horizontally, palette 5
+        map[0..2].copy_from_slice(&0x1001u16.to_le_bytes());
+        map[2..4].copy_from_slice(&0x5401u16.to_le_bytes());
+        // cell (6, 5), inside the game screen: tile 1, palette 4
+        map[(5 * 32 + 6) * 2..(5 * 32 + 6) * 2 + 2].copy_from_slice(&0x1001u16.to_le_bytes());
+        map[PALETTES_OFFSET + 2..PALETTES_OFFSET + 4].copy_from_slice(&0x001Fu16.to_le_bytes());
+        map[PALETTES_OFFSET + 34..PALETTES_OFFSET + 36].copy_from_slice(&0x03E0u16.to_le_bytes());
+        sgb.border.load_map(&map);
+        sgb.palettes[0] = [0x1111, 0x2222, 0x3333, 0x4444];
+
+        let screen: Vec<u8> = vec![3; SCREEN_WIDTH * SCREEN_HEIGHT];
+        let frame: Vec<u16> = sgb.compose(&screen);
+        assert_eq!(frame.len(), SGB_WIDTH * SGB_HEIGHT);
+        assert_eq!(frame[0], 0x001F);
+        assert_eq!(frame[1], 0x1111);
+        assert_eq!(frame[15], 0x03E0);
+        assert_eq!(frame[8], 0x1111);
+        assert_eq!(frame[SCREEN_Y * SGB_WIDTH + SCREEN_X], 0x001F);
+        assert_eq!(frame[SCREEN_Y * SGB_WIDTH + SCREEN_X + 1], 0x4444);
+
+        sgb.mask = Mask::Black;
+        let frame: Vec<u16> = sgb.compose(&screen);
+        assert_eq!(frame[SCREEN_Y * SGB_WIDTH + SCREEN_X + 1], 0x0000);
+        assert_eq!(frame[1], 0x1111);
+    }
+}
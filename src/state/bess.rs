@@ -0,0 +1,494 @@
+use crate::constants::io_registers::IE;
+use crate::error::state_error::StateError;
+use crate::gameboy::Gameboy;
+use crate::memory_bus::region::Region;
+use crate::model::Model;
+use crate::state::state_reader::StateReader;
+use crate::state::state_writer::StateWriter;
+
+const FOOTER_MAGIC: &[u8; 4] = b"BESS";
+const FOOTER_SIZE: usize = 8;
+const BLOCK_HEADER_SIZE: usize = 8;
+
+const NAME_BLOCK: [u8; 4] = *b"NAME";
+const INFO_BLOCK: [u8; 4] = *b"INFO";
+const CORE_BLOCK: [u8; 4] = *b"CORE";
+const XOAM_BLOCK: [u8; 4] = *b"XOAM";
+const MBC_BLOCK: [u8; 4] = *b"MBC ";
+const END_BLOCK: [u8; 4] = *b"END ";
+
+const MAJOR_VERSION: u16 = 1;
+const MINOR_VERSION: u16 = 1;
+const INFO_SIZE: usize = 0x12;
+const CORE_SIZE: usize = 0xD0;
+const XOAM_SIZE: usize = 0x60;
+
+const EXECUTION_RUNNING: u8 = 0;
+const EXECUTION_HALTED: u8 = 1;
+const EXECUTION_STOPPED: u8 = 2;
+
+/// Memory areas of the CORE block, in the order of their (size, offset) pairs.
+const BUFFERS: [Region; 7] = [
+    Region::WRAM,
+    Region::VRAM,
+    Region::ExternalRAM,
+    Region::OAM,
+    Region::HRAM,
+    Region::BgPalettes,
+    Region::ObjPalettes,
+];
+
+/// Machine state in the BESS format (Best Effort Save State) shared by SameBoy and other
+/// emulators, so the same point of a game can be inspected in each of them.
+///
+/// BESS lives at the end of a file, after the emulator's own data: blocks made of a 4-byte
+/// id, a size (u32) and the content, closed by an `END ` block. The memory areas referred
+/// to by `CORE` follow, then the footer: the offset of the first block (u32) and `BESS`.
+/// Everything is little-endian.
+///
+/// The blocks written are `NAME`, `INFO`, `CORE`, `XOAM`, `MBC ` and `END `. Only the bank
+/// number register of the MBC is emulated, so `MBC ` holds that single write, and there is
+/// no cartridge clock for `RTC `, which is ignored on import with the other unknown
+/// blocks. Anything missing from BESS (PPU position inside a line, sound channel timers, SGB state...)
+/// starts over when a state is imported.
+pub struct BessState {
+    name: String,
+    // cartridge title (0x134–0x143) followed by the global checksum (0x14E–0x14F)
+    info: Option<[u8; INFO_SIZE]>,
+    model: [u8; 4],
+    // PC, AF, BC, DE, HL and SP
+    registers: [u16; 6],
+    ime: bool,
+    ie: u8,
+    execution_state: u8,
+    io: [u8; 0x80],
+    buffers: Vec<Vec<u8>>,
+    extra_oam: Option<Vec<u8>>,
+    mbc_writes: Vec<(u16, u8)>,
+}
+
+impl BessState {
+    /// Takes a snapshot of `gameboy`.
+    pub fn capture(gameboy: &Gameboy) -> BessState {
+        let bus = &gameboy.memory_bus;
+        let cpu = &gameboy.cpu;
+        let model: Model = gameboy.get_model();
+        let registers = cpu.read_registers();
+        let execution_state: u8 = if !cpu.is_running() {
+            EXECUTION_STOPPED
+        } else if cpu.get_halt() {
+            EXECUTION_HALTED
+        } else {
+            EXECUTION_RUNNING
+        };
+        let buffers: Vec<Vec<u8>> = BUFFERS.iter()
+            .map(|region| match region {
+                // only the CGB has color palettes
+                Region::BgPalettes | Region::ObjPalettes if model != Model::CGB => Vec::new(),
+                _ => bus.get_region(*region),
+            })
+            .collect();
+        BessState {
+            name: format!("rustyboy {}", env!("CARGO_PKG_VERSION")),
+            info: Some(cartridge_info(gameboy)),
+            model: *match model {
+                Model::DMG => b"GD  ",
+                Model::SGB => b"SN  ",
+                Model::CGB => b"CCE ",
+            },
+            registers: [
+                cpu.get_pc(),
+                registers.get_af(),
+                registers.get_bc(),
+                registers.get_de(),
+                registers.get_hl(),
+                cpu.get_sp(),
+            ],
+            ime: cpu.get_ime(),
+            ie: bus.get_io_register(IE),
+            execution_state,
+            io: std::array::from_fn(|i| bus.get_io_register(0xFF00 + i as u16)),
+            buffers,
+            extra_oam: Some(bus.get_region(Region::ExtraOAM)),
+            mbc_writes: vec![(0x2000, bus.rom.get_bank())],
+        }
+    }
+
+    /// Puts `gameboy` in the saved state. Fields are applied as they are read, so the
+    /// caller has to take a backup to undo a failed import.
+    ///
+    /// # Returns
+    /// `StateError::CartridgeMismatch` when the INFO block names another cartridge.
+    pub fn restore(&self, gameboy: &mut Gameboy) -> Result<(), StateError> {
+        if self.info.is_some_and(|info| info != cartridge_info(gameboy)) {
+            return Err(StateError::CartridgeMismatch);
+        }
+        let model: Model = match self.model[0] {
+            b'G' => Model::DMG,
+            b'S' => Model::SGB,
+            // the GBA runs CGB software as a CGB
+            b'C' | b'A' => Model::CGB,
+            _ => return Err(StateError::InvalidValue("model")),
+        };
+        if self.execution_state > EXECUTION_STOPPED {
+            return Err(StateError::InvalidValue("execution state"));
+        }
+        gameboy.set_model(model);
+        gameboy.setup_model();
+
+        let bus = &mut gameboy.memory_bus;
+        for (region, bytes) in BUFFERS.iter().zip(self.buffers.iter()) {
+            bus.set_region(*region, bytes);
+        }
+        if let Some(extra_oam) = self.extra_oam.as_ref() {
+            bus.set_region(Region::ExtraOAM, extra_oam);
+        }
+        for (addr, value) in self.mbc_writes.iter() {
+            if *addr < 0x8000 {
+                bus.rom.write_byte(*addr, *value);
+            }
+        }
+        bus.restore_io_registers(&self.io);
+        bus.write(IE, self.ie);
+        bus.set_cpu_halted(self.execution_state == EXECUTION_HALTED);
+
+        let cpu = &mut gameboy.cpu;
+        let [pc, af, bc, de, hl, sp] = self.registers;
+        cpu.change_pc(pc);
+        cpu.set_sp(sp);
+        let registers = cpu.get_registers();
+        registers.set_af(af);
+        registers.set_bc(bc);
+        registers.set_de(de);
+        registers.set_hl(hl);
+        cpu.set_ime(self.ime);
+        cpu.set_halt(self.execution_state == EXECUTION_HALTED);
+        cpu.set_running(self.execution_state != EXECUTION_STOPPED);
+        Ok(())
+    }
+
+    /// Appends the blocks, the memory areas and the footer to `file`, which holds the
+    /// emulator's own data.
+    pub fn append_to(&self, file: &mut Vec<u8>) {
+        let start: usize = file.len();
+        // NAME, CORE, MBC and END are always there, the memory areas come right after END
+        let blocks_size: usize = 4 * BLOCK_HEADER_SIZE + self.name.len() + CORE_SIZE + 3 * self.mbc_writes.len()
+            + self.info.map_or(0, |_| BLOCK_HEADER_SIZE + INFO_SIZE)
+            + self.extra_oam.as_ref().map_or(0, |extra_oam| BLOCK_HEADER_SIZE + extra_oam.len());
+
+        let mut core: StateWriter = StateWriter::new();
+        core.write_u16(MAJOR_VERSION);
+        core.write_u16(MINOR_VERSION);
+        core.write_bytes(&self.model);
+        core.write_u16_slice(&self.registers);
+        core.write_bool(self.ime);
+        core.write_u8(self.ie);
+        core.write_u8(self.execution_state);
+        core.write_u8(0);
+        core.write_bytes(&self.io);
+        let mut offset: usize = start + blocks_size;
+        for buffer in self.buffers.iter() {
+            core.write_u32(buffer.len() as u32);
+            core.write_u32(offset as u32);
+            offset += buffer.len();
+        }
+
+        let mut mbc: StateWriter = StateWriter::new();
+        for (addr, value) in self.mbc_writes.iter() {
+            mbc.write_u16(*addr);
+            mbc.write_u8(*value);
+        }
+
+        let mut writer: StateWriter = StateWriter::new();
+        write_block(&mut writer, NAME_BLOCK, self.name.as_bytes());
+        if let Some(info) = self.info.as_ref() {
+            write_block(&mut writer, INFO_BLOCK, info);
+        }
+        write_block(&mut writer, CORE_BLOCK, &core.into_bytes());
+        if let Some(extra_oam) = self.extra_oam.as_ref() {
+            write_block(&mut writer, XOAM_BLOCK, extra_oam);
+        }
+        write_block(&mut writer, MBC_BLOCK, &mbc.into_bytes());
+        write_block(&mut writer, END_BLOCK, &[]);
+        for buffer in self.buffers.iter() {
+            writer.write_bytes(buffer);
+        }
+        writer.write_u32(start as u32);
+        writer.write_bytes(FOOTER_MAGIC);
+        file.extend_from_slice(&writer.into_bytes());
+    }
+
+    /// Returns the offset of the first BESS block of `file`, `None` if it has no BESS
+    /// footer.
+    pub fn find(file: &[u8]) -> Option<usize> {
+        if file.len() < FOOTER_SIZE || !file.ends_with(FOOTER_MAGIC) {
+            return None;
+        }
+        let footer: usize = file.len() - FOOTER_SIZE;
+        let offset: [u8; 4] = file[footer..footer + 4].try_into().expect("Slice is 4 bytes");
+        let offset: usize = u32::from_le_bytes(offset) as usize;
+        (offset <= footer).then_some(offset)
+    }
+
+    /// Reads the blocks of `file` starting at `offset` (see [`BessState::find`]).
+    ///
+    /// # Returns
+    /// `StateError::MissingSection` when there is no CORE block and
+    /// `StateError::UnsupportedVersion` for another major version of the format.
+    pub fn parse(file: &[u8], offset: usize) -> Result<BessState, StateError> {
+        let mut reader: StateReader = StateReader::new(&file[offset..]);
+        let mut state: Option<BessState> = None;
+        let mut name: String = String::new();
+        let mut info: Option<[u8; INFO_SIZE]> = None;
+        let mut extra_oam: Option<Vec<u8>> = None;
+        let mut mbc_writes: Vec<(u16, u8)> = Vec::new();
+        loop {
+            let mut id: [u8; 4] = [0; 4];
+            reader.read_bytes(&mut id)?;
+            let data: Vec<u8> = reader.read_vec()?;
+            match id {
+                NAME_BLOCK => name = String::from_utf8_lossy(&data).into_owned(),
+                INFO_BLOCK => {
+                    let mut block: [u8; INFO_SIZE] = [0; INFO_SIZE];
+                    StateReader::new(&data).read_bytes(&mut block)?;
+                    info = Some(block);
+                }
+                CORE_BLOCK => state = Some(BessState::parse_core(file, &data)?),
+                XOAM_BLOCK if data.len() < XOAM_SIZE => return Err(StateError::Truncated),
+                XOAM_BLOCK => extra_oam = Some(data),
+                MBC_BLOCK => {
+                    let mut mbc: StateReader = StateReader::new(&data);
+                    while !mbc.is_empty() {
+                        mbc_writes.push((mbc.read_u16()?, mbc.read_u8()?));
+                    }
+                }
+                END_BLOCK => break,
+                // blocks of newer revisions or of other emulators
+                _ => {}
+            }
+        }
+        let mut state: BessState = state.ok_or(StateError::MissingSection(CORE_BLOCK))?;
+        state.name = name;
+        state.info = info;
+        state.extra_oam = extra_oam;
+        state.mbc_writes = mbc_writes;
+        Ok(state)
+    }
+
+    fn parse_core(file: &[u8], data: &[u8]) -> Result<BessState, StateError> {
+        let mut core: StateReader = StateReader::new(data);
+        let major: u16 = core.read_u16()?;
+        if major != MAJOR_VERSION {
+            return Err(StateError::UnsupportedVersion(major as u32));
+        }
+        // newer minor versions only append fields
+        core.read_u16()?;
+        let mut model: [u8; 4] = [0; 4];
+        core.read_bytes(&mut model)?;
+        let mut registers: [u16; 6] = [0; 6];
+        core.read_u16_slice(&mut registers)?;
+        let ime: bool = core.read_u8()? != 0;
+        let ie: u8 = core.read_u8()?;
+        let execution_state: u8 = core.read_u8()?;
+        core.read_u8()?;
+        let mut io: [u8; 0x80] = [0; 0x80];
+        core.read_bytes(&mut io)?;
+        let mut buffers: Vec<Vec<u8>> = Vec::new();
+        for _ in BUFFERS.iter() {
+            let size: usize = core.read_u32()? as usize;
+            let offset: usize = core.read_u32()? as usize;
+            let buffer: &[u8] = file.get(offset..offset + size).ok_or(StateError::Truncated)?;
+            buffers.push(buffer.to_vec());
+        }
+        Ok(BessState {
+            name: String::new(),
+            info: None,
+            model,
+            registers,
+            ime,
+            ie,
+            execution_state,
+            io,
+            buffers,
+            extra_oam: None,
+            mbc_writes: Vec::new(),
+        })
+    }
+}
+
+fn write_block(writer: &mut StateWriter, id: [u8; 4], data: &[u8]) {
+    writer.write_bytes(&id);
+    writer.write_vec(data);
+}
+
+/// Returns the INFO block of the inserted cartridge.
+fn cartridge_info(gameboy: &Gameboy) -> [u8; INFO_SIZE] {
+    let rom = &gameboy.memory_bus.rom;
+    let mut info: [u8; INFO_SIZE] = [0; INFO_SIZE];
+    for (i, addr) in (0x134..=0x143).chain(0x14E..=0x14F).enumerate() {
+        info[i] = rom.read_byte(addr);
+    }
+    info
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::io_registers::{BGP, IF, INT_STAT, LCDC, LY, LYC, STAT};
+    use crate::constants::lcd::STAT_MODE;
+    use crate::ppu::ppu::MODE_VBLANK;
+
+    /// A DMG running `INC A; LD (0xC000), A; JR -6`.
+    fn machine() -> Box<Gameboy> {
+        let mut gameboy: Box<Gameboy> = Box::new(Gameboy::new());
+        gameboy.set_model(Model::DMG);
+        gameboy.memory_bus.rom.bank0[0x0134..0x0138].copy_from_slice(b"TEST");
+        gameboy.memory_bus.rom.bank0[0x0100..0x0106].copy_from_slice(&[0x3C, 0xEA, 0x00, 0xC0, 0x18, 0xFA]);
+        gameboy.setup_model();
+        gameboy.skip_boot();
+        gameboy
+    }
+
+    /// Returns the blocks of a BESS file as (offset of the data, id, data).
+    fn blocks(file: &[u8]) -> Vec<(usize, [u8; 4], Vec<u8>)> {
+        let mut offset: usize = BessState::find(file).unwrap();
+        let mut blocks: Vec<(usize, [u8; 4], Vec<u8>)> = Vec::new();
+        loop {
+            let id: [u8; 4] = file[offset..offset + 4].try_into().unwrap();
+            let size: usize = u32::from_le_bytes(file[offset + 4..offset + 8].try_into().unwrap()) as usize;
+            blocks.push((offset + BLOCK_HEADER_SIZE, id, file[offset + 8..offset + 8 + size].to_vec()));
+            offset += BLOCK_HEADER_SIZE + size;
+            if id == END_BLOCK {
+                return blocks;
+            }
+        }
+    }
+
+    fn block(id: [u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut writer: StateWriter = StateWriter::new();
+        write_block(&mut writer, id, data);
+        writer.into_bytes()
+    }
+
+    #[test]
+    fn exported_state_imports_back() {
+        let mut gameboy: Box<Gameboy> = machine();
+        gameboy.run_for(0.05);
+        gameboy.memory_bus.write(0xA000, 42);
+        let mut file: Vec<u8> = b"native".to_vec();
+        BessState::capture(&gameboy).append_to(&mut file);
+        assert_eq!(BessState::find(&file), Some(6));
+
+        let state: BessState = BessState::parse(&file, 6).unwrap();
+        assert_eq!(state.name, format!("rustyboy {}", env!("CARGO_PKG_VERSION")));
+        let mut restored: Box<Gameboy> = machine();
+        state.restore(&mut restored).unwrap();
+        assert_eq!(restored.cpu.get_pc(), gameboy.cpu.get_pc());
+        assert_eq!(restored.cpu.get_sp(), gameboy.cpu.get_sp());
+        assert_eq!(restored.cpu.read_registers().get_af(), gameboy.cpu.read_registers().get_af());
+        for region in BUFFERS.iter().chain([Region::ExtraOAM].iter()) {
+            assert_eq!(restored.memory_bus.get_region(*region), gameboy.memory_bus.get_region(*region), "{:?}", region);
+        }
+        for addr in [LCDC, STAT, LY, BGP, IF] {
+            assert_eq!(restored.memory_bus.get_io_register(addr), gameboy.memory_bus.get_io_register(addr), "{:04X}", addr);
+        }
+        assert_eq!(restored.memory_bus.rom.get_bank(), gameboy.memory_bus.rom.get_bank());
+
+        let mut other: Box<Gameboy> = machine();
+        other.memory_bus.rom.bank0[0x014F] = 0x01;
+        assert!(matches!(state.restore(&mut other), Err(StateError::CartridgeMismatch)));
+    }
+
+    #[test]
+    fn core_block_points_at_the_memory_areas() {
+        let gameboy: Box<Gameboy> = machine();
+        let mut file: Vec<u8> = vec![0xAA; 0x10];
+        BessState::capture(&gameboy).append_to(&mut file);
+        assert_eq!(&file[file.len() - 8..], &[0x10, 0, 0, 0, b'B', b'E', b'S', b'S']);
+
+        let blocks: Vec<(usize, [u8; 4], Vec<u8>)> = blocks(&file);
+        let ids: Vec<[u8; 4]> = blocks.iter().map(|(_, id, _)| *id).collect();
+        assert_eq!(ids, [NAME_BLOCK, INFO_BLOCK, CORE_BLOCK, XOAM_BLOCK, MBC_BLOCK, END_BLOCK]);
+        let (_, _, core) = &blocks[2];
+        assert_eq!(core.len(), 0xD0);
+        assert_eq!(core[0..8], [1, 0, 1, 0, b'G', b'D', b' ', b' ']);
+        assert_eq!(core[0x08..0x0A], 0x0100u16.to_le_bytes());
+
+        // the memory areas follow the END block, in order
+        let mut expected_offset: usize = blocks[5].0;
+        for (i, region) in BUFFERS.iter().enumerate() {
+            let pair: &[u8] = &core[0x98 + i * 8..0xA0 + i * 8];
+            let size: usize = u32::from_le_bytes(pair[0..4].try_into().unwrap()) as usize;
+            let offset: usize = u32::from_le_bytes(pair[4..8].try_into().unwrap()) as usize;
+            assert_eq!(offset, expected_offset, "{:?}", region);
+            // only the CGB has color palettes
+            let expected: Vec<u8> = match region {
+                Region::BgPalettes | Region::ObjPalettes => Vec::new(),
+                _ => gameboy.memory_bus.get_region(*region),
+            };
+            assert_eq!(file[offset..offset + size], expected, "{:?}", region);
+            expected_offset += size;
+        }
+        assert_eq!(expected_offset, file.len() - FOOTER_SIZE);
+    }
+
+    /// A state laid out as SameBoy writes it: its own data first, holding the memory areas
+    /// CORE points at, then the blocks with an INFO block and unknown SGB and RTC blocks.
+    #[test]
+    fn sameboy_layout_imports() {
+        let mut file: Vec<u8> = b"SAMEBOY NATIVE..".to_vec();
+        let mut pairs: StateWriter = StateWriter::new();
+        for (size, fill) in [(0x2000, 0x11), (0x2000, 0x22), (0x2000, 0x33), (0xA0, 0x44), (0x7F, 0x55), (0, 0), (0, 0)] {
+            pairs.write_u32(size as u32);
+            pairs.write_u32(file.len() as u32);
+            file.resize(file.len() + size, fill);
+        }
+        let mut io: [u8; 0x80] = [0; 0x80];
+        io[(LCDC - 0xFF00) as usize] = 0x91;
+        io[(BGP - 0xFF00) as usize] = 0xE4;
+        io[(LY - 0xFF00) as usize] = 0x90;
+        io[(LYC - 0xFF00) as usize] = 0x90;
+        io[(STAT - 0xFF00) as usize] = 0x85;
+        io[(IF - 0xFF00) as usize] = 0xE0;
+        let mut core: StateWriter = StateWriter::new();
+        core.write_u16(1);
+        core.write_u16(1);
+        core.write_bytes(b"GDB ");
+        core.write_u16_slice(&[0x0150, 0x01B0, 0x0013, 0x00D8, 0x014D, 0xDFF0]);
+        core.write_bytes(&[1, 0x01, EXECUTION_RUNNING, 0]);
+        core.write_bytes(&io);
+        core.write_bytes(&pairs.into_bytes());
+        let mut info: Vec<u8> = b"TEST".to_vec();
+        info.resize(INFO_SIZE, 0);
+
+        let start: usize = file.len();
+        file.extend(block(NAME_BLOCK, b"SameBoy v0.16.3"));
+        file.extend(block(INFO_BLOCK, &info));
+        file.extend(block(CORE_BLOCK, &core.into_bytes()));
+        file.extend(block(*b"SGB ", &[0xFF; 0x40]));
+        file.extend(block(MBC_BLOCK, &[0x00, 0x20, 0x01]));
+        file.extend(block(*b"RTC ", &[0; 0x30]));
+        file.extend(block(END_BLOCK, &[]));
+        file.extend((start as u32).to_le_bytes());
+        file.extend(FOOTER_MAGIC);
+
+        let state: BessState = BessState::parse(&file, BessState::find(&file).unwrap()).unwrap();
+        assert_eq!(state.name, "SameBoy v0.16.3");
+        let mut gameboy: Box<Gameboy> = machine();
+        state.restore(&mut gameboy).unwrap();
+        assert_eq!(gameboy.cpu.get_pc(), 0x0150);
+        assert_eq!(gameboy.cpu.get_sp(), 0xDFF0);
+        assert_eq!(gameboy.cpu.read_registers().get_hl(), 0x014D);
+        assert!(gameboy.cpu.get_ime());
+        let bus = &mut gameboy.memory_bus;
+        assert_eq!(bus.read(0xC123), 0x11);
+        assert_eq!(bus.read(0xFE9F), 0x44);
+        assert_eq!(bus.read(0xFFFE), 0x55);
+        assert_eq!(bus.read(BGP), 0xE4);
+        assert_eq!(bus.read(LY), 0x90);
+        assert_eq!(bus.read(STAT) & STAT_MODE, MODE_VBLANK);
+        // restoring STAT is not a CPU write: no spurious DMG STAT interrupt with LY = LYC
+        bus.tick(4);
+        assert_eq!(bus.get_io_register(IF) & INT_STAT, 0);
+    }
+}
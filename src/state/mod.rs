@@ -0,0 +1,5 @@
+pub mod save_state;
+pub mod snapshot;
+pub mod state_reader;
+pub mod state_writer;
+pub mod bess;
@@ -0,0 +1,237 @@
+use std::fs;
+
+use crate::error::state_error::StateError;
+use crate::gameboy::Gameboy;
+use crate::model::Model;
+use crate::state::bess::BessState;
+use crate::state::snapshot::Snapshot;
+use crate::state::state_reader::StateReader;
+use crate::state::state_writer::StateWriter;
+
+const MAGIC: &[u8; 4] = b"RBST";
+/// Version of the layout written by this build.
+pub const STATE_VERSION: u32 = 1;
+
+const MACHINE_SECTION: [u8; 4] = *b"GB  ";
+const CPU_SECTION: [u8; 4] = *b"CPU ";
+const BUS_SECTION: [u8; 4] = *b"BUS ";
+
+/// Upgrades the sections of a state from version `n + 1` to `n + 2`. Whenever the layout
+/// of a section changes, `STATE_VERSION` goes up and the conversion from the previous
+/// version is appended here, so states of every older version keep loading.
+const MIGRATIONS: [Migration; (STATE_VERSION - 1) as usize] = [];
+
+type Migration = fn(&mut SaveState) -> Result<(), StateError>;
+
+/// Snapshot of the whole machine.
+///
+/// The file starts with the magic `RBST`, the layout version (u32) and the hash of the
+/// ROM (u64), followed by sections: a 4-byte tag, the size of the data (u32) and the data
+/// written by [`Snapshot::save_state`]. Everything is little-endian. The cartridge has no
+/// real-time clock in this emulator, so there is no RTC section yet.
+///
+/// A [`BessState`] is appended after the sections, so other emulators can load the file
+/// too. States written by them only carry BESS and are imported from it.
+pub struct SaveState {
+    version: u32,
+    // `None` for states of other emulators
+    rom_hash: Option<u64>,
+    sections: Vec<([u8; 4], Vec<u8>)>,
+    bess: Option<BessState>,
+}
+
+impl SaveState {
+    /// Takes a snapshot of `gameboy`.
+    pub fn capture(gameboy: &Gameboy) -> SaveState {
+        let mut machine: StateWriter = StateWriter::new();
+        machine.write_u8(match gameboy.get_model() {
+            Model::DMG => 0,
+            Model::CGB => 1,
+            Model::SGB => 2,
+        });
+        let mut cpu: StateWriter = StateWriter::new();
+        gameboy.cpu.save_state(&mut cpu);
+        let mut bus: StateWriter = StateWriter::new();
+        gameboy.memory_bus.save_state(&mut bus);
+        SaveState {
+            version: STATE_VERSION,
+            rom_hash: Some(gameboy.memory_bus.rom.get_hash()),
+            sections: vec![
+                (MACHINE_SECTION, machine.into_bytes()),
+                (CPU_SECTION, cpu.into_bytes()),
+                (BUS_SECTION, bus.into_bytes()),
+            ],
+            bess: Some(BessState::capture(gameboy)),
+        }
+    }
+
+    /// Puts `gameboy` back in the captured state. On error, the machine is left as it was.
+    ///
+    /// # Returns
+    /// `StateError::RomMismatch` (or `StateError::CartridgeMismatch` for states of other
+    /// emulators) when the state was taken with another cartridge.
+    pub fn restore(&self, gameboy: &mut Gameboy) -> Result<(), StateError> {
+        let rom_hash: u64 = gameboy.memory_bus.rom.get_hash();
+        if let Some(found) = self.rom_hash.filter(|found| *found != rom_hash) {
+            return Err(StateError::RomMismatch { expected: rom_hash, found });
+        }
+        let backup: SaveState = SaveState::capture(gameboy);
+        self.apply(gameboy).inspect_err(|_| {
+            backup.apply(gameboy).expect("A state captured from this machine loads back");
+        })
+    }
+
+    fn apply(&self, gameboy: &mut Gameboy) -> Result<(), StateError> {
+        if self.sections.is_empty() {
+            let bess: &BessState = self.bess.as_ref().ok_or(StateError::MissingSection(MACHINE_SECTION))?;
+            return bess.restore(gameboy);
+        }
+        let mut machine: StateReader = StateReader::new(self.get_section(MACHINE_SECTION)?);
+        let model: Model = match machine.read_u8()? {
+            0 => Model::DMG,
+            1 => Model::CGB,
+            2 => Model::SGB,
+            _ => return Err(StateError::InvalidValue("model")),
+        };
+        gameboy.cpu.load_state(&mut StateReader::new(self.get_section(CPU_SECTION)?))?;
+        gameboy.memory_bus.load_state(&mut StateReader::new(self.get_section(BUS_SECTION)?))?;
+        gameboy.set_model(model);
+        Ok(())
+    }
+
+    fn get_section(&self, tag: [u8; 4]) -> Result<&[u8], StateError> {
+        self.sections.iter()
+            .find(|(section, _)| *section == tag)
+            .map(|(_, data)| data.as_slice())
+            .ok_or(StateError::MissingSection(tag))
+    }
+
+    /// Returns the file contents. States imported from other emulators are written back
+    /// as BESS only.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut file: Vec<u8> = Vec::new();
+        if let Some(rom_hash) = self.rom_hash {
+            let mut writer: StateWriter = StateWriter::new();
+            writer.write_bytes(MAGIC);
+            writer.write_u32(self.version);
+            writer.write_u64(rom_hash);
+            for (tag, data) in self.sections.iter() {
+                writer.write_bytes(tag);
+                writer.write_vec(data);
+            }
+            file = writer.into_bytes();
+        }
+        if let Some(bess) = self.bess.as_ref() {
+            bess.append_to(&mut file);
+        }
+        file
+    }
+
+    /// Parses a state, migrating it to the current version if it's older. Files of other
+    /// emulators are accepted when they end with a BESS footer.
+    pub fn from_bytes(data: &[u8]) -> Result<SaveState, StateError> {
+        let bess_offset: Option<usize> = BessState::find(data);
+        let bess: Option<BessState> = bess_offset
+            .map(|offset| BessState::parse(data, offset))
+            .transpose()?;
+        if !data.starts_with(MAGIC) {
+            return match bess {
+                Some(bess) => Ok(SaveState { version: STATE_VERSION, rom_hash: None, sections: Vec::new(), bess: Some(bess) }),
+                None => Err(StateError::InvalidMagic),
+            };
+        }
+        // the native sections stop where the BESS blocks begin
+        let end: usize = bess_offset.unwrap_or(data.len()).max(MAGIC.len());
+        let mut reader: StateReader = StateReader::new(&data[MAGIC.len()..end]);
+        let version: u32 = reader.read_u32()?;
+        if version == 0 || version > STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        let rom_hash: u64 = reader.read_u64()?;
+        let mut sections: Vec<([u8; 4], Vec<u8>)> = Vec::new();
+        while !reader.is_empty() {
+            let mut tag: [u8; 4] = [0; 4];
+            reader.read_bytes(&mut tag)?;
+            sections.push((tag, reader.read_vec()?));
+        }
+        let mut state: SaveState = SaveState { version, rom_hash: Some(rom_hash), sections, bess };
+        while state.version < STATE_VERSION {
+            MIGRATIONS[(state.version - 1) as usize](&mut state)?;
+            state.version += 1;
+        }
+        Ok(state)
+    }
+
+    pub fn write(&self, path: &str) -> Result<(), StateError> {
+        fs::write(path, self.to_bytes())?;
+        Ok(())
+    }
+
+    pub fn read(path: &str) -> Result<SaveState, StateError> {
+        SaveState::from_bytes(&fs::read(path)?)
+    }
+
+    /// Returns the file of save slot `slot` for the cartridge at `rom`: `game.gb.ss1`...
+    pub fn slot_path(rom: &str, slot: u8) -> String {
+        format!("{}.ss{}", rom, slot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A DMG running `INC A; LD (0xC000), A; JR -6`.
+    fn machine() -> Box<Gameboy> {
+        let mut gameboy: Box<Gameboy> = Box::new(Gameboy::new());
+        gameboy.set_model(Model::DMG);
+        gameboy.memory_bus.rom.bank0[0x0100..0x0106].copy_from_slice(&[0x3C, 0xEA, 0x00, 0xC0, 0x18, 0xFA]);
+        gameboy.setup_model();
+        gameboy.skip_boot();
+        gameboy
+    }
+
+    #[test]
+    fn run_save_run_load_run_gives_the_same_machine() {
+        let mut gameboy: Box<Gameboy> = machine();
+        gameboy.run_for(0.6);
+        let state: Vec<u8> = SaveState::capture(&gameboy).to_bytes();
+        gameboy.run_for(0.6);
+        // compared section by section to name the one that differs
+        let expected: Vec<([u8; 4], Vec<u8>)> = SaveState::capture(&gameboy).sections;
+        let counter: u8 = gameboy.memory_bus.read(0xC000);
+
+        let mut gameboy: Box<Gameboy> = machine();
+        SaveState::from_bytes(&state).unwrap().restore(&mut gameboy).unwrap();
+        gameboy.run_for(0.6);
+        let found: Vec<([u8; 4], Vec<u8>)> = SaveState::capture(&gameboy).sections;
+        assert_eq!(found.len(), expected.len());
+        for ((tag, found), (_, expected)) in found.iter().zip(expected.iter()) {
+            assert!(found == expected, "{} differs from byte {:?}", String::from_utf8_lossy(tag),
+                    found.iter().zip(expected).position(|(a, b)| a != b));
+        }
+        assert_eq!(gameboy.memory_bus.read(0xC000), counter);
+    }
+
+    #[test]
+    fn states_of_other_cartridges_are_refused() {
+        let state: SaveState = SaveState::capture(&machine());
+        let mut gameboy: Box<Gameboy> = machine();
+        gameboy.memory_bus.rom.bank0[0x0150] = 0x01;
+        gameboy.run_for(0.01);
+        let pc: u16 = gameboy.cpu.get_pc();
+        let result: Result<(), StateError> = state.restore(&mut gameboy);
+        assert!(matches!(result, Err(StateError::RomMismatch { found, .. }) if found == state.rom_hash.unwrap()));
+        assert_eq!(gameboy.cpu.get_pc(), pc);
+    }
+
+    #[test]
+    fn truncated_and_newer_states_are_refused() {
+        let bytes: Vec<u8> = SaveState::capture(&machine()).to_bytes();
+        assert!(matches!(SaveState::from_bytes(&bytes[..100]), Err(StateError::Truncated)));
+        assert!(matches!(SaveState::from_bytes(b"RBSX"), Err(StateError::InvalidMagic)));
+        let mut newer: Vec<u8> = bytes.clone();
+        newer[4..8].copy_from_slice(&(STATE_VERSION + 1).to_le_bytes());
+        assert!(matches!(SaveState::from_bytes(&newer), Err(StateError::UnsupportedVersion(version)) if version == STATE_VERSION + 1));
+    }
+}
@@ -0,0 +1,14 @@
+use crate::error::state_error::StateError;
+use crate::state::state_reader::StateReader;
+use crate::state::state_writer::StateWriter;
+
+/// A piece of the machine that goes into a save state.
+///
+/// Only what the emulated hardware holds is saved. Host side settings (sample rate,
+/// render mode, recordings) and whatever is plugged into the ports stay as they are.
+pub trait Snapshot {
+    fn save_state(&self, writer: &mut StateWriter);
+
+    /// Reads back, in the same order, what [`Snapshot::save_state`] wrote.
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError>;
+}
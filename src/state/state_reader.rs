@@ -0,0 +1,113 @@
+use crate::error::state_error::StateError;
+
+/// Decoder for the fields written by [`crate::state::state_writer::StateWriter`].
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> StateReader<'a> {
+        StateReader { data, position: 0 }
+    }
+
+    /// Returns `true` once every byte was read.
+    pub fn is_empty(&self) -> bool {
+        self.position >= self.data.len()
+    }
+
+    fn take(&mut self, size: usize) -> Result<&'a [u8], StateError> {
+        let end: usize = self.position.checked_add(size).ok_or(StateError::Truncated)?;
+        let bytes: &'a [u8] = self.data.get(self.position..end).ok_or(StateError::Truncated)?;
+        self.position = end;
+        Ok(bytes)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, StateError> {
+        Ok(self.read_u8()? != 0)
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, StateError> {
+        let bytes: &[u8] = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, StateError> {
+        let bytes: &[u8] = self.take(4)?;
+        Ok(u32::from_le_bytes(bytes.try_into().expect("4 bytes")))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, StateError> {
+        let bytes: &[u8] = self.take(8)?;
+        Ok(u64::from_le_bytes(bytes.try_into().expect("8 bytes")))
+    }
+
+    /// Fills `bytes` with a block written by `write_bytes`.
+    pub fn read_bytes(&mut self, bytes: &mut [u8]) -> Result<(), StateError> {
+        bytes.copy_from_slice(self.take(bytes.len())?);
+        Ok(())
+    }
+
+    pub fn read_u16_slice(&mut self, values: &mut [u16]) -> Result<(), StateError> {
+        for value in values.iter_mut() {
+            *value = self.read_u16()?;
+        }
+        Ok(())
+    }
+
+    pub fn read_vec(&mut self) -> Result<Vec<u8>, StateError> {
+        let size: usize = self.read_u32()? as usize;
+        Ok(self.take(size)?.to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::state_writer::StateWriter;
+
+    #[test]
+    fn fields_read_back_as_written() {
+        let mut writer: StateWriter = StateWriter::new();
+        writer.write_u8(0x12);
+        writer.write_bool(true);
+        writer.write_u16(0x3456);
+        writer.write_u32(0x789ABCDE);
+        writer.write_u64(0x0123456789ABCDEF);
+        writer.write_bytes(&[1, 2, 3]);
+        writer.write_u16_slice(&[0xAAAA, 0x5555]);
+        writer.write_vec(&[4, 5]);
+        let bytes: Vec<u8> = writer.into_bytes();
+        assert_eq!(bytes[2..4], [0x56, 0x34]);
+
+        let mut reader: StateReader = StateReader::new(&bytes);
+        assert_eq!(reader.read_u8().unwrap(), 0x12);
+        assert!(reader.read_bool().unwrap());
+        assert_eq!(reader.read_u16().unwrap(), 0x3456);
+        assert_eq!(reader.read_u32().unwrap(), 0x789ABCDE);
+        assert_eq!(reader.read_u64().unwrap(), 0x0123456789ABCDEF);
+        let mut block: [u8; 3] = [0; 3];
+        reader.read_bytes(&mut block).unwrap();
+        assert_eq!(block, [1, 2, 3]);
+        let mut values: [u16; 2] = [0; 2];
+        reader.read_u16_slice(&mut values).unwrap();
+        assert_eq!(values, [0xAAAA, 0x5555]);
+        assert_eq!(reader.read_vec().unwrap(), vec![4, 5]);
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn reading_past_the_end_is_truncated() {
+        let mut reader: StateReader = StateReader::new(&[0x01, 0x02, 0x03]);
+        assert!(matches!(reader.read_u32(), Err(StateError::Truncated)));
+        // a failed read consumes nothing
+        assert_eq!(reader.read_u16().unwrap(), 0x0201);
+        // a length prefix larger than the data
+        let mut reader: StateReader = StateReader::new(&[0xFF, 0xFF, 0xFF, 0xFF, 0x00]);
+        assert!(matches!(reader.read_vec(), Err(StateError::Truncated)));
+    }
+}
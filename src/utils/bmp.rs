@@ -0,0 +1,76 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+/// Expands a RGB555 color (red in the low bits, as the CGB and SNES store them) to 8 bits
+/// per channel.
+pub fn rgb555_to_rgb(color: u16) -> [u8; 3] {
+    let expand = |shift: u16| {
+        let value: u8 = ((color >> shift) & 0x1F) as u8;
+        (value << 3) | (value >> 2)
+    };
+    [expand(0), expand(5), expand(10)]
+}
+
+/// Writes a 24-bit uncompressed BMP file.
+///
+/// # Parameters
+/// - `pixels`: `width * height` RGB triples, top row first.
+pub fn write_bmp(path: &str, width: usize, height: usize, pixels: &[[u8; 3]]) -> io::Result<()> {
+    // rows are padded to a multiple of 4 bytes
+    let row_size: usize = (width * 3).div_ceil(4) * 4;
+    let data_size: u32 = (row_size * height) as u32;
+    let mut file: BufWriter<File> = BufWriter::new(File::create(path)?);
+
+    file.write_all(b"BM")?;
+    file.write_all(&(54 + data_size).to_le_bytes())?;
+    file.write_all(&0u32.to_le_bytes())?;
+    file.write_all(&54u32.to_le_bytes())?; // pixel data offset
+    file.write_all(&40u32.to_le_bytes())?; // BITMAPINFOHEADER
+    file.write_all(&(width as i32).to_le_bytes())?;
+    file.write_all(&(height as i32).to_le_bytes())?;
+    file.write_all(&1u16.to_le_bytes())?; // planes
+    file.write_all(&24u16.to_le_bytes())?; // bits per pixel
+    file.write_all(&0u32.to_le_bytes())?; // no compression
+    file.write_all(&data_size.to_le_bytes())?;
+    file.write_all(&2835i32.to_le_bytes())?; // 72 DPI
+    file.write_all(&2835i32.to_le_bytes())?;
+    file.write_all(&0u32.to_le_bytes())?;
+    file.write_all(&0u32.to_le_bytes())?;
+
+    let padding: Vec<u8> = vec![0; row_size - width * 3];
+    // bottom row first
+    for row in pixels.chunks(width).rev() {
+        for [r, g, b] in row {
+            file.write_all(&[*b, *g, *r])?;
+        }
+        file.write_all(&padding)?;
+    }
+    file.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rgb555_is_expanded_to_the_full_range() {
+        assert_eq!(rgb555_to_rgb(0x7FFF), [0xFF, 0xFF, 0xFF]);
+        assert_eq!(rgb555_to_rgb(0x001F), [0xFF, 0x00, 0x00]);
+        assert_eq!(rgb555_to_rgb(0x0200), [0x00, 0x84, 0x00]);
+    }
+
+    #[test]
+    fn rows_are_padded_and_stored_bottom_up() {
+        let path = std::env::temp_dir().join(format!("rustyboy-bmp-{}.bmp", std::process::id()));
+        let path: &str = path.to_str().unwrap();
+        // 2x2: red and green on top, blue and white below
+        let pixels: [[u8; 3]; 4] = [[0xFF, 0, 0], [0, 0xFF, 0], [0, 0, 0xFF], [0xFF, 0xFF, 0xFF]];
+        write_bmp(path, 2, 2, &pixels).unwrap();
+        let bytes: Vec<u8> = std::fs::read(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(bytes.len(), 54 + 2 * 8);
+        assert_eq!(&bytes[0..2], b"BM");
+        assert_eq!(&bytes[54..62], &[0xFF, 0, 0, 0xFF, 0xFF, 0xFF, 0, 0]);
+        assert_eq!(&bytes[62..70], &[0, 0, 0xFF, 0, 0xFF, 0, 0, 0]);
+    }
+}
@@ -1 +1,2 @@
-pub mod byte_utils;
\ No newline at end of file
+pub mod byte_utils;
+pub mod bmp;
\ No newline at end of file